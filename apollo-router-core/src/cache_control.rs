@@ -0,0 +1,156 @@
+//! Cache policies of the subgraph responses, read from their `Cache-Control` headers.
+
+use crate::Context;
+use http::header::CACHE_CONTROL;
+use http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Context entry holding the policy of the subgraph responses received so far.
+pub(crate) const CACHE_CONTROL_CONTEXT_KEY: &str = "apollo_cache_control::policy";
+
+/// The directives of a `Cache-Control` header that matter to the router.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CacheControl {
+    /// `s-maxage` if present, `max-age` otherwise.
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) private: bool,
+    /// `no-store` or `no-cache`.
+    pub(crate) no_store: bool,
+}
+
+impl CacheControl {
+    /// Parse all the `Cache-Control` headers of a response. Unknown or malformed directives are
+    /// ignored.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let mut cache_control = CacheControl::default();
+        let mut max_age = None;
+        let mut s_maxage = None;
+
+        for directive in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value.and_then(|value| value.parse::<u64>().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => max_age = max_age.or(seconds),
+                "s-maxage" => s_maxage = s_maxage.or(seconds),
+                "private" => cache_control.private = true,
+                "no-store" | "no-cache" => cache_control.no_store = true,
+                _ => {}
+            }
+        }
+
+        cache_control.max_age = s_maxage.or(max_age).map(Duration::from_secs);
+        cache_control
+    }
+
+    /// Whether a shared cache may store the response.
+    pub(crate) fn is_cacheable(&self) -> bool {
+        !self.private && !self.no_store
+    }
+
+    /// The policy of a response made of two responses: the shortest max-age, missing if any of
+    /// them has none, private or not stored if any of them is.
+    pub(crate) fn merge(&self, other: &CacheControl) -> CacheControl {
+        CacheControl {
+            max_age: self.max_age.zip(other.max_age).map(|(a, b)| a.min(b)),
+            private: self.private || other.private,
+            no_store: self.no_store || other.no_store,
+        }
+    }
+
+    /// Merge this policy of a subgraph response into the policy of the router response.
+    ///
+    /// Recording the same policy twice does not change the result, so the layers which answer
+    /// in place of a subgraph may record the policy of their responses too.
+    pub(crate) fn record(&self, context: &Context) {
+        if let Err(err) = context.upsert(
+            CACHE_CONTROL_CONTEXT_KEY,
+            |current: Option<CacheControl>| {
+                Some(current.map_or(*self, |current| current.merge(self)))
+            },
+        ) {
+            tracing::error!("could not record the subgraph cache policy: {}", err);
+        }
+    }
+
+    /// The `Cache-Control` header of this policy, if the response can be cached at all.
+    pub(crate) fn to_header_value(&self) -> Option<HeaderValue> {
+        if self.no_store {
+            return Some(HeaderValue::from_static("no-store"));
+        }
+        let max_age = self.max_age.filter(|max_age| !max_age.is_zero())?;
+        let scope = if self.private { "private" } else { "public" };
+        HeaderValue::from_str(&format!("max-age={}, {}", max_age.as_secs(), scope)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(values: &[&'static str]) -> CacheControl {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        CacheControl::from_headers(&headers)
+    }
+
+    #[test]
+    fn it_parses_cache_control() {
+        assert_eq!(parse(&[]), CacheControl::default());
+        assert_eq!(
+            parse(&["public, max-age=60"]),
+            CacheControl {
+                max_age: Some(Duration::from_secs(60)),
+                private: false,
+                no_store: false,
+            }
+        );
+        // s-maxage applies to shared caches
+        assert_eq!(
+            parse(&["max-age=60", "S-MaxAge=\"10\", private"]),
+            CacheControl {
+                max_age: Some(Duration::from_secs(10)),
+                private: true,
+                no_store: false,
+            }
+        );
+        assert!(!parse(&["no-cache"]).is_cacheable());
+        assert!(!parse(&["max-age=60, no-store"]).is_cacheable());
+        assert!(parse(&["max-age=invalid"]).is_cacheable());
+        assert_eq!(parse(&["max-age=invalid"]).max_age, None);
+    }
+
+    #[test]
+    fn it_merges_policies() {
+        let policy = parse(&["max-age=60"]).merge(&parse(&["max-age=30, private"]));
+        assert_eq!(
+            policy.to_header_value(),
+            Some(HeaderValue::from_static("max-age=30, private"))
+        );
+        assert_eq!(
+            parse(&["max-age=60"]).to_header_value(),
+            Some(HeaderValue::from_static("max-age=60, public"))
+        );
+        // a response without max-age makes the whole response uncacheable
+        assert_eq!(
+            parse(&["max-age=60"]).merge(&parse(&[])).to_header_value(),
+            None
+        );
+        assert_eq!(
+            parse(&["max-age=60"])
+                .merge(&parse(&["no-store"]))
+                .to_header_value(),
+            Some(HeaderValue::from_static("no-store"))
+        );
+    }
+}
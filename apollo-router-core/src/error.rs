@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::task::JoinError;
+use tower::BoxError;
 use tracing::level_filters::LevelFilter;
 use typed_builder::TypedBuilder;
 
@@ -30,6 +31,15 @@ pub enum FetchError {
         name: String,
     },
 
+    /// invalid value for argument '{name}' of field '{field}'
+    ValidationInvalidTypeArgument {
+        /// The field, as `Type.field`.
+        field: String,
+
+        /// Name of the argument.
+        name: String,
+    },
+
     /// query could not be planned: {reason}
     ValidationPlanningError {
         /// The failure reason.
@@ -74,6 +84,24 @@ pub enum FetchError {
         reason: String,
     },
 
+    /// request to service '{service}' timed out
+    SubrequestTimeout {
+        /// The service that did not answer in time.
+        service: String,
+    },
+
+    /// rate limit of service '{service}' reached
+    SubrequestRateLimited {
+        /// The service that was not called.
+        service: String,
+    },
+
+    /// circuit breaker of service '{service}' is open
+    SubrequestCircuitOpen {
+        /// The service that was not called.
+        service: String,
+    },
+
     /// subquery requires field '{field}' but it was not found in the current response
     ExecutionFieldNotFound {
         /// The field that is not found.
@@ -91,11 +119,53 @@ impl FetchError {
     /// Convert the fetch error to a GraphQL error.
     pub fn to_graphql_error(&self, path: Option<Path>) -> Error {
         let value: Value = serde_json::to_value(self).unwrap().into();
+        let mut extensions = value.as_object().unwrap().to_owned();
+        if let Some(code) = self.extension_code() {
+            extensions.insert("code", Value::String(code.into()));
+        }
         Error {
             message: self.to_string(),
             locations: Default::default(),
             path,
-            extensions: value.as_object().unwrap().to_owned(),
+            extensions,
+        }
+    }
+
+    /// The stable `code` of the error extensions, for the errors clients may handle, like
+    /// retrying later.
+    fn extension_code(&self) -> Option<&'static str> {
+        match self {
+            FetchError::SubrequestTimeout { .. } => Some("SUBREQUEST_TIMEOUT"),
+            FetchError::SubrequestRateLimited { .. } => Some("SUBREQUEST_RATE_LIMITED"),
+            FetchError::SubrequestCircuitOpen { .. } => Some("SUBREQUEST_CIRCUIT_OPEN"),
+            _ => None,
+        }
+    }
+
+    /// Convert an error returned by a subgraph service.
+    ///
+    /// Timeouts, rate limits and open circuits keep their own variant, other errors are reported
+    /// as HTTP errors.
+    pub(crate) fn from_subgraph_error(service: &str, err: BoxError) -> Self {
+        match err.downcast::<FetchError>() {
+            Ok(err)
+                if matches!(
+                    *err,
+                    FetchError::SubrequestTimeout { .. }
+                        | FetchError::SubrequestRateLimited { .. }
+                        | FetchError::SubrequestCircuitOpen { .. }
+                ) =>
+            {
+                *err
+            }
+            Ok(err) => FetchError::SubrequestHttpError {
+                service: service.to_string(),
+                reason: err.to_string(),
+            },
+            Err(err) => FetchError::SubrequestHttpError {
+                service: service.to_string(),
+                reason: err.to_string(),
+            },
         }
     }
 
@@ -107,6 +177,7 @@ impl FetchError {
             path: Default::default(),
             errors: vec![self.to_graphql_error(None)],
             extensions: Default::default(),
+            has_next: Default::default(),
         }
     }
 }
@@ -354,3 +425,43 @@ impl ParseErrors {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_gives_stable_extension_codes_to_the_errors_clients_may_handle() {
+        for (error, code) in [
+            (
+                FetchError::SubrequestTimeout {
+                    service: "products".to_string(),
+                },
+                "SUBREQUEST_TIMEOUT",
+            ),
+            (
+                FetchError::SubrequestRateLimited {
+                    service: "products".to_string(),
+                },
+                "SUBREQUEST_RATE_LIMITED",
+            ),
+            (
+                FetchError::SubrequestCircuitOpen {
+                    service: "products".to_string(),
+                },
+                "SUBREQUEST_CIRCUIT_OPEN",
+            ),
+        ] {
+            assert_eq!(
+                error.to_graphql_error(None).extensions.get("code"),
+                Some(&Value::String(code.into()))
+            );
+        }
+
+        let error = FetchError::SubrequestHttpError {
+            service: "products".to_string(),
+            reason: "connection refused".to_string(),
+        };
+        assert_eq!(error.to_graphql_error(None).extensions.get("code"), None);
+    }
+}
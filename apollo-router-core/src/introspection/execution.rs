@@ -0,0 +1,640 @@
+//! Execution of introspection operations against an [`IntrospectionSchema`].
+
+use super::schema::{
+    string_value, DirectiveDefinition, EnumValueDefinition, FieldDefinition, InputValueDefinition,
+    IntrospectionSchema, TypeDefinition, TypeKind, TypeReference,
+};
+use crate::{Object, Value};
+use apollo_parser::ast;
+use serde_json_bytes::ByteString;
+use std::collections::HashMap;
+
+/// An object of the introspection schema.
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    /// The root type of the operation.
+    Root(&'a str),
+    Schema,
+    Type(TypeNode<'a>),
+    Field(&'a FieldDefinition),
+    InputValue(&'a InputValueDefinition),
+    EnumValue(&'a EnumValueDefinition),
+    Directive(&'a DirectiveDefinition),
+}
+
+#[derive(Clone, Copy)]
+enum TypeNode<'a> {
+    Named(&'a TypeDefinition),
+    List(&'a TypeReference),
+    NonNull(&'a TypeReference),
+}
+
+impl Node<'_> {
+    fn type_name(&self) -> &str {
+        match self {
+            Node::Root(name) => name,
+            Node::Schema => "__Schema",
+            Node::Type(_) => "__Type",
+            Node::Field(_) => "__Field",
+            Node::InputValue(_) => "__InputValue",
+            Node::EnumValue(_) => "__EnumValue",
+            Node::Directive(_) => "__Directive",
+        }
+    }
+}
+
+/// The result of a field, before the selection of its subfields.
+enum Resolved<'a> {
+    Value(Value),
+    Node(Node<'a>),
+    List(Vec<Node<'a>>),
+}
+
+impl<'a> From<Option<Node<'a>>> for Resolved<'a> {
+    fn from(node: Option<Node<'a>>) -> Self {
+        node.map(Resolved::Node)
+            .unwrap_or(Resolved::Value(Value::Null))
+    }
+}
+
+fn optional_string(value: &Option<String>) -> Resolved<'static> {
+    Resolved::Value(value.clone().map(Value::from).unwrap_or_default())
+}
+
+pub(crate) struct Execution<'a> {
+    schema: &'a IntrospectionSchema,
+    fragments: HashMap<String, ast::FragmentDefinition>,
+    variables: Object,
+}
+
+impl<'a> Execution<'a> {
+    /// Execute an operation of a query document, with the variables of the request.
+    pub(crate) fn execute(
+        schema: &'a IntrospectionSchema,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Result<Object, String> {
+        let tree = apollo_parser::Parser::new(query).parse();
+        if let Some(error) = tree.errors().next() {
+            return Err(format!("invalid query: {}", error.message()));
+        }
+        let document = tree.document();
+
+        let mut operations = Vec::new();
+        let mut fragments = HashMap::new();
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::OperationDefinition(operation) => operations.push(operation),
+                ast::Definition::FragmentDefinition(fragment) => {
+                    if let Some(name) = fragment
+                        .fragment_name()
+                        .and_then(|fragment_name| fragment_name.name())
+                    {
+                        fragments.insert(name.text().to_string(), fragment);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let operation = match operation_name {
+            Some(operation_name) => operations.into_iter().find(|operation| {
+                operation
+                    .name()
+                    .map(|name| name.text().to_string() == operation_name)
+                    .unwrap_or(false)
+            }),
+            None if operations.len() == 1 => operations.pop(),
+            None => None,
+        }
+        .ok_or_else(|| match operation_name {
+            Some(operation_name) => format!("Unknown operation named \"{}\".", operation_name),
+            None => {
+                "Must provide operation name if query contains multiple operations.".to_string()
+            }
+        })?;
+
+        // variables missing from the request take the default value of the operation
+        let mut variables = variables.clone();
+        for definition in operation
+            .variable_definitions()
+            .iter()
+            .flat_map(|definitions| definitions.variable_definitions())
+        {
+            let name = definition
+                .variable()
+                .and_then(|variable| variable.name())
+                .map(|name| name.text().to_string());
+            let default_value = definition
+                .default_value()
+                .and_then(|default_value| default_value.value());
+            if let (Some(name), Some(default_value)) = (name, default_value) {
+                if !variables.contains_key(name.as_str()) {
+                    let value = literal_value(&default_value, &Object::new());
+                    variables.insert(name.into(), value);
+                }
+            }
+        }
+
+        let root_type = match operation.operation_type() {
+            Some(kind) if kind.mutation_token().is_some() => schema.mutation_type.as_deref(),
+            Some(kind) if kind.subscription_token().is_some() => {
+                schema.subscription_type.as_deref()
+            }
+            _ => Some(schema.query_type.as_str()),
+        }
+        .ok_or_else(|| "the schema does not support this operation type".to_string())?;
+
+        let execution = Execution {
+            schema,
+            fragments,
+            variables,
+        };
+        match operation.selection_set() {
+            Some(selection_set) => {
+                execution.selection_set(Node::Root(root_type), &selection_set, &mut Vec::new())
+            }
+            None => Ok(Object::new()),
+        }
+    }
+
+    fn selection_set(
+        &self,
+        node: Node<'a>,
+        selection_set: &ast::SelectionSet,
+        spread_fragments: &mut Vec<String>,
+    ) -> Result<Object, String> {
+        let mut object = Object::new();
+        self.collect(node, selection_set, spread_fragments, &mut object)?;
+        Ok(object)
+    }
+
+    fn collect(
+        &self,
+        node: Node<'a>,
+        selection_set: &ast::SelectionSet,
+        spread_fragments: &mut Vec<String>,
+        object: &mut Object,
+    ) -> Result<(), String> {
+        for selection in selection_set.selections() {
+            match selection {
+                // Spec: https://spec.graphql.org/draft/#Field
+                ast::Selection::Field(field) => {
+                    if !self.is_included(field.directives()) {
+                        continue;
+                    }
+                    let name = field
+                        .name()
+                        .expect("the node Name is not optional in the spec; qed")
+                        .text()
+                        .to_string();
+                    let response_key = field
+                        .alias()
+                        .and_then(|alias| alias.name())
+                        .map(|alias| alias.text().to_string())
+                        .unwrap_or_else(|| name.clone());
+
+                    let value = if name == "__typename" {
+                        Value::from(node.type_name())
+                    } else {
+                        let resolved = self.resolve(node, &name, &field)?;
+                        self.complete(resolved, &name, &field, spread_fragments)?
+                    };
+                    merge(object, response_key.into(), value);
+                }
+                // Spec: https://spec.graphql.org/draft/#InlineFragment
+                ast::Selection::InlineFragment(fragment) => {
+                    if !self.is_included(fragment.directives())
+                        || !applies_to(fragment.type_condition(), &node)
+                    {
+                        continue;
+                    }
+                    if let Some(selection_set) = fragment.selection_set() {
+                        self.collect(node, &selection_set, spread_fragments, object)?;
+                    }
+                }
+                // Spec: https://spec.graphql.org/draft/#FragmentSpread
+                ast::Selection::FragmentSpread(spread) => {
+                    if !self.is_included(spread.directives()) {
+                        continue;
+                    }
+                    let name = spread
+                        .fragment_name()
+                        .and_then(|fragment_name| fragment_name.name())
+                        .map(|name| name.text().to_string())
+                        .unwrap_or_default();
+                    let fragment = self
+                        .fragments
+                        .get(&name)
+                        .ok_or_else(|| format!("Unknown fragment \"{}\".", name))?;
+                    if !applies_to(fragment.type_condition(), &node) {
+                        continue;
+                    }
+                    // the fragments being spread are tracked through the subfields as well,
+                    // since the introspection types reference each other
+                    if spread_fragments.contains(&name) {
+                        return Err(format!(
+                            "Cannot spread fragment \"{}\" within itself.",
+                            name
+                        ));
+                    }
+                    if let Some(selection_set) = fragment.selection_set() {
+                        spread_fragments.push(name);
+                        let collected =
+                            self.collect(node, &selection_set, spread_fragments, object);
+                        spread_fragments.pop();
+                        collected?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Select the subfields of the objects returned by a field.
+    fn complete(
+        &self,
+        resolved: Resolved<'a>,
+        name: &str,
+        field: &ast::Field,
+        spread_fragments: &mut Vec<String>,
+    ) -> Result<Value, String> {
+        let selection_set = field.selection_set();
+        match (resolved, selection_set) {
+            (Resolved::Value(Value::Null), _) => Ok(Value::Null),
+            (Resolved::Value(value), None) => Ok(value),
+            (Resolved::Value(_), Some(_)) => Err(format!(
+                "Field \"{}\" must not have a selection since it is a leaf.",
+                name
+            )),
+            (Resolved::Node(node), Some(selection_set)) => self
+                .selection_set(node, &selection_set, spread_fragments)
+                .map(Value::Object),
+            (Resolved::List(nodes), Some(selection_set)) => nodes
+                .into_iter()
+                .map(|node| {
+                    self.selection_set(node, &selection_set, spread_fragments)
+                        .map(Value::Object)
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            (_, None) => Err(format!(
+                "Field \"{}\" must have a selection of subfields.",
+                name
+            )),
+        }
+    }
+
+    fn resolve(
+        &self,
+        node: Node<'a>,
+        name: &str,
+        field: &ast::Field,
+    ) -> Result<Resolved<'a>, String> {
+        let schema = self.schema;
+        let resolved = match (node, name) {
+            (Node::Root(_), "__schema") => Resolved::Node(Node::Schema),
+            (Node::Root(_), "__type") => {
+                let type_name = match self.argument(field, "name") {
+                    Value::String(type_name) => type_name.as_str().to_string(),
+                    _ => return Err("Field \"__type\" argument \"name\" is required.".to_string()),
+                };
+                schema
+                    .types
+                    .get(&type_name)
+                    .map(|ty| Node::Type(TypeNode::Named(ty)))
+                    .into()
+            }
+
+            (Node::Schema, "description") => optional_string(&schema.description),
+            (Node::Schema, "types") => Resolved::List(
+                schema
+                    .types
+                    .values()
+                    .map(|ty| Node::Type(TypeNode::Named(ty)))
+                    .collect(),
+            ),
+            (Node::Schema, "queryType") => self.named_type(&schema.query_type).into(),
+            (Node::Schema, "mutationType") => schema
+                .mutation_type
+                .as_ref()
+                .and_then(|name| self.named_type(name))
+                .into(),
+            (Node::Schema, "subscriptionType") => schema
+                .subscription_type
+                .as_ref()
+                .and_then(|name| self.named_type(name))
+                .into(),
+            (Node::Schema, "directives") => {
+                Resolved::List(schema.directives.iter().map(Node::Directive).collect())
+            }
+
+            (Node::Type(ty), _) => self.resolve_type(ty, name, field)?,
+
+            (Node::Field(definition), "name") => Resolved::Value(definition.name.as_str().into()),
+            (Node::Field(definition), "description") => optional_string(&definition.description),
+            (Node::Field(definition), "args") => self.input_values(&definition.args, field),
+            (Node::Field(definition), "type") => self.type_reference(&definition.ty),
+            (Node::Field(definition), "isDeprecated") => {
+                Resolved::Value(definition.deprecation_reason.is_some().into())
+            }
+            (Node::Field(definition), "deprecationReason") => {
+                optional_string(&definition.deprecation_reason)
+            }
+
+            (Node::InputValue(definition), "name") => {
+                Resolved::Value(definition.name.as_str().into())
+            }
+            (Node::InputValue(definition), "description") => {
+                optional_string(&definition.description)
+            }
+            (Node::InputValue(definition), "type") => self.type_reference(&definition.ty),
+            (Node::InputValue(definition), "defaultValue") => {
+                optional_string(&definition.default_value)
+            }
+            (Node::InputValue(definition), "isDeprecated") => {
+                Resolved::Value(definition.deprecation_reason.is_some().into())
+            }
+            (Node::InputValue(definition), "deprecationReason") => {
+                optional_string(&definition.deprecation_reason)
+            }
+
+            (Node::EnumValue(definition), "name") => {
+                Resolved::Value(definition.name.as_str().into())
+            }
+            (Node::EnumValue(definition), "description") => {
+                optional_string(&definition.description)
+            }
+            (Node::EnumValue(definition), "isDeprecated") => {
+                Resolved::Value(definition.deprecation_reason.is_some().into())
+            }
+            (Node::EnumValue(definition), "deprecationReason") => {
+                optional_string(&definition.deprecation_reason)
+            }
+
+            (Node::Directive(definition), "name") => {
+                Resolved::Value(definition.name.as_str().into())
+            }
+            (Node::Directive(definition), "description") => {
+                optional_string(&definition.description)
+            }
+            (Node::Directive(definition), "locations") => Resolved::Value(Value::Array(
+                definition
+                    .locations
+                    .iter()
+                    .map(|location| location.as_str().into())
+                    .collect(),
+            )),
+            (Node::Directive(definition), "args") => self.input_values(&definition.args, field),
+            (Node::Directive(definition), "isRepeatable") => {
+                Resolved::Value(definition.is_repeatable.into())
+            }
+
+            (node, name) => return Err(unknown_field(node.type_name(), name)),
+        };
+        Ok(resolved)
+    }
+
+    fn resolve_type(
+        &self,
+        ty: TypeNode<'a>,
+        name: &str,
+        field: &ast::Field,
+    ) -> Result<Resolved<'a>, String> {
+        let definition = match ty {
+            TypeNode::Named(definition) => definition,
+            TypeNode::List(inner) | TypeNode::NonNull(inner) => {
+                return Ok(match name {
+                    "kind" => Resolved::Value(
+                        if matches!(ty, TypeNode::List(_)) {
+                            "LIST"
+                        } else {
+                            "NON_NULL"
+                        }
+                        .into(),
+                    ),
+                    "ofType" => self.type_reference(inner),
+                    "name" | "description" | "specifiedByURL" | "specifiedByUrl" | "fields"
+                    | "interfaces" | "possibleTypes" | "enumValues" | "inputFields" => {
+                        Resolved::Value(Value::Null)
+                    }
+                    _ => return Err(unknown_field("__Type", name)),
+                })
+            }
+        };
+
+        let include_deprecated =
+            matches!(self.argument(field, "includeDeprecated"), Value::Bool(true));
+        let has_fields = matches!(definition.kind, TypeKind::Object | TypeKind::Interface);
+
+        Ok(match name {
+            "kind" => Resolved::Value(definition.kind.as_str().into()),
+            "name" => Resolved::Value(definition.name.as_str().into()),
+            "description" => optional_string(&definition.description),
+            "specifiedByURL" | "specifiedByUrl" => optional_string(&definition.specified_by_url),
+            "fields" if has_fields => Resolved::List(
+                definition
+                    .fields
+                    .iter()
+                    .filter(|field| include_deprecated || field.deprecation_reason.is_none())
+                    .map(Node::Field)
+                    .collect(),
+            ),
+            "interfaces" if has_fields => Resolved::List(
+                definition
+                    .interfaces
+                    .iter()
+                    .filter_map(|name| self.named_type(name))
+                    .collect(),
+            ),
+            "possibleTypes" => match self.schema.possible_types(definition) {
+                Some(types) => Resolved::List(
+                    types
+                        .into_iter()
+                        .map(|ty| Node::Type(TypeNode::Named(ty)))
+                        .collect(),
+                ),
+                None => Resolved::Value(Value::Null),
+            },
+            "enumValues" if definition.kind == TypeKind::Enum => Resolved::List(
+                definition
+                    .enum_values
+                    .iter()
+                    .filter(|value| include_deprecated || value.deprecation_reason.is_none())
+                    .map(Node::EnumValue)
+                    .collect(),
+            ),
+            "inputFields" if definition.kind == TypeKind::InputObject => {
+                self.input_values(&definition.input_fields, field)
+            }
+            "fields" | "interfaces" | "enumValues" | "inputFields" | "ofType" => {
+                Resolved::Value(Value::Null)
+            }
+            _ => return Err(unknown_field("__Type", name)),
+        })
+    }
+
+    fn input_values(
+        &self,
+        definitions: &'a [InputValueDefinition],
+        field: &ast::Field,
+    ) -> Resolved<'a> {
+        let include_deprecated =
+            matches!(self.argument(field, "includeDeprecated"), Value::Bool(true));
+        Resolved::List(
+            definitions
+                .iter()
+                .filter(|value| include_deprecated || value.deprecation_reason.is_none())
+                .map(Node::InputValue)
+                .collect(),
+        )
+    }
+
+    fn named_type(&self, name: &str) -> Option<Node<'a>> {
+        self.schema
+            .types
+            .get(name)
+            .map(|ty| Node::Type(TypeNode::Named(ty)))
+    }
+
+    fn type_reference(&self, ty: &'a TypeReference) -> Resolved<'a> {
+        match ty {
+            TypeReference::Named(name) => self.named_type(name).into(),
+            TypeReference::List(inner) => Resolved::Node(Node::Type(TypeNode::List(inner))),
+            TypeReference::NonNull(inner) => Resolved::Node(Node::Type(TypeNode::NonNull(inner))),
+        }
+    }
+
+    fn argument(&self, field: &ast::Field, name: &str) -> Value {
+        field
+            .arguments()
+            .iter()
+            .flat_map(|arguments| arguments.arguments())
+            .find(|argument| {
+                argument
+                    .name()
+                    .map(|argument_name| argument_name.text().to_string() == name)
+                    .unwrap_or(false)
+            })
+            .and_then(|argument| argument.value())
+            .map(|value| literal_value(&value, &self.variables))
+            .unwrap_or_default()
+    }
+
+    // Spec: https://spec.graphql.org/draft/#sec--skip
+    // Spec: https://spec.graphql.org/draft/#sec--include
+    fn is_included(&self, directives: Option<ast::Directives>) -> bool {
+        directives
+            .iter()
+            .flat_map(|directives| directives.directives())
+            .all(|directive| {
+                let name = directive
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default();
+                let condition = || {
+                    directive
+                        .arguments()
+                        .iter()
+                        .flat_map(|arguments| arguments.arguments())
+                        .find(|argument| {
+                            argument
+                                .name()
+                                .map(|name| name.text().to_string() == "if")
+                                .unwrap_or(false)
+                        })
+                        .and_then(|argument| argument.value())
+                        .map(|value| literal_value(&value, &self.variables))
+                };
+                match name.as_str() {
+                    "skip" => condition() != Some(Value::Bool(true)),
+                    "include" => condition() != Some(Value::Bool(false)),
+                    _ => true,
+                }
+            })
+    }
+}
+
+fn applies_to(type_condition: Option<ast::TypeCondition>, node: &Node) -> bool {
+    type_condition
+        .and_then(|condition| condition.named_type())
+        .and_then(|named| named.name())
+        .map(|name| name.text().to_string() == node.type_name())
+        .unwrap_or(true)
+}
+
+fn unknown_field(type_name: &str, name: &str) -> String {
+    format!("Cannot query field \"{}\" on type \"{}\".", name, type_name)
+}
+
+/// Add a field to an object, merging it with the same field selected several times.
+fn merge(object: &mut Object, key: ByteString, value: Value) {
+    if let Some(existing) = object.get_mut(&key) {
+        merge_value(existing, value);
+    } else {
+        object.insert(key, value);
+    }
+}
+
+fn merge_value(existing: &mut Value, value: Value) {
+    match (existing, value) {
+        (Value::Object(existing), Value::Object(value)) => {
+            for (key, value) in value {
+                merge(existing, key, value);
+            }
+        }
+        (Value::Array(existing), Value::Array(values)) if existing.len() == values.len() => {
+            for (existing, value) in existing.iter_mut().zip(values) {
+                merge_value(existing, value);
+            }
+        }
+        (existing, value) => *existing = value,
+    }
+}
+
+/// The value of an argument literal.
+fn literal_value(value: &ast::Value, variables: &Object) -> Value {
+    match value {
+        ast::Value::Variable(variable) => variable
+            .name()
+            .and_then(|name| variables.get(name.text().to_string().as_str()).cloned())
+            .unwrap_or_default(),
+        ast::Value::StringValue(string) => string_value(&string.to_string()).into(),
+        ast::Value::BooleanValue(boolean) => boolean.true_token().is_some().into(),
+        ast::Value::EnumValue(enum_value) => enum_value
+            .name()
+            .map(|name| name.text().to_string().into())
+            .unwrap_or_default(),
+        ast::Value::IntValue(int) => int
+            .to_string()
+            .trim()
+            .parse::<i64>()
+            .map(Value::from)
+            .unwrap_or_default(),
+        ast::Value::FloatValue(float) => float
+            .to_string()
+            .trim()
+            .parse::<f64>()
+            .map(Value::from)
+            .unwrap_or_default(),
+        ast::Value::ListValue(list) => Value::Array(
+            list.values()
+                .map(|value| literal_value(&value, variables))
+                .collect(),
+        ),
+        ast::Value::ObjectValue(object) => Value::Object(
+            object
+                .object_fields()
+                .filter_map(|field| {
+                    Some((
+                        field.name()?.text().to_string().into(),
+                        literal_value(&field.value()?, variables),
+                    ))
+                })
+                .collect(),
+        ),
+        ast::Value::NullValue(_) => Value::Null,
+    }
+}
@@ -0,0 +1,275 @@
+//! Introspection of the API schema, executed natively against the schema types.
+
+mod execution;
+pub(crate) mod schema;
+
+use crate::prelude::graphql::*;
+use execution::Execution;
+use schema::IntrospectionSchema;
+
+/// The introspection of a schema.
+#[derive(Debug)]
+pub struct Introspection {
+    schema: IntrospectionSchema,
+}
+
+impl Introspection {
+    /// Create an `Introspection` from the API schema of a `Schema`.
+    pub fn from_schema(schema: &Schema) -> Self {
+        let span = tracing::trace_span!("introspection_population");
+        let _guard = span.enter();
+
+        Self {
+            schema: IntrospectionSchema::parse(schema.api_schema().as_str()),
+        }
+    }
+
+    /// Execute an introspection query.
+    ///
+    /// The operation must only select introspection fields on the root type.
+    pub fn execute(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Response {
+        match Execution::execute(&self.schema, query, operation_name, variables) {
+            Ok(data) => Response::builder().data(Value::Object(data)).build(),
+            Err(message) => Response::builder()
+                .errors(vec![crate::Error::builder().message(message).build()])
+                .build(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod introspection_tests {
+    use super::*;
+    use include_dir::include_dir;
+    use serde_json_bytes::json;
+
+    const SCHEMA: &str = r#"
+        type Query {
+          "The current user"
+          me: User
+          node(id: ID!): Node
+          search(term: String = "all"): [SearchResult!]!
+          legacy: String @deprecated(reason: "use me")
+        }
+
+        interface Node {
+          id: ID!
+        }
+
+        type User implements Node {
+          id: ID!
+          name: String
+          role: Role
+        }
+
+        type Product implements Node {
+          id: ID!
+        }
+
+        union SearchResult = User | Product
+
+        enum Role {
+          ADMIN
+          GUEST @deprecated
+        }
+
+        input Filter {
+          term: String!
+          limit: Int = 10
+        }
+    "#;
+
+    fn execute(query: &str, variables: Object) -> Response {
+        let introspection = Introspection {
+            schema: IntrospectionSchema::parse(SCHEMA),
+        };
+        introspection.execute(query, None, &variables)
+    }
+
+    #[test]
+    fn it_executes_aliases_fragments_and_variables() {
+        let response = execute(
+            r#"query Q($name: String!) {
+                __typename
+                user: __type(name: $name) { ...TypeInfo }
+                role: __type(name: "Role") {
+                    kind
+                    enumValues(includeDeprecated: true) { name isDeprecated deprecationReason }
+                }
+            }
+
+            fragment TypeInfo on __Type {
+                __typename
+                name
+                ... on __Type { kind interfaces { name } }
+                fields { name type { kind name ofType { name } } }
+            }"#,
+            json!({ "name": "User" }).as_object().unwrap().clone(),
+        );
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.unwrap(),
+            json!({
+                "__typename": "Query",
+                "user": {
+                    "__typename": "__Type",
+                    "name": "User",
+                    "kind": "OBJECT",
+                    "interfaces": [{ "name": "Node" }],
+                    "fields": [
+                        { "name": "id", "type": { "kind": "NON_NULL", "name": null, "ofType": { "name": "ID" } } },
+                        { "name": "name", "type": { "kind": "SCALAR", "name": "String", "ofType": null } },
+                        { "name": "role", "type": { "kind": "ENUM", "name": "Role", "ofType": null } },
+                    ]
+                },
+                "role": {
+                    "kind": "ENUM",
+                    "enumValues": [
+                        { "name": "ADMIN", "isDeprecated": false, "deprecationReason": null },
+                        { "name": "GUEST", "isDeprecated": true, "deprecationReason": "No longer supported" },
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn it_introspects_the_schema() {
+        let response = execute(
+            r#"{
+                __schema { queryType { name } mutationType { name } types { name } }
+                node: __type(name: "Node") { possibleTypes { name } }
+                search: __type(name: "SearchResult") { possibleTypes { name } fields { name } }
+                query: __type(name: "Query") {
+                    fields { name description args { name defaultValue } }
+                }
+                filter: __type(name: "Filter") { inputFields { name defaultValue } }
+                missing: __type(name: "Missing") { name }
+            }"#,
+            Object::new(),
+        );
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let mut data = response.data.unwrap();
+        let types = data
+            .as_object_mut()
+            .unwrap()
+            .remove("__schema")
+            .unwrap()
+            .as_object_mut()
+            .unwrap()
+            .remove("types")
+            .unwrap();
+        let type_names: Vec<&str> = types
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|ty| {
+                ty.as_object()
+                    .unwrap()
+                    .get("name")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+            })
+            .collect();
+        assert!(type_names.contains(&"User"));
+        assert!(type_names.contains(&"ID"));
+        assert!(type_names.contains(&"__Schema"));
+        // not referenced by the schema
+        assert!(!type_names.contains(&"Float"));
+
+        assert_eq!(
+            data,
+            json!({
+                "node": { "possibleTypes": [{ "name": "User" }, { "name": "Product" }] },
+                "search": { "possibleTypes": [{ "name": "User" }, { "name": "Product" }], "fields": null },
+                "query": {
+                    "fields": [
+                        { "name": "me", "description": "The current user", "args": [] },
+                        { "name": "node", "description": null, "args": [{ "name": "id", "defaultValue": null }] },
+                        { "name": "search", "description": null, "args": [{ "name": "term", "defaultValue": "\"all\"" }] },
+                    ]
+                },
+                "filter": {
+                    "inputFields": [
+                        { "name": "term", "defaultValue": null },
+                        { "name": "limit", "defaultValue": "10" },
+                    ]
+                },
+                "missing": null
+            })
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        let response = execute("{ __schema { unknown } }", Object::new());
+
+        assert!(response.data.is_none());
+        assert_eq!(
+            response.errors[0].message,
+            "Cannot query field \"unknown\" on type \"__Schema\"."
+        );
+    }
+
+    #[test]
+    fn it_rejects_fragment_cycles_through_fields() {
+        let response = execute(
+            r#"{ __schema { types { ...TypeRef } } }
+
+            fragment TypeRef on __Type {
+                fields { type { ofType { ...TypeRef } } }
+            }"#,
+            Object::new(),
+        );
+
+        assert!(response.data.is_none());
+        assert_eq!(
+            response.errors[0].message,
+            "Cannot spread fragment \"TypeRef\" within itself."
+        );
+    }
+
+    #[test]
+    fn test_known_introspection_queries() {
+        let schema: Schema = include_str!("../testdata/starstuff@current.graphql")
+            .parse()
+            .unwrap();
+        let introspection = Introspection::from_schema(&schema);
+
+        let queries_dir = include_dir!("$CARGO_MANIFEST_DIR/well_known_introspection_queries");
+        let files: Vec<_> = queries_dir.files().collect();
+        let queries: Vec<String> = files
+            .iter()
+            .map(|file| file.contents_utf8().unwrap().to_string())
+            .collect();
+        // the native execution must answer like the query planner's introspection
+        let expected = router_bridge::introspect::batch_introspect(schema.as_str(), queries)
+            .unwrap()
+            .unwrap();
+
+        for (file, expected) in files.into_iter().zip(expected) {
+            let query = file.contents_utf8().unwrap();
+            let response = introspection.execute(query, None, &Object::new());
+            assert!(
+                response.errors.is_empty(),
+                "{}: {:?}",
+                file.path().display(),
+                response.errors
+            );
+            assert_eq!(
+                response.data.unwrap(),
+                expected.into_result().unwrap(),
+                "{}",
+                file.path().display()
+            );
+        }
+    }
+}
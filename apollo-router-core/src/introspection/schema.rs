@@ -0,0 +1,788 @@
+//! The types and directives of a schema, as seen by introspection.
+
+use apollo_parser::ast;
+use indexmap::IndexMap;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+
+const DEFAULT_DEPRECATION_REASON: &str = "No longer supported";
+
+/// Built-in scalars, introspection types and directives, with the descriptions of the reference
+/// implementation.
+const BUILT_IN_DEFINITIONS: &str = r#"
+"The `String` scalar type represents textual data, represented as UTF-8 character sequences. The String type is most often used by GraphQL to represent free-form human-readable text."
+scalar String
+
+"The `Int` scalar type represents non-fractional signed whole numeric values. Int can represent values between -(2^31) and 2^31 - 1."
+scalar Int
+
+"The `Float` scalar type represents signed double-precision fractional values as specified by [IEEE 754](https://en.wikipedia.org/wiki/IEEE_floating_point)."
+scalar Float
+
+"The `Boolean` scalar type represents `true` or `false`."
+scalar Boolean
+
+"The `ID` scalar type represents a unique identifier, often used to refetch an object or as key for a cache. The ID type appears in a JSON response as a String; however, it is not intended to be human-readable. When expected as an input type, any string (such as `\"4\"`) or integer (such as `4`) input value will be accepted as an ID."
+scalar ID
+
+"A GraphQL Schema defines the capabilities of a GraphQL server. It exposes all available types and directives on the server, as well as the entry points for query, mutation, and subscription operations."
+type __Schema {
+  description: String
+  "A list of all types supported by this server."
+  types: [__Type!]!
+  "The type that query operations will be rooted at."
+  queryType: __Type!
+  "If this server supports mutation, the type that mutation operations will be rooted at."
+  mutationType: __Type
+  "If this server support subscription, the type that subscription operations will be rooted at."
+  subscriptionType: __Type
+  "A list of all directives supported by this server."
+  directives: [__Directive!]!
+}
+
+"The fundamental unit of any GraphQL Schema is the type. There are many kinds of types in GraphQL as represented by the `__TypeKind` enum.\n\nDepending on the kind of a type, certain fields describe information about that type. Scalar types provide no information beyond a name, description and optional `specifiedByURL`, while Enum types provide their values. Object and Interface types provide the fields they describe. Abstract types, Union and Interface, provide the Object types possible at runtime. List and NonNull types compose other types."
+type __Type {
+  kind: __TypeKind!
+  name: String
+  description: String
+  specifiedByURL: String
+  fields(includeDeprecated: Boolean = false): [__Field!]
+  interfaces: [__Type!]
+  possibleTypes: [__Type!]
+  enumValues(includeDeprecated: Boolean = false): [__EnumValue!]
+  inputFields(includeDeprecated: Boolean = false): [__InputValue!]
+  ofType: __Type
+}
+
+"An enum describing what kind of type a given `__Type` is."
+enum __TypeKind {
+  "Indicates this type is a scalar."
+  SCALAR
+  "Indicates this type is an object. `fields` and `interfaces` are valid fields."
+  OBJECT
+  "Indicates this type is an interface. `fields`, `interfaces`, and `possibleTypes` are valid fields."
+  INTERFACE
+  "Indicates this type is a union. `possibleTypes` is a valid field."
+  UNION
+  "Indicates this type is an enum. `enumValues` is a valid field."
+  ENUM
+  "Indicates this type is an input object. `inputFields` is a valid field."
+  INPUT_OBJECT
+  "Indicates this type is a list. `ofType` is a valid field."
+  LIST
+  "Indicates this type is a non-null. `ofType` is a valid field."
+  NON_NULL
+}
+
+"Object and Interface types are described by a list of Fields, each of which has a name, potentially a list of arguments, and a return type."
+type __Field {
+  name: String!
+  description: String
+  args(includeDeprecated: Boolean = false): [__InputValue!]!
+  type: __Type!
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+"Arguments provided to Fields or Directives and the input fields of an InputObject are represented as Input Values which describe their type and optionally a default value."
+type __InputValue {
+  name: String!
+  description: String
+  type: __Type!
+  "A GraphQL-formatted string representing the default value for this input value."
+  defaultValue: String
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+"One possible value for a given Enum. Enum values are unique values, not a placeholder for a string or numeric value. However an Enum value is returned in a JSON response as a string."
+type __EnumValue {
+  name: String!
+  description: String
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+"A Directive provides a way to describe alternate runtime execution and type validation behavior in a GraphQL document.\n\nIn some cases, you need to provide options to alter GraphQL's execution behavior in ways field arguments will not suffice, such as conditionally including or skipping a field. Directives provide this by describing additional information to the executor."
+type __Directive {
+  name: String!
+  description: String
+  isRepeatable: Boolean!
+  locations: [__DirectiveLocation!]!
+  args(includeDeprecated: Boolean = false): [__InputValue!]!
+}
+
+"A Directive can be adjacent to many parts of the GraphQL language, a __DirectiveLocation describes one such possible adjacencies."
+enum __DirectiveLocation {
+  "Location adjacent to a query operation."
+  QUERY
+  "Location adjacent to a mutation operation."
+  MUTATION
+  "Location adjacent to a subscription operation."
+  SUBSCRIPTION
+  "Location adjacent to a field."
+  FIELD
+  "Location adjacent to a fragment definition."
+  FRAGMENT_DEFINITION
+  "Location adjacent to a fragment spread."
+  FRAGMENT_SPREAD
+  "Location adjacent to an inline fragment."
+  INLINE_FRAGMENT
+  "Location adjacent to a variable definition."
+  VARIABLE_DEFINITION
+  "Location adjacent to a schema definition."
+  SCHEMA
+  "Location adjacent to a scalar definition."
+  SCALAR
+  "Location adjacent to an object type definition."
+  OBJECT
+  "Location adjacent to a field definition."
+  FIELD_DEFINITION
+  "Location adjacent to an argument definition."
+  ARGUMENT_DEFINITION
+  "Location adjacent to an interface definition."
+  INTERFACE
+  "Location adjacent to a union definition."
+  UNION
+  "Location adjacent to an enum definition."
+  ENUM
+  "Location adjacent to an enum value definition."
+  ENUM_VALUE
+  "Location adjacent to an input object type definition."
+  INPUT_OBJECT
+  "Location adjacent to an input object field definition."
+  INPUT_FIELD_DEFINITION
+}
+
+"Directs the executor to include this field or fragment only when the `if` argument is true."
+directive @include(
+  "Included when true."
+  if: Boolean!
+) on FIELD | FRAGMENT_SPREAD | INLINE_FRAGMENT
+
+"Directs the executor to skip this field or fragment when the `if` argument is true."
+directive @skip(
+  "Skipped when true."
+  if: Boolean!
+) on FIELD | FRAGMENT_SPREAD | INLINE_FRAGMENT
+
+"Marks an element of a GraphQL schema as no longer supported."
+directive @deprecated(
+  "Explains why this element was deprecated, usually also including a suggestion for how to access supported similar data. Formatted using the Markdown syntax, as specified by [CommonMark](https://commonmark.org/)."
+  reason: String = "No longer supported"
+) on FIELD_DEFINITION | ARGUMENT_DEFINITION | INPUT_FIELD_DEFINITION | ENUM_VALUE
+
+"Directs the executor to deliver this fragment after the rest of the response, when the `if` argument is true."
+directive @defer(
+  "Identifies the fragment in the incremental responses."
+  label: String
+  "Deferred when true."
+  if: Boolean! = true
+) on FRAGMENT_SPREAD | INLINE_FRAGMENT
+
+"Exposes a URL that specifies the behavior of this scalar."
+directive @specifiedBy(
+  "The URL that specifies the behavior of this scalar."
+  url: String!
+) on SCALAR
+"#;
+
+static BUILT_INS: Lazy<Definitions> = Lazy::new(|| Definitions::parse(BUILT_IN_DEFINITIONS));
+
+/// The types and directives of a schema, including the built-in ones.
+#[derive(Debug, Default)]
+pub(crate) struct IntrospectionSchema {
+    pub(crate) description: Option<String>,
+    pub(crate) types: IndexMap<String, TypeDefinition>,
+    pub(crate) directives: Vec<DirectiveDefinition>,
+    pub(crate) query_type: String,
+    pub(crate) mutation_type: Option<String>,
+    pub(crate) subscription_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TypeKind {
+    Scalar,
+    Object,
+    Interface,
+    Union,
+    Enum,
+    InputObject,
+}
+
+impl TypeKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TypeKind::Scalar => "SCALAR",
+            TypeKind::Object => "OBJECT",
+            TypeKind::Interface => "INTERFACE",
+            TypeKind::Union => "UNION",
+            TypeKind::Enum => "ENUM",
+            TypeKind::InputObject => "INPUT_OBJECT",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TypeDefinition {
+    pub(crate) kind: TypeKind,
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) specified_by_url: Option<String>,
+    pub(crate) fields: Vec<FieldDefinition>,
+    pub(crate) interfaces: Vec<String>,
+    /// Members of a union. The possible types of an interface are computed from the objects.
+    pub(crate) possible_types: Vec<String>,
+    pub(crate) enum_values: Vec<EnumValueDefinition>,
+    pub(crate) input_fields: Vec<InputValueDefinition>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FieldDefinition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) args: Vec<InputValueDefinition>,
+    pub(crate) ty: TypeReference,
+    pub(crate) deprecation_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct InputValueDefinition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) ty: TypeReference,
+    /// The default value, as a GraphQL literal.
+    pub(crate) default_value: Option<String>,
+    pub(crate) deprecation_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EnumValueDefinition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) deprecation_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DirectiveDefinition {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) args: Vec<InputValueDefinition>,
+    pub(crate) locations: Vec<String>,
+    pub(crate) is_repeatable: bool,
+}
+
+/// A reference to a type, wrapped in lists and non null types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TypeReference {
+    Named(String),
+    List(Box<TypeReference>),
+    NonNull(Box<TypeReference>),
+}
+
+impl TypeReference {
+    fn named_type(&self) -> &str {
+        match self {
+            TypeReference::Named(name) => name,
+            TypeReference::List(inner) | TypeReference::NonNull(inner) => inner.named_type(),
+        }
+    }
+}
+
+impl IntrospectionSchema {
+    /// Build the introspection schema of an SDL, usually the API schema.
+    pub(crate) fn parse(sdl: &str) -> Self {
+        let definitions = Definitions::parse(sdl);
+
+        let referenced: HashSet<&str> = definitions
+            .types
+            .values()
+            .flat_map(|ty| {
+                ty.fields
+                    .iter()
+                    .flat_map(|field| {
+                        std::iter::once(&field.ty).chain(field.args.iter().map(|arg| &arg.ty))
+                    })
+                    .chain(ty.input_fields.iter().map(|field| &field.ty))
+            })
+            .chain(
+                definitions
+                    .directives
+                    .iter()
+                    .flat_map(|directive| directive.args.iter().map(|arg| &arg.ty)),
+            )
+            .map(TypeReference::named_type)
+            // used by the introspection types
+            .chain(["String", "Boolean"])
+            .collect();
+
+        let mut types = definitions.types.clone();
+        for (name, ty) in &BUILT_INS.types {
+            let is_used = name.starts_with("__") || referenced.contains(name.as_str());
+            if is_used && !types.contains_key(name) {
+                types.insert(name.clone(), ty.clone());
+            }
+        }
+
+        let mut directives = BUILT_INS.directives.clone();
+        directives.retain(|built_in| {
+            !definitions
+                .directives
+                .iter()
+                .any(|directive| directive.name == built_in.name)
+        });
+        directives.extend(definitions.directives.iter().cloned());
+
+        let root_type = |operation: &str, default_name: &str| {
+            definitions
+                .root_operations
+                .iter()
+                .find(|(kind, _)| kind == operation)
+                .map(|(_, name)| name.clone())
+                .or_else(|| {
+                    types
+                        .contains_key(default_name)
+                        .then(|| default_name.to_string())
+                })
+        };
+
+        Self {
+            description: definitions.description.clone(),
+            query_type: root_type("query", "Query").unwrap_or_else(|| "Query".to_string()),
+            mutation_type: root_type("mutation", "Mutation"),
+            subscription_type: root_type("subscription", "Subscription"),
+            types,
+            directives,
+        }
+    }
+
+    /// The object types implementing an interface, or the members of a union.
+    pub(crate) fn possible_types<'a>(
+        &'a self,
+        ty: &'a TypeDefinition,
+    ) -> Option<Vec<&'a TypeDefinition>> {
+        match ty.kind {
+            TypeKind::Interface => Some(
+                self.types
+                    .values()
+                    .filter(|object| {
+                        object.kind == TypeKind::Object && object.interfaces.contains(&ty.name)
+                    })
+                    .collect(),
+            ),
+            TypeKind::Union => Some(
+                ty.possible_types
+                    .iter()
+                    .filter_map(|name| self.types.get(name))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The definitions of a document, without the built-in ones.
+#[derive(Debug, Default)]
+struct Definitions {
+    description: Option<String>,
+    types: IndexMap<String, TypeDefinition>,
+    directives: Vec<DirectiveDefinition>,
+    root_operations: Vec<(String, String)>,
+}
+
+impl Definitions {
+    fn parse(sdl: &str) -> Self {
+        let tree = apollo_parser::Parser::new(sdl).parse();
+        let mut definitions = Definitions::default();
+        let mut extensions = Vec::new();
+
+        for definition in tree.document().definitions() {
+            match definition {
+                // Spec: https://spec.graphql.org/draft/#SchemaDefinition
+                ast::Definition::SchemaDefinition(schema) => {
+                    definitions.description = description(schema.description());
+                    for root in schema.root_operation_type_definitions() {
+                        let kind = root.operation_type().map(|kind| {
+                            if kind.mutation_token().is_some() {
+                                "mutation"
+                            } else if kind.subscription_token().is_some() {
+                                "subscription"
+                            } else {
+                                "query"
+                            }
+                        });
+                        let name = root
+                            .named_type()
+                            .and_then(|named| named.name())
+                            .map(|name| name.text().to_string());
+                        if let (Some(kind), Some(name)) = (kind, name) {
+                            definitions.root_operations.push((kind.to_string(), name));
+                        }
+                    }
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Scalars
+                ast::Definition::ScalarTypeDefinition(scalar) => {
+                    let specified_by_url = scalar.directives().and_then(|directives| {
+                        directives
+                            .directives()
+                            .find(|directive| directive_name(directive) == "specifiedBy")
+                            .and_then(|directive| string_argument(&directive, "url"))
+                    });
+                    definitions.insert(TypeDefinition {
+                        specified_by_url,
+                        ..TypeDefinition::new(
+                            TypeKind::Scalar,
+                            scalar.name(),
+                            description(scalar.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Objects
+                ast::Definition::ObjectTypeDefinition(object) => {
+                    definitions.insert(TypeDefinition {
+                        fields: fields(object.fields_definition()),
+                        interfaces: interfaces(object.implements_interfaces()),
+                        ..TypeDefinition::new(
+                            TypeKind::Object,
+                            object.name(),
+                            description(object.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Interfaces
+                ast::Definition::InterfaceTypeDefinition(interface) => {
+                    definitions.insert(TypeDefinition {
+                        fields: fields(interface.fields_definition()),
+                        interfaces: interfaces(interface.implements_interfaces()),
+                        ..TypeDefinition::new(
+                            TypeKind::Interface,
+                            interface.name(),
+                            description(interface.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Unions
+                ast::Definition::UnionTypeDefinition(union) => {
+                    let possible_types = union
+                        .union_member_types()
+                        .iter()
+                        .flat_map(|members| members.named_types())
+                        .filter_map(|named| named.name())
+                        .map(|name| name.text().to_string())
+                        .collect();
+                    definitions.insert(TypeDefinition {
+                        possible_types,
+                        ..TypeDefinition::new(
+                            TypeKind::Union,
+                            union.name(),
+                            description(union.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Enums
+                ast::Definition::EnumTypeDefinition(enum_type) => {
+                    let enum_values = enum_type
+                        .enum_values_definition()
+                        .iter()
+                        .flat_map(|values| values.enum_value_definitions())
+                        .filter_map(|value| {
+                            Some(EnumValueDefinition {
+                                name: value.enum_value()?.name()?.text().to_string(),
+                                description: description(value.description()),
+                                deprecation_reason: deprecation_reason(value.directives()),
+                            })
+                        })
+                        .collect();
+                    definitions.insert(TypeDefinition {
+                        enum_values,
+                        ..TypeDefinition::new(
+                            TypeKind::Enum,
+                            enum_type.name(),
+                            description(enum_type.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Input-Objects
+                ast::Definition::InputObjectTypeDefinition(input_object) => {
+                    let input_fields = input_object
+                        .input_fields_definition()
+                        .iter()
+                        .flat_map(|fields| fields.input_value_definitions())
+                        .filter_map(input_value)
+                        .collect();
+                    definitions.insert(TypeDefinition {
+                        input_fields,
+                        ..TypeDefinition::new(
+                            TypeKind::InputObject,
+                            input_object.name(),
+                            description(input_object.description()),
+                        )
+                    });
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Object-Extensions
+                ast::Definition::ObjectTypeExtension(extension) => extensions.push((
+                    extension.name(),
+                    fields(extension.fields_definition()),
+                    interfaces(extension.implements_interfaces()),
+                )),
+                // Spec: https://spec.graphql.org/draft/#sec-Interface-Extensions
+                ast::Definition::InterfaceTypeExtension(extension) => extensions.push((
+                    extension.name(),
+                    fields(extension.fields_definition()),
+                    interfaces(extension.implements_interfaces()),
+                )),
+                // Spec: https://spec.graphql.org/draft/#sec-Type-System.Directives
+                ast::Definition::DirectiveDefinition(directive) => {
+                    if let Some(name) = directive.name() {
+                        definitions.directives.push(DirectiveDefinition {
+                            name: name.text().to_string(),
+                            description: description(directive.description()),
+                            args: arguments(directive.arguments_definition()),
+                            locations: directive
+                                .directive_locations()
+                                .iter()
+                                .flat_map(|locations| locations.directive_locations())
+                                .map(|location| location.to_string().trim().to_string())
+                                .collect(),
+                            is_repeatable: directive.repeatable_token().is_some(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (name, fields, interfaces) in extensions {
+            if let Some(ty) =
+                name.and_then(|name| definitions.types.get_mut(&name.text().to_string()))
+            {
+                ty.fields.extend(fields);
+                ty.interfaces.extend(interfaces);
+            }
+        }
+
+        definitions
+    }
+
+    fn insert(&mut self, ty: TypeDefinition) {
+        self.types.insert(ty.name.clone(), ty);
+    }
+}
+
+impl TypeDefinition {
+    fn new(kind: TypeKind, name: Option<ast::Name>, description: Option<String>) -> Self {
+        Self {
+            kind,
+            name: name
+                .expect("the node Name is not optional in the spec; qed")
+                .text()
+                .to_string(),
+            description,
+            specified_by_url: None,
+            fields: Vec::new(),
+            interfaces: Vec::new(),
+            possible_types: Vec::new(),
+            enum_values: Vec::new(),
+            input_fields: Vec::new(),
+        }
+    }
+}
+
+impl From<ast::Type> for TypeReference {
+    // Spec: https://spec.graphql.org/draft/#sec-Type-References
+    fn from(ty: ast::Type) -> Self {
+        match ty {
+            ast::Type::NamedType(named) => TypeReference::Named(
+                named
+                    .name()
+                    .expect("the node Name is not optional in the spec; qed")
+                    .text()
+                    .to_string(),
+            ),
+            ast::Type::ListType(list) => TypeReference::List(Box::new(
+                list.ty()
+                    .expect("the node Type is not optional in the spec; qed")
+                    .into(),
+            )),
+            ast::Type::NonNullType(non_null) => {
+                let inner = match (non_null.named_type(), non_null.list_type()) {
+                    (Some(named), _) => ast::Type::NamedType(named),
+                    (None, Some(list)) => ast::Type::ListType(list),
+                    (None, None) => unreachable!(
+                        "either the NamedType node is provided, either the ListType node; qed"
+                    ),
+                };
+                TypeReference::NonNull(Box::new(inner.into()))
+            }
+        }
+    }
+}
+
+fn fields(definition: Option<ast::FieldsDefinition>) -> Vec<FieldDefinition> {
+    definition
+        .iter()
+        .flat_map(|fields| fields.field_definitions())
+        .filter_map(|field| {
+            Some(FieldDefinition {
+                name: field.name()?.text().to_string(),
+                description: description(field.description()),
+                args: arguments(field.arguments_definition()),
+                ty: field.ty()?.into(),
+                deprecation_reason: deprecation_reason(field.directives()),
+            })
+        })
+        .collect()
+}
+
+fn arguments(definition: Option<ast::ArgumentsDefinition>) -> Vec<InputValueDefinition> {
+    definition
+        .iter()
+        .flat_map(|arguments| arguments.input_value_definitions())
+        .filter_map(input_value)
+        .collect()
+}
+
+fn input_value(definition: ast::InputValueDefinition) -> Option<InputValueDefinition> {
+    Some(InputValueDefinition {
+        name: definition.name()?.text().to_string(),
+        description: description(definition.description()),
+        ty: definition.ty()?.into(),
+        default_value: definition
+            .default_value()
+            .and_then(|default_value| default_value.value())
+            .map(|value| value.to_string().trim().to_string()),
+        deprecation_reason: deprecation_reason(definition.directives()),
+    })
+}
+
+fn interfaces(implements: Option<ast::ImplementsInterfaces>) -> Vec<String> {
+    implements
+        .iter()
+        .flat_map(|implements| implements.named_types())
+        .filter_map(|named| named.name())
+        .map(|name| name.text().to_string())
+        .collect()
+}
+
+fn description(description: Option<ast::Description>) -> Option<String> {
+    description.map(|description| string_value(&description.to_string()))
+}
+
+fn directive_name(directive: &ast::Directive) -> String {
+    directive
+        .name()
+        .map(|name| name.text().to_string())
+        .unwrap_or_default()
+}
+
+fn string_argument(directive: &ast::Directive, name: &str) -> Option<String> {
+    directive
+        .arguments()?
+        .arguments()
+        .find(|argument| {
+            argument
+                .name()
+                .map(|argument_name| argument_name.text().to_string() == name)
+                .unwrap_or(false)
+        })
+        .and_then(|argument| match argument.value() {
+            Some(ast::Value::StringValue(value)) => Some(string_value(&value.to_string())),
+            _ => None,
+        })
+}
+
+fn deprecation_reason(directives: Option<ast::Directives>) -> Option<String> {
+    directives?
+        .directives()
+        .find(|directive| directive_name(directive) == "deprecated")
+        .map(|directive| {
+            string_argument(&directive, "reason")
+                .unwrap_or_else(|| DEFAULT_DEPRECATION_REASON.to_string())
+        })
+}
+
+/// The value of a string literal, without its quotes, with its escape sequences or the
+/// indentation of a block string processed.
+pub(crate) fn string_value(literal: &str) -> String {
+    let literal = literal.trim();
+    if let Some(block) = literal
+        .strip_prefix("\"\"\"")
+        .and_then(|literal| literal.strip_suffix("\"\"\""))
+    {
+        block_string_value(&block.replace("\\\"\"\"", "\"\"\""))
+    } else {
+        let string = literal
+            .strip_prefix('"')
+            .and_then(|literal| literal.strip_suffix('"'))
+            .unwrap_or(literal);
+        unescape(string)
+    }
+}
+
+// Spec: https://spec.graphql.org/draft/#sec-String-Value
+fn unescape(string: &str) -> String {
+    let mut result = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('b') => result.push('\u{8}'),
+            Some('f') => result.push('\u{c}'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    result.push(c);
+                }
+            }
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+// Spec: https://spec.graphql.org/draft/#BlockStringValue()
+fn block_string_value(raw: &str) -> String {
+    let is_whitespace = |c: char| c == ' ' || c == '\t';
+    let lines: Vec<&str> = raw.lines().collect();
+    let common_indent = lines
+        .iter()
+        .skip(1)
+        .filter_map(|line| {
+            let indent = line.len() - line.trim_start_matches(is_whitespace).len();
+            (indent < line.len()).then(|| indent)
+        })
+        .min()
+        .unwrap_or(0);
+
+    let mut lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                line
+            } else {
+                line.get(common_indent..).unwrap_or("")
+            }
+        })
+        .collect();
+    while lines
+        .first()
+        .map_or(false, |line| line.trim_matches(is_whitespace).is_empty())
+    {
+        lines.remove(0);
+    }
+    while lines
+        .last()
+        .map_or(false, |line| line.trim_matches(is_whitespace).is_empty())
+    {
+        lines.pop();
+    }
+    lines.join("\n")
+}
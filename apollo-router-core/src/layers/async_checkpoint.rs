@@ -75,7 +75,6 @@ where
 }
 
 /// [`Service`] for Asynchronous Checkpoints.
-#[derive(Clone)]
 #[allow(clippy::type_complexity)]
 pub struct AsyncCheckpointService<S, Request>
 where
@@ -97,6 +96,22 @@ where
     >,
 }
 
+// not derived, the requests do not need to be `Clone`
+impl<S, Request> Clone for AsyncCheckpointService<S, Request>
+where
+    Request: Send + 'static,
+    S: Service<Request, Error = BoxError> + Clone + Send + 'static,
+    <S as Service<Request>>::Response: Send + 'static,
+    <S as Service<Request>>::Future: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            checkpoint_fn: Arc::clone(&self.checkpoint_fn),
+        }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 impl<S, Request> AsyncCheckpointService<S, Request>
 where
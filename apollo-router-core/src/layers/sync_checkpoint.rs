@@ -77,7 +77,6 @@ where
     }
 }
 
-#[derive(Clone)]
 #[allow(clippy::type_complexity)]
 pub struct CheckpointService<S, Request>
 where
@@ -100,6 +99,23 @@ where
     >,
 }
 
+// not derived, the requests do not need to be `Clone`
+impl<S, Request> Clone for CheckpointService<S, Request>
+where
+    Request: Send + 'static,
+    S: Service<Request> + Clone + Send + 'static,
+    <S as Service<Request>>::Error: Into<BoxError> + Send + 'static,
+    <S as Service<Request>>::Response: Send + 'static,
+    <S as Service<Request>>::Future: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            checkpoint_fn: Arc::clone(&self.checkpoint_fn),
+        }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 impl<S, Request> CheckpointService<S, Request>
 where
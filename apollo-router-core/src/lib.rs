@@ -25,6 +25,7 @@ macro_rules! failfast_error {
 }
 
 mod cache;
+mod cache_control;
 mod context;
 mod error;
 mod introspection;
@@ -0,0 +1,492 @@
+//! Compute the cache policy of the responses from the `Cache-Control` headers of the subgraphs.
+//!
+//! The router response gets the shortest max-age of the subgraph responses, and is private if
+//! any of them is. Identical GET queries can be served from an in-memory cache for that max-age.
+
+use crate::cache_control::{CacheControl, CACHE_CONTROL_CONTEXT_KEY};
+use crate::fetch::OperationKind;
+use crate::{
+    register_plugin, IncrementalResponses, Plugin, Request, Response, ResponseBody, RouterRequest,
+    RouterResponse, ServiceBuilderExt, SubgraphRequest, SubgraphResponse,
+};
+use http::header::CACHE_CONTROL;
+use http::{HeaderValue, Method, StatusCode};
+use lru::LruCache;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+const DEFAULT_RESPONSE_CACHE_MAX_ENTRIES: usize = 1000;
+
+register_plugin!("experimental", "cache_control", CacheControlPlugin);
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Serve identical GET queries from an in-memory cache, for the max-age of their response.
+    /// Private responses are never cached
+    response_cache: Option<ResponseCache>,
+}
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResponseCache {
+    /// Maximum number of responses kept in memory (default: 1000)
+    max_entries: Option<usize>,
+    /// Headers of the client request whose values are part of the cache key, so that the
+    /// responses are not shared between users (default: [authorization, cookie])
+    headers: Option<Vec<String>>,
+}
+
+struct CachedResponse {
+    response: Response,
+    expires_at: Instant,
+}
+
+/// A query and the values of the configured headers of the client request.
+#[derive(PartialEq, Eq, Hash)]
+struct ResponseCacheKey {
+    request: Request,
+    headers: Vec<Vec<HeaderValue>>,
+}
+
+impl ResponseCacheKey {
+    fn new(req: &RouterRequest, headers: &[String]) -> Self {
+        ResponseCacheKey {
+            request: req.originating_request.body().clone(),
+            headers: headers
+                .iter()
+                .map(|name| {
+                    req.originating_request
+                        .headers()
+                        .get_all(name.as_str())
+                        .iter()
+                        .cloned()
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+type ResponseCacheStorage = Arc<Mutex<LruCache<ResponseCacheKey, CachedResponse>>>;
+
+struct CacheControlPlugin {
+    response_cache: Option<ResponseCacheStorage>,
+    /// Headers of the client request which are part of the response cache key.
+    cache_key_headers: Arc<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for CacheControlPlugin {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        let cache_key_headers = config
+            .response_cache
+            .as_ref()
+            .and_then(|response_cache| response_cache.headers.clone())
+            .unwrap_or_else(|| vec!["authorization".to_string(), "cookie".to_string()]);
+        let response_cache = config
+            .response_cache
+            .map(|response_cache| {
+                match response_cache
+                    .max_entries
+                    .unwrap_or(DEFAULT_RESPONSE_CACHE_MAX_ENTRIES)
+                {
+                    0 => Err("response_cache max_entries must be greater than 0"),
+                    max_entries => Ok(Arc::new(Mutex::new(LruCache::new(max_entries)))),
+                }
+            })
+            .transpose()?;
+
+        Ok(CacheControlPlugin {
+            response_cache,
+            cache_key_headers: Arc::new(cache_key_headers),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let lookup_cache = self.response_cache.clone();
+        let store_cache = self.response_cache.clone();
+        let caching = self.response_cache.is_some();
+        let lookup_headers = self.cache_key_headers.clone();
+        let store_headers = self.cache_key_headers.clone();
+        ServiceBuilder::new()
+            .checkpoint(move |req: RouterRequest| {
+                let cached = lookup_cache
+                    .as_ref()
+                    .filter(|_| req.originating_request.method() == Method::GET)
+                    .and_then(|cache| {
+                        let mut cache = cache.lock().expect("Lock poisoned");
+                        let key = ResponseCacheKey::new(&req, &lookup_headers);
+                        let now = Instant::now();
+                        match cache.get(&key) {
+                            Some(cached) if cached.expires_at > now => {
+                                Some((cached.response.clone(), cached.expires_at - now))
+                            }
+                            Some(_) => {
+                                cache.pop(&key);
+                                None
+                            }
+                            None => None,
+                        }
+                    });
+
+                match cached {
+                    Some((response, max_age)) => {
+                        let policy = CacheControl {
+                            max_age: Some(max_age),
+                            private: false,
+                            no_store: false,
+                        };
+                        let mut response = http::Response::new(ResponseBody::GraphQL(response));
+                        if let Some(value) = policy.to_header_value() {
+                            response.headers_mut().insert(CACHE_CONTROL, value);
+                        }
+                        Ok(ControlFlow::Break(RouterResponse {
+                            response: response.into(),
+                            context: req.context,
+                        }))
+                    }
+                    None => Ok(ControlFlow::Continue(req)),
+                }
+            })
+            .map_future_with_context(
+                move |req: &RouterRequest| {
+                    (caching && req.originating_request.method() == Method::GET)
+                        .then(|| ResponseCacheKey::new(req, &store_headers))
+                },
+                move |cache_key: Option<ResponseCacheKey>, fut| {
+                    let cache = store_cache.clone();
+                    async move {
+                        let mut response: RouterResponse = fut.await?;
+                        let policy = response_policy(&response);
+                        if let Some(value) = policy.and_then(|policy| policy.to_header_value()) {
+                            response.response.headers_mut().insert(CACHE_CONTROL, value);
+                        }
+
+                        if let (Some(cache), Some(cache_key), Some(policy)) =
+                            (cache, cache_key, policy)
+                        {
+                            match (policy.max_age, response.response.body()) {
+                                (Some(max_age), ResponseBody::GraphQL(body))
+                                    if policy.is_cacheable() && !max_age.is_zero() =>
+                                {
+                                    cache.lock().expect("Lock poisoned").put(
+                                        cache_key,
+                                        CachedResponse {
+                                            response: body.clone(),
+                                            expires_at: Instant::now() + max_age,
+                                        },
+                                    );
+                                }
+                                _ => {}
+                            }
+                        }
+
+                        Ok::<_, BoxError>(response)
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        _name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        ServiceBuilder::new()
+            .map_future_with_context(
+                |req: &SubgraphRequest| req.operation_kind,
+                |operation_kind: OperationKind, fut| async move {
+                    let response: SubgraphResponse = fut.await?;
+                    // the results of mutations are never cached
+                    let policy = match operation_kind {
+                        OperationKind::Mutation => CacheControl {
+                            no_store: true,
+                            ..Default::default()
+                        },
+                        _ => CacheControl::from_headers(response.response.headers()),
+                    };
+                    policy.record(&response.context);
+                    Ok::<_, BoxError>(response)
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+/// The policy of a router response: only successful, complete responses built from subgraph
+/// responses can be cached.
+fn response_policy(response: &RouterResponse) -> Option<CacheControl> {
+    let policy = response
+        .context
+        .get::<_, CacheControl>(CACHE_CONTROL_CONTEXT_KEY)
+        .ok()
+        .flatten()?;
+    let complete = response.response.status() == StatusCode::OK
+        && response
+            .response
+            .extensions()
+            .get::<IncrementalResponses>()
+            .is_none()
+        && matches!(response.response.body(), ResponseBody::GraphQL(body) if body.errors.is_empty());
+
+    if complete || policy.no_store {
+        Some(policy)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Context;
+    use serde_json_bytes::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tower::{service_fn, Service};
+
+    impl CacheControlPlugin {
+        fn cached_responses(&self) -> usize {
+            self.response_cache
+                .as_ref()
+                .map(|cache| cache.lock().expect("Lock poisoned").len())
+                .unwrap_or_default()
+        }
+    }
+
+    /// The max-age of the Cache-Control header of a response.
+    fn max_age(response: &RouterResponse) -> Option<Duration> {
+        response
+            .response
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("max-age="))
+            .and_then(|value| value.split(',').next())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs)
+    }
+
+    fn subgraph(
+        plugin: &mut CacheControlPlugin,
+        name: &str,
+        cache_control: Option<&'static str>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        plugin.subgraph_service(
+            name,
+            service_fn(move |req: SubgraphRequest| async move {
+                let mut response = SubgraphResponse::fake_builder()
+                    .context(req.context)
+                    .build();
+                if let Some(cache_control) = cache_control {
+                    response
+                        .response
+                        .headers_mut()
+                        .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+                }
+                Ok(response)
+            })
+            .boxed(),
+        )
+    }
+
+    /// A router calling the products and reviews subgraphs for every request.
+    fn router(
+        plugin: &mut CacheControlPlugin,
+        products: Option<&'static str>,
+        reviews: Option<&'static str>,
+        calls: Arc<AtomicUsize>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let products = Arc::new(tokio::sync::Mutex::new(subgraph(
+            plugin, "products", products,
+        )));
+        let reviews = Arc::new(tokio::sync::Mutex::new(subgraph(
+            plugin, "reviews", reviews,
+        )));
+        plugin.router_service(
+            service_fn(move |req: RouterRequest| {
+                let products = products.clone();
+                let reviews = reviews.clone();
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    for subgraph in [products, reviews] {
+                        subgraph
+                            .lock()
+                            .await
+                            .ready()
+                            .await?
+                            .call(
+                                SubgraphRequest::fake_builder()
+                                    .context(req.context.clone())
+                                    .build(),
+                            )
+                            .await?;
+                    }
+                    RouterResponse::fake_builder()
+                        .data(json!({ "topProducts": [] }))
+                        .context(req.context)
+                        .build()
+                }
+            })
+            .boxed(),
+        )
+    }
+
+    async fn plugin(config: &str) -> CacheControlPlugin {
+        CacheControlPlugin::new(serde_yaml::from_str::<Config>(config).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn call(
+        router: &mut BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> RouterResponse {
+        call_with_authorization(router, None).await
+    }
+
+    async fn call_with_authorization(
+        router: &mut BoxService<RouterRequest, RouterResponse, BoxError>,
+        authorization: Option<&'static str>,
+    ) -> RouterResponse {
+        let mut request = RouterRequest::fake_builder()
+            .query("{ topProducts { name } }".to_string())
+            .context(Context::new())
+            .build()
+            .unwrap();
+        if let Some(authorization) = authorization {
+            request.originating_request.headers_mut().insert(
+                http::header::AUTHORIZATION,
+                HeaderValue::from_static(authorization),
+            );
+        }
+        router.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_aggregates_subgraph_cache_policies() {
+        let mut plugin = plugin("{}").await;
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60"),
+            Some("public, max-age=30"),
+            calls.clone(),
+        );
+        let response = call(&mut service).await;
+        assert_eq!(
+            response.response.headers().get(CACHE_CONTROL),
+            Some(&HeaderValue::from_static("max-age=30, public"))
+        );
+
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60, private"),
+            Some("max-age=30"),
+            calls.clone(),
+        );
+        let response = call(&mut service).await;
+        assert_eq!(
+            response.response.headers().get(CACHE_CONTROL),
+            Some(&HeaderValue::from_static("max-age=30, private"))
+        );
+
+        // the reviews have no cache policy
+        let mut service = router(&mut plugin, Some("max-age=60"), None, calls.clone());
+        let response = call(&mut service).await;
+        assert_eq!(response.response.headers().get(CACHE_CONTROL), None);
+    }
+
+    #[tokio::test]
+    async fn it_serves_get_queries_from_the_response_cache() {
+        let mut plugin = plugin("response_cache:\n  max_entries: 10").await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60"),
+            Some("max-age=30"),
+            calls.clone(),
+        );
+
+        let first = call(&mut service).await;
+        let second = call(&mut service).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(plugin.cached_responses(), 1);
+        assert!(max_age(&second).unwrap() <= Duration::from_secs(30));
+        match (first.response.body(), second.response.body()) {
+            (ResponseBody::GraphQL(first), ResponseBody::GraphQL(second)) => {
+                assert_eq!(first, second)
+            }
+            _ => panic!("expected GraphQL responses"),
+        }
+
+        // private responses are not cached
+        let mut plugin = self::plugin("response_cache: {}").await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60, private"),
+            Some("max-age=30"),
+            calls.clone(),
+        );
+        call(&mut service).await;
+        call(&mut service).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(plugin.cached_responses(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_keys_the_response_cache_by_the_configured_headers() {
+        let mut plugin = plugin("response_cache: {}").await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60"),
+            Some("max-age=30"),
+            calls.clone(),
+        );
+        call_with_authorization(&mut service, Some("Bearer alice")).await;
+        call_with_authorization(&mut service, Some("Bearer bob")).await;
+        call(&mut service).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(plugin.cached_responses(), 3);
+        call_with_authorization(&mut service, Some("Bearer alice")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // without the authorization header in the key, the users share the responses
+        let mut plugin = self::plugin("response_cache:\n  headers: [x-tenant]").await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = router(
+            &mut plugin,
+            Some("max-age=60"),
+            Some("max-age=30"),
+            calls.clone(),
+        );
+        call_with_authorization(&mut service, Some("Bearer alice")).await;
+        call_with_authorization(&mut service, Some("Bearer bob")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_an_empty_response_cache() {
+        assert!(CacheControlPlugin::new(
+            serde_yaml::from_str::<Config>("response_cache:\n  max_entries: 0").unwrap()
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Expose the query plan of a request, and the fetches it made, in the response extensions.
+//!
+//! This is only done for the requests carrying the configured header, to debug federated
+//! queries without enabling trace logs.
+
+use crate::{
+    register_plugin, Context, ExecutionRequest, ExecutionResponse, Plugin, ResponseBody,
+    RouterRequest, RouterResponse, ServiceBuilderExt, SubgraphRequest, SubgraphResponse, Value,
+};
+use http::HeaderName;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tower::util::BoxService;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+const QUERY_PLAN_CONTEXT_KEY: &str = "experimental::expose_query_plan.plan";
+const FETCHES_CONTEXT_KEY: &str = "experimental::expose_query_plan.fetches";
+
+/// Key of the response extension holding the query plan.
+const EXTENSION_KEY: &str = "apolloQueryPlan";
+
+register_plugin!("experimental", "expose_query_plan", ExposeQueryPlan);
+
+#[derive(Clone, Debug, JsonSchema, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Requests with this header get their query plan in the extensions of the response
+    #[serde(default = "default_header")]
+    header: String,
+}
+
+fn default_header() -> String {
+    "apollo-expose-query-plan".to_string()
+}
+
+/// A fetch made to a subgraph while executing the query plan.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Fetch {
+    subgraph: String,
+    operation: Option<String>,
+    operation_name: Option<String>,
+    duration_ms: f64,
+    failed: bool,
+}
+
+struct ExposeQueryPlan {
+    header: HeaderName,
+}
+
+#[async_trait::async_trait]
+impl Plugin for ExposeQueryPlan {
+    type Config = Config;
+
+    async fn new(config: Self::Config) -> Result<Self, BoxError> {
+        Ok(ExposeQueryPlan {
+            header: HeaderName::try_from(config.header.as_str())?,
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        let header = self.header.clone();
+        ServiceBuilder::new()
+            .map_future_with_context(
+                move |req: &RouterRequest| req.originating_request.headers().contains_key(&header),
+                |exposed: bool, fut| async move {
+                    let mut response: RouterResponse = fut.await?;
+                    if !exposed {
+                        return Ok(response);
+                    }
+
+                    let plan = response
+                        .context
+                        .get::<_, Value>(QUERY_PLAN_CONTEXT_KEY)
+                        .ok()
+                        .flatten();
+                    let fetches = response
+                        .context
+                        .get::<_, Vec<Fetch>>(FETCHES_CONTEXT_KEY)
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default();
+                    if let (Some(plan), ResponseBody::GraphQL(body)) =
+                        (plan, response.response.body_mut())
+                    {
+                        body.extensions.insert(
+                            EXTENSION_KEY,
+                            serde_json_bytes::json!({
+                                "plan": plan,
+                                "fetches": serde_json_bytes::to_value(fetches)?,
+                            }),
+                        );
+                    }
+                    Ok::<_, BoxError>(response)
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+
+    fn execution_service(
+        &mut self,
+        service: BoxService<ExecutionRequest, ExecutionResponse, BoxError>,
+    ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
+        let header = self.header.clone();
+        service
+            .map_request(move |req: ExecutionRequest| {
+                if req.originating_request.headers().contains_key(&header) {
+                    if let Err(err) = req
+                        .context
+                        .insert(QUERY_PLAN_CONTEXT_KEY, req.query_plan.to_json())
+                    {
+                        tracing::error!("could not record the query plan: {}", err);
+                    }
+                }
+                req
+            })
+            .boxed()
+    }
+
+    fn subgraph_service(
+        &mut self,
+        name: &str,
+        service: BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        let header = self.header.clone();
+        let name = name.to_string();
+        ServiceBuilder::new()
+            .map_future_with_context(
+                move |req: &SubgraphRequest| {
+                    req.originating_request
+                        .headers()
+                        .contains_key(&header)
+                        .then(|| {
+                            let body = req.subgraph_request.body();
+                            (
+                                req.context.clone(),
+                                Fetch {
+                                    subgraph: name.clone(),
+                                    operation: body.query.clone(),
+                                    operation_name: body.operation_name.clone(),
+                                    duration_ms: 0.0,
+                                    failed: false,
+                                },
+                                Instant::now(),
+                            )
+                        })
+                },
+                |fetch: Option<(Context, Fetch, Instant)>, fut| async move {
+                    let response: Result<SubgraphResponse, BoxError> = fut.await;
+                    if let Some((context, mut fetch, start)) = fetch {
+                        fetch.duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+                        fetch.failed = response.is_err();
+                        if let Err(err) =
+                            context.upsert(FETCHES_CONTEXT_KEY, |mut fetches: Vec<Fetch>| {
+                                fetches.push(fetch.clone());
+                                fetches
+                            })
+                        {
+                            tracing::error!("could not record the subgraph fetch: {}", err);
+                        }
+                    }
+                    response
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::utils::test::mock::canned;
+    use crate::{PluggableRouterServiceBuilder, Schema};
+    use std::sync::Arc;
+    use tower::Service;
+
+    async fn call(exposed: bool) -> RouterResponse {
+        let plugin = ExposeQueryPlan::new(serde_json::from_value(serde_json::json!({})).unwrap())
+            .await
+            .unwrap();
+        let schema: Arc<Schema> = Arc::new(
+            include_str!("../../../examples/graphql/local.graphql")
+                .parse()
+                .unwrap(),
+        );
+        let (mut router, _) = PluggableRouterServiceBuilder::new(schema)
+            .with_plugin("experimental.expose_query_plan".to_string(), plugin)
+            .with_subgraph_service("products", canned::products_subgraph())
+            .with_subgraph_service("accounts", canned::accounts_subgraph())
+            .with_subgraph_service("reviews", canned::reviews_subgraph())
+            .build()
+            .await
+            .unwrap();
+
+        let mut request = RouterRequest::fake_builder()
+            .query("query TopProducts($first: Int) { topProducts(first: $first) { upc name reviews { id product { name } author { id name } } } }")
+            .variable("first", 2usize)
+            .build()
+            .unwrap();
+        if exposed {
+            request.originating_request.headers_mut().insert(
+                "apollo-expose-query-plan",
+                http::HeaderValue::from_static("true"),
+            );
+        }
+        router.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    fn extensions(response: &RouterResponse) -> &crate::Object {
+        match response.response.body() {
+            ResponseBody::GraphQL(body) => &body.extensions,
+            _ => panic!("expected a GraphQL response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_exposes_the_query_plan() {
+        let response = call(true).await;
+        let exposed = extensions(&response)
+            .get(EXTENSION_KEY)
+            .and_then(|exposed| exposed.as_object())
+            .expect("the query plan should be exposed");
+
+        let plan = exposed.get("plan").unwrap();
+        assert_eq!(
+            plan.as_object()
+                .and_then(|plan| plan.get("kind"))
+                .and_then(|kind| kind.as_str()),
+            Some("Sequence")
+        );
+
+        let fetches: Vec<Fetch> =
+            serde_json_bytes::from_value(exposed.get("fetches").unwrap().clone()).unwrap();
+        let mut subgraphs = fetches
+            .iter()
+            .map(|fetch| fetch.subgraph.as_str())
+            .collect::<Vec<_>>();
+        subgraphs.sort_unstable();
+        assert_eq!(
+            subgraphs,
+            vec!["accounts", "products", "products", "reviews"]
+        );
+        assert!(fetches.iter().all(|fetch| !fetch.failed));
+        assert_eq!(
+            fetches[0].operation_name.as_deref(),
+            Some("TopProducts__products__0")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_does_not_expose_the_query_plan_without_the_header() {
+        let response = call(false).await;
+        assert!(extensions(&response).get(EXTENSION_KEY).is_none());
+    }
+}
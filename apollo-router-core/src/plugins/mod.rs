@@ -2,7 +2,9 @@
 //!
 //! These plugins are compiled into the router and configured via YAML configuration.
 
+mod cache_control;
 pub mod csrf;
+mod expose_query_plan;
 mod forbid_mutations;
 mod headers;
 mod include_subgraph_errors;
@@ -0,0 +1,290 @@
+//! Stop calling a failing subgraph for a while. Implemented as a tower Layer.
+//!
+//! The circuit opens when the failure rate over the current window reaches the threshold, and
+//! requests then fail immediately. Once the open duration has elapsed, a single probe request
+//! is let through: the circuit closes if it succeeds, and opens again if it fails.
+//!
+//! See [`Layer`] and [`tower::Service`] for more details.
+
+use crate::{FetchError, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use opentelemetry::KeyValue;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer};
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        window_start: Instant,
+        requests: u64,
+        failures: u64,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        probe_start: Instant,
+    },
+}
+
+impl State {
+    fn closed(now: Instant) -> Self {
+        State::Closed {
+            window_start: now,
+            requests: 0,
+            failures: 0,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// State of the circuit of one subgraph.
+pub struct Circuit {
+    service_name: String,
+    failure_rate_threshold: f32,
+    minimum_requests: u64,
+    window: Duration,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl Circuit {
+    pub fn new(
+        service_name: &str,
+        failure_rate_threshold: f32,
+        minimum_requests: u64,
+        window: Duration,
+        open_duration: Duration,
+    ) -> Self {
+        Self {
+            service_name: service_name.to_string(),
+            failure_rate_threshold,
+            minimum_requests,
+            window,
+            open_duration,
+            state: Mutex::new(State::closed(Instant::now())),
+        }
+    }
+
+    /// Returns false if the request must not be sent to the subgraph.
+    pub fn try_acquire(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Lock poisoned");
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now >= until => {
+                self.transition(&mut state, State::HalfOpen { probe_start: now });
+                true
+            }
+            State::Open { .. } => false,
+            // the probe may have been cancelled without reporting its result, so another one is
+            // allowed after the open duration
+            State::HalfOpen { probe_start }
+                if now.duration_since(probe_start) >= self.open_duration =>
+            {
+                *state = State::HalfOpen { probe_start: now };
+                true
+            }
+            State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Record the outcome of a request that was let through.
+    pub fn record(&self, success: bool) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Lock poisoned");
+        match &mut *state {
+            State::Closed {
+                window_start,
+                requests,
+                failures,
+            } => {
+                if now.duration_since(*window_start) >= self.window {
+                    *window_start = now;
+                    *requests = 0;
+                    *failures = 0;
+                }
+                *requests += 1;
+                if !success {
+                    *failures += 1;
+                }
+
+                if *requests >= self.minimum_requests
+                    && *failures as f32 / *requests as f32 >= self.failure_rate_threshold
+                {
+                    let until = now + self.open_duration;
+                    self.transition(&mut state, State::Open { until });
+                }
+            }
+            State::HalfOpen { .. } if success => self.transition(&mut state, State::closed(now)),
+            State::HalfOpen { .. } => {
+                let until = now + self.open_duration;
+                self.transition(&mut state, State::Open { until });
+            }
+            // a request sent before the circuit opened
+            State::Open { .. } => {}
+        }
+    }
+
+    fn transition(&self, state: &mut State, new_state: State) {
+        match new_state {
+            State::Open { .. } => tracing::warn!(
+                subgraph = %self.service_name,
+                "circuit breaker opened, requests to the subgraph fail for {:?}",
+                self.open_duration
+            ),
+            State::HalfOpen { .. } => {
+                tracing::info!(
+                    subgraph = %self.service_name,
+                    "circuit breaker half open, probing the subgraph"
+                )
+            }
+            State::Closed { .. } => {
+                tracing::info!(subgraph = %self.service_name, "circuit breaker closed")
+            }
+        }
+
+        opentelemetry::global::meter("apollo/router")
+            .u64_counter("apollo_router_circuit_breaker_transitions_total")
+            .with_description("Total number of circuit breaker state changes.")
+            .init()
+            .add(
+                1,
+                &[
+                    KeyValue::new("subgraph", self.service_name.clone()),
+                    KeyValue::new("state", new_state.name()),
+                ],
+            );
+
+        *state = new_state;
+    }
+}
+
+pub struct CircuitBreakerLayer {
+    circuit: Arc<Circuit>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(circuit: Circuit) -> Self {
+        CircuitBreakerLayer {
+            circuit: Arc::new(circuit),
+        }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CircuitBreakerService {
+            service,
+            circuit: self.circuit.clone(),
+        }
+    }
+}
+
+pub struct CircuitBreakerService<S> {
+    service: S,
+    circuit: Arc<Circuit>,
+}
+
+impl<S> tower::Service<SubgraphRequest> for CircuitBreakerService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        if !self.circuit.try_acquire() {
+            let service_name = self.circuit.service_name.clone();
+            return Box::pin(async move {
+                Err(Box::new(FetchError::SubrequestCircuitOpen {
+                    service: service_name,
+                }) as BoxError)
+            });
+        }
+
+        let circuit = self.circuit.clone();
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            circuit.record(response.is_ok());
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit(open_duration: Duration) -> Circuit {
+        Circuit::new("products", 0.5, 4, Duration::from_secs(3600), open_duration)
+    }
+
+    #[test]
+    fn it_opens_when_the_failure_rate_is_reached() {
+        let circuit = circuit(Duration::from_secs(3600));
+
+        // not enough requests yet
+        circuit.record(false);
+        circuit.record(false);
+        circuit.record(false);
+        assert!(circuit.try_acquire());
+
+        circuit.record(true);
+        assert!(!circuit.try_acquire());
+    }
+
+    #[test]
+    fn it_stays_closed_under_the_failure_rate() {
+        let circuit = circuit(Duration::from_secs(3600));
+
+        for _ in 0..10 {
+            circuit.record(true);
+            circuit.record(true);
+            circuit.record(false);
+        }
+        assert!(circuit.try_acquire());
+    }
+
+    #[test]
+    fn it_probes_the_subgraph_when_half_open() {
+        let circuit = circuit(Duration::from_millis(10));
+        for _ in 0..4 {
+            circuit.record(false);
+        }
+        assert!(!circuit.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(20));
+        // a single probe is let through
+        assert!(circuit.try_acquire());
+        assert!(!circuit.try_acquire());
+
+        // the probe failed
+        circuit.record(false);
+        assert!(!circuit.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(circuit.try_acquire());
+        circuit.record(true);
+        assert!(circuit.try_acquire());
+        assert!(circuit.try_acquire());
+    }
+}
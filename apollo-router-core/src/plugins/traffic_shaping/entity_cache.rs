@@ -0,0 +1,799 @@
+//! Cache the entities returned by the subgraphs. Implemented as a tower Layer.
+//!
+//! Only the queries fetching `_entities` are cached, one cache entry per representation. When
+//! some representations are found in the cache, only the missing ones are requested from the
+//! subgraph, and the response is rebuilt in the order of the original representations.
+//!
+//! See [`Layer`] and [`tower::Service`] for more details.
+
+use crate::cache_control::CacheControl;
+use crate::fetch::OperationKind;
+use crate::json_ext::PathElement;
+use crate::{IncrementalResponses, SubgraphRequest, SubgraphResponse, Value};
+use futures::future::BoxFuture;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer, ServiceExt};
+
+const REPRESENTATIONS: &str = "representations";
+const ENTITIES: &str = "_entities";
+
+/// Storage of the cached entities.
+///
+/// The cache keys already contain the subgraph name, so a storage can be shared by all subgraphs.
+#[async_trait::async_trait]
+pub trait EntityCacheStorage: Send + Sync {
+    /// Get an entity and its remaining time to live, if it is present and has not expired.
+    async fn get(&self, key: &str) -> Option<(Value, Duration)>;
+
+    /// Store an entity for the duration of its time to live.
+    async fn insert(&self, key: String, entity: Value, ttl: Duration);
+}
+
+/// Entities stored in the memory of the router, the least recently used ones being evicted
+/// once the maximum number of entries is reached.
+pub struct InMemoryStorage {
+    entries: Mutex<LruCache<String, (Value, Instant)>>,
+}
+
+impl InMemoryStorage {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(max_entries)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EntityCacheStorage for InMemoryStorage {
+    async fn get(&self, key: &str) -> Option<(Value, Duration)> {
+        let mut entries = self.entries.lock().expect("Lock poisoned");
+        let now = Instant::now();
+        match entries.get(key) {
+            Some((entity, expires_at)) if *expires_at > now => {
+                Some((entity.clone(), *expires_at - now))
+            }
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn insert(&self, key: String, entity: Value, ttl: Duration) {
+        self.entries
+            .lock()
+            .expect("Lock poisoned")
+            .put(key, (entity, Instant::now() + ttl));
+    }
+}
+
+struct Settings {
+    service_name: String,
+    storage: Arc<dyn EntityCacheStorage>,
+    ttl: Duration,
+    headers: Vec<String>,
+}
+
+impl Settings {
+    /// The key of a representation: the subgraph, the entity type, then a hash of the query,
+    /// the other variables, the representation and the configured headers of the client request.
+    fn key(&self, request: &SubgraphRequest, query: &str, representation: &Value) -> String {
+        let typename = representation
+            .as_object()
+            .and_then(|representation| representation.get("__typename"))
+            .and_then(|typename| typename.as_str())
+            .unwrap_or_default();
+
+        let mut digest = Sha256::new();
+        digest.update(query.as_bytes());
+        // the arguments of the fields change the entities, whatever the order of the variables
+        let mut variables = request
+            .subgraph_request
+            .body()
+            .variables
+            .iter()
+            .filter(|(name, _)| name.as_str() != REPRESENTATIONS)
+            .collect::<Vec<_>>();
+        variables.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        for (name, value) in variables {
+            digest.update(name.as_str().as_bytes());
+            digest.update(b"\0");
+            digest.update(serde_json::to_vec(value).expect("a JSON value is serializable; qed"));
+            digest.update(b"\n");
+        }
+        digest
+            .update(serde_json::to_vec(representation).expect("a JSON value is serializable; qed"));
+        for name in &self.headers {
+            digest.update(name.as_bytes());
+            for value in request.originating_request.headers().get_all(name) {
+                digest.update(b"\0");
+                digest.update(value.as_bytes());
+            }
+            digest.update(b"\n");
+        }
+
+        format!(
+            "{}:{}:{}",
+            self.service_name,
+            typename,
+            hex::encode(digest.finalize())
+        )
+    }
+}
+
+pub struct EntityCacheLayer {
+    settings: Arc<Settings>,
+}
+
+impl EntityCacheLayer {
+    pub fn new(
+        service_name: &str,
+        storage: Arc<dyn EntityCacheStorage>,
+        ttl: Duration,
+        headers: Vec<String>,
+    ) -> Self {
+        EntityCacheLayer {
+            settings: Arc::new(Settings {
+                service_name: service_name.to_string(),
+                storage,
+                ttl,
+                headers,
+            }),
+        }
+    }
+}
+
+impl<S> Layer<S> for EntityCacheLayer
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError> + Clone,
+{
+    type Service = EntityCacheService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        EntityCacheService {
+            service,
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+pub struct EntityCacheService<S> {
+    service: S,
+    settings: Arc<Settings>,
+}
+
+impl<S> EntityCacheService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError> + Clone,
+{
+    async fn fetch(
+        service: S,
+        settings: Arc<Settings>,
+        mut request: SubgraphRequest,
+    ) -> Result<SubgraphResponse, BoxError> {
+        let body = request.subgraph_request.body();
+        let representations = match (
+            request.operation_kind,
+            body.query.as_deref(),
+            body.variables.get(REPRESENTATIONS),
+        ) {
+            (OperationKind::Query, Some(query), Some(Value::Array(representations))) => {
+                let keys = representations
+                    .iter()
+                    .map(|representation| settings.key(&request, query, representation))
+                    .collect::<Vec<_>>();
+                representations
+                    .iter()
+                    .cloned()
+                    .zip(keys)
+                    .collect::<Vec<_>>()
+            }
+            _ => return service.ready_oneshot().await?.call(request).await,
+        };
+
+        let mut entities = Vec::with_capacity(representations.len());
+        // the cached entities expire with the shortest remaining time to live
+        let mut cached_ttl: Option<Duration> = None;
+        for (_, key) in &representations {
+            let entity = settings.storage.get(key).await.map(|(entity, ttl)| {
+                cached_ttl = Some(cached_ttl.map_or(ttl, |cached_ttl| cached_ttl.min(ttl)));
+                entity
+            });
+            entities.push(entity);
+        }
+        let missing = entities
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entity)| entity.is_none().then(|| index))
+            .collect::<Vec<_>>();
+        tracing::trace!(
+            subgraph = %settings.service_name,
+            "{} of {} entities found in the cache",
+            representations.len() - missing.len(),
+            representations.len()
+        );
+
+        if missing.is_empty() {
+            let data = serde_json_bytes::json!({
+                ENTITIES: entities.into_iter().flatten().collect::<Vec<_>>()
+            });
+            let mut response = SubgraphResponse::new(
+                None,
+                Some(data),
+                None,
+                Vec::new(),
+                Default::default(),
+                None,
+                request.context,
+            );
+            // entities keyed by client headers belong to the user
+            let cache_control = CacheControl {
+                max_age: cached_ttl,
+                private: !settings.headers.is_empty(),
+                ..Default::default()
+            };
+            if let Some(value) = cache_control.to_header_value() {
+                response
+                    .response
+                    .headers_mut()
+                    .insert(http::header::CACHE_CONTROL, value);
+            }
+            // the subgraph service layers of the plugins are not called for this response
+            cache_control.record(&response.context);
+            return Ok(response);
+        }
+
+        if missing.len() < representations.len() {
+            let mut variables = (*request.subgraph_request.body().variables).clone();
+            variables.insert(
+                REPRESENTATIONS,
+                Value::Array(
+                    missing
+                        .iter()
+                        .map(|index| representations[*index].0.clone())
+                        .collect(),
+                ),
+            );
+            request.subgraph_request.body_mut().variables = Arc::new(variables);
+        }
+
+        let mut response = service.ready_oneshot().await?.call(request).await?;
+
+        let cache_control = CacheControl::from_headers(response.response.headers());
+        let ttl = (cache_control.is_cacheable()
+            // the following responses of a stream do not go through the cache
+            && response.response.extensions().get::<IncrementalResponses>().is_none())
+        .then(|| cache_control.max_age.unwrap_or(settings.ttl));
+
+        // the response may not outlive the cached entities it contains
+        if let Some(cached_ttl) = cached_ttl {
+            let cache_control = CacheControl {
+                max_age: cache_control.max_age.map(|max_age| max_age.min(cached_ttl)),
+                private: cache_control.private || !settings.headers.is_empty(),
+                ..cache_control
+            };
+            response
+                .response
+                .headers_mut()
+                .remove(http::header::CACHE_CONTROL);
+            if let Some(value) = cache_control.to_header_value() {
+                response
+                    .response
+                    .headers_mut()
+                    .insert(http::header::CACHE_CONTROL, value);
+            }
+            // the plugins wrapping the subgraph service only saw the policy of the subgraph
+            cache_control.record(&response.context);
+        }
+
+        let body = response.response.body_mut();
+        let fetched = match body
+            .data
+            .as_mut()
+            .and_then(|data| data.as_object_mut())
+            .and_then(|data| data.get_mut(ENTITIES))
+        {
+            Some(Value::Array(fetched)) if fetched.len() == missing.len() => {
+                std::mem::take(fetched)
+            }
+            // let the query planner report the invalid response
+            _ => return Ok(response),
+        };
+
+        // entities with errors are not cached, and errors without a path may concern any of them
+        let mut failed = vec![false; missing.len()];
+        for error in &body.errors {
+            match error.path.as_ref().map(|path| path.0.as_slice()) {
+                Some([PathElement::Key(key), PathElement::Index(position), ..])
+                    if key == ENTITIES && *position < failed.len() =>
+                {
+                    failed[*position] = true
+                }
+                _ => failed.iter_mut().for_each(|failed| *failed = true),
+            }
+        }
+
+        for ((position, entity), index) in fetched.into_iter().enumerate().zip(missing.iter()) {
+            if let Some(ttl) = ttl {
+                if !failed[position] && !entity.is_null() && !ttl.is_zero() {
+                    settings
+                        .storage
+                        .insert(representations[*index].1.clone(), entity.clone(), ttl)
+                        .await;
+                }
+            }
+            entities[*index] = Some(entity);
+        }
+
+        // the paths of the errors now point to the original representations
+        for error in body.errors.iter_mut() {
+            if let Some(path) = error.path.as_mut() {
+                if let [PathElement::Key(key), PathElement::Index(position), ..] =
+                    path.0.as_mut_slice()
+                {
+                    if key.as_str() == ENTITIES {
+                        if let Some(index) = missing.get(*position) {
+                            *position = *index;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(data) = body.data.as_mut().and_then(|data| data.as_object_mut()) {
+            data.insert(
+                ENTITIES,
+                Value::Array(
+                    entities
+                        .into_iter()
+                        .map(|entity| entity.unwrap_or_default())
+                        .collect(),
+                ),
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+impl<S> tower::Service<SubgraphRequest> for EntityCacheService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let service = self.service.clone();
+        let settings = self.settings.clone();
+
+        Box::pin(async move { Self::fetch(service, settings, request).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache_control::CACHE_CONTROL_CONTEXT_KEY;
+    use crate::{http_compat, Context, Error, Object, Path, Request};
+    use serde_json_bytes::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::util::BoxCloneService;
+    use tower::{service_fn, Service, ServiceBuilder};
+
+    const QUERY: &str = "query($representations:[_Any!]!){_entities(representations:$representations){...on User{name}}}";
+
+    fn request(ids: &[&str]) -> SubgraphRequest {
+        let representations = ids
+            .iter()
+            .map(|id| json!({"__typename": "User", "id": id}))
+            .collect::<Vec<_>>();
+        let mut variables = Object::new();
+        variables.insert(REPRESENTATIONS, Value::Array(representations));
+
+        SubgraphRequest::fake_builder()
+            .subgraph_request(
+                http_compat::Request::fake_builder()
+                    .body(
+                        Request::builder()
+                            .query(Some(QUERY.to_string()))
+                            .variables(Arc::new(variables))
+                            .build(),
+                    )
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+    }
+
+    fn entities(response: &SubgraphResponse) -> Value {
+        response
+            .response
+            .body()
+            .data
+            .as_ref()
+            .and_then(|data| data.as_object())
+            .and_then(|data| data.get(ENTITIES))
+            .cloned()
+            .unwrap()
+    }
+
+    /// An accounts subgraph recording the ids it was asked for. The user "error" fails.
+    fn accounts(
+        requested: Arc<Mutex<Vec<Vec<String>>>>,
+        cache_control: &'static str,
+    ) -> BoxCloneService<SubgraphRequest, SubgraphResponse, BoxError> {
+        service_fn(move |request: SubgraphRequest| {
+            let requested = requested.clone();
+            async move {
+                let ids = request
+                    .subgraph_request
+                    .body()
+                    .variables
+                    .get(REPRESENTATIONS)
+                    .and_then(|representations| representations.as_array())
+                    .unwrap()
+                    .iter()
+                    .filter_map(|representation| representation.as_object()?.get("id")?.as_str())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                requested.lock().unwrap().push(ids.clone());
+
+                let mut errors = Vec::new();
+                let entities = ids
+                    .iter()
+                    .enumerate()
+                    .map(|(position, id)| {
+                        if id == "error" {
+                            errors.push(
+                                Error::builder()
+                                    .message("user not found".to_string())
+                                    .path(Some(Path::from(format!("_entities/{}", position))))
+                                    .build(),
+                            );
+                            Value::Null
+                        } else {
+                            json!({ "name": format!("user {}", id) })
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let mut response = SubgraphResponse::new(
+                    None,
+                    Some(json!({ ENTITIES: entities })),
+                    None,
+                    errors,
+                    Default::default(),
+                    None,
+                    Context::new(),
+                );
+                if !cache_control.is_empty() {
+                    response.response.headers_mut().insert(
+                        http::header::CACHE_CONTROL,
+                        http::HeaderValue::from_static(cache_control),
+                    );
+                }
+                Ok::<_, BoxError>(response)
+            }
+        })
+        .boxed_clone()
+    }
+
+    #[tokio::test]
+    async fn it_only_requests_missing_entities() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                Arc::new(InMemoryStorage::new(100)),
+                Duration::from_secs(60),
+                Vec::new(),
+            ))
+            .service(accounts(requested.clone(), ""));
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1", "2"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            entities(&response),
+            json!([{"name": "user 1"}, {"name": "user 2"}])
+        );
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["3", "2", "error", "1"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            entities(&response),
+            json!([{"name": "user 3"}, {"name": "user 2"}, null, {"name": "user 1"}])
+        );
+        // the error points to the original representation
+        assert_eq!(
+            response.response.body().errors[0].path,
+            Some(Path::from("_entities/2"))
+        );
+
+        // only the failed entity is fetched again
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1", "error"]))
+            .await
+            .unwrap();
+        assert_eq!(entities(&response), json!([{"name": "user 1"}, null]));
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["3"]))
+            .await
+            .unwrap();
+        assert_eq!(entities(&response), json!([{"name": "user 3"}]));
+
+        assert_eq!(
+            *requested.lock().unwrap(),
+            vec![
+                vec!["1".to_string(), "2".to_string()],
+                vec!["3".to_string(), "error".to_string()],
+                vec!["error".to_string()],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_follows_the_subgraph_cache_control() {
+        let storage = Arc::new(InMemoryStorage::new(100));
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                storage.clone(),
+                Duration::from_secs(60),
+                Vec::new(),
+            ))
+            .service(accounts(requested.clone(), "private, max-age=60"));
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(request(&["1"]))
+                .await
+                .unwrap();
+        }
+        assert_eq!(requested.lock().unwrap().len(), 2);
+
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                storage,
+                Duration::from_secs(60),
+                Vec::new(),
+            ))
+            .service(accounts(requested.clone(), "max-age=0"));
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(request(&["1"]))
+                .await
+                .unwrap();
+        }
+        assert_eq!(requested.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_keys_entities_by_header() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counting = {
+            let calls = calls.clone();
+            let requested = Arc::new(Mutex::new(Vec::new()));
+            let accounts = accounts(requested, "");
+            ServiceBuilder::new()
+                .map_request(move |request| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    request
+                })
+                .service(accounts)
+        };
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                Arc::new(InMemoryStorage::new(100)),
+                Duration::from_secs(60),
+                vec!["authorization".to_string()],
+            ))
+            .service(counting);
+
+        for token in ["a", "b", "a"] {
+            let mut request = request(&["1"]);
+            request.originating_request = Arc::new(
+                http_compat::Request::fake_builder()
+                    .header("authorization", token)
+                    .body(Request::default())
+                    .build()
+                    .unwrap(),
+            );
+            service.ready().await.unwrap().call(request).await.unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_keys_entities_by_variables() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                Arc::new(InMemoryStorage::new(100)),
+                Duration::from_secs(60),
+                Vec::new(),
+            ))
+            .service(accounts(requested.clone(), ""));
+
+        for first in [10, 20, 10] {
+            let mut request = request(&["1"]);
+            let body = request.subgraph_request.body_mut();
+            let mut variables = (*body.variables).clone();
+            variables.insert("first", json!(first));
+            body.variables = Arc::new(variables);
+            service.ready().await.unwrap().call(request).await.unwrap();
+        }
+        assert_eq!(
+            *requested.lock().unwrap(),
+            vec![vec!["1".to_string()], vec!["1".to_string()]]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_remaining_ttl_of_cached_entities() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                Arc::new(InMemoryStorage::new(100)),
+                Duration::from_secs(60),
+                Vec::new(),
+            ))
+            .service(accounts(requested.clone(), "public, max-age=120"));
+
+        let max_age = |response: &SubgraphResponse| {
+            CacheControl::from_headers(response.response.headers()).max_age
+        };
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1"]))
+            .await
+            .unwrap();
+        assert_eq!(max_age(&response), Some(Duration::from_secs(120)));
+
+        // a full cache hit
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1"]))
+            .await
+            .unwrap();
+        let cached = max_age(&response).unwrap();
+        assert!(cached > Duration::from_secs(110) && cached <= Duration::from_secs(120));
+
+        // a partial cache hit: the subgraph max-age is lowered to the one of the cached entity
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1", "2"]))
+            .await
+            .unwrap();
+        assert!(max_age(&response).unwrap() < Duration::from_secs(120));
+        assert_eq!(requested.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_records_the_cache_policy_of_cache_hits() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mut service = ServiceBuilder::new()
+            .layer(EntityCacheLayer::new(
+                "accounts",
+                Arc::new(InMemoryStorage::new(100)),
+                Duration::from_secs(60),
+                vec!["authorization".to_string()],
+            ))
+            .service(accounts(requested.clone(), "public, max-age=120"));
+        let policy = |context: &Context| {
+            context
+                .get::<_, CacheControl>(CACHE_CONTROL_CONTEXT_KEY)
+                .unwrap()
+                .unwrap()
+        };
+
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(&["1"]))
+            .await
+            .unwrap();
+
+        // the subgraph is not called, the policy is recorded for the router response
+        let full_hit = request(&["1"]);
+        let context = full_hit.context.clone();
+        let response = service.ready().await.unwrap().call(full_hit).await.unwrap();
+        assert_eq!(requested.lock().unwrap().len(), 1);
+        let cache_control = CacheControl::from_headers(response.response.headers());
+        assert_eq!(policy(&context), cache_control);
+        // the entities are keyed by the authorization header of the user
+        assert!(cache_control.private);
+        assert!(cache_control.max_age.unwrap() <= Duration::from_secs(120));
+
+        let partial_hit = request(&["1", "2"]);
+        let context = partial_hit.context.clone();
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(partial_hit)
+            .await
+            .unwrap();
+        assert_eq!(requested.lock().unwrap().len(), 2);
+        let cache_control = CacheControl::from_headers(response.response.headers());
+        assert_eq!(policy(&context), cache_control);
+        assert!(cache_control.private);
+    }
+
+    #[tokio::test]
+    async fn it_expires_entries() {
+        let storage = InMemoryStorage::new(1);
+        storage
+            .insert("a".to_string(), json!(1), Duration::from_millis(10))
+            .await;
+        assert_eq!(
+            storage.get("a").await.map(|(entity, _)| entity),
+            Some(json!(1))
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(storage.get("a").await, None);
+
+        // the least recently used entry is evicted
+        storage
+            .insert("a".to_string(), json!(1), Duration::from_secs(60))
+            .await;
+        storage
+            .insert("b".to_string(), json!(2), Duration::from_secs(60))
+            .await;
+        assert_eq!(storage.get("a").await, None);
+        assert_eq!(
+            storage.get("b").await.map(|(entity, _)| entity),
+            Some(json!(2))
+        );
+    }
+}
@@ -2,29 +2,81 @@
 //!
 //! Currently includes:
 //! * Query deduplication
+//! * Subgraph request timeouts
+//! * Retries of subgraph queries
+//! * Rate limiting, for the router and per subgraph
+//! * Circuit breaking of failing subgraphs
+//! * Caching of the entities returned by subgraphs
 //!
 //! Future functionality:
 //! * APQ (already written, but config needs to be moved here)
-//! * Caching
-//! * Rate limiting
 //!
 
+mod circuit_breaker;
 mod deduplication;
+mod entity_cache;
+mod rate_limit;
+mod retry;
+mod timeout;
 
 use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
 
+use http::StatusCode;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use serde_json_bytes::ByteString;
+use tower::retry::RetryLayer;
 use tower::util::BoxService;
 use tower::{BoxError, ServiceBuilder, ServiceExt};
 
 use crate::plugin::Plugin;
+use crate::plugins::traffic_shaping::circuit_breaker::{Circuit, CircuitBreakerLayer};
 use crate::plugins::traffic_shaping::deduplication::QueryDeduplicationLayer;
-use crate::{register_plugin, ServiceBuilderExt, SubgraphRequest, SubgraphResponse};
+use crate::plugins::traffic_shaping::entity_cache::{
+    EntityCacheLayer, EntityCacheStorage, InMemoryStorage,
+};
+use crate::plugins::traffic_shaping::rate_limit::{RateLimitLayer, RateLimiter};
+use crate::plugins::traffic_shaping::retry::RetryPolicy;
+use crate::plugins::traffic_shaping::timeout::TimeoutLayer;
+use crate::{
+    register_plugin, Object, RouterRequest, RouterResponse, ServiceBuilderExt, SubgraphRequest,
+    SubgraphResponse, Value,
+};
+
+/// Extension code of the error returned to rate limited clients.
+const RATE_LIMITED_CODE: &str = "RATE_LIMITED";
+
+const DEFAULT_RETRY_ATTEMPTS: usize = 3;
+const DEFAULT_RETRY_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(2);
+const DEFAULT_RETRY_BUDGET_RATIO: f32 = 0.2;
+
+const DEFAULT_CIRCUIT_FAILURE_RATE_THRESHOLD: f32 = 0.5;
+const DEFAULT_CIRCUIT_MINIMUM_REQUESTS: u64 = 20;
+const DEFAULT_CIRCUIT_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_CIRCUIT_OPEN_DURATION: Duration = Duration::from_secs(30);
+
+const DEFAULT_ENTITY_CACHE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_ENTITY_CACHE_MAX_ENTRIES: usize = 10_000;
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 struct Shaping {
     dedup: Option<bool>,
+    /// Timeout of each request to the subgraph, every retry getting the same timeout (e.g. "30s")
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    timeout: Option<Duration>,
+    /// Retry failed queries. Mutations are never retried
+    retry: Option<Retry>,
+    /// Limit the rate of requests sent to the subgraph
+    rate_limit: Option<RateLimit>,
+    /// Stop sending requests to the subgraph for a while when too many of them fail
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Cache the entities returned by the subgraph
+    entity_cache: Option<EntityCache>,
 }
 
 impl Shaping {
@@ -33,21 +85,232 @@ impl Shaping {
             None => self.clone(),
             Some(fallback) => Shaping {
                 dedup: self.dedup.or(fallback.dedup),
+                timeout: self.timeout.or(fallback.timeout),
+                retry: match (&self.retry, &fallback.retry) {
+                    (Some(retry), fallback) => Some(retry.merge(fallback.as_ref())),
+                    (None, fallback) => fallback.clone(),
+                },
+                rate_limit: self
+                    .rate_limit
+                    .clone()
+                    .or_else(|| fallback.rate_limit.clone()),
+                circuit_breaker: match (&self.circuit_breaker, &fallback.circuit_breaker) {
+                    (Some(circuit_breaker), fallback) => {
+                        Some(circuit_breaker.merge(fallback.as_ref()))
+                    }
+                    (None, fallback) => fallback.clone(),
+                },
+                entity_cache: match (&self.entity_cache, &fallback.entity_cache) {
+                    (Some(entity_cache), fallback) => Some(entity_cache.merge(fallback.as_ref())),
+                    (None, fallback) => fallback.clone(),
+                },
+            },
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Retry {
+    /// Maximum number of retries of a failed query (default: 3)
+    attempts: Option<usize>,
+    /// Delay before the first retry, doubled for every following one (default: 100ms)
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    min_backoff: Option<Duration>,
+    /// Maximum delay between two retries (default: 2s)
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    max_backoff: Option<Duration>,
+    /// Maximum ratio of retries to successful requests over the last 10 seconds, on top of
+    /// 10 retries per second (default: 0.2)
+    budget_ratio: Option<f32>,
+}
+
+impl Retry {
+    fn merge(&self, fallback: Option<&Retry>) -> Retry {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => Retry {
+                attempts: self.attempts.or(fallback.attempts),
+                min_backoff: self.min_backoff.or(fallback.min_backoff),
+                max_backoff: self.max_backoff.or(fallback.max_backoff),
+                budget_ratio: self.budget_ratio.or(fallback.budget_ratio),
             },
         }
     }
+
+    fn policy(&self) -> RetryPolicy {
+        RetryPolicy::new(
+            self.attempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS),
+            self.min_backoff.unwrap_or(DEFAULT_RETRY_MIN_BACKOFF),
+            self.max_backoff.unwrap_or(DEFAULT_RETRY_MAX_BACKOFF),
+            self.budget_ratio.unwrap_or(DEFAULT_RETRY_BUDGET_RATIO),
+        )
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct CircuitBreaker {
+    /// Ratio of failed requests in the window opening the circuit, up to 1 (default: 0.5)
+    failure_rate_threshold: Option<f32>,
+    /// Minimum number of requests in the window before the circuit can open (default: 20)
+    minimum_requests: Option<u64>,
+    /// Duration over which the requests are counted (default: 10s)
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    window: Option<Duration>,
+    /// Duration during which requests fail immediately, before a probe request is let through
+    /// (default: 30s)
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    open_duration: Option<Duration>,
+}
+
+impl CircuitBreaker {
+    fn merge(&self, fallback: Option<&CircuitBreaker>) -> CircuitBreaker {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => CircuitBreaker {
+                failure_rate_threshold: self
+                    .failure_rate_threshold
+                    .or(fallback.failure_rate_threshold),
+                minimum_requests: self.minimum_requests.or(fallback.minimum_requests),
+                window: self.window.or(fallback.window),
+                open_duration: self.open_duration.or(fallback.open_duration),
+            },
+        }
+    }
+
+    fn circuit(&self, service_name: &str) -> Result<Circuit, BoxError> {
+        let failure_rate_threshold = self
+            .failure_rate_threshold
+            .unwrap_or(DEFAULT_CIRCUIT_FAILURE_RATE_THRESHOLD);
+        let window = self.window.unwrap_or(DEFAULT_CIRCUIT_WINDOW);
+        let open_duration = self.open_duration.unwrap_or(DEFAULT_CIRCUIT_OPEN_DURATION);
+        if failure_rate_threshold <= 0.0 || failure_rate_threshold > 1.0 {
+            return Err(
+                "circuit_breaker failure_rate_threshold must be greater than 0 and at most 1"
+                    .into(),
+            );
+        }
+        if window.is_zero() || open_duration.is_zero() {
+            return Err("circuit_breaker window and open_duration must be greater than 0".into());
+        }
+        Ok(Circuit::new(
+            service_name,
+            failure_rate_threshold,
+            self.minimum_requests
+                .unwrap_or(DEFAULT_CIRCUIT_MINIMUM_REQUESTS),
+            window,
+            open_duration,
+        ))
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EntityCache {
+    /// Time to live of the cached entities, used when the subgraph response has no
+    /// `Cache-Control` max-age (default: 60s)
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    ttl: Option<Duration>,
+    /// Headers of the client request whose values are part of the cache key,
+    /// like `authorization` when the entities depend on the user
+    headers: Option<Vec<String>>,
+}
+
+impl EntityCache {
+    fn merge(&self, fallback: Option<&EntityCache>) -> EntityCache {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => EntityCache {
+                ttl: self.ttl.or(fallback.ttl),
+                headers: self.headers.clone().or_else(|| fallback.headers.clone()),
+            },
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EntityCacheStorageConfig {
+    /// Maximum number of entities kept in memory, shared by all subgraphs (default: 10000)
+    max_entries: Option<usize>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RateLimit {
+    /// Number of requests allowed per interval, which is also the maximum burst
+    capacity: u64,
+    /// Interval over which the capacity is refilled (e.g. "1s")
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    interval: Duration,
+}
+
+impl RateLimit {
+    fn limiter(&self) -> Result<RateLimiter, BoxError> {
+        limiter(self.capacity, self.interval)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RouterRateLimit {
+    /// Number of requests allowed per interval, which is also the maximum burst
+    capacity: u64,
+    /// Interval over which the capacity is refilled (e.g. "1s")
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    interval: Duration,
+    /// Header identifying the client, every client gets its own limit.
+    /// The requests without this header share the same limit
+    key_header: Option<String>,
+}
+
+impl RouterRateLimit {
+    fn limiter(&self) -> Result<RateLimiter, BoxError> {
+        limiter(self.capacity, self.interval)
+    }
+}
+
+fn limiter(capacity: u64, interval: Duration) -> Result<RateLimiter, BoxError> {
+    if capacity == 0 || interval.is_zero() {
+        return Err("rate_limit capacity and interval must be greater than 0".into());
+    }
+    Ok(RateLimiter::new(capacity, interval))
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct RouterShaping {
+    /// Limit the rate of requests accepted by the router
+    rate_limit: Option<RouterRateLimit>,
 }
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 struct Config {
+    #[serde(default)]
+    router: Option<RouterShaping>,
     #[serde(default)]
     all: Option<Shaping>,
     #[serde(default)]
     subgraphs: HashMap<String, Shaping>,
+    /// Storage of the entity cache
+    #[serde(default)]
+    entity_cache: Option<EntityCacheStorageConfig>,
 }
 
 struct TrafficShaping {
     config: Config,
+    // shared by the router services created with this configuration
+    router_limiter: Option<Arc<RateLimiter>>,
+    // shared by all subgraphs
+    entity_cache_storage: Arc<dyn EntityCacheStorage>,
 }
 
 #[async_trait::async_trait]
@@ -55,7 +318,97 @@ impl Plugin for TrafficShaping {
     type Config = Config;
 
     async fn new(config: Self::Config) -> Result<Self, BoxError> {
-        Ok(Self { config })
+        for retry in config
+            .all
+            .iter()
+            .chain(config.subgraphs.values())
+            .filter_map(|shaping| shaping.retry.as_ref())
+        {
+            if let Some(ratio) = retry.budget_ratio {
+                if !(0.0..=1000.0).contains(&ratio) {
+                    return Err("retry budget_ratio must be between 0 and 1000".into());
+                }
+            }
+        }
+        for rate_limit in config
+            .all
+            .iter()
+            .chain(config.subgraphs.values())
+            .filter_map(|shaping| shaping.rate_limit.as_ref())
+        {
+            rate_limit.limiter()?;
+        }
+        for circuit_breaker in config
+            .all
+            .iter()
+            .chain(config.subgraphs.values())
+            .filter_map(|shaping| shaping.circuit_breaker.as_ref())
+        {
+            circuit_breaker.circuit("")?;
+        }
+        let router_limiter = config
+            .router
+            .as_ref()
+            .and_then(|router| router.rate_limit.as_ref())
+            .map(|rate_limit| rate_limit.limiter().map(Arc::new))
+            .transpose()?;
+        let max_entries = config
+            .entity_cache
+            .as_ref()
+            .and_then(|entity_cache| entity_cache.max_entries)
+            .unwrap_or(DEFAULT_ENTITY_CACHE_MAX_ENTRIES);
+        if max_entries == 0 {
+            return Err("entity_cache max_entries must be greater than 0".into());
+        }
+
+        Ok(Self {
+            config,
+            router_limiter,
+            entity_cache_storage: Arc::new(InMemoryStorage::new(max_entries)),
+        })
+    }
+
+    fn router_service(
+        &mut self,
+        service: BoxService<RouterRequest, RouterResponse, BoxError>,
+    ) -> BoxService<RouterRequest, RouterResponse, BoxError> {
+        if let Some(limiter) = self.router_limiter.clone() {
+            let key_header = self
+                .config
+                .router
+                .as_ref()
+                .and_then(|router| router.rate_limit.as_ref())
+                .and_then(|rate_limit| rate_limit.key_header.clone());
+            ServiceBuilder::new()
+                .checkpoint(move |req: RouterRequest| {
+                    let key = key_header
+                        .as_ref()
+                        .and_then(|header| req.originating_request.headers().get(header))
+                        .and_then(|value| value.to_str().ok());
+                    if limiter.try_acquire(key) {
+                        Ok(ControlFlow::Continue(req))
+                    } else {
+                        tracing::debug!("request rate limited");
+                        let mut extensions = Object::new();
+                        extensions
+                            .insert("code", Value::String(ByteString::from(RATE_LIMITED_CODE)));
+                        let error = crate::Error::builder()
+                            .message("Your request has been rate limited".to_string())
+                            .extensions(extensions)
+                            .build();
+                        let res = RouterResponse::builder()
+                            .error(error)
+                            .status_code(StatusCode::TOO_MANY_REQUESTS)
+                            .context(req.context)
+                            .build()?;
+                        Ok(ControlFlow::Break(res))
+                    }
+                })
+                .service(service)
+                .boxed()
+        } else {
+            service
+        }
     }
 
     fn subgraph_service(
@@ -76,6 +429,44 @@ impl Plugin for TrafficShaping {
                         .layer(QueryDeduplicationLayer::default())
                         .buffered()
                 }))
+                // inside of dedup, so identical requests in flight share the cache lookups
+                .option_layer(config.entity_cache.as_ref().map(|entity_cache| {
+                    //Buffer is required because entity cache layer requires a clone service.
+                    ServiceBuilder::new()
+                        .layer(EntityCacheLayer::new(
+                            name,
+                            self.entity_cache_storage.clone(),
+                            entity_cache.ttl.unwrap_or(DEFAULT_ENTITY_CACHE_TTL),
+                            entity_cache.headers.clone().unwrap_or_default(),
+                        ))
+                        .buffered()
+                }))
+                .option_layer(
+                    config
+                        .rate_limit
+                        .as_ref()
+                        .and_then(|rate_limit| rate_limit.limiter().ok())
+                        .map(|limiter| RateLimitLayer::new(name, limiter)),
+                )
+                // outside of retries, so a request retried until it fails counts as one failure
+                .option_layer(
+                    config
+                        .circuit_breaker
+                        .as_ref()
+                        .and_then(|circuit_breaker| circuit_breaker.circuit(name).ok())
+                        .map(CircuitBreakerLayer::new),
+                )
+                .option_layer(config.retry.as_ref().map(|retry| {
+                    //Buffer is required because retry layer requires a clone service.
+                    ServiceBuilder::new()
+                        .layer(RetryLayer::new(retry.policy()))
+                        .buffered()
+                }))
+                .option_layer(
+                    config
+                        .timeout
+                        .map(|timeout| TimeoutLayer::new(name, timeout)),
+                )
                 .service(service)
                 .boxed()
         } else {
@@ -99,6 +490,39 @@ register_plugin!("experimental", "traffic_shaping", TrafficShaping);
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fetch::OperationKind;
+    use crate::FetchError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::{service_fn, Service};
+
+    /// A subgraph service failing a number of times before answering.
+    fn failing_service(
+        failures: usize,
+        calls: Arc<AtomicUsize>,
+    ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
+        service_fn(move |request: SubgraphRequest| {
+            let calls = calls.clone();
+            async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                    Err(Box::new(FetchError::SubrequestHttpError {
+                        service: "products".to_string(),
+                        reason: "connection refused".to_string(),
+                    }) as BoxError)
+                } else {
+                    Ok(SubgraphResponse::fake_builder()
+                        .context(request.context)
+                        .build())
+                }
+            }
+        })
+        .boxed()
+    }
+
+    async fn traffic_shaping(config: &str) -> TrafficShaping {
+        TrafficShaping::new(serde_yaml::from_str::<Config>(config).unwrap())
+            .await
+            .unwrap()
+    }
 
     #[test]
     fn test_merge_config() {
@@ -129,4 +553,358 @@ mod test {
             config.subgraphs.get("products")
         );
     }
+
+    #[test]
+    fn test_merge_retry_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          timeout: 10s
+          retry:
+            attempts: 5
+            min_backoff: 50ms
+        subgraphs:
+          products:
+            timeout: 1s
+            retry:
+              attempts: 1
+        "#,
+        )
+        .unwrap();
+
+        let merged =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap();
+        assert_eq!(merged.timeout, Some(Duration::from_secs(1)));
+        assert_eq!(
+            merged.retry,
+            Some(Retry {
+                attempts: Some(1),
+                min_backoff: Some(Duration::from_millis(50)),
+                max_backoff: None,
+                budget_ratio: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_entity_cache_config() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        entity_cache:
+          max_entries: 100
+        all:
+          entity_cache:
+            ttl: 30s
+            headers: [authorization]
+        subgraphs:
+          products:
+            entity_cache:
+              ttl: 5m
+        "#,
+        )
+        .unwrap();
+
+        let merged =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap();
+        assert_eq!(
+            merged.entity_cache,
+            Some(EntityCache {
+                ttl: Some(Duration::from_secs(300)),
+                headers: Some(vec!["authorization".to_string()]),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn it_retries_failed_queries() {
+        let mut shaping = traffic_shaping(
+            r#"
+        all:
+          retry:
+            attempts: 2
+            min_backoff: 1ms
+        "#,
+        )
+        .await;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = shaping.subgraph_service("products", failing_service(2, calls.clone()));
+        service
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .expect("the third attempt succeeds");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = shaping.subgraph_service("products", failing_service(3, calls.clone()));
+        service
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .expect_err("the query fails after two retries");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_retry_mutations() {
+        let mut shaping = traffic_shaping(
+            r#"
+        all:
+          retry:
+            attempts: 2
+            min_backoff: 1ms
+        "#,
+        )
+        .await;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = shaping.subgraph_service("products", failing_service(1, calls.clone()));
+        service
+            .oneshot(
+                SubgraphRequest::fake_builder()
+                    .operation_kind(OperationKind::Mutation)
+                    .build(),
+            )
+            .await
+            .expect_err("the mutation is not retried");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_times_out_subgraph_requests() {
+        let mut shaping = traffic_shaping(
+            r#"
+        subgraphs:
+          products:
+            timeout: 10ms
+        "#,
+        )
+        .await;
+
+        let service = shaping.subgraph_service(
+            "products",
+            service_fn(|request: SubgraphRequest| async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(SubgraphResponse::fake_builder()
+                    .context(request.context)
+                    .build())
+            })
+            .boxed(),
+        );
+        let err = service
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .expect_err("the subgraph is too slow");
+        let err = FetchError::from_subgraph_error("products", err);
+        assert_eq!(
+            err.to_graphql_error(None).extensions.get("code"),
+            Some(&Value::String(ByteString::from("SUBREQUEST_TIMEOUT")))
+        );
+        assert!(matches!(
+            err,
+            FetchError::SubrequestTimeout { service } if service == "products"
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_budget_ratio() {
+        assert!(TrafficShaping::new(
+            serde_yaml::from_str::<Config>(
+                r#"
+        all:
+          retry:
+            budget_ratio: -1
+        "#,
+            )
+            .unwrap(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_rate_limits_router_requests_by_client() {
+        let mut shaping = traffic_shaping(
+            r#"
+        router:
+          rate_limit:
+            capacity: 1
+            interval: 1h
+            key_header: x-client-id
+        "#,
+        )
+        .await;
+
+        let mut service = shaping.router_service(
+            service_fn(|request: RouterRequest| async move {
+                RouterResponse::fake_builder()
+                    .context(request.context)
+                    .build()
+            })
+            .boxed(),
+        );
+        let request = |client: &str| {
+            RouterRequest::fake_builder()
+                .headers(
+                    [("x-client-id".into(), client.into())]
+                        .into_iter()
+                        .collect(),
+                )
+                .build()
+                .unwrap()
+        };
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request("a"))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request("a"))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::TOO_MANY_REQUESTS);
+        match response.response.body() {
+            crate::ResponseBody::GraphQL(response) => assert_eq!(
+                response.errors[0].extensions.get("code"),
+                Some(&Value::String(ByteString::from(RATE_LIMITED_CODE)))
+            ),
+            _ => panic!("expected a GraphQL response"),
+        }
+
+        // other clients have their own limit
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request("b"))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_fails_fast_when_subgraph_is_rate_limited() {
+        let mut shaping = traffic_shaping(
+            r#"
+        subgraphs:
+          products:
+            rate_limit:
+              capacity: 1
+              interval: 1h
+        "#,
+        )
+        .await;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = shaping.subgraph_service("products", failing_service(0, calls.clone()));
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+        let err = service
+            .ready()
+            .await
+            .unwrap()
+            .call(SubgraphRequest::fake_builder().build())
+            .await
+            .expect_err("the rate limit is reached");
+        let err = FetchError::from_subgraph_error("products", err);
+        assert_eq!(
+            err.to_graphql_error(None).extensions.get("code"),
+            Some(&Value::String(ByteString::from("SUBREQUEST_RATE_LIMITED")))
+        );
+        assert!(matches!(
+            err,
+            FetchError::SubrequestRateLimited { service } if service == "products"
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_empty_rate_limit() {
+        assert!(TrafficShaping::new(
+            serde_yaml::from_str::<Config>(
+                r#"
+        router:
+          rate_limit:
+            capacity: 0
+            interval: 1s
+        "#,
+            )
+            .unwrap(),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_opens_the_circuit_of_a_failing_subgraph() {
+        let mut shaping = traffic_shaping(
+            r#"
+        subgraphs:
+          products:
+            circuit_breaker:
+              minimum_requests: 2
+              open_duration: 1h
+        "#,
+        )
+        .await;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service = shaping.subgraph_service("products", failing_service(2, calls.clone()));
+        for _ in 0..2 {
+            service
+                .ready()
+                .await
+                .unwrap()
+                .call(SubgraphRequest::fake_builder().build())
+                .await
+                .expect_err("the subgraph fails");
+        }
+        let err = service
+            .ready()
+            .await
+            .unwrap()
+            .call(SubgraphRequest::fake_builder().build())
+            .await
+            .expect_err("the circuit is open");
+        let err = FetchError::from_subgraph_error("products", err);
+        assert_eq!(
+            err.to_graphql_error(None).extensions.get("code"),
+            Some(&Value::String(ByteString::from("SUBREQUEST_CIRCUIT_OPEN")))
+        );
+        assert!(matches!(
+            err,
+            FetchError::SubrequestCircuitOpen { service } if service == "products"
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_failure_rate_threshold() {
+        assert!(TrafficShaping::new(
+            serde_yaml::from_str::<Config>(
+                r#"
+        all:
+          circuit_breaker:
+            failure_rate_threshold: 1.5
+        "#,
+            )
+            .unwrap(),
+        )
+        .await
+        .is_err());
+    }
 }
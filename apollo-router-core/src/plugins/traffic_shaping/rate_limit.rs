@@ -0,0 +1,161 @@
+//! Limit the rate of requests with token buckets. The subgraph limit is implemented as a tower
+//! Layer.
+//!
+//! See [`Layer`] and [`tower::Service`] for more details.
+
+use crate::{FetchError, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use lru::LruCache;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tower::{BoxError, Layer};
+
+/// Maximum number of clients tracked by a keyed rate limiter, the least recently seen ones are
+/// forgotten first.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token buckets refilled continuously, with `capacity` tokens per `interval`.
+///
+/// Requests are counted in a separate bucket for every key, the requests without a key share
+/// the same bucket.
+pub struct RateLimiter {
+    capacity: f64,
+    interval: Duration,
+    buckets: Mutex<LruCache<Option<String>, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u64, interval: Duration) -> Self {
+        Self {
+            capacity: capacity as f64,
+            interval,
+            buckets: Mutex::new(LruCache::new(MAX_TRACKED_KEYS)),
+        }
+    }
+
+    /// Take a token from the bucket of this key, returns false if it is empty.
+    pub fn try_acquire(&self, key: Option<&str>) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("Lock poisoned");
+        let key = key.map(ToString::to_string);
+        if !buckets.contains(&key) {
+            buckets.put(
+                key.clone(),
+                Bucket {
+                    tokens: self.capacity,
+                    last_refill: now,
+                },
+            );
+        }
+        let bucket = buckets
+            .get_mut(&key)
+            .expect("the bucket exists or was just inserted; qed");
+
+        let elapsed = now.duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens
+            + self.capacity * elapsed.as_secs_f64() / self.interval.as_secs_f64())
+        .min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct RateLimitLayer {
+    service_name: String,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(service_name: &str, limiter: RateLimiter) -> Self {
+        RateLimitLayer {
+            service_name: service_name.to_string(),
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RateLimitService {
+            service,
+            service_name: self.service_name.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+pub struct RateLimitService<S> {
+    service: S,
+    service_name: String,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> tower::Service<SubgraphRequest> for RateLimitService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        if self.limiter.try_acquire(None) {
+            Box::pin(self.service.call(request))
+        } else {
+            let service_name = self.service_name.clone();
+            Box::pin(async move {
+                Err(Box::new(FetchError::SubrequestRateLimited {
+                    service: service_name,
+                }) as BoxError)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_limits_requests_by_key() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(3600));
+
+        assert!(limiter.try_acquire(Some("a")));
+        assert!(limiter.try_acquire(Some("a")));
+        assert!(!limiter.try_acquire(Some("a")));
+
+        assert!(limiter.try_acquire(Some("b")));
+        assert!(limiter.try_acquire(None));
+        assert!(limiter.try_acquire(None));
+        assert!(!limiter.try_acquire(None));
+    }
+
+    #[test]
+    fn it_refills_buckets() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+
+        assert!(limiter.try_acquire(None));
+        assert!(!limiter.try_acquire(None));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.try_acquire(None));
+    }
+}
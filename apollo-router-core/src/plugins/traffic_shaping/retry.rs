@@ -0,0 +1,102 @@
+//! Retry failed subgraph queries, with an exponential backoff and a retry budget.
+//!
+//! Only queries are retried: mutations are not idempotent, and subscriptions are long lived.
+
+use crate::{fetch::OperationKind, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use rand::Rng;
+use std::{sync::Arc, time::Duration};
+use tower::retry::budget::Budget;
+use tower::retry::Policy;
+use tower::BoxError;
+
+/// The retry budget allows this many retries per second, whatever the ratio.
+const BUDGET_MIN_RETRIES_PER_SECOND: u32 = 10;
+/// Duration over which the requests are counted in the retry budget.
+const BUDGET_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    budget: Arc<Budget>,
+    attempts: usize,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    /// Number of retries already made for the current request.
+    retried: usize,
+}
+
+impl RetryPolicy {
+    pub fn new(
+        attempts: usize,
+        min_backoff: Duration,
+        max_backoff: Duration,
+        budget_ratio: f32,
+    ) -> Self {
+        Self {
+            budget: Arc::new(Budget::new(
+                BUDGET_TTL,
+                BUDGET_MIN_RETRIES_PER_SECOND,
+                budget_ratio,
+            )),
+            attempts,
+            min_backoff,
+            max_backoff,
+            retried: 0,
+        }
+    }
+
+    /// Exponential backoff, where the delay is picked randomly between half and all of the
+    /// backoff to spread the retries of concurrent requests.
+    fn backoff(&self) -> Duration {
+        let backoff = self
+            .min_backoff
+            .saturating_mul(2u32.saturating_pow(self.retried as u32))
+            .min(self.max_backoff);
+
+        let half = backoff / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+impl Policy<SubgraphRequest, SubgraphResponse, BoxError> for RetryPolicy {
+    type Future = BoxFuture<'static, Self>;
+
+    fn retry(
+        &self,
+        _request: &SubgraphRequest,
+        result: Result<&SubgraphResponse, &BoxError>,
+    ) -> Option<Self::Future> {
+        match result {
+            Ok(_) => {
+                self.budget.deposit();
+                None
+            }
+            Err(_) if self.retried < self.attempts => {
+                if self.budget.withdraw().is_err() {
+                    tracing::debug!("retry budget exhausted, the subgraph request is not retried");
+                    return None;
+                }
+
+                let delay = self.backoff();
+                let policy = Self {
+                    retried: self.retried + 1,
+                    ..self.clone()
+                };
+                Some(Box::pin(async move {
+                    tokio::time::sleep(delay).await;
+                    policy
+                }))
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn clone_request(&self, request: &SubgraphRequest) -> Option<SubgraphRequest> {
+        (request.operation_kind == OperationKind::Query).then(|| SubgraphRequest {
+            originating_request: request.originating_request.clone(),
+            subgraph_request: request.subgraph_request.clone(),
+            operation_kind: request.operation_kind,
+            context: request.context.clone(),
+        })
+    }
+}
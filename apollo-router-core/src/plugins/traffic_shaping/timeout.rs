@@ -0,0 +1,74 @@
+//! Limit the duration of subgraph requests. Implemented as a tower Layer.
+//!
+//! See [`Layer`] and [`tower::Service`] for more details.
+
+use crate::{fetch::OperationKind, FetchError, SubgraphRequest, SubgraphResponse};
+use futures::future::BoxFuture;
+use std::{task::Poll, time::Duration};
+use tower::{BoxError, Layer};
+
+pub struct TimeoutLayer {
+    service_name: String,
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(service_name: &str, timeout: Duration) -> Self {
+        TimeoutLayer {
+            service_name: service_name.to_string(),
+            timeout,
+        }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = TimeoutService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        TimeoutService {
+            service,
+            service_name: self.service_name.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+pub struct TimeoutService<S> {
+    service: S,
+    service_name: String,
+    timeout: Duration,
+}
+
+impl<S> tower::Service<SubgraphRequest> for TimeoutService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        // a subscription response is its first event, which can take any time to come
+        if request.operation_kind == OperationKind::Subscription {
+            return Box::pin(self.service.call(request));
+        }
+
+        let timeout = self.timeout;
+        let service_name = self.service_name.clone();
+        let response = self.service.call(request);
+
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(response) => response,
+                Err(_) => Err(Box::new(FetchError::SubrequestTimeout {
+                    service: service_name,
+                }) as BoxError),
+            }
+        })
+    }
+}
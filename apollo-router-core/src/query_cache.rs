@@ -2,34 +2,43 @@
 use opentelemetry::trace::SpanKind;
 use tracing::{info_span, Instrument};
 
+use crate::introspection::schema::IntrospectionSchema;
 use crate::prelude::graphql::*;
 use crate::CacheResolver;
 use std::sync::Arc;
 
+/// A parsed query, or the validation errors of the query.
+type ParsedQuery = Result<Option<Arc<Query>>, Vec<crate::Error>>;
+
 /// A cache for parsed GraphQL queries.
 #[derive(Debug)]
 pub struct QueryCache {
-    cm: CachingMap<String, Option<Arc<Query>>>,
+    cm: CachingMap<String, ParsedQuery>,
 }
 
 /// A resolver for cache misses
 struct QueryCacheResolver {
     schema: Arc<Schema>,
+    api_schema: Arc<IntrospectionSchema>,
 }
 
 #[async_trait::async_trait]
-impl CacheResolver<String, Option<Arc<Query>>> for QueryCacheResolver {
-    async fn retrieve(&self, key: String) -> Result<Option<Arc<Query>>, CacheResolverError> {
+impl CacheResolver<String, ParsedQuery> for QueryCacheResolver {
+    async fn retrieve(&self, key: String) -> Result<ParsedQuery, CacheResolverError> {
         let schema = self.schema.clone();
-        let query_parsing_future = tokio::task::spawn_blocking(move || Query::parse(key, &schema))
-            .instrument(info_span!("parse_query", "otel.kind" = %SpanKind::Internal));
+        let api_schema = self.api_schema.clone();
+        let query_parsing_future = tokio::task::spawn_blocking(move || {
+            crate::spec::validate(&key, &api_schema)?;
+            Ok(Query::parse(key, &schema))
+        })
+        .instrument(info_span!("parse_query", "otel.kind" = %SpanKind::Internal));
         let parsed_query = match query_parsing_future.await {
-            Ok(res) => res.map(Arc::new),
+            Ok(res) => res.map(|query| query.map(Arc::new)),
             // Silently ignore cancelled tasks (never happen for blocking tasks).
-            Err(err) if err.is_cancelled() => None,
+            Err(err) if err.is_cancelled() => Ok(None),
             Err(err) => {
                 failfast_debug!("parsing query task failed: {}", err);
-                None
+                Ok(None)
             }
         };
         Ok(parsed_query)
@@ -39,20 +48,27 @@ impl CacheResolver<String, Option<Arc<Query>>> for QueryCacheResolver {
 impl QueryCache {
     /// Instantiate a new cache for parsed GraphQL queries.
     pub fn new(cache_limit: usize, schema: Arc<Schema>) -> Self {
-        let resolver = QueryCacheResolver { schema };
+        let api_schema = Arc::new(IntrospectionSchema::parse(schema.api_schema().as_str()));
+        let resolver = QueryCacheResolver { schema, api_schema };
         let cm = CachingMap::new(Box::new(resolver), cache_limit);
         Self { cm }
     }
 
     /// Attempt to parse a string to a [`Query`] using cache if possible.
-    pub async fn get(&self, query: impl AsRef<str>) -> Option<Arc<Query>> {
+    ///
+    /// Queries that are not valid against the API schema return their validation errors, located
+    /// in the query.
+    pub async fn get(
+        &self,
+        query: impl AsRef<str>,
+    ) -> Result<Option<Arc<Query>>, Vec<crate::Error>> {
         let key = query.as_ref().to_string();
 
         match self.cm.get(key).await {
             Ok(v) => v,
             Err(err) => {
                 failfast_debug!("parsing query task failed: {}", err);
-                None
+                Ok(None)
             }
         }
     }
@@ -6,6 +6,7 @@ use std::marker::PhantomData;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::task;
+use std::time::Duration;
 
 type PlanResult = Result<Arc<QueryPlan>, QueryPlannerError>;
 
@@ -37,6 +38,33 @@ impl<T: QueryPlanner + 'static> CachingQueryPlanner<T> {
     pub async fn get_hot_keys(&self) -> Vec<QueryKey> {
         self.cm.get_hot_keys().await
     }
+
+    /// Plan queries ahead of their requests, typically the hot keys of the planner of a previous
+    /// schema, until the budget is spent.
+    ///
+    /// Returns the number of queries planned.
+    pub async fn warm_up(&self, keys: Vec<QueryKey>, budget: Duration) -> usize {
+        let mut planned = 0;
+        let _ = tokio::time::timeout(budget, async {
+            for key in keys {
+                // planning errors are cached too, there is nothing to do about them here
+                let _ = self.cm.get(key).await;
+                planned += 1;
+            }
+        })
+        .await;
+        planned
+    }
+}
+
+// Implemented by hand because deriving it would require T: Clone
+impl<T: QueryPlanner> Clone for CachingQueryPlanner<T> {
+    fn clone(&self) -> Self {
+        Self {
+            cm: self.cm.clone(),
+            phantom: PhantomData,
+        }
+    }
 }
 
 #[async_trait]
@@ -189,4 +217,34 @@ mod tests {
             .await
             .is_err());
     }
+    #[test(tokio::test)]
+    async fn test_warm_up() {
+        let mut delegate = MockMyQueryPlanner::new();
+        delegate
+            .expect_sync_get()
+            .times(2)
+            .return_const(Err(QueryPlannerError::from(PlanErrors {
+                errors: Default::default(),
+                usage_reporting: UsageReporting {
+                    stats_report_key: "this is a test key".to_string(),
+                    referenced_fields_by_type: Default::default(),
+                },
+            })));
+
+        let planner = CachingQueryPlanner::new(delegate, 10);
+        let keys = vec![
+            ("query1".to_string(), None, QueryPlanOptions::default()),
+            ("query2".to_string(), None, QueryPlanOptions::default()),
+        ];
+        assert_eq!(planner.warm_up(keys, Duration::from_secs(5)).await, 2);
+
+        // the warmed up queries are not planned again
+        for query in ["query1", "query2"] {
+            assert!(planner
+                .get(query.into(), None, QueryPlanOptions::default())
+                .await
+                .is_err());
+        }
+        assert_eq!(planner.get_hot_keys().await.len(), 2);
+    }
 }
@@ -5,10 +5,11 @@ use crate::prelude::graphql::*;
 pub use bridge_query_planner::*;
 pub use caching_query_planner::*;
 use fetch::OperationKind;
+use futures::channel::mpsc;
 use futures::prelude::*;
 use opentelemetry::trace::SpanKind;
 use router_bridge::planner::UsageReporting;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use tracing::Instrument;
 /// Query planning options.
@@ -41,7 +42,7 @@ impl QueryPlan {
 }
 
 /// Query plans are composed of a set of nodes.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase", tag = "kind")]
 pub(crate) enum PlanNode {
     /// These nodes must be executed in order.
@@ -61,6 +62,25 @@ pub(crate) enum PlanNode {
 
     /// Merge the current resultset with the response.
     Flatten(FlattenNode),
+
+    /// Execute the primary node first, then the deferred nodes, that will be sent to the client
+    /// as incremental responses if it supports them.
+    Defer {
+        /// The node producing the primary response.
+        primary: Primary,
+
+        /// The nodes producing the deferred fragments.
+        deferred: Vec<DeferredNode>,
+    },
+
+    /// Open a subscription on a subgraph, then execute the rest of the plan for every event.
+    Subscription {
+        /// The subscription to the subgraph owning the root field.
+        primary: fetch::FetchNode,
+
+        /// The entity fetches completing every event.
+        rest: Option<Box<PlanNode>>,
+    },
 }
 
 impl PlanNode {
@@ -70,6 +90,20 @@ impl PlanNode {
             Self::Parallel { nodes } => nodes.iter().any(|n| n.contains_mutations()),
             Self::Fetch(fetch_node) => fetch_node.operation_kind() == &OperationKind::Mutation,
             Self::Flatten(_) => false,
+            Self::Defer { primary, deferred } => {
+                primary
+                    .node
+                    .as_ref()
+                    .map(|n| n.contains_mutations())
+                    .unwrap_or(false)
+                    || deferred.iter().any(|d| {
+                        d.node
+                            .as_ref()
+                            .map(|n| n.contains_mutations())
+                            .unwrap_or(false)
+                    })
+            }
+            Self::Subscription { .. } => false,
         }
     }
 }
@@ -117,9 +151,182 @@ impl QueryPlan {
         Response::builder().data(value).errors(errors).build()
     }
 
+    /// Execute the plan and send the primary [`Response`], followed by the incremental responses
+    /// for the deferred fragments, through the `sender`.
+    ///
+    /// Deferred fragments are only delivered incrementally if the root of the plan is a defer
+    /// node, otherwise the complete response is sent at once.
+    pub async fn execute_incremental<'a>(
+        &'a self,
+        context: &'a Context,
+        service_registry: &'a ServiceRegistry,
+        originating_request: http_compat::Request<Request>,
+        schema: &'a Schema,
+        mut sender: mpsc::Sender<Response>,
+    ) {
+        let (primary, deferred) = match &self.root {
+            PlanNode::Defer { primary, deferred } => (primary, deferred),
+            _ => {
+                let response = self
+                    .execute(context, service_registry, originating_request, schema)
+                    .await;
+                let _ = sender.send(response).await;
+                return;
+            }
+        };
+
+        log::trace_query_plan(&self.root);
+
+        let root = Path::empty();
+        let (value, errors) = match &primary.node {
+            Some(node) => {
+                node.execute_recursively(
+                    &root,
+                    context,
+                    service_registry,
+                    schema,
+                    originating_request.clone(),
+                    &Value::default(),
+                )
+                .await
+            }
+            None => (Value::default(), Vec::new()),
+        };
+
+        let primary_response = Response::builder()
+            .data(value.clone())
+            .errors(errors)
+            .has_next(!deferred.is_empty())
+            .build();
+        if sender.send(primary_response).await.is_err() || deferred.is_empty() {
+            return;
+        }
+
+        let span = tracing::info_span!("deferred");
+        let mut stream: stream::FuturesUnordered<_> = deferred
+            .iter()
+            .map(|deferred_node| {
+                deferred_node
+                    .execute(
+                        context,
+                        service_registry,
+                        schema,
+                        originating_request.clone(),
+                        &value,
+                    )
+                    .instrument(span.clone())
+            })
+            .collect();
+
+        // a patch is only sent once we know if another one follows it, so that
+        // the last one can be marked with `hasNext: false`
+        let mut pending: Option<Response> = None;
+        while let Some(patches) = stream.next().await {
+            for patch in patches {
+                if let Some(mut previous) = pending.replace(patch) {
+                    previous.has_next = Some(true);
+                    if sender.send(previous).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let mut last = pending.unwrap_or_else(|| Response::builder().build());
+        last.has_next = Some(false);
+        let _ = sender.send(last).await;
+    }
+
+    /// Open the subscription and send a [`Response`] for every event through the `sender`.
+    ///
+    /// Plans that are not subscriptions send their response at once.
+    pub async fn subscribe<'a>(
+        &'a self,
+        context: &'a Context,
+        service_registry: &'a ServiceRegistry,
+        originating_request: http_compat::Request<Request>,
+        schema: &'a Schema,
+        mut sender: mpsc::Sender<Response>,
+    ) {
+        let (primary, rest) = match &self.root {
+            PlanNode::Subscription { primary, rest } => (primary, rest),
+            _ => {
+                let response = self
+                    .execute(context, service_registry, originating_request, schema)
+                    .await;
+                let _ = sender.send(response).await;
+                return;
+            }
+        };
+
+        log::trace_query_plan(&self.root);
+
+        let mut events = match primary
+            .subscribe(
+                context,
+                service_registry,
+                originating_request.clone(),
+                schema,
+            )
+            .await
+        {
+            Ok(events) => events,
+            Err(err) => {
+                failfast_error!("Fetch error: {}", err);
+                let _ = sender
+                    .send(
+                        Response::builder()
+                            .errors(vec![err.to_graphql_error(None)])
+                            .build(),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        let root = Path::empty();
+        while let Some(mut event) = events.next().await {
+            if let (Some(rest), Some(data)) = (rest, event.data.as_mut()) {
+                let (value, errors) = rest
+                    .execute_recursively(
+                        &root,
+                        context,
+                        service_registry,
+                        schema,
+                        originating_request.clone(),
+                        data,
+                    )
+                    .instrument(tracing::info_span!("subscription_event"))
+                    .await;
+                data.deep_merge(value);
+                event.errors.extend(errors.into_iter());
+            }
+
+            if sender.send(event).await.is_err() {
+                // the client is gone, dropping the events stream closes the subscription
+                return;
+            }
+        }
+    }
+
     pub fn contains_mutations(&self) -> bool {
         self.root.contains_mutations()
     }
+
+    /// Returns true if the plan executes a subscription.
+    pub fn is_subscription(&self) -> bool {
+        matches!(self.root, PlanNode::Subscription { .. })
+    }
+
+    /// Returns true if the root of the plan defers some fragments.
+    pub fn is_deferred(&self) -> bool {
+        matches!(self.root, PlanNode::Defer { .. })
+    }
+
+    /// The plan in the JSON format of the query planner, for debugging.
+    pub fn to_json(&self) -> Value {
+        serde_json_bytes::to_value(&self.root).expect("a query plan is serializable; qed")
+    }
 }
 
 impl PlanNode {
@@ -233,6 +440,64 @@ impl PlanNode {
                         }
                     }
                 }
+                PlanNode::Defer { primary, deferred } => {
+                    // this defer node is not at the root of the plan, so the deferred
+                    // fragments are merged in the current response instead of being
+                    // delivered incrementally
+                    value = parent_value.clone();
+                    errors = Vec::new();
+
+                    if let Some(node) = &primary.node {
+                        let (v, err) = node
+                            .execute_recursively(
+                                current_dir,
+                                context,
+                                service_registry,
+                                schema,
+                                originating_request.clone(),
+                                &value,
+                            )
+                            .instrument(tracing::info_span!("primary"))
+                            .await;
+                        value.deep_merge(v);
+                        errors.extend(err.into_iter());
+                    }
+
+                    let span = tracing::info_span!("deferred");
+                    let mut stream: stream::FuturesUnordered<_> = deferred
+                        .iter()
+                        .filter_map(|deferred_node| deferred_node.node.as_ref())
+                        .map(|node| {
+                            node.execute_recursively(
+                                current_dir,
+                                context,
+                                service_registry,
+                                schema,
+                                originating_request.clone(),
+                                &value,
+                            )
+                            .instrument(span.clone())
+                        })
+                        .collect();
+
+                    let mut deferred_value = Value::default();
+                    while let Some((v, err)) = stream.next().await {
+                        deferred_value.deep_merge(v);
+                        errors.extend(err.into_iter());
+                    }
+                    drop(stream);
+                    value.deep_merge(deferred_value);
+                }
+                PlanNode::Subscription { .. } => {
+                    // subscriptions are only supported at the root of the plan,
+                    // by `QueryPlan::subscribe`
+                    errors = vec![FetchError::ExecutionInvalidContent {
+                        reason: "subscriptions can only be executed at the root of the plan"
+                            .to_string(),
+                    }
+                    .to_graphql_error(Some(current_dir.to_owned()))];
+                    value = Value::default();
+                }
             }
 
             (value, errors)
@@ -249,6 +514,18 @@ impl PlanNode {
             }
             Self::Fetch(fetch) => Box::new(Some(fetch.service_name()).into_iter()),
             Self::Flatten(flatten) => flatten.node.service_usage(),
+            Self::Defer { primary, deferred } => Box::new(
+                primary.node.iter().flat_map(|n| n.service_usage()).chain(
+                    deferred
+                        .iter()
+                        .flat_map(|d| d.node.iter().flat_map(|n| n.service_usage())),
+                ),
+            ),
+            Self::Subscription { primary, rest } => Box::new(
+                Some(primary.service_name())
+                    .into_iter()
+                    .chain(rest.iter().flat_map(|n| n.service_usage())),
+            ),
         }
     }
 
@@ -279,12 +556,14 @@ impl PlanNode {
 pub(crate) mod fetch {
     use super::selection::{select_object, Selection};
     use crate::prelude::graphql::*;
-    use serde::Deserialize;
+    use futures::future;
+    use futures::stream::{self, BoxStream, StreamExt};
+    use serde::{Deserialize, Serialize};
     use std::{fmt::Display, sync::Arc};
     use tower::ServiceExt;
     use tracing::{instrument, Instrument};
 
-    #[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+    #[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub enum OperationKind {
         Query,
@@ -303,7 +582,7 @@ pub(crate) mod fetch {
     }
 
     /// A fetch node.
-    #[derive(Debug, PartialEq, Deserialize)]
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub(crate) struct FetchNode {
         /// The name of the service or subgraph that the fetch is querying.
@@ -398,8 +677,6 @@ pub(crate) mod fetch {
         ) -> Result<(Value, Vec<Error>), FetchError> {
             let FetchNode {
                 operation,
-                operation_kind,
-                operation_name,
                 service_name,
                 ..
             } = self;
@@ -421,38 +698,8 @@ pub(crate) mod fetch {
                 }
             };
 
-            let subgraph_request = SubgraphRequest::builder()
-                .originating_request(Arc::new(originating_request))
-                .subgraph_request(
-                    http_compat::Request::builder()
-                        .method(http::Method::POST)
-                        .uri(
-                            schema
-                                .subgraphs()
-                                .find_map(|(name, url)| (name == service_name).then(|| url))
-                                .unwrap_or_else(|| {
-                                    panic!(
-                        "schema uri for subgraph '{}' should already have been checked",
-                        service_name
-                    )
-                                })
-                                .clone(),
-                        )
-                        .body(
-                            Request::builder()
-                                .query(Some(operation.to_string()))
-                                .operation_name(operation_name.clone())
-                                .variables(Arc::new(variables.clone()))
-                                .build(),
-                        )
-                        .build()
-                        .expect(
-                            "it won't fail because the url is correct and already checked; qed",
-                        ),
-                )
-                .operation_kind(*operation_kind)
-                .context(context.clone())
-                .build();
+            let subgraph_request =
+                self.subgraph_request(variables.clone(), originating_request, schema, context);
 
             let service = service_registry
                 .get(service_name)
@@ -463,10 +710,7 @@ pub(crate) mod fetch {
                 .oneshot(subgraph_request)
                 .instrument(tracing::trace_span!("subfetch_stream"))
                 .await
-                .map_err(|e| FetchError::SubrequestHttpError {
-                    service: service_name.to_string(),
-                    reason: e.to_string(),
-                })?
+                .map_err(|e| FetchError::from_subgraph_error(service_name, e))?
                 .response
                 .into_parts();
 
@@ -495,6 +739,96 @@ pub(crate) mod fetch {
                 .map(|value| (value, errors))
         }
 
+        /// Open a subscription on the subgraph, and return the stream of its events.
+        pub(crate) async fn subscribe<'a>(
+            &'a self,
+            context: &'a Context,
+            service_registry: &'a ServiceRegistry,
+            originating_request: http_compat::Request<Request>,
+            schema: &'a Schema,
+        ) -> Result<BoxStream<'static, Response>, FetchError> {
+            let Variables { variables, .. } = Variables::new(
+                &self.requires,
+                self.variable_usages.as_ref(),
+                &Value::default(),
+                &Path::empty(),
+                originating_request.clone(),
+                schema,
+            )
+            .await
+            .ok_or_else(|| FetchError::ExecutionInvalidContent {
+                reason: "a subscription cannot require entities".to_string(),
+            })?;
+
+            let subgraph_request =
+                self.subgraph_request(variables, originating_request, schema, context);
+
+            let service = service_registry
+                .get(&self.service_name)
+                .expect("we already checked that the service exists during planning; qed");
+
+            let (parts, response) = service
+                .oneshot(subgraph_request)
+                .instrument(tracing::trace_span!("subscription_stream"))
+                .await
+                .map_err(|e| FetchError::from_subgraph_error(&self.service_name, e))?
+                .response
+                .into_parts();
+
+            // the subgraph service returns the first event as the response,
+            // and the following ones in its extensions
+            let following = parts
+                .extensions
+                .get::<IncrementalResponses>()
+                .and_then(|responses| responses.take())
+                .unwrap_or_else(|| stream::empty().boxed());
+
+            Ok(stream::once(future::ready(response))
+                .chain(following)
+                .boxed())
+        }
+
+        fn subgraph_request(
+            &self,
+            variables: Object,
+            originating_request: http_compat::Request<Request>,
+            schema: &Schema,
+            context: &Context,
+        ) -> SubgraphRequest {
+            SubgraphRequest::builder()
+                .originating_request(Arc::new(originating_request))
+                .subgraph_request(
+                    http_compat::Request::builder()
+                        .method(http::Method::POST)
+                        .uri(
+                            schema
+                                .subgraphs()
+                                .find_map(|(name, url)| (name == &self.service_name).then(|| url))
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "schema uri for subgraph '{}' should already have been checked",
+                                        self.service_name
+                                    )
+                                })
+                                .clone(),
+                        )
+                        .body(
+                            Request::builder()
+                                .query(Some(self.operation.to_string()))
+                                .operation_name(self.operation_name.clone())
+                                .variables(Arc::new(variables))
+                                .build(),
+                        )
+                        .build()
+                        .expect(
+                            "it won't fail because the url is correct and already checked; qed",
+                        ),
+                )
+                .operation_kind(self.operation_kind)
+                .context(context.clone())
+                .build()
+        }
+
         #[instrument(skip_all, level = "debug", name = "response_insert")]
         fn response_at_path<'a>(
             &'a self,
@@ -543,7 +877,7 @@ pub(crate) mod fetch {
 }
 
 /// A flatten node.
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct FlattenNode {
     /// The path when result should be merged.
@@ -553,6 +887,89 @@ pub(crate) struct FlattenNode {
     node: Box<PlanNode>,
 }
 
+/// The primary part of a defer node.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Primary {
+    /// The plan for the non deferred part of the query. It can be absent if
+    /// all the data is provided by the deferred nodes.
+    node: Option<Box<PlanNode>>,
+}
+
+/// A deferred part of a defer node.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DeferredNode {
+    /// The label of the `@defer` directive, if any.
+    label: Option<String>,
+
+    /// The path of the deferred fragment in the response.
+    query_path: Path,
+
+    /// The plan for the deferred fragment. It can be absent if the data
+    /// was already fetched for the primary response.
+    node: Option<Box<PlanNode>>,
+}
+
+impl DeferredNode {
+    /// Execute the deferred node over the data of the primary response, and return
+    /// the patches for every object found at the deferred fragment's path.
+    async fn execute<'a>(
+        &'a self,
+        context: &'a Context,
+        service_registry: &'a ServiceRegistry,
+        schema: &'a Schema,
+        originating_request: http_compat::Request<Request>,
+        primary_value: &'a Value,
+    ) -> Vec<Response> {
+        let mut value = primary_value.clone();
+        let mut errors = Vec::new();
+
+        if let Some(node) = &self.node {
+            let (v, err) = node
+                .execute_recursively(
+                    &Path::empty(),
+                    context,
+                    service_registry,
+                    schema,
+                    originating_request,
+                    primary_value,
+                )
+                .await;
+            value.deep_merge(v);
+            errors = err;
+        }
+
+        let mut patches = Vec::new();
+        value.select_values_and_paths(&self.query_path, |path, data| {
+            if !data.is_null() {
+                patches.push(
+                    Response::builder()
+                        .label(self.label.clone())
+                        .path(path)
+                        .data(data.clone())
+                        .build(),
+                );
+            }
+        });
+
+        // the errors are reported with the first patch, since they carry their own path
+        match patches.first_mut() {
+            Some(patch) => patch.errors = errors,
+            None if !errors.is_empty() => patches.push(
+                Response::builder()
+                    .label(self.label.clone())
+                    .path(self.query_path.clone())
+                    .errors(errors)
+                    .build(),
+            ),
+            None => {}
+        }
+
+        patches
+    }
+}
+
 // The code resides in a separate submodule to allow writing a log filter activating it
 // separately from the query planner logs, as follows:
 // `router -s supergraph.graphql --log info,apollo_router_core::query_planner::log=trace`
@@ -607,6 +1024,18 @@ mod tests {
         insta::assert_debug_snapshot!(query_plan);
     }
 
+    #[test]
+    fn query_plan_to_json() {
+        let query_plan = QueryPlan::fake_builder()
+            .root(serde_json::from_str::<PlanNode>(test_query_plan!()).unwrap())
+            .build();
+        let json = query_plan.to_json();
+        assert_eq!(
+            serde_json_bytes::from_value::<PlanNode>(json).unwrap(),
+            query_plan.root
+        );
+    }
+
     #[test]
     fn service_usage() {
         assert_eq!(
@@ -745,4 +1174,244 @@ mod tests {
             "subgraph requests must be http post"
         );
     }
+
+    fn defer_query_plan() -> QueryPlan {
+        QueryPlan {
+            root: serde_json::from_str(
+                r#"{
+                    "kind": "Defer",
+                    "primary": {
+                        "node": {
+                            "kind": "Fetch",
+                            "serviceName": "product",
+                            "variableUsages": [],
+                            "operation": "{me{id}}",
+                            "operationKind": "query"
+                        }
+                    },
+                    "deferred": [{
+                        "label": "username",
+                        "queryPath": ["me"],
+                        "depends": [],
+                        "node": {
+                            "kind": "Fetch",
+                            "serviceName": "books",
+                            "variableUsages": [],
+                            "operation": "{me{username}}",
+                            "operationKind": "query"
+                        }
+                    }]
+                }"#,
+            )
+            .unwrap(),
+            usage_reporting: UsageReporting {
+                stats_report_key: "this is a test report key".to_string(),
+                referenced_fields_by_type: Default::default(),
+            },
+        }
+    }
+
+    fn defer_service_registry() -> ServiceRegistry {
+        let mut mock_products_service = plugin::utils::test::MockSubgraphService::new();
+        mock_products_service.expect_call().times(1).returning(|_| {
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json_bytes::json!({"me": {"id": "1"}}))
+                .build())
+        });
+        let mut mock_books_service = plugin::utils::test::MockSubgraphService::new();
+        mock_books_service.expect_call().times(1).returning(|_| {
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json_bytes::json!({"me": {"username": "Ada"}}))
+                .build())
+        });
+
+        ServiceRegistry::new(HashMap::from([
+            (
+                "product".into(),
+                ServiceBuilder::new()
+                    .buffer(1)
+                    .service(mock_products_service.build().boxed()),
+            ),
+            (
+                "books".into(),
+                ServiceBuilder::new()
+                    .buffer(1)
+                    .service(mock_books_service.build().boxed()),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn defer_service_usage() {
+        let query_plan = defer_query_plan();
+        assert!(query_plan.is_deferred());
+        assert!(!query_plan.contains_mutations());
+        assert_eq!(
+            query_plan.root.service_usage().collect::<Vec<_>>(),
+            vec!["product", "books"]
+        );
+    }
+
+    #[tokio::test]
+    async fn defer_sends_primary_response_then_patches() {
+        let query_plan = defer_query_plan();
+        let schema = Schema::from_str(test_schema!()).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+
+        query_plan
+            .execute_incremental(
+                &Context::new(),
+                &defer_service_registry(),
+                http_compat::Request::mock(),
+                &schema,
+                sender,
+            )
+            .await;
+
+        // the router formats the responses against the primary and deferred selections
+        let query = Query::parse(
+            r#"{ me { id ... @defer(label: "username") { username } } }"#,
+            &schema,
+        )
+        .unwrap();
+        let mut responses = receiver.collect::<Vec<_>>().await;
+        for response in &mut responses {
+            query.format_response(response, None, Object::new(), schema.api_schema());
+        }
+        assert_eq!(responses.len(), 2);
+
+        assert_eq!(
+            responses[0].data,
+            Some(serde_json_bytes::json!({"me": {"id": "1"}}))
+        );
+        assert_eq!(responses[0].has_next, Some(true));
+        assert!(responses[0].path.is_none());
+
+        assert_eq!(responses[1].label, Some("username".to_string()));
+        assert_eq!(responses[1].path, Some(Path::from("me")));
+        assert_eq!(
+            responses[1].data,
+            Some(serde_json_bytes::json!({"username": "Ada"}))
+        );
+        assert_eq!(responses[1].has_next, Some(false));
+    }
+
+    #[tokio::test]
+    async fn defer_is_merged_when_executed_at_once() {
+        let query_plan = defer_query_plan();
+
+        let response = query_plan
+            .execute(
+                &Context::new(),
+                &defer_service_registry(),
+                http_compat::Request::mock(),
+                &Schema::from_str(test_schema!()).unwrap(),
+            )
+            .await;
+
+        assert!(response.errors.is_empty());
+        assert_eq!(response.has_next, None);
+        assert_eq!(
+            response.data,
+            Some(serde_json_bytes::json!({"me": {"id": "1", "username": "Ada"}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_events_go_through_the_rest_of_the_plan() {
+        let query_plan = QueryPlan {
+            root: serde_json::from_str(
+                r#"{
+                    "kind": "Subscription",
+                    "primary": {
+                        "kind": "Fetch",
+                        "serviceName": "product",
+                        "variableUsages": [],
+                        "operation": "subscription{productAdded{upc}}",
+                        "operationKind": "subscription"
+                    },
+                    "rest": {
+                        "kind": "Fetch",
+                        "serviceName": "books",
+                        "variableUsages": [],
+                        "operation": "{bestSeller{isbn}}",
+                        "operationKind": "query"
+                    }
+                }"#,
+            )
+            .unwrap(),
+            usage_reporting: UsageReporting {
+                stats_report_key: "this is a test report key".to_string(),
+                referenced_fields_by_type: Default::default(),
+            },
+        };
+        assert!(query_plan.is_subscription());
+        assert_eq!(
+            query_plan.root.service_usage().collect::<Vec<_>>(),
+            vec!["product", "books"]
+        );
+
+        let mut mock_products_service = plugin::utils::test::MockSubgraphService::new();
+        mock_products_service
+            .expect_call()
+            .times(1)
+            .withf(|request| request.operation_kind == OperationKind::Subscription)
+            .returning(|_| {
+                let events = stream::iter(vec![Response::builder()
+                    .data(serde_json_bytes::json!({"productAdded": {"upc": "2"}}))
+                    .build()])
+                .boxed();
+                let mut response = SubgraphResponse::fake_builder()
+                    .data(serde_json_bytes::json!({"productAdded": {"upc": "1"}}))
+                    .build();
+                response
+                    .response
+                    .extensions_mut()
+                    .insert(IncrementalResponses::new(events));
+                Ok(response)
+            });
+        let mut mock_books_service = plugin::utils::test::MockSubgraphService::new();
+        mock_books_service.expect_call().times(2).returning(|_| {
+            Ok(SubgraphResponse::fake_builder()
+                .data(serde_json_bytes::json!({"bestSeller": {"isbn": "3"}}))
+                .build())
+        });
+        let service_registry = ServiceRegistry::new(HashMap::from([
+            (
+                "product".into(),
+                ServiceBuilder::new()
+                    .buffer(1)
+                    .service(mock_products_service.build().boxed()),
+            ),
+            (
+                "books".into(),
+                ServiceBuilder::new()
+                    .buffer(1)
+                    .service(mock_books_service.build().boxed()),
+            ),
+        ]));
+
+        let (sender, receiver) = mpsc::channel(10);
+        query_plan
+            .subscribe(
+                &Context::new(),
+                &service_registry,
+                http_compat::Request::mock(),
+                &Schema::from_str(test_schema!()).unwrap(),
+                sender,
+            )
+            .await;
+
+        let events = receiver.collect::<Vec<_>>().await;
+        assert_eq!(
+            events
+                .into_iter()
+                .map(|event| event.data.unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                serde_json_bytes::json!({"productAdded": {"upc": "1"}, "bestSeller": {"isbn": "3"}}),
+                serde_json_bytes::json!({"productAdded": {"upc": "2"}, "bestSeller": {"isbn": "3"}}),
+            ]
+        );
+    }
 }
@@ -1,6 +1,9 @@
 use crate::prelude::graphql::*;
 use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
 use typed_builder::TypedBuilder;
 
 /// A graphql primary response.
@@ -33,6 +36,11 @@ pub struct Response {
     #[serde(skip_serializing_if = "Object::is_empty", default)]
     #[builder(default)]
     pub extensions: Object,
+
+    /// Set on incremental responses: true if more patches will follow this one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[builder(default)]
+    pub has_next: Option<bool>,
 }
 
 impl Response {
@@ -91,6 +99,11 @@ impl Response {
                 service: service_name.to_string(),
                 reason: err.to_string(),
             })?;
+        let has_next = extract_key_value_from_object!(object, "hasNext", Value::Bool(b) => b)
+            .map_err(|err| FetchError::SubrequestMalformedResponse {
+                service: service_name.to_string(),
+                reason: err.to_string(),
+            })?;
 
         Ok(Response {
             label,
@@ -98,10 +111,49 @@ impl Response {
             path,
             errors,
             extensions,
+            has_next,
         })
     }
 }
 
+/// The incremental responses following a primary [`Response`], when some fragments are deferred.
+///
+/// They are stored in the extensions of the HTTP response carrying the primary response, and sent
+/// to the client in a `multipart/mixed` body.
+pub struct IncrementalResponses(Mutex<Option<BoxStream<'static, Response>>>);
+
+impl IncrementalResponses {
+    pub fn new(stream: BoxStream<'static, Response>) -> Self {
+        Self(Mutex::new(Some(stream)))
+    }
+
+    /// Take the stream of incremental responses. It can only be taken once.
+    pub fn take(&self) -> Option<BoxStream<'static, Response>> {
+        self.0.lock().expect("Lock poisoned").take()
+    }
+
+    /// Transform every incremental response, once it is produced.
+    ///
+    /// The `map_response` layers of plugins only see the primary response, so this is how they
+    /// can modify the following subscription events and deferred patches. Nothing is done if the
+    /// stream was already taken.
+    pub fn map<F>(&self, f: F)
+    where
+        F: FnMut(Response) -> Response + Send + 'static,
+    {
+        let mut stream = self.0.lock().expect("Lock poisoned");
+        if let Some(responses) = stream.take() {
+            *stream = Some(responses.map(f).boxed());
+        }
+    }
+}
+
+/// The size in bytes of a subgraph response body, once decompressed.
+///
+/// It is stored in the extensions of the HTTP response of a subgraph response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseBodySize(pub usize);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -273,6 +325,7 @@ mod tests {
             result.unwrap(),
             Response::builder()
                 .label("part".to_owned())
+                .has_next(true)
                 .data(json!({
                   "hero": {
                     "name": "R2-D2",
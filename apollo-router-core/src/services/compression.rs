@@ -0,0 +1,201 @@
+//! Compression of the bodies exchanged with the subgraphs.
+
+use async_compression::tokio::bufread::{
+    BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder,
+};
+use bytes::Bytes;
+use displaydoc::Display;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Value of the `Accept-Encoding` header sent to the subgraphs.
+pub(crate) const ACCEPTED_ENCODINGS: &str = "gzip, br, deflate";
+
+/// Default maximum size of a decompressed subgraph response body, in bytes.
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 50 * 1024 * 1024;
+
+/// Errors of the decoding of a body.
+#[derive(Debug, Display, Error)]
+pub(crate) enum DecodeError {
+    /// the decompressed body is larger than {0} bytes
+    TooLarge(usize),
+
+    /// {0}
+    Invalid(#[from] io::Error),
+}
+
+/// Compression algorithms, named after their `Content-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Br,
+    Deflate,
+}
+
+impl Compression {
+    /// The `Content-Encoding` of this algorithm.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Br => "br",
+            Compression::Deflate => "deflate",
+        }
+    }
+
+    pub(crate) async fn compress(&self, body: &[u8]) -> io::Result<Bytes> {
+        let mut compressed = Vec::new();
+        match self {
+            Compression::Gzip => GzipEncoder::new(body).read_to_end(&mut compressed).await?,
+            Compression::Br => {
+                BrotliEncoder::new(body)
+                    .read_to_end(&mut compressed)
+                    .await?
+            }
+            Compression::Deflate => ZlibEncoder::new(body).read_to_end(&mut compressed).await?,
+        };
+        Ok(compressed.into())
+    }
+
+    /// Decompress a body, reading at most `max_size` decompressed bytes.
+    async fn decompress(&self, body: &[u8], max_size: usize) -> Result<Bytes, DecodeError> {
+        match self {
+            Compression::Gzip => read_at_most(GzipDecoder::new(body), max_size).await,
+            Compression::Br => read_at_most(BrotliDecoder::new(body), max_size).await,
+            Compression::Deflate => read_at_most(ZlibDecoder::new(body), max_size).await,
+        }
+    }
+}
+
+async fn read_at_most(
+    reader: impl AsyncRead + Unpin,
+    max_size: usize,
+) -> Result<Bytes, DecodeError> {
+    let mut decompressed = Vec::new();
+    // one more byte tells whether the body is larger than allowed
+    reader
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .await?;
+    if decompressed.len() > max_size {
+        return Err(DecodeError::TooLarge(max_size));
+    }
+    Ok(decompressed.into())
+}
+
+/// Decode a body according to its `Content-Encoding` header, which can list several encodings
+/// in the order they were applied.
+///
+/// Every decoding step stops once `max_size` bytes are decompressed, so a small body cannot
+/// exhaust the memory.
+pub(crate) async fn decode(
+    content_encoding: Option<&str>,
+    mut body: Bytes,
+    max_size: usize,
+) -> Result<Bytes, DecodeError> {
+    let encodings = content_encoding.unwrap_or_default().split(',').rev();
+    for encoding in encodings
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty())
+    {
+        body = match encoding.to_ascii_lowercase().as_str() {
+            "identity" => body,
+            "gzip" | "x-gzip" => Compression::Gzip.decompress(&body, max_size).await?,
+            "br" => Compression::Br.decompress(&body, max_size).await?,
+            "deflate" => Compression::Deflate.decompress(&body, max_size).await?,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported content encoding '{}'", other),
+                )
+                .into())
+            }
+        };
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_decodes_compressed_bodies() {
+        let body = br#"{"data":{"me":{"name":"Ada Lovelace"}}}"#;
+        for compression in [Compression::Gzip, Compression::Br, Compression::Deflate] {
+            let compressed = compression.compress(body).await.unwrap();
+            assert_ne!(&compressed[..], &body[..]);
+            let decoded = decode(
+                Some(compression.as_str()),
+                compressed,
+                DEFAULT_MAX_DECOMPRESSED_SIZE,
+            )
+            .await
+            .unwrap();
+            assert_eq!(&decoded[..], &body[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_decodes_successive_encodings() {
+        let body = b"hello";
+        let compressed = Compression::Gzip.compress(body).await.unwrap();
+        let compressed = Compression::Br.compress(&compressed).await.unwrap();
+        let decoded = decode(Some("gzip, br"), compressed, DEFAULT_MAX_DECOMPRESSED_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+
+        let decoded = decode(
+            None,
+            Bytes::from_static(body),
+            DEFAULT_MAX_DECOMPRESSED_SIZE,
+        )
+        .await
+        .unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_unknown_encodings() {
+        let err = decode(
+            Some("compress"),
+            Bytes::from_static(b"hello"),
+            DEFAULT_MAX_DECOMPRESSED_SIZE,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, DecodeError::Invalid(err) if err.kind() == io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_limits_the_decompressed_size() {
+        let body = vec![0; 1000];
+        for compression in [Compression::Gzip, Compression::Br, Compression::Deflate] {
+            let compressed = compression.compress(&body).await.unwrap();
+            assert!(compressed.len() < 100);
+
+            let decoded = decode(Some(compression.as_str()), compressed.clone(), 1000)
+                .await
+                .unwrap();
+            assert_eq!(decoded.len(), 1000);
+            assert!(matches!(
+                decode(Some(compression.as_str()), compressed, 999).await,
+                Err(DecodeError::TooLarge(999))
+            ));
+        }
+
+        // every layer of successive encodings is limited
+        let compressed = Compression::Gzip.compress(&body).await.unwrap();
+        let compressed = Compression::Gzip.compress(&compressed).await.unwrap();
+        assert!(matches!(
+            decode(Some("gzip, gzip"), compressed, 999).await,
+            Err(DecodeError::TooLarge(999))
+        ));
+    }
+}
@@ -1,8 +1,12 @@
 //! Implements the Execution phase of the request lifecycle.
 
+use crate::{http_compat, Request};
 use crate::{ExecutionRequest, ExecutionResponse, SubgraphRequest, SubgraphResponse};
-use crate::{Schema, ServiceRegistry};
+use crate::{IncrementalResponses, Schema, ServiceRegistry};
+use futures::channel::mpsc;
 use futures::future::BoxFuture;
+use futures::StreamExt;
+use http::header::ACCEPT;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::task::Poll;
@@ -55,6 +59,60 @@ impl Service<ExecutionRequest> for ExecutionService {
         let this = self.clone();
         let fut = async move {
             let context = req.context;
+
+            let is_subscription = req.query_plan.is_subscription();
+            if is_subscription
+                || (req.query_plan.is_deferred() && accepts_multipart(&req.originating_request))
+            {
+                let (sender, mut receiver) = mpsc::channel(10);
+                let query_plan = req.query_plan.clone();
+                let execution_context = context.clone();
+                let originating_request = req.originating_request.clone();
+
+                // the deferred fragments and subscription events are still executing after the
+                // first response is returned, so the execution cannot borrow from this future
+                tokio::task::spawn(
+                    async move {
+                        if is_subscription {
+                            query_plan
+                                .subscribe(
+                                    &execution_context,
+                                    &this.subgraph_services,
+                                    originating_request,
+                                    &this.schema,
+                                    sender,
+                                )
+                                .await
+                        } else {
+                            query_plan
+                                .execute_incremental(
+                                    &execution_context,
+                                    &this.subgraph_services,
+                                    originating_request,
+                                    &this.schema,
+                                    sender,
+                                )
+                                .await
+                        }
+                    }
+                    .in_current_span(),
+                );
+
+                let primary = receiver
+                    .next()
+                    .await
+                    .ok_or_else(|| BoxError::from("query plan execution stopped"))?;
+                let mut response = http::Response::new(primary);
+                response
+                    .extensions_mut()
+                    .insert(IncrementalResponses::new(receiver.boxed()));
+
+                return Ok(ExecutionResponse::new_from_response(
+                    response.into(),
+                    context,
+                ));
+            }
+
             let response = req
                 .query_plan
                 .execute(
@@ -76,3 +134,17 @@ impl Service<ExecutionRequest> for ExecutionService {
         Box::pin(fut)
     }
 }
+
+/// Returns true if the client accepts incremental responses in a multipart body.
+pub(crate) fn accepts_multipart(request: &http_compat::Request<Request>) -> bool {
+    request.headers().get_all(ACCEPT).iter().any(|value| {
+        value
+            .to_str()
+            .map(|accept| {
+                accept
+                    .split(',')
+                    .any(|mime| mime.trim().starts_with("multipart/mixed"))
+            })
+            .unwrap_or_default()
+    })
+}
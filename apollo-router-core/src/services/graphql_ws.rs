@@ -0,0 +1,292 @@
+//! Messages of the `graphql-transport-ws` protocol, used to execute subscriptions over WebSocket.
+//!
+//! See: https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md
+
+use crate::prelude::graphql::*;
+use futures::stream::BoxStream;
+use futures::{SinkExt, StreamExt};
+use http::header::{HeaderName, HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+use http::uri::{Scheme, Uri};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+/// The WebSocket sub protocol implemented by the router.
+pub const GRAPHQL_TRANSPORT_WS_PROTOCOL: &str = "graphql-transport-ws";
+
+/// Close code: a message could not be parsed, or was not expected.
+pub const CLOSE_INVALID_MESSAGE: u16 = 4400;
+/// Close code: an operation was sent before the connection was acknowledged.
+pub const CLOSE_UNAUTHORIZED: u16 = 4401;
+/// Close code: the client did not request the `graphql-transport-ws` sub protocol.
+pub const CLOSE_SUBPROTOCOL_NOT_ACCEPTABLE: u16 = 4406;
+/// Close code: the connection was not initialised in time.
+pub const CLOSE_CONNECTION_INIT_TIMEOUT: u16 = 4408;
+/// Close code: an operation with the same id is already running.
+pub const CLOSE_SUBSCRIBER_ALREADY_EXISTS: u16 = 4409;
+/// Close code: the connection was initialised more than once.
+pub const CLOSE_TOO_MANY_INITIALISATION_REQUESTS: u16 = 4429;
+
+/// A message of the `graphql-transport-ws` protocol, in either direction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// Sent by the client to start the connection.
+    ConnectionInit {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        payload: Option<Value>,
+    },
+
+    /// Sent by the server to accept the connection.
+    ConnectionAck {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        payload: Option<Value>,
+    },
+
+    /// Can be sent by both sides at any time, must be answered with a pong.
+    Ping {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        payload: Option<Value>,
+    },
+
+    /// Answer to a ping, or unidirectional heartbeat.
+    Pong {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        payload: Option<Value>,
+    },
+
+    /// Sent by the client to execute an operation.
+    Subscribe { id: String, payload: Request },
+
+    /// Sent by the server for every result of an operation.
+    Next { id: String, payload: Response },
+
+    /// Sent by the server when an operation failed before execution, it ends the operation.
+    Error { id: String, payload: Vec<Error> },
+
+    /// Sent by the server when an operation is finished, or by the client to stop it.
+    Complete { id: String },
+}
+
+impl Message {
+    /// Serialize the message in a WebSocket text message.
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("protocol messages are serializable; qed")
+    }
+}
+
+type SubgraphSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The id of the operation in subgraph connections, there is only one per connection.
+const SUBGRAPH_OPERATION_ID: &str = "1";
+
+/// Start a subscription on a subgraph, and return the stream of its events.
+///
+/// A new WebSocket connection is opened for every subscription, and closed when the stream is
+/// dropped. Secure connections use `tls_config`, or the native roots if not set.
+pub(crate) async fn subscribe(
+    service_name: &str,
+    request: http_compat::Request<Request>,
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+) -> Result<BoxStream<'static, Response>, FetchError> {
+    let http_error = |reason: String| FetchError::SubrequestHttpError {
+        service: service_name.to_string(),
+        reason,
+    };
+
+    let (parts, body) = request.into_parts();
+    let mut ws_request = http::Request::builder()
+        .uri(websocket_uri(&parts.uri).map_err(|err| http_error(err.to_string()))?)
+        .body(())
+        .expect("the request only contains valid parts; qed");
+    // the handshake headers are generated by the WebSocket client
+    for (name, value) in parts.headers.iter() {
+        if !is_handshake_header(name) {
+            ws_request.headers_mut().append(name, value.clone());
+        }
+    }
+    ws_request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(GRAPHQL_TRANSPORT_WS_PROTOCOL),
+    );
+
+    let (mut socket, _) = tokio_tungstenite::connect_async_tls_with_config(
+        ws_request,
+        None,
+        tls_config.map(Connector::Rustls),
+    )
+    .await
+    .map_err(|err| http_error(err.to_string()))?;
+
+    send(&mut socket, Message::ConnectionInit { payload: None })
+        .await
+        .map_err(http_error)?;
+    loop {
+        match receive(&mut socket).await {
+            Some(Message::ConnectionAck { .. }) => break,
+            Some(Message::Ping { .. }) => send(&mut socket, Message::Pong { payload: None })
+                .await
+                .map_err(http_error)?,
+            Some(message) => {
+                return Err(FetchError::SubrequestMalformedResponse {
+                    service: service_name.to_string(),
+                    reason: format!("unexpected message before connection_ack: {:?}", message),
+                })
+            }
+            None => return Err(http_error("connection closed before connection_ack".into())),
+        }
+    }
+
+    send(
+        &mut socket,
+        Message::Subscribe {
+            id: SUBGRAPH_OPERATION_ID.to_string(),
+            payload: body,
+        },
+    )
+    .await
+    .map_err(http_error)?;
+
+    Ok(futures::stream::unfold(Some(socket), |socket| async move {
+        let mut socket = socket?;
+        loop {
+            match receive(&mut socket).await? {
+                Message::Next { payload, .. } => return Some((payload, Some(socket))),
+                // the error message ends the operation
+                Message::Error { payload, .. } => {
+                    return Some((Response::builder().errors(payload).build(), None))
+                }
+                Message::Complete { .. } => return None,
+                Message::Ping { .. } => {
+                    send(&mut socket, Message::Pong { payload: None })
+                        .await
+                        .ok()?;
+                }
+                _ => {}
+            }
+        }
+    })
+    .boxed())
+}
+
+async fn send(socket: &mut SubgraphSocket, message: Message) -> Result<(), String> {
+    socket
+        .send(WsMessage::Text(message.to_text()))
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Receive the next protocol message, or `None` if the connection is closed.
+async fn receive(socket: &mut SubgraphSocket) -> Option<Message> {
+    loop {
+        match socket.next().await? {
+            Ok(WsMessage::Text(text)) => match serde_json::from_str(&text) {
+                Ok(message) => return Some(message),
+                Err(err) => {
+                    tracing::error!("invalid message received from subgraph: {}", err);
+                    return None;
+                }
+            },
+            Ok(WsMessage::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn websocket_uri(uri: &Uri) -> Result<Uri, http::Error> {
+    let scheme = if uri.scheme() == Some(&Scheme::HTTPS) {
+        "wss"
+    } else {
+        "ws"
+    };
+    let mut builder = Uri::builder().scheme(scheme);
+    if let Some(authority) = uri.authority() {
+        builder = builder.authority(authority.clone());
+    }
+    if let Some(path_and_query) = uri.path_and_query() {
+        builder = builder.path_and_query(path_and_query.clone());
+    }
+    builder.build()
+}
+
+fn is_handshake_header(name: &HeaderName) -> bool {
+    matches!(
+        name.as_str(),
+        "host"
+            | "connection"
+            | "upgrade"
+            | "content-length"
+            | "content-type"
+            | "accept"
+            | "sec-websocket-key"
+            | "sec-websocket-version"
+            | "sec-websocket-protocol"
+            | "sec-websocket-extensions"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_serialization() {
+        let message: Message = serde_json::from_value(json!({
+            "type": "subscribe",
+            "id": "1",
+            "payload": {
+                "query": "subscription { reviewAdded { id } }"
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            message,
+            Message::Subscribe {
+                id: "1".to_string(),
+                payload: Request::builder()
+                    .query(Some("subscription { reviewAdded { id } }".to_string()))
+                    .build()
+            }
+        );
+
+        assert_eq!(
+            serde_json::from_str::<Message>(r#"{"type":"connection_init"}"#).unwrap(),
+            Message::ConnectionInit { payload: None }
+        );
+        assert_eq!(
+            Message::ConnectionAck { payload: None }.to_text(),
+            r#"{"type":"connection_ack"}"#
+        );
+        assert_eq!(
+            Message::Next {
+                id: "1".to_string(),
+                payload: Response::builder()
+                    .data(serde_json_bytes::json!({"reviewAdded": {"id": "2"}}))
+                    .build()
+            }
+            .to_text(),
+            r#"{"type":"next","id":"1","payload":{"data":{"reviewAdded":{"id":"2"}}}}"#
+        );
+        assert_eq!(
+            Message::Complete {
+                id: "1".to_string()
+            }
+            .to_text(),
+            r#"{"type":"complete","id":"1"}"#
+        );
+    }
+
+    #[test]
+    fn subgraph_websocket_uri() {
+        assert_eq!(
+            websocket_uri(&Uri::from_static("http://products:4001/graphql?a=b")).unwrap(),
+            Uri::from_static("ws://products:4001/graphql?a=b")
+        );
+        assert_eq!(
+            websocket_uri(&Uri::from_static("https://products/graphql")).unwrap(),
+            Uri::from_static("wss://products/graphql")
+        );
+    }
+}
@@ -2,8 +2,12 @@
 //!
 //! To improve their usability.
 
-use axum::{body::boxed, response::IntoResponse};
+use axum::{
+    body::{boxed, StreamBody},
+    response::IntoResponse,
+};
 use bytes::Bytes;
+use futures::{future, stream, StreamExt};
 use http::{
     header::{self, HeaderName},
     request::Parts,
@@ -13,11 +17,15 @@ use http::{
 use multimap::MultiMap;
 use std::{
     cmp::PartialEq,
+    convert::Infallible,
     hash::Hash,
     ops::{Deref, DerefMut},
 };
 
-use crate::ResponseBody;
+use crate::{IncrementalResponses, ResponseBody};
+
+/// Content type of the responses containing deferred fragments.
+const MULTIPART_DEFER_CONTENT_TYPE: &str = "multipart/mixed;boundary=\"-\";deferSpec=20220824";
 
 /// Temporary holder of header name while for use while building requests and responses. Required
 /// because header name creation is faillable.
@@ -318,8 +326,38 @@ impl<T: Clone> Clone for Response<T> {
 
 impl IntoResponse for Response<ResponseBody> {
     fn into_response(self) -> axum::response::Response {
-        // todo: chunks?
         let (mut parts, body) = self.into_parts();
+
+        let incremental = parts
+            .extensions
+            .remove::<IncrementalResponses>()
+            .and_then(|responses| responses.take());
+        let body = match (body, incremental) {
+            (ResponseBody::GraphQL(primary), Some(incremental)) => {
+                parts.headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(MULTIPART_DEFER_CONTENT_TYPE),
+                );
+
+                let body = stream::once(future::ready(primary))
+                    .chain(incremental)
+                    .map(|response| {
+                        let mut part =
+                            b"\r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n"
+                                .to_vec();
+                        serde_json::to_writer(&mut part, &response)
+                            .expect("response should be serializable; qed");
+                        Ok::<_, Infallible>(Bytes::from(part))
+                    })
+                    .chain(stream::once(future::ready(Ok(Bytes::from_static(
+                        b"\r\n-----\r\n",
+                    )))));
+
+                return axum::response::Response::from_parts(parts, boxed(StreamBody::new(body)));
+            }
+            (body, _) => body,
+        };
+
         let json_body_bytes =
             Bytes::from(serde_json::to_vec(&body).expect("body should be serializable; qed"));
         parts.headers.insert(
@@ -342,7 +380,10 @@ impl IntoResponse for Response<Bytes> {
 
 #[cfg(test)]
 mod test {
-    use crate::http_compat::Request;
+    use crate::http_compat::{Request, Response};
+    use crate::{IncrementalResponses, ResponseBody};
+    use axum::response::IntoResponse;
+    use futures::{stream, StreamExt};
     use http::{HeaderValue, Method, Uri};
 
     #[test]
@@ -367,4 +408,38 @@ mod test {
         assert_eq!(request.method(), Method::POST);
         assert_eq!(request.body(), &"test");
     }
+
+    #[tokio::test]
+    async fn incremental_responses_are_sent_as_multipart() {
+        let primary = crate::Response::builder()
+            .data(serde_json_bytes::json!({"me": {"id": "1"}}))
+            .has_next(true)
+            .build();
+        let patch = crate::Response::builder()
+            .label(Some("name".to_string()))
+            .path(crate::Path::from("me"))
+            .data(serde_json_bytes::json!({"name": "Ada"}))
+            .has_next(false)
+            .build();
+
+        let mut response = http::Response::new(ResponseBody::GraphQL(primary));
+        response
+            .extensions_mut()
+            .insert(IncrementalResponses::new(stream::iter(vec![patch]).boxed()));
+        let response = Response::from(response).into_response();
+
+        assert_eq!(
+            response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "multipart/mixed;boundary=\"-\";deferSpec=20220824"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            "\r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n\
+            {\"data\":{\"me\":{\"id\":\"1\"}},\"hasNext\":true}\
+            \r\n---\r\ncontent-type: application/json; charset=utf-8\r\n\r\n\
+            {\"label\":\"name\",\"data\":{\"name\":\"Ada\"},\"path\":[\"me\"],\"hasNext\":false}\
+            \r\n-----\r\n"
+        );
+    }
 }
@@ -4,15 +4,29 @@
 //!  <https://www.apollographql.com/docs/apollo-server/performance/apq/>
 
 use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::sync_checkpoint::CheckpointService;
+use crate::layers::async_checkpoint::AsyncCheckpointService;
 use crate::{RouterRequest, RouterResponse};
+use futures::FutureExt;
 use moka::sync::Cache;
 use serde::Deserialize;
 use serde_json_bytes::{json, Value};
 use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 use tower::{BoxError, Layer, Service};
 
+/// Default number of queries kept by the in memory storage.
+pub const DEFAULT_APQ_MAX_ENTRIES: u64 = 512;
+
+const REDIS_KEY_PREFIX: &str = "apq:";
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+/// Delay before the first reconnection attempt, doubled after every failed attempt.
+const REDIS_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const REDIS_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
 /// A persisted query.
 #[derive(Deserialize, Clone, Debug)]
 pub struct PersistedQuery {
@@ -21,102 +35,346 @@ pub struct PersistedQuery {
     pub sha256hash: String,
 }
 
+/// Storage of the registered queries, by the hex encoded sha256 hash of the query.
+///
+/// Storage failures are not request failures: a query missing from the storage is registered
+/// again by the client.
+#[async_trait::async_trait]
+pub trait ApqStorage: Send + Sync {
+    /// Get a registered query.
+    async fn get(&self, hash: &str) -> Option<String>;
+
+    /// Register a query.
+    async fn insert(&self, hash: String, query: String);
+}
+
+/// Queries stored in the memory of the router.
+#[derive(Clone)]
+pub struct InMemoryApqStorage {
+    cache: Cache<String, String>,
+}
+
+impl InMemoryApqStorage {
+    /// Storage of at most `max_entries` queries, expiring `ttl` after their registration.
+    pub fn new(max_entries: u64, ttl: Option<Duration>) -> Self {
+        let mut builder = Cache::builder().max_capacity(max_entries);
+        if let Some(ttl) = ttl {
+            builder = builder.time_to_live(ttl);
+        }
+        Self {
+            cache: builder.build(),
+        }
+    }
+
+    /// Storage of at most `max_bytes` bytes of queries, expiring `ttl` after their registration.
+    pub fn with_max_bytes(max_bytes: u64, ttl: Option<Duration>) -> Self {
+        let mut builder =
+            Cache::builder()
+                .max_capacity(max_bytes)
+                .weigher(|hash: &String, query: &String| {
+                    u32::try_from(hash.len() + query.len()).unwrap_or(u32::MAX)
+                });
+        if let Some(ttl) = ttl {
+            builder = builder.time_to_live(ttl);
+        }
+        Self {
+            cache: builder.build(),
+        }
+    }
+}
+
+impl Default for InMemoryApqStorage {
+    fn default() -> Self {
+        Self::new(DEFAULT_APQ_MAX_ENTRIES, None)
+    }
+}
+
+#[async_trait::async_trait]
+impl ApqStorage for InMemoryApqStorage {
+    async fn get(&self, hash: &str) -> Option<String> {
+        self.cache.get(&hash.to_string())
+    }
+
+    async fn insert(&self, hash: String, query: String) {
+        self.cache.insert(hash, query);
+    }
+}
+
+/// Queries shared by several routers through Redis, with a local in memory storage in front of
+/// it.
+///
+/// When Redis cannot be reached, the router keeps working with its local storage only, and
+/// tries to reconnect with an exponential backoff.
+pub struct RedisApqStorage {
+    redis: Arc<RedisClient>,
+    ttl: Option<Duration>,
+    local: InMemoryApqStorage,
+}
+
+/// The Redis client, shared with the background registrations.
+struct RedisClient {
+    client: redis::Client,
+    connection: Mutex<RedisConnection>,
+}
+
+/// The shared connection to Redis, or when to try connecting again.
+#[derive(Default)]
+struct RedisConnection {
+    connection: Option<redis::aio::MultiplexedConnection>,
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl RedisConnection {
+    /// Drop the connection, and wait for an exponentially growing delay before reconnecting.
+    fn failed(&mut self) {
+        self.connection = None;
+        self.backoff = (self.backoff * 2).clamp(REDIS_MIN_BACKOFF, REDIS_MAX_BACKOFF);
+        self.retry_at = Some(Instant::now() + self.backoff);
+    }
+}
+
+impl RedisApqStorage {
+    /// The connection to `url` is established on first use.
+    pub fn new(
+        url: &str,
+        ttl: Option<Duration>,
+        local: InMemoryApqStorage,
+    ) -> Result<Self, BoxError> {
+        Ok(Self {
+            redis: Arc::new(RedisClient {
+                client: redis::Client::open(url)?,
+                connection: Mutex::new(RedisConnection::default()),
+            }),
+            ttl,
+            local,
+        })
+    }
+}
+
+impl RedisClient {
+    /// The multiplexed connection, which is cloned so that the lock is not held during the
+    /// commands. It is only held while connecting, so that a single connection is opened.
+    async fn connection(&self) -> Option<redis::aio::MultiplexedConnection> {
+        let mut state = self.connection.lock().await;
+        if let Some(connection) = &state.connection {
+            return Some(connection.clone());
+        }
+        if matches!(state.retry_at, Some(retry_at) if Instant::now() < retry_at) {
+            return None;
+        }
+
+        let connection = tokio::time::timeout(
+            REDIS_TIMEOUT,
+            self.client.get_multiplexed_tokio_connection(),
+        )
+        .await
+        .map_err(BoxError::from)
+        .and_then(|result| result.map_err(BoxError::from));
+        match connection {
+            Ok(connection) => {
+                *state = RedisConnection {
+                    connection: Some(connection.clone()),
+                    ..Default::default()
+                };
+                Some(connection)
+            }
+            Err(err) => {
+                state.failed();
+                tracing::warn!(
+                    "apq: cannot connect to redis, retrying in {:?}: {}",
+                    state.backoff,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    async fn query<T: redis::FromRedisValue + Send>(&self, cmd: redis::Cmd) -> Option<T> {
+        let mut connection = self.connection().await?;
+        let result = tokio::time::timeout(REDIS_TIMEOUT, cmd.query_async(&mut connection))
+            .await
+            .map_err(BoxError::from)
+            .and_then(|result: redis::RedisResult<T>| result.map_err(BoxError::from));
+
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                // an unresponsive server is not queried again before the backoff delay
+                let mut state = self.connection.lock().await;
+                state.failed();
+                tracing::warn!(
+                    "apq: redis storage error, reconnecting in {:?}: {}",
+                    state.backoff,
+                    err
+                );
+                None
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ApqStorage for RedisApqStorage {
+    async fn get(&self, hash: &str) -> Option<String> {
+        if let Some(query) = self.local.get(hash).await {
+            return Some(query);
+        }
+
+        let mut cmd = redis::cmd("GET");
+        cmd.arg(format!("{}{}", REDIS_KEY_PREFIX, hash));
+        let query: String = self.redis.query::<Option<String>>(cmd).await.flatten()?;
+        self.local.insert(hash.to_string(), query.clone()).await;
+        Some(query)
+    }
+
+    async fn insert(&self, hash: String, query: String) {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{}", REDIS_KEY_PREFIX, hash)).arg(&query);
+        if let Some(ttl) = self.ttl {
+            cmd.arg("EX").arg(ttl.as_secs().max(1));
+        }
+        self.local.insert(hash, query).await;
+
+        // the query is already available locally, the request does not wait for Redis
+        let redis = self.redis.clone();
+        tokio::spawn(async move {
+            let _: Option<()> = redis.query(cmd).await;
+        });
+    }
+}
+
 /// [`Layer`] for APQ implementation.
+///
+/// Without storage, APQ is disabled and requests only sending the hash of a query are answered
+/// with a `PERSISTED_QUERY_NOT_SUPPORTED` error.
 #[derive(Clone)]
 pub struct APQLayer {
-    cache: Cache<Vec<u8>, String>,
+    storage: Option<Arc<dyn ApqStorage>>,
 }
 
 impl APQLayer {
-    pub fn with_cache(cache: Cache<Vec<u8>, String>) -> Self {
-        Self { cache }
+    pub fn new(storage: Option<Arc<dyn ApqStorage>>) -> Self {
+        Self { storage }
+    }
+
+    pub fn with_storage(storage: Arc<dyn ApqStorage>) -> Self {
+        Self::new(Some(storage))
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(None)
     }
 }
 
 impl Default for APQLayer {
     fn default() -> Self {
-        Self::with_cache(Cache::new(512))
+        Self::with_storage(Arc::new(InMemoryApqStorage::default()))
     }
 }
 
 impl<S> Layer<S> for APQLayer
 where
-    S: Service<RouterRequest, Response = RouterResponse> + Send + 'static,
+    S: Service<RouterRequest, Response = RouterResponse, Error = BoxError> + Clone + Send + 'static,
     <S as Service<RouterRequest>>::Future: Send + 'static,
-    <S as Service<RouterRequest>>::Error: Into<BoxError> + Send + 'static,
 {
-    type Service = CheckpointService<S, RouterRequest>;
+    type Service = AsyncCheckpointService<S, RouterRequest>;
 
     fn layer(&self, service: S) -> Self::Service {
-        let cache = self.cache.clone();
-        CheckpointService::new(
-            move |mut req| {
-                let maybe_query_hash: Option<Vec<u8>> = req
-                    .originating_request
-                    .body()
-                    .extensions
-                    .get("persistedQuery")
-                    .and_then(|value| {
-                        serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok()
-                    })
-                    .and_then(|persisted_query| {
-                        hex::decode(persisted_query.sha256hash.as_bytes()).ok()
-                    });
-
-                let body_query = req.originating_request.body().query.clone();
-
-                match (maybe_query_hash, body_query) {
-                    (Some(query_hash), Some(query)) => {
-                        if query_matches_hash(query.as_str(), query_hash.as_slice()) {
-                            tracing::trace!("apq: cache insert");
-                            let _ = req.context.insert("persisted_query_hit", false);
-                            cache.insert(query_hash, query);
-                        } else {
-                            tracing::warn!(
-                                "apq: graphql request doesn't match provided sha256Hash"
-                            );
-                        }
-                        Ok(ControlFlow::Continue(req))
-                    }
-                    (Some(apq_hash), _) => {
-                        if let Some(cached_query) = cache.get(&apq_hash) {
-                            let _ = req.context.insert("persisted_query_hit", true);
-                            tracing::trace!("apq: cache hit");
-                            req.originating_request.body_mut().query = Some(cached_query);
-                            Ok(ControlFlow::Continue(req))
-                        } else {
-                            tracing::trace!("apq: cache miss");
-                            let errors = vec![crate::Error {
-                                message: "PersistedQueryNotFound".to_string(),
-                                locations: Default::default(),
-                                path: Default::default(),
-                                extensions: serde_json_bytes::from_value(json!({
-                                      "code": "PERSISTED_QUERY_NOT_FOUND",
-                                      "exception": {
-                                      "stacktrace": [
-                                          "PersistedQueryNotFoundError: PersistedQueryNotFound",
-                                      ],
-                                  },
-                                }))
-                                .unwrap(),
-                            }];
-                            let res = RouterResponse::builder()
-                                .data(Value::default())
-                                .errors(errors)
-                                .context(req.context)
-                                .build()
-                                .expect("response is valid");
-
-                            Ok(ControlFlow::Break(res))
-                        }
-                    }
-                    _ => Ok(ControlFlow::Continue(req)),
-                }
+        let storage = self.storage.clone();
+        AsyncCheckpointService::new(
+            move |req| {
+                let storage = storage.clone();
+                async move { apq_request(storage.as_deref(), req).await }.boxed()
             },
             service,
         )
     }
 }
+
+async fn apq_request(
+    storage: Option<&dyn ApqStorage>,
+    mut req: RouterRequest,
+) -> Result<ControlFlow<RouterResponse, RouterRequest>, BoxError> {
+    let maybe_query_hash: Option<Vec<u8>> = req
+        .originating_request
+        .body()
+        .extensions
+        .get("persistedQuery")
+        .and_then(|value| serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok())
+        .and_then(|persisted_query| hex::decode(persisted_query.sha256hash.as_bytes()).ok());
+
+    let body_query = req.originating_request.body().query.clone();
+
+    let storage = match (storage, &maybe_query_hash, &body_query) {
+        (Some(storage), _, _) => storage,
+        (None, Some(_), None) => {
+            tracing::trace!("apq: disabled");
+            return apq_error(
+                req,
+                "PersistedQueryNotSupported",
+                "PERSISTED_QUERY_NOT_SUPPORTED",
+            );
+        }
+        (None, _, _) => return Ok(ControlFlow::Continue(req)),
+    };
+
+    match (maybe_query_hash, body_query) {
+        (Some(query_hash), Some(query)) => {
+            if query_matches_hash(query.as_str(), query_hash.as_slice()) {
+                tracing::trace!("apq: cache insert");
+                let _ = req.context.insert("persisted_query_hit", false);
+                storage.insert(hex::encode(query_hash), query).await;
+            } else {
+                tracing::warn!("apq: graphql request doesn't match provided sha256Hash");
+            }
+            Ok(ControlFlow::Continue(req))
+        }
+        (Some(apq_hash), _) => {
+            if let Some(cached_query) = storage.get(&hex::encode(apq_hash)).await {
+                let _ = req.context.insert("persisted_query_hit", true);
+                tracing::trace!("apq: cache hit");
+                req.originating_request.body_mut().query = Some(cached_query);
+                Ok(ControlFlow::Continue(req))
+            } else {
+                tracing::trace!("apq: cache miss");
+                apq_error(req, "PersistedQueryNotFound", "PERSISTED_QUERY_NOT_FOUND")
+            }
+        }
+        _ => Ok(ControlFlow::Continue(req)),
+    }
+}
+
+fn apq_error(
+    req: RouterRequest,
+    message: &str,
+    code: &str,
+) -> Result<ControlFlow<RouterResponse, RouterRequest>, BoxError> {
+    let errors = vec![crate::Error {
+        message: message.to_string(),
+        locations: Default::default(),
+        path: Default::default(),
+        extensions: serde_json_bytes::from_value(json!({
+              "code": code,
+              "exception": {
+              "stacktrace": [
+                  format!("{}Error: {}", message, message),
+              ],
+          },
+        }))
+        .unwrap(),
+    }];
+    let res = RouterResponse::builder()
+        .data(Value::default())
+        .errors(errors)
+        .context(req.context)
+        .build()
+        .expect("response is valid");
+
+    Ok(ControlFlow::Break(res))
+}
+
 fn query_matches_hash(query: &str, hash: &[u8]) -> bool {
     let mut digest = Sha256::new();
     digest.update(query.as_bytes());
@@ -343,6 +601,121 @@ mod apq_tests {
         assert_error_matches(&expected_apq_miss_error, second_apq_error);
     }
 
+    #[tokio::test]
+    async fn it_answers_hash_only_requests_when_disabled() {
+        let mut mock_service = MockRouterService::new();
+        // requests with a query are still executed
+        mock_service.expect_call().times(1).returning(move |_| {
+            Ok(RouterResponse::fake_builder()
+                .build()
+                .expect("expecting valid request"))
+        });
+
+        let mut service_stack = APQLayer::disabled().layer(mock_service.build());
+
+        let extensions = HashMap::from([(
+            "persistedQuery".to_string(),
+            json!({
+                "version" : 1,
+                "sha256Hash" : "ecf4edb46db40b5132295c0291d62fb65d6759a9eedfa4d5d612dd5ec54a6b38"
+            }),
+        )]);
+
+        let with_query = RouterRequest::fake_builder()
+            .extensions(extensions.clone())
+            .query("{__typename}".to_string())
+            .build()
+            .expect("expecting valid request");
+        let hash_only = RouterRequest::fake_builder()
+            .extensions(extensions)
+            .build()
+            .expect("expecting valid request");
+
+        let services = service_stack.ready().await.unwrap();
+        services.call(with_query).await.unwrap();
+
+        let services = services.ready().await.unwrap();
+        let apq_error = services.call(hash_only).await.unwrap();
+
+        assert_error_matches(
+            &crate::Error {
+                message: "PersistedQueryNotSupported".to_string(),
+                locations: Default::default(),
+                path: Default::default(),
+                extensions: serde_json_bytes::from_value(json!({
+                      "code": "PERSISTED_QUERY_NOT_SUPPORTED",
+                      "exception": {
+                      "stacktrace": [
+                          "PersistedQueryNotSupportedError: PersistedQueryNotSupported",
+                      ],
+                  },
+                }))
+                .unwrap(),
+            },
+            apq_error,
+        );
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_the_local_storage() {
+        // nothing listens on this port
+        let storage =
+            RedisApqStorage::new("redis://127.0.0.1:1", None, InMemoryApqStorage::default())
+                .unwrap();
+
+        storage
+            .insert("hash".to_string(), "{__typename}".to_string())
+            .await;
+        assert_eq!(storage.get("hash").await.as_deref(), Some("{__typename}"));
+        assert_eq!(storage.get("unknown").await, None);
+    }
+
+    #[tokio::test]
+    async fn it_backs_off_between_reconnections() {
+        let storage =
+            RedisApqStorage::new("redis://127.0.0.1:1", None, InMemoryApqStorage::default())
+                .unwrap();
+
+        assert!(storage.redis.connection().await.is_none());
+        assert_eq!(
+            storage.redis.connection.lock().await.backoff,
+            REDIS_MIN_BACKOFF
+        );
+
+        // no new attempt before the retry time
+        assert!(storage.redis.connection().await.is_none());
+        assert_eq!(
+            storage.redis.connection.lock().await.backoff,
+            REDIS_MIN_BACKOFF
+        );
+
+        storage.redis.connection.lock().await.retry_at = Some(Instant::now());
+        assert!(storage.redis.connection().await.is_none());
+        assert_eq!(
+            storage.redis.connection.lock().await.backoff,
+            REDIS_MIN_BACKOFF * 2
+        );
+    }
+
+    #[tokio::test]
+    async fn it_backs_off_after_a_failed_command() {
+        // accepts the connections and closes them without answering the commands
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                drop(socket);
+            }
+        });
+        let storage = RedisApqStorage::new(&url, None, InMemoryApqStorage::default()).unwrap();
+
+        assert_eq!(storage.get("hash").await, None);
+        let state = storage.redis.connection.lock().await;
+        assert!(state.connection.is_none());
+        assert_eq!(state.backoff, REDIS_MIN_BACKOFF);
+        assert!(matches!(state.retry_at, Some(retry_at) if Instant::now() < retry_at));
+    }
+
     fn assert_error_matches(expected_error: &crate::Error, res: crate::RouterResponse) {
         if let ResponseBody::GraphQL(graphql_response) = res.response.body() {
             assert_eq!(&graphql_response.errors[0], expected_error);
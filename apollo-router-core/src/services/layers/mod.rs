@@ -2,3 +2,4 @@
 pub(crate) mod allow_only_http_post_mutations;
 pub(crate) mod apq;
 pub(crate) mod ensure_query_presence;
+pub(crate) mod safelist;
@@ -0,0 +1,345 @@
+//! Safelisting of the operations with a persisted query manifest.
+//!
+//! Requests sending only the id of a persisted query get the query of the manifest, and free-form
+//! queries missing from the manifest are rejected, or only logged in log-only mode.
+
+use super::apq::PersistedQuery;
+use crate::sync_checkpoint::CheckpointService;
+use crate::{RouterRequest, RouterResponse};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json_bytes::Value;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::sync::{Arc, RwLock};
+use tower::{BoxError, Layer, Service};
+
+const PERSISTED_QUERY_NOT_IN_LIST: &str = "PERSISTED_QUERY_NOT_IN_LIST";
+const QUERY_NOT_IN_SAFELIST: &str = "QUERY_NOT_IN_SAFELIST";
+
+/// The operations approved for execution.
+#[derive(Debug, Default)]
+pub struct PersistedQueryManifest {
+    operations: HashMap<String, String>,
+    bodies: HashSet<String>,
+}
+
+/// A manifest file, in the Apollo persisted query manifest format.
+#[derive(Deserialize)]
+struct ManifestFile {
+    operations: Vec<ManifestOperation>,
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: String,
+    body: String,
+}
+
+impl PersistedQueryManifest {
+    /// Parse a manifest in the Apollo persisted query manifest format, listing its operations
+    /// with their `id` and `body`.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let file: ManifestFile = serde_json::from_str(json)?;
+        Ok(file
+            .operations
+            .into_iter()
+            .map(|operation| (operation.id, operation.body))
+            .collect())
+    }
+
+    /// Number of operations in the manifest.
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    fn query(&self, id: &str) -> Option<&String> {
+        self.operations.get(id)
+    }
+
+    fn contains_query(&self, query: &str) -> bool {
+        self.bodies.contains(query)
+    }
+}
+
+impl FromIterator<(String, String)> for PersistedQueryManifest {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let operations: HashMap<String, String> = iter.into_iter().collect();
+        let bodies = operations.values().cloned().collect();
+        Self { operations, bodies }
+    }
+}
+
+/// A persisted query manifest shared by the routers, updated in place when the manifest changes.
+#[derive(Clone, Debug)]
+pub struct Safelist {
+    manifest: Arc<RwLock<Arc<PersistedQueryManifest>>>,
+    log_only: bool,
+}
+
+impl Safelist {
+    pub fn new(manifest: PersistedQueryManifest) -> Self {
+        Self {
+            manifest: Arc::new(RwLock::new(Arc::new(manifest))),
+            log_only: false,
+        }
+    }
+
+    /// The same safelist, only logging the operations missing from the manifest instead of
+    /// rejecting them.
+    pub fn with_log_only(&self, log_only: bool) -> Self {
+        Self {
+            manifest: self.manifest.clone(),
+            log_only,
+        }
+    }
+
+    /// Replace the manifest, for all the clones of this safelist.
+    pub fn update(&self, manifest: PersistedQueryManifest) {
+        *self.manifest.write().expect("poisoned lock") = Arc::new(manifest);
+    }
+
+    fn manifest(&self) -> Arc<PersistedQueryManifest> {
+        self.manifest.read().expect("poisoned lock").clone()
+    }
+}
+
+/// [`Layer`] enforcing the safelist, if any.
+#[derive(Clone, Default)]
+pub(crate) struct SafelistLayer {
+    safelist: Option<Safelist>,
+}
+
+impl SafelistLayer {
+    pub(crate) fn new(safelist: Option<Safelist>) -> Self {
+        Self { safelist }
+    }
+}
+
+impl<S> Layer<S> for SafelistLayer
+where
+    S: Service<RouterRequest, Response = RouterResponse> + Send + 'static,
+    <S as Service<RouterRequest>>::Future: Send + 'static,
+    <S as Service<RouterRequest>>::Error: Into<BoxError> + Send + 'static,
+{
+    type Service = CheckpointService<S, RouterRequest>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        let safelist = self.safelist.clone();
+        CheckpointService::new(
+            move |mut req: RouterRequest| {
+                let safelist = match &safelist {
+                    Some(safelist) => safelist,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+                let manifest = safelist.manifest();
+
+                let body = req.originating_request.body();
+                let id = body
+                    .extensions
+                    .get("persistedQuery")
+                    .and_then(|value| {
+                        serde_json_bytes::from_value::<PersistedQuery>(value.clone()).ok()
+                    })
+                    .map(|persisted_query| persisted_query.sha256hash);
+
+                match (body.query.as_deref(), id) {
+                    (None, Some(id)) => match manifest.query(&id) {
+                        Some(query) => {
+                            let body = req.originating_request.body_mut();
+                            body.query = Some(query.clone());
+                            // the manifest id is not necessarily the hash of the query
+                            body.extensions.remove("persistedQuery");
+                            Ok(ControlFlow::Continue(req))
+                        }
+                        None if safelist.log_only => {
+                            tracing::warn!("persisted query {} is not in the safelist", id);
+                            Ok(ControlFlow::Continue(req))
+                        }
+                        None => reject(
+                            req,
+                            "Persisted query not found in the safelist",
+                            PERSISTED_QUERY_NOT_IN_LIST,
+                        ),
+                    },
+                    (Some(query), _) if !manifest.contains_query(query) => {
+                        if safelist.log_only {
+                            tracing::warn!(
+                                operation_name = ?body.operation_name,
+                                "query is not in the safelist"
+                            );
+                            Ok(ControlFlow::Continue(req))
+                        } else {
+                            reject(req, "Query not in the safelist", QUERY_NOT_IN_SAFELIST)
+                        }
+                    }
+                    _ => Ok(ControlFlow::Continue(req)),
+                }
+            },
+            service,
+        )
+    }
+}
+
+fn reject(
+    req: RouterRequest,
+    message: &str,
+    code: &str,
+) -> Result<ControlFlow<RouterResponse, RouterRequest>, BoxError> {
+    let mut extensions = crate::Object::new();
+    extensions.insert("code", Value::String(code.into()));
+    let res = RouterResponse::builder()
+        .error(
+            crate::Error::builder()
+                .message(message.to_string())
+                .extensions(extensions)
+                .build(),
+        )
+        .status_code(StatusCode::BAD_REQUEST)
+        .context(req.context)
+        .build()?;
+    Ok(ControlFlow::Break(res))
+}
+
+#[cfg(test)]
+mod safelist_tests {
+    use super::*;
+    use crate::plugin::utils::test::MockRouterService;
+    use crate::ResponseBody;
+    use serde_json_bytes::json;
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    const MANIFEST: &str = r#"{
+        "format": "apollo-persisted-query-manifest",
+        "version": 1,
+        "operations": [
+            { "id": "me", "name": "Me", "type": "query", "body": "query Me { me { id } }" }
+        ]
+    }"#;
+
+    fn request(query: Option<&str>, id: Option<&str>) -> RouterRequest {
+        let extensions = id
+            .map(|id| {
+                HashMap::from([(
+                    "persistedQuery".to_string(),
+                    json!({ "version": 1, "sha256Hash": id }),
+                )])
+            })
+            .unwrap_or_default();
+        RouterRequest::fake_builder()
+            .and_query(query.map(str::to_string))
+            .extensions(extensions)
+            .build()
+            .expect("expecting valid request")
+    }
+
+    fn error_code(response: RouterResponse) -> Option<Value> {
+        match response.response.body() {
+            ResponseBody::GraphQL(response) => response.errors[0].extensions.get("code").cloned(),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_enforces_the_safelist() {
+        let mut mock_service = MockRouterService::new();
+        mock_service.expect_call().times(2).returning(move |req| {
+            let body = req.originating_request.body();
+            assert_eq!(body.query.as_deref(), Some("query Me { me { id } }"));
+            assert!(body.extensions.get("persistedQuery").is_none());
+            Ok(RouterResponse::fake_builder()
+                .build()
+                .expect("expecting valid request"))
+        });
+        let safelist = Safelist::new(PersistedQueryManifest::from_json(MANIFEST).unwrap());
+        let mut service = SafelistLayer::new(Some(safelist.clone())).layer(mock_service.build());
+
+        // resolved from the id
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(None, Some("me")))
+            .await
+            .unwrap();
+        // free-form query of the manifest
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(Some("query Me { me { id } }"), None))
+            .await
+            .unwrap();
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(Some("{ me { name } }"), None))
+            .await
+            .unwrap();
+        assert_eq!(
+            error_code(response),
+            Some(Value::String(QUERY_NOT_IN_SAFELIST.into()))
+        );
+
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(None, Some("unknown")))
+            .await
+            .unwrap();
+        assert_eq!(
+            error_code(response),
+            Some(Value::String(PERSISTED_QUERY_NOT_IN_LIST.into()))
+        );
+
+        // updates are shared by the clones
+        safelist.update(PersistedQueryManifest::default());
+        let response = service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(None, Some("me")))
+            .await
+            .unwrap();
+        assert_eq!(
+            error_code(response),
+            Some(Value::String(PERSISTED_QUERY_NOT_IN_LIST.into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_only_logs_in_log_only_mode() {
+        let mut mock_service = MockRouterService::new();
+        mock_service.expect_call().times(2).returning(move |_| {
+            Ok(RouterResponse::fake_builder()
+                .build()
+                .expect("expecting valid request"))
+        });
+        let safelist =
+            Safelist::new(PersistedQueryManifest::from_json(MANIFEST).unwrap()).with_log_only(true);
+        let mut service = SafelistLayer::new(Some(safelist)).layer(mock_service.build());
+
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(Some("{ me { name } }"), None))
+            .await
+            .unwrap();
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(request(None, Some("unknown")))
+            .await
+            .unwrap();
+    }
+}
@@ -4,10 +4,13 @@ pub use self::execution_service::*;
 pub use self::router_service::*;
 use crate::fetch::OperationKind;
 use crate::prelude::graphql::*;
+pub use compression::Compression;
 use http::{header::HeaderName, HeaderValue, StatusCode};
 use http::{method::Method, Uri};
 use http_compat::IntoHeaderName;
 use http_compat::IntoHeaderValue;
+pub use layers::apq::{ApqStorage, InMemoryApqStorage, RedisApqStorage, DEFAULT_APQ_MAX_ENTRIES};
+pub use layers::safelist::{PersistedQueryManifest, Safelist};
 use multimap::MultiMap;
 use serde::{Deserialize, Serialize};
 use serde_json_bytes::ByteString;
@@ -19,7 +22,9 @@ use std::sync::Arc;
 use tower::BoxError;
 pub use tower_subgraph_service::TowerSubgraphService;
 
+mod compression;
 mod execution_service;
+pub mod graphql_ws;
 pub mod http_compat;
 pub(crate) mod layers;
 mod router_service;
@@ -1,18 +1,22 @@
 //! Implements the router phase of the request lifecycle.
 
-use crate::services::execution_service::ExecutionService;
+use crate::services::execution_service::{accepts_multipart, ExecutionService};
 use crate::services::layers::allow_only_http_post_mutations::AllowOnlyHttpPostMutationsLayer;
-use crate::services::layers::apq::APQLayer;
+use crate::services::layers::apq::{APQLayer, ApqStorage, InMemoryApqStorage};
 use crate::services::layers::ensure_query_presence::EnsureQueryPresence;
+use crate::services::layers::safelist::SafelistLayer;
+use crate::spec::GRAPHQL_PARSE_FAILED;
 use crate::{
     BridgeQueryPlanner, CachingQueryPlanner, DynPlugin, ExecutionRequest, ExecutionResponse,
-    Introspection, Plugin, QueryCache, QueryPlannerRequest, QueryPlannerResponse, ResponseBody,
-    RouterRequest, RouterResponse, Schema, ServiceBuildError, ServiceBuilderExt, SubgraphRequest,
-    SubgraphResponse, DEFAULT_BUFFER_SIZE,
+    IncrementalResponses, Introspection, OperationLimits, Plugin, QueryCache, QueryPlannerRequest,
+    QueryPlannerResponse, ResponseBody, RouterRequest, RouterResponse, Safelist, Schema,
+    ServiceBuildError, ServiceBuilderExt, SubgraphRequest, SubgraphResponse, ValueExt,
+    DEFAULT_BUFFER_SIZE, USAGE_REPORTING,
 };
-use futures::{future::BoxFuture, TryFutureExt};
+use futures::{future::BoxFuture, stream, StreamExt, TryFutureExt};
 use http::StatusCode;
 use indexmap::IndexMap;
+use router_bridge::planner::UsageReporting;
 use std::sync::Arc;
 use std::task::Poll;
 use tower::buffer::Buffer;
@@ -33,6 +37,7 @@ pub struct RouterService<QueryPlannerService, ExecutionService> {
     schema: Arc<Schema>,
     query_cache: Arc<QueryCache>,
     introspection: Option<Arc<Introspection>>,
+    operation_limits: Option<Arc<OperationLimits>>,
 }
 
 #[buildstructor::builder]
@@ -43,6 +48,7 @@ impl<QueryPlannerService, ExecutionService> RouterService<QueryPlannerService, E
         schema: Arc<Schema>,
         query_cache: Arc<QueryCache>,
         introspection: Option<Arc<Introspection>>,
+        operation_limits: Option<Arc<OperationLimits>>,
     ) -> RouterService<QueryPlannerService, ExecutionService> {
         RouterService {
             query_planner_service,
@@ -52,6 +58,7 @@ impl<QueryPlannerService, ExecutionService> RouterService<QueryPlannerService, E
             schema,
             query_cache,
             introspection,
+            operation_limits,
         }
     }
 }
@@ -103,130 +110,241 @@ where
 
         let schema = self.schema.clone();
         let query_cache = self.query_cache.clone();
+        let operation_limits = self.operation_limits.clone();
 
         let context_cloned = req.context.clone();
-        let fut =
-            async move {
-                // Check if we already have the query in the known introspection queries
-                if let Some(naive_introspection) = naive_introspection.as_ref() {
-                    if let Some(response) =
-                        naive_introspection
-                            .get(req.originating_request.body().query.as_ref().expect(
-                                "apollo.ensure-query-is-present has checked this already; qed",
-                            ))
-                            .await
-                    {
-                        return Ok(RouterResponse {
-                            response: http::Response::new(ResponseBody::GraphQL(response)).into(),
-                            context: req.context,
-                        });
+        let fut = async move {
+            let context = req.context;
+            let body = req.originating_request.body();
+            let variables = body.variables.clone();
+            let query = query_cache
+                .get(
+                    body.query
+                        .as_ref()
+                        .expect("apollo.ensure-query-is-present has checked this already; qed")
+                        .as_str(),
+                )
+                .await;
+            let query = match query {
+                Ok(query) => query,
+                Err(errors) => {
+                    // the operation is reported like the planner reports invalid operations
+                    let is_parse_failure = errors.iter().any(|error| {
+                        error.extensions.get("code").and_then(|code| code.as_str())
+                            == Some(GRAPHQL_PARSE_FAILED)
+                    });
+                    let usage_reporting = UsageReporting {
+                        stats_report_key: if is_parse_failure {
+                            "## GraphQLParseFailure\n".to_string()
+                        } else {
+                            "## GraphQLValidationFailure\n".to_string()
+                        },
+                        referenced_fields_by_type: Default::default(),
+                    };
+                    if let Err(e) = context.insert(USAGE_REPORTING, usage_reporting) {
+                        tracing::error!("usage reporting was not serializable to context, {}", e);
                     }
+
+                    let mut resp = http::Response::new(ResponseBody::GraphQL(
+                        crate::Response::builder().errors(errors).build(),
+                    ));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                    return Ok(RouterResponse {
+                        response: resp.into(),
+                        context,
+                    });
                 }
+            };
+
+            // introspection queries are limited too, they can be as deep as the type system
+            if let (Some(operation_limits), Some(current_query)) =
+                (operation_limits.as_ref(), query.as_ref())
+            {
+                if let Err(errors) = operation_limits.check(
+                    current_query,
+                    body.operation_name.as_deref(),
+                    &body.variables,
+                    &schema,
+                    &context,
+                ) {
+                    let mut resp = http::Response::new(ResponseBody::GraphQL(
+                        crate::Response::builder().errors(errors).build(),
+                    ));
+                    *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                    return Ok(RouterResponse {
+                        response: resp.into(),
+                        context,
+                    });
+                }
+            }
 
-                let context = req.context;
-                let body = req.originating_request.body();
-                let variables = body.variables.clone();
-                let query = query_cache
-                    .get(
-                        body.query
-                            .as_ref()
-                            .expect("apollo.ensure-query-is-present has checked this already; qed")
-                            .as_str(),
-                    )
-                    .await;
-
-                // Check if it's an introspection query
-                if let Some(current_query) = query.as_ref().filter(|q| q.contains_introspection()) {
-                    match naive_introspection.as_ref() {
-                        Some(naive_introspection) => {
-                            match naive_introspection
-                                .execute(schema.as_str(), current_query.as_str())
-                                .await
-                            {
-                                Ok(resp) => {
-                                    return Ok(RouterResponse {
-                                        response: http::Response::new(ResponseBody::GraphQL(resp))
-                                            .into(),
-                                        context,
-                                    });
-                                }
-                                Err(err) => return Err(BoxError::from(err)),
-                            }
-                        }
-                        None => {
-                            let mut resp = http::Response::new(ResponseBody::GraphQL(
-                                crate::Response::builder()
-                                    .errors(vec![crate::Error::builder()
-                                        .message(String::from("introspection has been disabled"))
-                                        .build()])
-                                    .build(),
-                            ));
+            // Check if it's an introspection query
+            if let Some(current_query) = query.as_ref().filter(|q| q.contains_introspection()) {
+                match naive_introspection.as_ref() {
+                    Some(naive_introspection) => {
+                        let response = naive_introspection.execute(
+                            current_query.as_str(),
+                            body.operation_name.as_deref(),
+                            &body.variables,
+                        );
+                        let mut resp = http::Response::new(ResponseBody::GraphQL(response.clone()));
+                        if response.data.is_none() {
                             *resp.status_mut() = StatusCode::BAD_REQUEST;
-
-                            return Ok(RouterResponse {
-                                response: resp.into(),
-                                context,
-                            });
                         }
+                        return Ok(RouterResponse {
+                            response: resp.into(),
+                            context,
+                        });
+                    }
+                    None => {
+                        let mut resp = http::Response::new(ResponseBody::GraphQL(
+                            crate::Response::builder()
+                                .errors(vec![crate::Error::builder()
+                                    .message(String::from("introspection has been disabled"))
+                                    .build()])
+                                .build(),
+                        ));
+                        *resp.status_mut() = StatusCode::BAD_REQUEST;
+
+                        return Ok(RouterResponse {
+                            response: resp.into(),
+                            context,
+                        });
                     }
                 }
+            }
 
-                if let Some(err) = query
-                    .as_ref()
-                    .and_then(|q| q.validate_variables(body, &schema).err())
-                {
-                    Ok(RouterResponse {
+            let variables = match query.as_ref().map(|q| q.coerce_variables(body, &schema)) {
+                Some(Err(err)) => {
+                    return Ok(RouterResponse {
                         response: http::Response::new(ResponseBody::GraphQL(err)).into(),
                         context,
-                    })
-                } else {
-                    let operation_name = body.operation_name.clone();
-                    let planned_query = planning
-                        .call(
-                            QueryPlannerRequest::builder()
-                                .originating_request(req.originating_request.clone())
-                                .context(context)
-                                .build(),
-                        )
-                        .await?;
-                    let mut response = execution
-                        .call(
-                            ExecutionRequest::builder()
-                                .originating_request(req.originating_request.clone())
-                                .query_plan(planned_query.query_plan)
-                                .context(planned_query.context)
-                                .build(),
-                        )
-                        .await?;
-
-                    if let Some(query) = query {
-                        tracing::debug_span!("format_response").in_scope(|| {
-                            query.format_response(
-                                response.response.body_mut(),
-                                operation_name.as_deref(),
-                                (*variables).clone(),
-                                schema.api_schema(),
-                            )
+                    });
+                }
+                Some(Ok(coerced)) => Arc::new(coerced),
+                None => variables,
+            };
+            let operation_name = body.operation_name.clone();
+            // the query planner and the subgraphs get the coerced variables
+            let mut originating_request = req.originating_request;
+            originating_request.body_mut().variables = variables.clone();
+            let planned_query = planning
+                .call(
+                    QueryPlannerRequest::builder()
+                        .originating_request(originating_request.clone())
+                        .context(context)
+                        .build(),
+                )
+                .await?;
+            let is_subscription = planned_query.query_plan.is_subscription();
+            let is_deferred = planned_query.query_plan.is_deferred();
+            let mut response = execution
+                .call(
+                    ExecutionRequest::builder()
+                        .originating_request(originating_request.clone())
+                        .query_plan(planned_query.query_plan)
+                        .context(planned_query.context)
+                        .build(),
+                )
+                .await?;
+
+            if let Some(query) = query {
+                // when the query plan does not defer the fragments, the complete response is
+                // split into the primary response and a patch for every deferred fragment
+                let deferred = query.deferred_fragments(operation_name.as_deref(), &variables);
+                if !is_subscription
+                    && !is_deferred
+                    && !deferred.is_empty()
+                    && accepts_multipart(&originating_request)
+                    && response
+                        .response
+                        .extensions()
+                        .get::<IncrementalResponses>()
+                        .is_none()
+                {
+                    let primary = response.response.body_mut();
+                    let data = primary.data.clone().unwrap_or_default();
+                    let mut patches = Vec::new();
+                    for (label, path) in &deferred {
+                        data.select_values_and_paths(path, |path, value| {
+                            if value.is_object() {
+                                patches.push(
+                                    crate::Response::builder()
+                                        .label(label.clone())
+                                        .path(path)
+                                        .data(value.clone())
+                                        .has_next(true)
+                                        .build(),
+                                );
+                            }
                         });
                     }
+                    if let Some(last) = patches.last_mut() {
+                        last.has_next = Some(false);
+                    }
+                    primary.has_next = Some(!patches.is_empty());
+                    response
+                        .response
+                        .extensions_mut()
+                        .insert(IncrementalResponses::new(stream::iter(patches).boxed()));
+                }
 
-                    Ok(RouterResponse {
-                        context: response.context,
-                        response: response.response.map(ResponseBody::GraphQL),
-                    })
+                tracing::debug_span!("format_response").in_scope(|| {
+                    query.format_response(
+                        response.response.body_mut(),
+                        operation_name.as_deref(),
+                        (*variables).clone(),
+                        schema.api_schema(),
+                    )
+                });
+
+                // every subscription event is a complete response, and every patch is formatted
+                // against its deferred fragment
+                if let Some(events) = response
+                    .response
+                    .extensions()
+                    .get::<IncrementalResponses>()
+                    .and_then(|events| events.take())
+                {
+                    let events = events
+                        .map(move |mut event| {
+                            // the last patch may only carry errors, or nothing but `hasNext`
+                            if event.data.is_some() {
+                                query.format_response(
+                                    &mut event,
+                                    operation_name.as_deref(),
+                                    (*variables).clone(),
+                                    schema.api_schema(),
+                                );
+                            }
+                            event
+                        })
+                        .boxed();
+                    response
+                        .response
+                        .extensions_mut()
+                        .insert(IncrementalResponses::new(events));
                 }
             }
-            .or_else(|error: BoxError| async move {
-                let errors = vec![crate::Error {
-                    message: error.to_string(),
-                    ..Default::default()
-                }];
-                RouterResponse::builder()
-                    .errors(errors)
-                    .status_code(StatusCode::INTERNAL_SERVER_ERROR)
-                    .context(context_cloned)
-                    .build()
-            });
+
+            Ok(RouterResponse {
+                context: response.context,
+                response: response.response.map(ResponseBody::GraphQL),
+            })
+        }
+        .or_else(|error: BoxError| async move {
+            let errors = vec![crate::Error {
+                message: error.to_string(),
+                ..Default::default()
+            }];
+            RouterResponse::builder()
+                .errors(errors)
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .context(context_cloned)
+                .build()
+        });
 
         Box::pin(fut)
     }
@@ -246,6 +364,9 @@ pub struct PluggableRouterServiceBuilder {
         BoxService<SubgraphRequest, SubgraphResponse, BoxError>,
     )>,
     introspection: bool,
+    operation_limits: Option<OperationLimits>,
+    safelist: Option<Safelist>,
+    apq_storage: Option<Arc<dyn ApqStorage>>,
 }
 
 impl PluggableRouterServiceBuilder {
@@ -255,6 +376,9 @@ impl PluggableRouterServiceBuilder {
             plugins: Default::default(),
             subgraph_services: Default::default(),
             introspection: false,
+            operation_limits: None,
+            safelist: None,
+            apq_storage: Some(Arc::new(InMemoryApqStorage::default())),
         }
     }
 
@@ -301,12 +425,57 @@ impl PluggableRouterServiceBuilder {
         self
     }
 
+    pub fn with_operation_limits(
+        mut self,
+        operation_limits: OperationLimits,
+    ) -> PluggableRouterServiceBuilder {
+        self.operation_limits = Some(operation_limits);
+        self
+    }
+
+    pub fn with_safelist(mut self, safelist: Safelist) -> PluggableRouterServiceBuilder {
+        self.safelist = Some(safelist);
+        self
+    }
+
+    /// Store the queries registered with automatic persisted queries in `storage` instead of the
+    /// default in memory storage.
+    pub fn with_apq_storage(
+        mut self,
+        storage: Arc<dyn ApqStorage>,
+    ) -> PluggableRouterServiceBuilder {
+        self.apq_storage = Some(storage);
+        self
+    }
+
+    pub fn without_apq(mut self) -> PluggableRouterServiceBuilder {
+        self.apq_storage = None;
+        self
+    }
+
     pub async fn build(
+        self,
+    ) -> Result<
+        (
+            BoxCloneService<RouterRequest, RouterResponse, BoxError>,
+            Plugins,
+        ),
+        crate::ServiceBuildError,
+    > {
+        self.build_with_query_planner()
+            .await
+            .map(|(router_service, plugins, _)| (router_service, plugins))
+    }
+
+    /// Build the router service, also returning its caching query planner so that its cache can
+    /// be warmed up, or used to warm up the cache of the next router.
+    pub async fn build_with_query_planner(
         mut self,
     ) -> Result<
         (
             BoxCloneService<RouterRequest, RouterResponse, BoxError>,
             Plugins,
+            CachingQueryPlanner<BridgeQueryPlanner>,
         ),
         crate::ServiceBuildError,
     > {
@@ -327,13 +496,16 @@ impl PluggableRouterServiceBuilder {
         let bridge_query_planner = BridgeQueryPlanner::new(self.schema.clone())
             .await
             .map_err(ServiceBuildError::QueryPlannerError)?;
-        let query_planner_service =
-            ServiceBuilder::new()
-                .buffered()
-                .service(self.plugins.iter_mut().rev().fold(
-                    CachingQueryPlanner::new(bridge_query_planner, plan_cache_limit).boxed(),
-                    |acc, (_, e)| e.query_planning_service(acc),
-                ));
+        let caching_query_planner =
+            CachingQueryPlanner::new(bridge_query_planner, plan_cache_limit);
+        let query_planner_service = ServiceBuilder::new().buffered().service(
+            self.plugins
+                .iter_mut()
+                .rev()
+                .fold(caching_query_planner.clone().boxed(), |acc, (_, e)| {
+                    e.query_planning_service(acc)
+                }),
+        );
 
         // SubgraphService takes a SubgraphRequest and outputs a RouterResponse
         let subgraphs = self
@@ -393,27 +565,214 @@ impl PluggableRouterServiceBuilder {
 
         // Router service takes a graphql::Request and outputs a graphql::Response
         // NB: Cannot use .buffer() here or the code won't compile...
-        let router_service = Buffer::new(
-            ServiceBuilder::new()
-                .layer(APQLayer::default())
-                .layer(EnsureQueryPresence::default())
-                .service(
-                    self.plugins.iter_mut().rev().fold(
-                        RouterService::builder()
-                            .query_planner_service(query_planner_service)
-                            .query_execution_service(execution_service)
-                            .schema(self.schema)
-                            .query_cache(query_cache)
-                            .and_introspection(introspection)
-                            .build()
-                            .boxed(),
-                        |acc, (_, e)| e.router_service(acc),
-                    ),
-                )
-                .boxed(),
-            DEFAULT_BUFFER_SIZE,
+        // The buffer is the innermost layer so that the APQ layer, which needs to clone the
+        // service it wraps, does not require another one
+        let router_service = ServiceBuilder::new()
+            .layer(SafelistLayer::new(self.safelist))
+            .layer(APQLayer::new(self.apq_storage))
+            .service(Buffer::new(
+                ServiceBuilder::new()
+                    .layer(EnsureQueryPresence::default())
+                    .service(
+                        self.plugins.iter_mut().rev().fold(
+                            RouterService::builder()
+                                .query_planner_service(query_planner_service)
+                                .query_execution_service(execution_service)
+                                .schema(self.schema)
+                                .query_cache(query_cache)
+                                .and_introspection(introspection)
+                                .and_operation_limits(self.operation_limits.map(Arc::new))
+                                .build()
+                                .boxed(),
+                            |acc, (_, e)| e.router_service(acc),
+                        ),
+                    )
+                    .boxed(),
+                DEFAULT_BUFFER_SIZE,
+            ));
+
+        Ok((
+            router_service.boxed_clone(),
+            self.plugins,
+            caching_query_planner,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QueryPlan, Value};
+    use serde_json_bytes::json;
+    use tower::service_fn;
+
+    type TestRouterService = RouterService<
+        BoxCloneService<QueryPlannerRequest, QueryPlannerResponse, BoxError>,
+        BoxCloneService<ExecutionRequest, ExecutionResponse, BoxError>,
+    >;
+
+    /// A router service whose execution always returns the same data, without deferring it.
+    fn router_service(operation_limits: Option<OperationLimits>) -> TestRouterService {
+        let schema: Arc<Schema> = Arc::new(
+            include_str!("../query_planner/testdata/schema.graphql")
+                .parse()
+                .unwrap(),
+        );
+        let planning = service_fn(|request: QueryPlannerRequest| async move {
+            Ok::<_, BoxError>(QueryPlannerResponse::new(
+                Arc::new(QueryPlan::fake_builder().build()),
+                request.context,
+            ))
+        });
+        let execution = service_fn(|request: ExecutionRequest| async move {
+            Ok::<_, BoxError>(
+                ExecutionResponse::fake_builder()
+                    .data(json!({"me": {"id": "1", "username": "ada"}}))
+                    .context(request.context)
+                    .build(),
+            )
+        });
+        RouterService::builder()
+            .query_planner_service(planning.boxed_clone())
+            .query_execution_service(execution.boxed_clone())
+            .schema(schema.clone())
+            .query_cache(Arc::new(QueryCache::new(0, schema.clone())))
+            .introspection(Arc::new(Introspection::from_schema(&schema)))
+            .and_operation_limits(operation_limits.map(Arc::new))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn it_delivers_deferred_fragments_incrementally() {
+        let mut router = router_service(None);
+        let query = r#"{ me { id ... @defer(label: "username") { username } } }"#;
+
+        let request = RouterRequest::fake_builder()
+            .query(query.to_string())
+            .header("accept", "multipart/mixed; deferSpec=20220824")
+            .build()
+            .unwrap();
+        let response = router.ready().await.unwrap().call(request).await.unwrap();
+        let patches = response
+            .response
+            .extensions()
+            .get::<IncrementalResponses>()
+            .and_then(|patches| patches.take())
+            .expect("the deferred fragment is sent in a patch")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            response.response.body(),
+            &ResponseBody::GraphQL(
+                crate::Response::builder()
+                    .data(json!({"me": {"id": "1"}}))
+                    .has_next(true)
+                    .build()
+            )
+        );
+        assert_eq!(
+            patches,
+            vec![crate::Response::builder()
+                .label("username".to_string())
+                .path(crate::Path::from("me"))
+                .data(json!({"username": "ada"}))
+                .has_next(false)
+                .build()]
+        );
+
+        // the clients which do not accept multipart responses get the complete response
+        let request = RouterRequest::fake_builder()
+            .query(query.to_string())
+            .build()
+            .unwrap();
+        let response = router.ready().await.unwrap().call(request).await.unwrap();
+        assert!(response
+            .response
+            .extensions()
+            .get::<IncrementalResponses>()
+            .is_none());
+        assert_eq!(
+            response.response.body(),
+            &ResponseBody::GraphQL(
+                crate::Response::builder()
+                    .data(json!({"me": {"id": "1", "username": "ada"}}))
+                    .build()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn plugins_map_every_incremental_response() {
+        let router = router_service(None);
+        // what a plugin's `map_response` layer does to modify the following responses
+        let mut router = ServiceBuilder::new()
+            .map_response(|response: RouterResponse| {
+                if let Some(responses) =
+                    response.response.extensions().get::<IncrementalResponses>()
+                {
+                    responses.map(|mut response| {
+                        response.extensions.insert("mapped", Value::Bool(true));
+                        response
+                    });
+                }
+                response
+            })
+            .service(router);
+
+        let request = RouterRequest::fake_builder()
+            .query(r#"{ me { id ... @defer(label: "username") { username } } }"#.to_string())
+            .header("accept", "multipart/mixed; deferSpec=20220824")
+            .build()
+            .unwrap();
+        let response = router.ready().await.unwrap().call(request).await.unwrap();
+        let patches = response
+            .response
+            .extensions()
+            .get::<IncrementalResponses>()
+            .and_then(|patches| patches.take())
+            .expect("the deferred fragment is sent in a patch")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(patches.len(), 1);
+        assert_eq!(
+            patches[0].extensions.get("mapped"),
+            Some(&Value::Bool(true))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_limits_introspection_queries() {
+        let limits = serde_json::from_value(serde_json::json!({ "max_depth": 5 })).unwrap();
+        let mut router = router_service(Some(limits));
+
+        let request = RouterRequest::fake_builder()
+            .query(
+                "{ __schema { types { fields { type { ofType { ofType { ofType { name } } } } } } } }"
+                    .to_string(),
+            )
+            .build()
+            .unwrap();
+        let response = router.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+        let errors = match response.response.body() {
+            ResponseBody::GraphQL(response) => &response.errors,
+            body => panic!("unexpected body: {:?}", body),
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0]
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str()),
+            Some("MAX_DEPTH_LIMIT")
         );
 
-        Ok((router_service.boxed_clone(), self.plugins))
+        // shallow introspection queries are still answered
+        let request = RouterRequest::fake_builder()
+            .query("{ __schema { queryType { name } } }".to_string())
+            .build()
+            .unwrap();
+        let response = router.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
     }
 }
@@ -1,10 +1,14 @@
 //! Tower fetcher for subgraphs.
 
+use super::compression::{self, DecodeError, ACCEPTED_ENCODINGS, DEFAULT_MAX_DECOMPRESSED_SIZE};
+use crate::fetch::OperationKind;
 use crate::prelude::*;
+use crate::{graphql_ws, Compression, IncrementalResponses, ResponseBodySize};
 use futures::future::BoxFuture;
+use futures::StreamExt;
 use global::get_text_map_propagator;
 use http::{
-    header::{ACCEPT, CONTENT_TYPE},
+    header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
     HeaderValue,
 };
 use hyper::client::HttpConnector;
@@ -21,6 +25,12 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 pub struct TowerSubgraphService {
     client: hyper::Client<HttpsConnector<HttpConnector>>,
     service: Arc<String>,
+    /// Compression of the request bodies, and minimum size of the compressed bodies.
+    compression: Option<(Compression, usize)>,
+    /// Maximum size of the decompressed response bodies.
+    max_decompressed_size: usize,
+    /// TLS settings of the WebSocket connections, the native roots are used if not set.
+    tls_config: Option<Arc<rustls::ClientConfig>>,
 }
 
 impl TowerSubgraphService {
@@ -32,11 +42,41 @@ impl TowerSubgraphService {
             .enable_http2()
             .build();
 
+        Self::with_client(service, hyper::Client::builder().build(connector))
+    }
+
+    /// Send the requests with a client configured for this subgraph.
+    pub fn with_client(
+        service: impl Into<String>,
+        client: hyper::Client<HttpsConnector<HttpConnector>>,
+    ) -> Self {
         Self {
-            client: ServiceBuilder::new().service(hyper::Client::builder().build(connector)),
+            client: ServiceBuilder::new().service(client),
             service: Arc::new(service.into()),
+            compression: None,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            tls_config: None,
         }
     }
+
+    /// Compress the request bodies of at least `min_size` bytes.
+    pub fn with_request_compression(mut self, compression: Compression, min_size: usize) -> Self {
+        self.compression = Some((compression, min_size));
+        self
+    }
+
+    /// Reject the compressed responses larger than `max_size` bytes once decompressed.
+    pub fn with_max_decompressed_size(mut self, max_size: usize) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// Open the WebSocket connections of subscriptions with these TLS settings, which should be
+    /// the ones of the http client.
+    pub fn with_tls_config(mut self, tls_config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
 }
 
 impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
@@ -54,21 +94,54 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
         let graphql::SubgraphRequest {
             subgraph_request,
             context,
+            operation_kind,
             ..
         } = request;
 
         let mut client = self.client.clone();
         let service_name = (*self.service).to_owned();
+        let compression = self.compression;
+        let max_decompressed_size = self.max_decompressed_size;
+
+        if operation_kind == OperationKind::Subscription {
+            return Box::pin(subscribe(
+                service_name,
+                subgraph_request,
+                context,
+                self.tls_config.clone(),
+            ));
+        }
 
         Box::pin(async move {
             let (parts, body) = subgraph_request.into_parts();
 
-            let body = serde_json::to_string(&body).expect("JSON serialization should not fail");
+            let body = serde_json::to_vec(&body).expect("JSON serialization should not fail");
+            let (body, content_encoding) = match compression {
+                Some((compression, min_size)) if body.len() >= min_size => (
+                    compression.compress(&body).await.map_err(|err| {
+                        graphql::FetchError::SubrequestHttpError {
+                            service: service_name.clone(),
+                            reason: format!("could not compress the request: {}", err),
+                        }
+                    })?,
+                    Some(compression.as_str()),
+                ),
+                _ => (body.into(), None),
+            };
 
             let mut request = http::request::Request::from_parts(parts, body.into());
             let app_json: HeaderValue = "application/json".parse().unwrap();
             request.headers_mut().insert(CONTENT_TYPE, app_json.clone());
             request.headers_mut().insert(ACCEPT, app_json);
+            request.headers_mut().insert(
+                ACCEPT_ENCODING,
+                HeaderValue::from_static(ACCEPTED_ENCODINGS),
+            );
+            if let Some(content_encoding) = content_encoding {
+                request
+                    .headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+            }
 
             get_text_map_propagator(|propagator| {
                 propagator.inject_context(
@@ -101,7 +174,7 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
                 })?;
 
             // Keep our parts, we'll need them later
-            let (parts, body) = response.into_parts();
+            let (mut parts, body) = response.into_parts();
             let body = hyper::body::to_bytes(body)
                 .instrument(tracing::debug_span!("aggregate_response_data"))
                 .await
@@ -113,6 +186,30 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
                         reason: err.to_string(),
                     }
                 })?;
+            // the body is decoded, so its encoding and length do not apply anymore
+            let content_encoding = parts.headers.remove(CONTENT_ENCODING);
+            let body = compression::decode(
+                content_encoding
+                    .as_ref()
+                    .and_then(|value| value.to_str().ok()),
+                body,
+                max_decompressed_size,
+            )
+            .await
+            .map_err(|err| match err {
+                DecodeError::TooLarge(_) => graphql::FetchError::SubrequestHttpError {
+                    service: service_name.clone(),
+                    reason: err.to_string(),
+                },
+                DecodeError::Invalid(_) => graphql::FetchError::SubrequestMalformedResponse {
+                    service: service_name.clone(),
+                    reason: err.to_string(),
+                },
+            })?;
+            if content_encoding.is_some() {
+                parts.headers.remove(CONTENT_LENGTH);
+            }
+            parts.extensions.insert(ResponseBodySize(body.len()));
 
             let graphql: graphql::Response = tracing::debug_span!("parse_subgraph_response")
                 .in_scope(|| {
@@ -133,3 +230,125 @@ impl tower::Service<graphql::SubgraphRequest> for TowerSubgraphService {
         })
     }
 }
+
+/// Open a subscription on the subgraph over WebSocket.
+///
+/// The first event is returned as the response, and the following ones are stored
+/// as [`IncrementalResponses`] in its extensions. The layers of the subgraph service only see
+/// the first event, they can transform the following ones with [`IncrementalResponses::map`].
+async fn subscribe(
+    service_name: String,
+    subgraph_request: graphql::http_compat::Request<graphql::Request>,
+    context: graphql::Context,
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+) -> Result<graphql::SubgraphResponse, BoxError> {
+    let schema_uri = subgraph_request.uri();
+    let host = schema_uri.host().map(String::from).unwrap_or_default();
+    let port = schema_uri.port_u16().unwrap_or_default();
+    let path = schema_uri.path().to_string();
+
+    let mut events = graphql_ws::subscribe(&service_name, subgraph_request, tls_config)
+        .instrument(tracing::info_span!("subgraph_subscription",
+            "otel.kind" = %SpanKind::Client,
+            "net.peer.name" = &display(host),
+            "net.peer.port" = &display(port),
+            "http.route" = &display(path),
+            "net.transport" = "ip_tcp"
+        ))
+        .await
+        .map_err(|err| {
+            tracing::error!(fetch_error = format!("{:?}", err).as_str());
+            err
+        })?;
+
+    let first = events
+        .next()
+        .await
+        .unwrap_or_else(|| graphql::Response::builder().build());
+    let mut response = http::Response::new(first);
+    response
+        .extensions_mut()
+        .insert(IncrementalResponses::new(events));
+
+    Ok(graphql::SubgraphResponse::new_from_response(
+        response.into(),
+        context,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router, Server};
+    use bytes::Bytes;
+    use http::{HeaderMap, Method};
+    use serde_json_bytes::json;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn it_compresses_requests_and_decodes_responses() {
+        let app = Router::new().route(
+            "/",
+            post(|headers: HeaderMap, body: Bytes| async move {
+                assert_eq!(headers.get(CONTENT_ENCODING).unwrap(), "gzip");
+                assert_eq!(headers.get(ACCEPT_ENCODING).unwrap(), ACCEPTED_ENCODINGS);
+                let body = compression::decode(Some("gzip"), body, DEFAULT_MAX_DECOMPRESSED_SIZE)
+                    .await
+                    .unwrap();
+                let request: graphql::Request = serde_json::from_slice(&body).unwrap();
+                assert_eq!(request.query.as_deref(), Some("{ me { name } }"));
+
+                let response = Compression::Br
+                    .compress(br#"{"data":{"me":{"name":"Ada Lovelace"}}}"#)
+                    .await
+                    .unwrap();
+                ([(CONTENT_ENCODING, "br")], response)
+            }),
+        );
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        let subgraph_request = graphql::http_compat::Request::fake_builder()
+            .uri(format!("http://{}/", address).parse::<http::Uri>().unwrap())
+            .method(Method::POST)
+            .body(
+                graphql::Request::builder()
+                    .query("{ me { name } }".to_string())
+                    .build(),
+            )
+            .build()
+            .unwrap();
+        let response = TowerSubgraphService::new("accounts")
+            .with_request_compression(Compression::Gzip, 0)
+            .oneshot(
+                graphql::SubgraphRequest::fake_builder()
+                    .subgraph_request(subgraph_request.clone())
+                    .build(),
+            )
+            .await
+            .unwrap();
+
+        assert!(response.response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(
+            response.response.body().data,
+            Some(json!({"me": {"name": "Ada Lovelace"}}))
+        );
+
+        let err = TowerSubgraphService::new("accounts")
+            .with_request_compression(Compression::Gzip, 0)
+            .with_max_decompressed_size(10)
+            .oneshot(
+                graphql::SubgraphRequest::fake_builder()
+                    .subgraph_request(subgraph_request)
+                    .build(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            *err.downcast::<graphql::FetchError>().unwrap(),
+            graphql::FetchError::SubrequestHttpError { service, reason }
+                if service == "accounts" && reason == "the decompressed body is larger than 10 bytes"
+        ));
+    }
+}
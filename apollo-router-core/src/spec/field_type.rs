@@ -20,21 +20,28 @@ pub(crate) enum FieldType {
 }
 
 impl FieldType {
-    pub(crate) fn validate_value(
+    /// Coerce a variable value to this type.
+    ///
+    /// Spec: https://spec.graphql.org/draft/#sec-Coercing-Variable-Values
+    pub(crate) fn coerce_value(
         &self,
         value: &Value,
         schema: &Schema,
-    ) -> Result<(), InvalidValue> {
+    ) -> Result<Value, InvalidValue> {
         match (self, value) {
+            (FieldType::NonNull(_), Value::Null) => Err(InvalidValue),
+            (FieldType::NonNull(inner_ty), value) => inner_ty.coerce_value(value, schema),
+            // NOTE: graphql's types are all optional by default
+            (_, Value::Null) => Ok(Value::Null),
             // Type coercion from string to Int, Float or Boolean
             (FieldType::Int | FieldType::Float | FieldType::Boolean, Value::String(s)) => {
                 if let Ok(value) = Value::from_bytes(s.inner().clone()) {
-                    self.validate_value(&value, schema)
+                    self.coerce_value(&value, schema)
                 } else {
                     Err(InvalidValue)
                 }
             }
-            (FieldType::String, Value::String(_)) => Ok(()),
+            (FieldType::String, Value::String(_)) => Ok(value.clone()),
             // Spec: https://spec.graphql.org/June2018/#sec-Int
             (FieldType::Int, Value::Number(number)) if number.is_i64() || number.is_u64() => {
                 if number
@@ -46,47 +53,115 @@ impl FieldType {
                         .and_then(|x| i32::try_from(x).ok())
                         .is_some()
                 {
-                    Ok(())
+                    Ok(value.clone())
                 } else {
                     Err(InvalidValue)
                 }
             }
-            // Spec: https://spec.graphql.org/draft/#sec-Float
-            (FieldType::Float, Value::Number(number)) if number.is_f64() => Ok(()),
+            // Spec: https://spec.graphql.org/draft/#sec-Float.Input-Coercion
+            // Int values are converted to Float
+            (FieldType::Float, Value::Number(number)) => {
+                number.as_f64().map(Value::from).ok_or(InvalidValue)
+            }
             // "The ID scalar type represents a unique identifier, often used to refetch an object
             // or as the key for a cache. The ID type is serialized in the same way as a String;
             // however, it is not intended to be human-readable. While it is often numeric, it
             // should always serialize as a String."
             //
-            // In practice it seems Int works too
-            (FieldType::Id, Value::String(_) | Value::Number(_)) => Ok(()),
-            (FieldType::Boolean, Value::Bool(_)) => Ok(()),
+            // Spec: https://spec.graphql.org/draft/#sec-ID.Input-Coercion
+            (FieldType::Id, Value::String(_)) => Ok(value.clone()),
+            (FieldType::Id, Value::Number(number)) if number.is_i64() || number.is_u64() => {
+                Ok(Value::String(number.to_string().into()))
+            }
+            (FieldType::Boolean, Value::Bool(_)) => Ok(value.clone()),
+            // Spec: https://spec.graphql.org/draft/#sec-List.Input-Coercion
             (FieldType::List(inner_ty), Value::Array(vec)) => vec
                 .iter()
-                .try_for_each(|x| inner_ty.validate_value(x, schema)),
-            (FieldType::NonNull(inner_ty), value) => {
-                if value.is_null() {
-                    Err(InvalidValue)
+                .map(|x| inner_ty.coerce_value(x, schema))
+                .collect::<Result<_, _>>()
+                .map(Value::Array),
+            // a single value is coerced to a list of one item
+            (FieldType::List(inner_ty), value) => {
+                Ok(Value::Array(vec![inner_ty.coerce_value(value, schema)?]))
+            }
+            (FieldType::Named(name), _) if schema.custom_scalars.contains(name) => {
+                Ok(value.clone())
+            }
+            // Spec: https://spec.graphql.org/draft/#sec-Enums.Input-Coercion
+            (FieldType::Named(name), Value::String(s)) if schema.enums.contains_key(name) => {
+                if schema.enums[name].contains(s.as_str()) {
+                    Ok(value.clone())
                 } else {
-                    inner_ty.validate_value(value, schema)
+                    Err(InvalidValue)
                 }
             }
-            (FieldType::Named(name), _)
-                if schema.custom_scalars.contains(name) || schema.enums.contains_key(name) =>
-            {
-                Ok(())
-            }
-            (FieldType::Named(name), value) if value.is_object() => {
+            (FieldType::Named(name), Value::Object(object)) => {
                 if let Some(object_ty) = schema.input_types.get(name) {
                     object_ty
-                        .validate_object(value.as_object().unwrap(), schema)
+                        .coerce_object(object, schema)
+                        .map(Value::Object)
                         .map_err(|_| InvalidValue)
                 } else {
                     Err(InvalidValue)
                 }
             }
-            // NOTE: graphql's types are all optional by default
-            (_, Value::Null) => Ok(()),
+            _ => Err(InvalidValue),
+        }
+    }
+
+    /// Check that an argument literal of the query can be coerced to this type.
+    ///
+    /// Unlike variable values, literals are never converted from strings, and enum values must
+    /// be enum literals. The variables in the literal are coerced with the other variables.
+    ///
+    /// Spec: https://spec.graphql.org/draft/#sec-Coercing-Field-Arguments
+    pub(crate) fn validate_literal(
+        &self,
+        value: &ast::Value,
+        schema: &Schema,
+    ) -> Result<(), InvalidValue> {
+        match (self, value) {
+            (_, ast::Value::Variable(_)) => Ok(()),
+            (FieldType::NonNull(_), ast::Value::NullValue(_)) => Err(InvalidValue),
+            (FieldType::NonNull(inner_ty), value) => inner_ty.validate_literal(value, schema),
+            (_, ast::Value::NullValue(_)) => Ok(()),
+            // Spec: https://spec.graphql.org/draft/#sec-List.Input-Coercion
+            (FieldType::List(inner_ty), ast::Value::ListValue(list)) => list
+                .values()
+                .try_for_each(|value| inner_ty.validate_literal(&value, schema)),
+            // a single value is coerced to a list of one item
+            (FieldType::List(inner_ty), value) => inner_ty.validate_literal(value, schema),
+            // Spec: https://spec.graphql.org/draft/#sec-Int.Input-Coercion
+            (FieldType::Int, ast::Value::IntValue(int)) => int
+                .to_string()
+                .trim()
+                .parse::<i32>()
+                .map(|_| ())
+                .map_err(|_| InvalidValue),
+            // Spec: https://spec.graphql.org/draft/#sec-Float.Input-Coercion
+            (FieldType::Float, ast::Value::IntValue(_) | ast::Value::FloatValue(_)) => Ok(()),
+            (FieldType::String, ast::Value::StringValue(_)) => Ok(()),
+            (FieldType::Boolean, ast::Value::BooleanValue(_)) => Ok(()),
+            // Spec: https://spec.graphql.org/draft/#sec-ID.Input-Coercion
+            (FieldType::Id, ast::Value::StringValue(_) | ast::Value::IntValue(_)) => Ok(()),
+            (FieldType::Named(name), _) if schema.custom_scalars.contains(name) => Ok(()),
+            // Spec: https://spec.graphql.org/draft/#sec-Enums.Input-Coercion
+            (FieldType::Named(name), ast::Value::EnumValue(value))
+                if schema.enums.contains_key(name) =>
+            {
+                match value.name() {
+                    Some(value) if schema.enums[name].contains(&value.text().to_string()) => Ok(()),
+                    _ => Err(InvalidValue),
+                }
+            }
+            (FieldType::Named(name), ast::Value::ObjectValue(object)) => {
+                match schema.input_types.get(name) {
+                    Some(object_ty) => object_ty
+                        .validate_literal(object, schema)
+                        .map_err(|_| InvalidValue),
+                    None => Err(InvalidValue),
+                }
+            }
             _ => Err(InvalidValue),
         }
     }
@@ -1,11 +1,15 @@
 mod field_type;
 mod fragments;
+mod operation_limits;
 mod query;
 mod schema;
 mod selection;
+mod validation;
 
 pub(crate) use field_type::*;
 pub(crate) use fragments::*;
+pub use operation_limits::*;
 pub use query::*;
 pub use schema::*;
 pub(crate) use selection::*;
+pub(crate) use validation::*;
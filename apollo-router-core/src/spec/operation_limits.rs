@@ -0,0 +1,655 @@
+//! Operation limits.
+//!
+//! Measures the depth, the field count, the alias count, the root field count and the estimated
+//! cost of an operation, to reject the operations exceeding the configured limits before planning.
+//!
+//! The measured parts of a query are extracted once, when the [`Query`] is parsed and cached, and
+//! measured for every request with its variables.
+
+use crate::prelude::graphql::*;
+use apollo_parser::ast;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Context key of the [`OperationMeasurements`] of the request.
+pub const OPERATION_MEASUREMENTS: &str = "apollo_operation_limits::measurements";
+
+// Same limit as the selection parsing, deeper operations cannot be parsed anyway
+const RECURSION_LIMIT: usize = 512;
+
+/// Limits on the shape of the operations, checked before planning them.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OperationLimits {
+    /// Maximum depth of the selection sets
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// Maximum number of fields, counting the fields of a fragment every time it is spread
+    #[serde(default)]
+    pub max_fields: Option<usize>,
+
+    /// Maximum number of aliased fields
+    #[serde(default)]
+    pub max_aliases: Option<usize>,
+
+    /// Maximum number of fields selected on the root type
+    #[serde(default)]
+    pub max_root_fields: Option<usize>,
+
+    /// Maximum estimated cost
+    #[serde(default)]
+    pub max_cost: Option<usize>,
+
+    /// Estimated size of the lists returned by the fields without a `first` or `limit` argument.
+    /// Defaults to 10
+    #[serde(default = "default_list_size")]
+    pub default_list_size: usize,
+
+    /// Cost of the fields returning a type, by type name. Other fields with a selection set cost
+    /// 1, and fields without one cost 0
+    #[serde(default)]
+    pub type_weights: HashMap<String, usize>,
+
+    /// Only measure the operations, recording the measurements in the context, without rejecting
+    /// the operations exceeding the limits
+    #[serde(default)]
+    pub measure_only: bool,
+}
+
+fn default_list_size() -> usize {
+    10
+}
+
+/// Measurements of an operation, after expanding its fragments.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OperationMeasurements {
+    pub depth: usize,
+    pub fields: usize,
+    pub aliases: usize,
+    pub root_fields: usize,
+    /// Sum of the weights of the fields, multiplied by the estimated size of the lists
+    /// containing them.
+    pub cost: usize,
+}
+
+impl OperationLimits {
+    /// Measure the operation and record its measurements in the context.
+    ///
+    /// Returns the errors of the exceeded limits, unless only measuring.
+    pub(crate) fn check(
+        &self,
+        query: &Query,
+        operation_name: Option<&str>,
+        variables: &Object,
+        schema: &Schema,
+        context: &Context,
+    ) -> Result<(), Vec<Error>> {
+        let measurements =
+            match OperationMeasurements::measure(query, operation_name, variables, schema, self) {
+                Some(measurements) => measurements,
+                // the query planner reports the missing operations
+                None => return Ok(()),
+            };
+        if let Err(err) = context.insert(OPERATION_MEASUREMENTS, measurements.clone()) {
+            tracing::error!("could not record the operation measurements: {}", err);
+        }
+
+        let errors = self.errors(&measurements);
+        if errors.is_empty() {
+            Ok(())
+        } else if self.measure_only {
+            tracing::debug!(
+                "operation exceeding its limits: {}",
+                errors
+                    .iter()
+                    .map(|error| error.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn errors(&self, measurements: &OperationMeasurements) -> Vec<Error> {
+        [
+            (
+                self.max_depth,
+                measurements.depth,
+                "depth",
+                "MAX_DEPTH_LIMIT",
+            ),
+            (
+                self.max_fields,
+                measurements.fields,
+                "field count",
+                "MAX_FIELDS_LIMIT",
+            ),
+            (
+                self.max_aliases,
+                measurements.aliases,
+                "alias count",
+                "MAX_ALIASES_LIMIT",
+            ),
+            (
+                self.max_root_fields,
+                measurements.root_fields,
+                "root field count",
+                "MAX_ROOT_FIELDS_LIMIT",
+            ),
+            (
+                self.max_cost,
+                measurements.cost,
+                "estimated cost",
+                "MAX_COST_LIMIT",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(limit, measured, name, code)| {
+            let limit = limit.filter(|limit| measured > *limit)?;
+            let mut extensions = Object::new();
+            extensions.insert("code", Value::String(code.into()));
+            Some(
+                Error::builder()
+                    .message(format!(
+                        "the operation {} of {} exceeds the limit of {}",
+                        name, measured, limit
+                    ))
+                    .extensions(extensions)
+                    .build(),
+            )
+        })
+        .collect()
+    }
+}
+
+impl OperationMeasurements {
+    /// Measure the operation selected by `operation_name`, or the first one if there is no name.
+    ///
+    /// The size of the lists is estimated from the `first` or `limit` argument of the fields
+    /// returning them, literal or variable.
+    pub fn measure(
+        query: &Query,
+        operation_name: Option<&str>,
+        variables: &Object,
+        schema: &Schema,
+        limits: &OperationLimits,
+    ) -> Option<Self> {
+        let shape = query.shape();
+        let operation = match operation_name {
+            Some(operation_name) => shape
+                .operations
+                .iter()
+                .find(|operation| operation.name.as_deref() == Some(operation_name)),
+            None => shape.operations.first(),
+        }?;
+
+        let mut measurer = Measurer {
+            schema,
+            limits,
+            variables,
+            default_values: &operation.default_values,
+            fragments: &shape.fragments,
+            measured_fragments: HashMap::new(),
+            visiting: HashSet::new(),
+        };
+        let measure =
+            measurer.selection_set(&operation.selection_set, Some(operation.root_type), 0);
+
+        Some(OperationMeasurements {
+            depth: measure.depth,
+            fields: measure.fields,
+            aliases: measure.aliases,
+            root_fields: measure.top_fields,
+            cost: measure.cost,
+        })
+    }
+}
+
+/// The measured parts of the operations and fragments of a query.
+#[derive(Debug, Default)]
+pub(crate) struct QueryShape {
+    operations: Vec<OperationShape>,
+    fragments: HashMap<String, FragmentShape>,
+}
+
+#[derive(Debug)]
+struct OperationShape {
+    name: Option<String>,
+    root_type: &'static str,
+    /// List sizes given by the default values of the variables
+    default_values: HashMap<String, usize>,
+    selection_set: Vec<SelectionShape>,
+}
+
+#[derive(Debug)]
+struct FragmentShape {
+    type_condition: Option<String>,
+    selection_set: Vec<SelectionShape>,
+}
+
+#[derive(Debug)]
+enum SelectionShape {
+    Field {
+        name: String,
+        has_alias: bool,
+        /// The `first` or `limit` argument
+        list_size: Option<ListSize>,
+        selection_set: Option<Vec<SelectionShape>>,
+    },
+    InlineFragment(FragmentShape),
+    FragmentSpread(String),
+}
+
+#[derive(Debug)]
+enum ListSize {
+    Literal(usize),
+    Variable(String),
+}
+
+impl QueryShape {
+    pub(crate) fn from_ast(document: &ast::Document) -> Self {
+        let mut shape = QueryShape::default();
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::OperationDefinition(operation) => {
+                    shape.operations.push(OperationShape::from_ast(operation))
+                }
+                ast::Definition::FragmentDefinition(fragment) => {
+                    if let Some(name) = fragment
+                        .fragment_name()
+                        .and_then(|name| name.name())
+                        .map(|name| name.text().to_string())
+                    {
+                        shape.fragments.insert(
+                            name,
+                            FragmentShape {
+                                type_condition: type_condition(fragment.type_condition()),
+                                selection_set: selection_set_shape(fragment.selection_set(), 0),
+                            },
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        shape
+    }
+}
+
+impl OperationShape {
+    fn from_ast(operation: ast::OperationDefinition) -> Self {
+        let root_type = match operation.operation_type() {
+            Some(operation_type) if operation_type.mutation_token().is_some() => "Mutation",
+            Some(operation_type) if operation_type.subscription_token().is_some() => "Subscription",
+            _ => "Query",
+        };
+
+        let default_values = operation
+            .variable_definitions()
+            .iter()
+            .flat_map(|definitions| definitions.variable_definitions())
+            .filter_map(|definition| {
+                let name = definition.variable()?.name()?.text().to_string();
+                let value = definition.default_value()?.value()?;
+                Some((name, parse_list_size(&value)?))
+            })
+            .collect();
+
+        OperationShape {
+            name: operation.name().map(|name| name.text().to_string()),
+            root_type,
+            default_values,
+            selection_set: selection_set_shape(operation.selection_set(), 0),
+        }
+    }
+}
+
+fn selection_set_shape(
+    selection_set: Option<ast::SelectionSet>,
+    level: usize,
+) -> Vec<SelectionShape> {
+    let selection_set = match selection_set {
+        Some(selection_set) if level <= RECURSION_LIMIT => selection_set,
+        _ => return Vec::new(),
+    };
+
+    selection_set
+        .selections()
+        .map(|selection| match selection {
+            ast::Selection::Field(field) => SelectionShape::Field {
+                name: field
+                    .name()
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default(),
+                has_alias: field.alias().is_some(),
+                list_size: list_size(&field),
+                selection_set: field
+                    .selection_set()
+                    .map(|selection_set| selection_set_shape(Some(selection_set), level + 1)),
+            },
+            ast::Selection::InlineFragment(inline_fragment) => {
+                SelectionShape::InlineFragment(FragmentShape {
+                    type_condition: type_condition(inline_fragment.type_condition()),
+                    selection_set: selection_set_shape(inline_fragment.selection_set(), level + 1),
+                })
+            }
+            ast::Selection::FragmentSpread(fragment_spread) => SelectionShape::FragmentSpread(
+                fragment_spread
+                    .fragment_name()
+                    .and_then(|name| name.name())
+                    .map(|name| name.text().to_string())
+                    .unwrap_or_default(),
+            ),
+        })
+        .collect()
+}
+
+fn type_condition(type_condition: Option<ast::TypeCondition>) -> Option<String> {
+    type_condition
+        .and_then(|condition| condition.named_type())
+        .and_then(|named_type| named_type.name())
+        .map(|name| name.text().to_string())
+}
+
+fn list_size(field: &ast::Field) -> Option<ListSize> {
+    field
+        .arguments()
+        .into_iter()
+        .flat_map(|arguments| arguments.arguments())
+        .find(|argument| {
+            argument
+                .name()
+                .map(|name| matches!(name.text().to_string().as_str(), "first" | "limit"))
+                .unwrap_or_default()
+        })
+        .and_then(|argument| argument.value())
+        .and_then(|value| match value {
+            ast::Value::Variable(variable) => {
+                Some(ListSize::Variable(variable.name()?.text().to_string()))
+            }
+            value => parse_list_size(&value).map(ListSize::Literal),
+        })
+}
+
+/// Measurements of a selection set.
+#[derive(Clone, Copy, Debug, Default)]
+struct Measure {
+    depth: usize,
+    fields: usize,
+    aliases: usize,
+    /// Fields of the selection set itself, including the ones of its fragments
+    top_fields: usize,
+    cost: usize,
+}
+
+impl Measure {
+    fn add(&mut self, other: Measure) {
+        self.depth = self.depth.max(other.depth);
+        self.fields = self.fields.saturating_add(other.fields);
+        self.aliases = self.aliases.saturating_add(other.aliases);
+        self.top_fields = self.top_fields.saturating_add(other.top_fields);
+        self.cost = self.cost.saturating_add(other.cost);
+    }
+}
+
+struct Measurer<'a> {
+    schema: &'a Schema,
+    limits: &'a OperationLimits,
+    variables: &'a Object,
+    default_values: &'a HashMap<String, usize>,
+    fragments: &'a HashMap<String, FragmentShape>,
+    /// Fragments are measured once, whatever the number of times they are spread
+    measured_fragments: HashMap<&'a str, Measure>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'a> Measurer<'a> {
+    fn selection_set(
+        &mut self,
+        selection_set: &'a [SelectionShape],
+        parent_type: Option<&str>,
+        level: usize,
+    ) -> Measure {
+        let mut measure = Measure::default();
+        if level > RECURSION_LIMIT {
+            return measure;
+        }
+
+        for selection in selection_set {
+            match selection {
+                SelectionShape::Field {
+                    name,
+                    has_alias,
+                    list_size,
+                    selection_set,
+                } => measure.add(self.field(
+                    name,
+                    *has_alias,
+                    list_size.as_ref(),
+                    selection_set.as_deref(),
+                    parent_type,
+                    level,
+                )),
+                SelectionShape::InlineFragment(fragment) => {
+                    measure.add(self.selection_set(
+                        &fragment.selection_set,
+                        fragment.type_condition.as_deref().or(parent_type),
+                        level + 1,
+                    ));
+                }
+                SelectionShape::FragmentSpread(name) => measure.add(self.fragment(name, level)),
+            }
+        }
+        measure
+    }
+
+    fn field(
+        &mut self,
+        name: &str,
+        has_alias: bool,
+        list_size: Option<&ListSize>,
+        selection_set: Option<&'a [SelectionShape]>,
+        parent_type: Option<&str>,
+        level: usize,
+    ) -> Measure {
+        let schema = self.schema;
+        let field_type = parent_type.and_then(|parent_type| {
+            schema
+                .object_types
+                .get(parent_type)
+                .and_then(|ty| ty.field(name))
+                .or_else(|| {
+                    schema
+                        .interfaces
+                        .get(parent_type)
+                        .and_then(|ty| ty.field(name))
+                })
+        });
+        let type_name = field_type.and_then(|field_type| field_type.inner_type_name());
+
+        let children = match selection_set {
+            Some(selection_set) => self.selection_set(selection_set, type_name, level + 1),
+            None => Measure::default(),
+        };
+        let weight = type_name
+            .and_then(|type_name| self.limits.type_weights.get(type_name).copied())
+            .unwrap_or(if selection_set.is_some() { 1 } else { 0 });
+        let size = if field_type.map(is_list).unwrap_or_default() {
+            self.list_size(list_size)
+        } else {
+            1
+        };
+
+        Measure {
+            depth: children.depth + 1,
+            fields: children.fields.saturating_add(1),
+            aliases: children.aliases.saturating_add(has_alias as usize),
+            top_fields: 1,
+            cost: size.saturating_mul(weight.saturating_add(children.cost)),
+        }
+    }
+
+    fn fragment(&mut self, name: &'a str, level: usize) -> Measure {
+        if let Some(measure) = self.measured_fragments.get(name) {
+            return *measure;
+        }
+        // fragment cycles are invalid, they must not make the measure loop forever though
+        if !self.visiting.insert(name) {
+            return Measure::default();
+        }
+
+        let fragments = self.fragments;
+        let measure = match fragments.get(name) {
+            Some(fragment) => self.selection_set(
+                &fragment.selection_set,
+                fragment.type_condition.as_deref(),
+                level + 1,
+            ),
+            None => Measure::default(),
+        };
+
+        self.visiting.remove(name);
+        self.measured_fragments.insert(name, measure);
+        measure
+    }
+
+    fn list_size(&self, list_size: Option<&ListSize>) -> usize {
+        match list_size {
+            Some(ListSize::Literal(size)) => Some(*size),
+            Some(ListSize::Variable(name)) => self
+                .variables
+                .get(name.as_str())
+                .and_then(|value| value.as_u64())
+                .and_then(|size| usize::try_from(size).ok())
+                .or_else(|| self.default_values.get(name).copied()),
+            None => None,
+        }
+        .unwrap_or(self.limits.default_list_size)
+    }
+}
+
+fn parse_list_size(value: &ast::Value) -> Option<usize> {
+    match value {
+        ast::Value::IntValue(int) => int.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+fn is_list(field_type: &FieldType) -> bool {
+    match field_type {
+        FieldType::List(_) => true,
+        FieldType::NonNull(inner) => is_list(inner),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    fn schema() -> Schema {
+        include_str!("../../../examples/graphql/local.graphql")
+            .parse()
+            .unwrap()
+    }
+
+    fn limits(config: serde_json::Value) -> OperationLimits {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn measure(query: &str, variables: serde_json_bytes::Value) -> OperationMeasurements {
+        let schema = schema();
+        OperationMeasurements::measure(
+            &Query::parse(query, &schema).unwrap(),
+            None,
+            variables.as_object().unwrap(),
+            &schema,
+            &limits(serde_json::json!({})),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_measures_operations() {
+        let query = r#"
+            query TopProducts($first: Int) {
+                topProducts(first: $first) {
+                    upc
+                    name
+                    reviews { ...ReviewFields }
+                }
+                me { name reviews { ...ReviewFields } }
+            }
+            fragment ReviewFields on Review { id body author { id name: username } }
+        "#;
+
+        assert_eq!(
+            measure(query, json!({ "first": 2 })),
+            OperationMeasurements {
+                depth: 4,
+                // topProducts: 1 + upc + name + reviews (1 + 5), me: 1 + name + reviews (1 + 5)
+                fields: 9 + 8,
+                aliases: 2,
+                root_fields: 2,
+                // topProducts: 2 * (1 + 10 * (1 + author)), me: 1 + 10 * (1 + author)
+                cost: 2 * (1 + 10 * 2) + (1 + 10 * 2),
+            }
+        );
+
+        // without variable, the list size defaults to 10
+        assert_eq!(measure(query, json!({})).cost, 10 * 21 + 21);
+    }
+
+    #[test]
+    fn it_rejects_operations_exceeding_the_limits() {
+        let schema = schema();
+        let query = Query::parse("{ a: me { id } b: me { id } c: me { id } }", &schema).unwrap();
+        let context = Context::new();
+
+        let errors = limits(serde_json::json!({ "max_aliases": 2, "max_depth": 2 }))
+            .check(&query, None, &Object::new(), &schema, &context)
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "the operation alias count of 3 exceeds the limit of 2"
+        );
+        assert_eq!(
+            errors[0].extensions.get("code"),
+            Some(&Value::String("MAX_ALIASES_LIMIT".into()))
+        );
+
+        let measure_only =
+            limits(serde_json::json!({ "max_root_fields": 1, "measure_only": true }));
+        assert!(measure_only
+            .check(&query, None, &Object::new(), &schema, &context)
+            .is_ok());
+        assert_eq!(
+            context
+                .get::<_, OperationMeasurements>(OPERATION_MEASUREMENTS)
+                .unwrap()
+                .unwrap()
+                .root_fields,
+            3
+        );
+    }
+
+    #[test]
+    fn it_weights_types() {
+        let schema = schema();
+        let measurements = OperationMeasurements::measure(
+            &Query::parse("{ topProducts(first: 3) { upc reviews { id } } }", &schema).unwrap(),
+            None,
+            &Object::new(),
+            &schema,
+            &limits(serde_json::json!({ "type_weights": { "Product": 5, "Review": 2 } })),
+        )
+        .unwrap();
+        assert_eq!(measurements.cost, 3 * (5 + 10 * 2));
+    }
+}
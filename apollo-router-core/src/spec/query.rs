@@ -2,6 +2,7 @@
 //!
 //! Parsing, formatting and manipulation of queries.
 
+use crate::introspection::schema::string_value;
 use crate::{fetch::OperationKind, prelude::graphql::*};
 use apollo_parser::ast;
 use derivative::Derivative;
@@ -11,6 +12,33 @@ use tracing::level_filters::LevelFilter;
 
 const TYPENAME: &str = "__typename";
 
+/// The fragments with an active `@defer` directive that are kept when formatting a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeferredFragments<'a> {
+    /// All of them: the response is complete.
+    Include,
+    /// None of them: the response is the primary part of an incremental delivery.
+    Skip,
+    /// Only the content of the ones with this label: the response is an incremental patch.
+    Only(Option<&'a str>),
+}
+
+impl<'a> DeferredFragments<'a> {
+    /// How to format the content of a fragment, or `None` if it is left out.
+    fn enter(self, defer: &Defer, label: Option<&str>, variables: &Object) -> Option<Self> {
+        let is_deferred = defer.should_defer(variables).unwrap_or(true);
+        match self {
+            DeferredFragments::Include => Some(self),
+            DeferredFragments::Skip => (!is_deferred).then(|| self),
+            // the fragments deferred inside of this one are sent in their own patches
+            DeferredFragments::Only(expected) if is_deferred => {
+                (label == expected).then(|| DeferredFragments::Skip)
+            }
+            DeferredFragments::Only(_) => Some(self),
+        }
+    }
+}
+
 /// A GraphQL query.
 #[derive(Debug, Derivative)]
 #[derivative(PartialEq, Hash, Eq)]
@@ -20,6 +48,11 @@ pub struct Query {
     fragments: Fragments,
     #[derivative(PartialEq = "ignore", Hash = "ignore")]
     operations: Vec<Operation>,
+    #[derivative(PartialEq = "ignore", Hash = "ignore")]
+    shape: QueryShape,
+    /// The field arguments whose literal cannot be coerced to the type of the argument.
+    #[derivative(PartialEq = "ignore", Hash = "ignore")]
+    invalid_arguments: Vec<FetchError>,
 }
 
 impl Query {
@@ -28,10 +61,19 @@ impl Query {
         self.string.as_str()
     }
 
+    /// The measured parts of the operations, for the operation limits.
+    pub(crate) fn shape(&self) -> &QueryShape {
+        &self.shape
+    }
+
     /// Re-format the response value to match this query.
     ///
     /// This will discard unrequested fields and re-order the output to match the order of the
     /// query.
+    ///
+    /// If the response is the primary part of an incremental delivery, the deferred fragments
+    /// are left out: their content will be sent in the following patches. If the response is
+    /// one of these patches, only the content of its deferred fragment is kept.
     #[tracing::instrument(skip_all, level = "trace")]
     pub fn format_response(
         &self,
@@ -42,16 +84,7 @@ impl Query {
     ) {
         let data = std::mem::take(&mut response.data);
         if let Some(Value::Object(mut input)) = data {
-            let operation = match operation_name {
-                Some(name) => self
-                    .operations
-                    .iter()
-                    // we should have an error if the only operation is anonymous but the query specifies a name
-                    .find(|op| op.name.is_some() && op.name.as_deref().unwrap() == name),
-                None => self.operations.get(0),
-            };
-
-            if let Some(operation) = operation {
+            if let Some(operation) = self.operation(operation_name) {
                 let mut output = Object::default();
 
                 let all_variables = if operation.variables.is_empty() {
@@ -66,18 +99,36 @@ impl Query {
                         .collect()
                 };
 
-                response.data = Some(
-                    match self.apply_root_selection_set(
+                let result = match &response.path {
+                    Some(path) => {
+                        let selection_set =
+                            self.selection_set_at(&operation.selection_set, &path.0);
+                        self.apply_selection_set(
+                            &selection_set,
+                            &all_variables,
+                            DeferredFragments::Only(response.label.as_deref()),
+                            &mut input,
+                            &mut output,
+                            schema,
+                        )
+                    }
+                    None => self.apply_root_selection_set(
                         operation,
                         &all_variables,
+                        if response.has_next.unwrap_or_default() {
+                            DeferredFragments::Skip
+                        } else {
+                            DeferredFragments::Include
+                        },
                         &mut input,
                         &mut output,
                         schema,
-                    ) {
-                        Ok(()) => output.into(),
-                        Err(InvalidValue) => Value::Null,
-                    },
-                );
+                    ),
+                };
+                response.data = Some(match result {
+                    Ok(()) => output.into(),
+                    Err(InvalidValue) => Value::Null,
+                });
 
                 return;
             } else {
@@ -89,6 +140,177 @@ impl Query {
 
         response.data = Some(Value::default());
     }
+
+    fn operation(&self, operation_name: Option<&str>) -> Option<&Operation> {
+        match operation_name {
+            Some(name) => self
+                .operations
+                .iter()
+                // we should have an error if the only operation is anonymous but the query specifies a name
+                .find(|op| op.name.is_some() && op.name.as_deref().unwrap() == name),
+            None => self.operations.get(0),
+        }
+    }
+
+    /// The selections of the fields found at the path of an incremental patch.
+    fn selection_set_at(
+        &self,
+        selection_set: &[Selection],
+        path: &[PathElement],
+    ) -> Vec<Selection> {
+        match path.split_first() {
+            None => selection_set.to_vec(),
+            Some((PathElement::Key(key), path)) => {
+                let mut fields = Vec::new();
+                self.collect_field_selections(selection_set, key, &mut fields);
+                self.selection_set_at(&fields, path)
+            }
+            Some((PathElement::Index(_) | PathElement::Flatten, path)) => {
+                self.selection_set_at(selection_set, path)
+            }
+        }
+    }
+
+    /// Collect the selections of the fields with this response key, through the fragments.
+    fn collect_field_selections(
+        &self,
+        selection_set: &[Selection],
+        key: &str,
+        fields: &mut Vec<Selection>,
+    ) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    ..
+                } => {
+                    if alias.as_ref().unwrap_or(name).as_str() == key {
+                        fields.extend(selection_set.iter().flatten().cloned());
+                    }
+                }
+                Selection::InlineFragment { fragment, .. } => {
+                    self.collect_field_selections(&fragment.selection_set, key, fields)
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    if let Some(fragment) = self.fragments.get(name) {
+                        self.collect_field_selections(&fragment.selection_set, key, fields)
+                    }
+                }
+            }
+        }
+    }
+
+    /// The labels and paths of the fragments deferred by an operation, in the order of the query.
+    ///
+    /// They are used to deliver incrementally a response that was executed at once.
+    pub fn deferred_fragments(
+        &self,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Vec<(Option<String>, Path)> {
+        let mut deferred = Vec::new();
+        if let Some(operation) = self.operation(operation_name) {
+            self.collect_deferred_fragments(
+                &operation.selection_set,
+                variables,
+                &mut Vec::new(),
+                &mut deferred,
+            );
+        }
+        deferred
+    }
+
+    fn collect_deferred_fragments(
+        &self,
+        selection_set: &[Selection],
+        variables: &Object,
+        path: &mut Vec<PathElement>,
+        deferred: &mut Vec<(Option<String>, Path)>,
+    ) {
+        for selection in selection_set {
+            let (selection_set, defer, defer_label) = match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set: Some(selection_set),
+                    field_type,
+                    skip,
+                    include,
+                } => {
+                    if skip.should_skip(variables).unwrap_or(false)
+                        || !include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+                    let depth = path.len();
+                    path.push(PathElement::Key(
+                        alias.as_ref().unwrap_or(name).as_str().to_string(),
+                    ));
+                    // the path goes through every element of the lists
+                    let mut field_type = field_type;
+                    loop {
+                        match field_type {
+                            FieldType::NonNull(inner_type) => field_type = &**inner_type,
+                            FieldType::List(inner_type) => {
+                                path.push(PathElement::Flatten);
+                                field_type = &**inner_type;
+                            }
+                            _ => break,
+                        }
+                    }
+                    self.collect_deferred_fragments(selection_set, variables, path, deferred);
+                    path.truncate(depth);
+                    continue;
+                }
+                Selection::Field { .. } => continue,
+                Selection::InlineFragment {
+                    fragment,
+                    defer,
+                    defer_label,
+                    ..
+                } => {
+                    if fragment.skip.should_skip(variables).unwrap_or(false)
+                        || !fragment.include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+                    (&fragment.selection_set, defer, defer_label)
+                }
+                Selection::FragmentSpread {
+                    name,
+                    skip,
+                    include,
+                    defer,
+                    defer_label,
+                    ..
+                } => {
+                    let fragment = match self.fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    if skip.should_skip(variables).unwrap_or(false)
+                        || !include.should_include(variables).unwrap_or(true)
+                        || fragment.skip.should_skip(variables).unwrap_or(false)
+                        || !fragment.include.should_include(variables).unwrap_or(true)
+                    {
+                        continue;
+                    }
+                    (&fragment.selection_set, defer, defer_label)
+                }
+            };
+
+            if defer.should_defer(variables).unwrap_or(true) {
+                let fragment = (defer_label.clone(), Path(path.clone()));
+                if !deferred.contains(&fragment) {
+                    deferred.push(fragment);
+                }
+            }
+            self.collect_deferred_fragments(selection_set, variables, path, deferred);
+        }
+    }
+
     pub fn parse(query: impl Into<String>, schema: &Schema) -> Option<Self> {
         let string = query.into();
 
@@ -106,6 +328,8 @@ impl Query {
 
         let document = tree.document();
         let fragments = Fragments::from_ast(&document, schema)?;
+        let shape = QueryShape::from_ast(&document);
+        let invalid_arguments = invalid_arguments(&document, schema);
 
         let operations = document
             .definitions()
@@ -122,6 +346,8 @@ impl Query {
             string,
             fragments,
             operations,
+            shape,
+            invalid_arguments,
         })
     }
 
@@ -129,6 +355,7 @@ impl Query {
         &self,
         field_type: &FieldType,
         variables: &Object,
+        deferred: DeferredFragments,
         input: &mut Value,
         output: &mut Value,
         selection_set: &[Selection],
@@ -141,8 +368,15 @@ impl Query {
             // we set it to null and immediately return an error instead of Ok(()), because we
             // want the error to go up until the next nullable parent
             FieldType::NonNull(inner_type) => {
-                match self.format_value(inner_type, variables, input, output, selection_set, schema)
-                {
+                match self.format_value(
+                    inner_type,
+                    variables,
+                    deferred,
+                    input,
+                    output,
+                    selection_set,
+                    schema,
+                ) {
                     Err(_) => Err(InvalidValue),
                     Ok(_) => {
                         if output.is_null() {
@@ -175,6 +409,7 @@ impl Query {
                             self.format_value(
                                 inner_type,
                                 variables,
+                                deferred,
                                 element,
                                 &mut output_array[i],
                                 selection_set,
@@ -234,6 +469,7 @@ impl Query {
                         match self.apply_selection_set(
                             selection_set,
                             variables,
+                            deferred,
                             input_object,
                             output_object,
                             schema,
@@ -310,6 +546,7 @@ impl Query {
         &self,
         selection_set: &[Selection],
         variables: &Object,
+        deferred: DeferredFragments,
         input: &mut Object,
         output: &mut Object,
         schema: &Schema,
@@ -324,6 +561,11 @@ impl Query {
                     skip,
                     include,
                 } => {
+                    // the fields outside of the deferred fragment were sent before the patch
+                    if let DeferredFragments::Only(_) = deferred {
+                        continue;
+                    }
+
                     let field_name = alias.as_ref().unwrap_or(name);
                     if skip
                         .should_skip(variables)
@@ -365,6 +607,7 @@ impl Query {
                             self.format_value(
                                 field_type,
                                 variables,
+                                deferred,
                                 input_value,
                                 output_value,
                                 selection_set,
@@ -389,7 +632,14 @@ impl Query {
                             include,
                         },
                     known_type,
+                    defer,
+                    defer_label,
                 } => {
+                    let deferred = match deferred.enter(defer, defer_label.as_deref(), variables) {
+                        Some(deferred) => deferred,
+                        None => continue,
+                    };
+
                     if skip
                         .should_skip(variables)
                         // validate_variables should have already checked that
@@ -428,7 +678,14 @@ impl Query {
                     };
 
                     if is_apply {
-                        self.apply_selection_set(selection_set, variables, input, output, schema)?;
+                        self.apply_selection_set(
+                            selection_set,
+                            variables,
+                            deferred,
+                            input,
+                            output,
+                            schema,
+                        )?;
                     }
                 }
                 Selection::FragmentSpread {
@@ -436,7 +693,14 @@ impl Query {
                     known_type,
                     skip,
                     include,
+                    defer,
+                    defer_label,
                 } => {
+                    let deferred = match deferred.enter(defer, defer_label.as_deref(), variables) {
+                        Some(deferred) => deferred,
+                        None => continue,
+                    };
+
                     if skip
                         .should_skip(variables)
                         // validate_variables should have already checked that
@@ -484,6 +748,7 @@ impl Query {
                             self.apply_selection_set(
                                 &fragment.selection_set,
                                 variables,
+                                deferred,
                                 input,
                                 output,
                                 schema,
@@ -504,6 +769,7 @@ impl Query {
         &self,
         operation: &Operation,
         variables: &Object,
+        deferred: DeferredFragments,
         input: &mut Object,
         output: &mut Object,
         schema: &Schema,
@@ -555,6 +821,7 @@ impl Query {
                         self.format_value(
                             field_type,
                             variables,
+                            deferred,
                             input_value,
                             output_value,
                             selection_set,
@@ -580,28 +847,51 @@ impl Query {
                             include: _,
                         },
                     known_type: _,
+                    defer,
+                    defer_label,
                 } => {
+                    let deferred = match deferred.enter(defer, defer_label.as_deref(), variables) {
+                        Some(deferred) => deferred,
+                        None => continue,
+                    };
+
                     // top level objects will not provide a __typename field
                     match (type_condition.as_str(), operation.kind) {
-                        ("Query", OperationKind::Query) | ("Mutation", OperationKind::Mutation) => {
-                        }
+                        ("Query", OperationKind::Query)
+                        | ("Mutation", OperationKind::Mutation)
+                        | ("Subscription", OperationKind::Subscription) => {}
                         _ => {
                             return Err(InvalidValue);
                         }
                     }
-                    self.apply_selection_set(selection_set, variables, input, output, schema)?;
+                    self.apply_selection_set(
+                        selection_set,
+                        variables,
+                        deferred,
+                        input,
+                        output,
+                        schema,
+                    )?;
                 }
                 Selection::FragmentSpread {
                     name,
                     known_type: _,
                     skip: _,
                     include: _,
+                    defer,
+                    defer_label,
                 } => {
+                    let deferred = match deferred.enter(defer, defer_label.as_deref(), variables) {
+                        Some(deferred) => deferred,
+                        None => continue,
+                    };
+
                     if let Some(fragment) = self.fragments.get(name) {
                         // top level objects will not provide a __typename field
                         match (fragment.type_condition.as_str(), operation.kind) {
                             ("Query", OperationKind::Query)
-                            | ("Mutation", OperationKind::Mutation) => {}
+                            | ("Mutation", OperationKind::Mutation)
+                            | ("Subscription", OperationKind::Subscription) => {}
                             _ => {
                                 return Err(InvalidValue);
                             }
@@ -609,6 +899,7 @@ impl Query {
                         self.apply_selection_set(
                             &fragment.selection_set,
                             variables,
+                            deferred,
                             input,
                             output,
                             schema,
@@ -627,6 +918,18 @@ impl Query {
     /// Validate a [`Request`]'s variables against this [`Query`] using a provided [`Schema`].
     #[tracing::instrument(skip_all, level = "trace")]
     pub fn validate_variables(&self, request: &Request, schema: &Schema) -> Result<(), Response> {
+        self.coerce_variables(request, schema).map(|_| ())
+    }
+
+    /// Coerce the variables of the request to the types of the operation variables, applying
+    /// the default values of the missing variables.
+    ///
+    /// The argument literals of the query were checked when it was parsed, their errors are
+    /// returned as well.
+    ///
+    /// Spec: https://spec.graphql.org/draft/#CoerceVariableValues()
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub fn coerce_variables(&self, request: &Request, schema: &Schema) -> Result<Object, Response> {
         let operation_name = request.operation_name.as_deref();
         let operation_variable_types =
             self.operations
@@ -656,21 +959,32 @@ impl Query {
             }
         }
 
-        let errors = operation_variable_types
+        let mut variables = (*request.variables).clone();
+        let mut errors = self
+            .invalid_arguments
             .iter()
-            .filter_map(|(name, (ty, _))| {
-                let value = request.variables.get(*name).unwrap_or(&Value::Null);
-                ty.validate_value(value, schema).err().map(|_| {
+            .map(|error| error.to_graphql_error(None))
+            .collect::<Vec<_>>();
+        for (name, (ty, default_value)) in operation_variable_types {
+            let value = request.variables.get(name).or(default_value.as_ref());
+            if value.is_none() && !ty.is_non_null() {
+                continue;
+            }
+            match ty.coerce_value(value.unwrap_or(&Value::Null), schema) {
+                Ok(value) => {
+                    variables.insert(name, value);
+                }
+                Err(_) => errors.push(
                     FetchError::ValidationInvalidTypeVariable {
                         name: name.to_string(),
                     }
-                    .to_graphql_error(None)
-                })
-            })
-            .collect::<Vec<_>>();
+                    .to_graphql_error(None),
+                ),
+            }
+        }
 
         if errors.is_empty() {
-            Ok(())
+            Ok(variables)
         } else {
             Err(Response::builder().errors(errors).build())
         }
@@ -710,7 +1024,7 @@ impl Operation {
         let current_field_type = match kind {
             OperationKind::Query => FieldType::Named("Query".to_string()),
             OperationKind::Mutation => FieldType::Named("Mutation".to_string()),
-            OperationKind::Subscription => return None,
+            OperationKind::Subscription => FieldType::Named("Subscription".to_string()),
         };
 
         let selection_set = operation
@@ -786,6 +1100,130 @@ impl From<ast::OperationType> for OperationType {
     }
 }
 
+/// The field arguments of the document whose literal cannot be coerced to the type of the
+/// argument.
+///
+/// Spec: https://spec.graphql.org/draft/#sec-Coercing-Field-Arguments
+fn invalid_arguments(document: &ast::Document, schema: &Schema) -> Vec<FetchError> {
+    let mut errors = Vec::new();
+    for definition in document.definitions() {
+        let (type_name, selection_set) = match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                let type_name = match operation.operation_type().map(OperationType::from) {
+                    None | Some(OperationType::Query) => "Query",
+                    Some(OperationType::Mutation) => "Mutation",
+                    Some(OperationType::Subscription) => "Subscription",
+                };
+                (Some(type_name.to_string()), operation.selection_set())
+            }
+            ast::Definition::FragmentDefinition(fragment) => (
+                fragment
+                    .type_condition()
+                    .and_then(|condition| condition.named_type())
+                    .and_then(|named_type| named_type.name())
+                    .map(|name| name.text().to_string()),
+                fragment.selection_set(),
+            ),
+            _ => continue,
+        };
+        if let (Some(type_name), Some(selection_set)) = (type_name, selection_set) {
+            collect_invalid_arguments(&selection_set, &type_name, schema, 0, &mut errors);
+        }
+    }
+    errors
+}
+
+fn collect_invalid_arguments(
+    selection_set: &ast::SelectionSet,
+    type_name: &str,
+    schema: &Schema,
+    depth: usize,
+    errors: &mut Vec<FetchError>,
+) {
+    // same limit as the parsing of the selections
+    const RECURSION_LIMIT: usize = 512;
+    if depth > RECURSION_LIMIT {
+        return;
+    }
+
+    for selection in selection_set.selections() {
+        match selection {
+            ast::Selection::Field(field) => {
+                let name = match field.name() {
+                    Some(name) => name.text().to_string(),
+                    None => continue,
+                };
+                let definition = schema
+                    .object_types
+                    .get(type_name)
+                    .and_then(|ty| Some((ty.field(&name)?, ty.arguments(&name)?)))
+                    .or_else(|| {
+                        schema
+                            .interfaces
+                            .get(type_name)
+                            .and_then(|ty| Some((ty.field(&name)?, ty.arguments(&name)?)))
+                    });
+                // introspection fields and unknown fields are left to the validation
+                let (field_type, arguments) = match definition {
+                    Some(definition) => definition,
+                    None => continue,
+                };
+
+                for argument in field
+                    .arguments()
+                    .iter()
+                    .flat_map(|arguments| arguments.arguments())
+                {
+                    let argument_name = argument
+                        .name()
+                        .map(|name| name.text().to_string())
+                        .unwrap_or_default();
+                    if let (Some(ty), Some(value)) =
+                        (arguments.get(&argument_name), argument.value())
+                    {
+                        if ty.validate_literal(&value, schema).is_err() {
+                            errors.push(FetchError::ValidationInvalidTypeArgument {
+                                field: format!("{}.{}", type_name, name),
+                                name: argument_name,
+                            });
+                        }
+                    }
+                }
+
+                if let (Some(inner_type), Some(selection_set)) =
+                    (field_type.inner_type_name(), field.selection_set())
+                {
+                    collect_invalid_arguments(
+                        &selection_set,
+                        inner_type,
+                        schema,
+                        depth + 1,
+                        errors,
+                    );
+                }
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                let type_condition = fragment
+                    .type_condition()
+                    .and_then(|condition| condition.named_type())
+                    .and_then(|named_type| named_type.name())
+                    .map(|name| name.text().to_string());
+                if let Some(selection_set) = fragment.selection_set() {
+                    collect_invalid_arguments(
+                        &selection_set,
+                        type_condition.as_deref().unwrap_or(type_name),
+                        schema,
+                        depth + 1,
+                        errors,
+                    );
+                }
+            }
+            // the fragment definitions are checked on their own
+            ast::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
 fn parse_default_value(definition: &ast::VariableDefinition) -> Option<Value> {
     definition
         .default_value()
@@ -793,13 +1231,14 @@ fn parse_default_value(definition: &ast::VariableDefinition) -> Option<Value> {
         .and_then(|value| parse_value(&value))
 }
 
-fn parse_value(value: &ast::Value) -> Option<Value> {
+pub(crate) fn parse_value(value: &ast::Value) -> Option<Value> {
     match value {
         ast::Value::Variable(_) => None,
-        ast::Value::StringValue(s) => Some(s.to_string().into()),
-        ast::Value::FloatValue(f) => f.to_string().parse::<f64>().ok().map(Into::into),
+        ast::Value::StringValue(s) => Some(string_value(&s.to_string()).into()),
+        ast::Value::FloatValue(f) => f.to_string().trim().parse::<f64>().ok().map(Into::into),
         ast::Value::IntValue(i) => {
             let s = i.to_string();
+            let s = s.trim();
             s.parse::<i64>()
                 .ok()
                 .map(Into::into)
@@ -1332,10 +1771,10 @@ mod tests {
         assert_validation!(schema, "query($foo:String){x}", json!({"foo": "str"}));
         assert_validation!(schema, "query($foo:Float){x}", json!({"foo":2.0}));
         assert_validation!(schema, "query($foo:Float){x}", json!({"foo":"2.0"}));
-        assert_validation_error!(schema, "query($foo:Float){x}", json!({"foo":2}));
+        assert_validation!(schema, "query($foo:Float){x}", json!({"foo":2}));
         assert_validation_error!(schema, "query($foo:Int!){x}", json!({}));
         assert_validation!(schema, "query($foo:[Int]){x}", json!({}));
-        assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":1}));
+        assert_validation!(schema, "query($foo:[Int]){x}", json!({"foo":1}));
         assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":"str"}));
         assert_validation_error!(schema, "query($foo:[Int]){x}", json!({"foo":{}}));
         assert_validation_error!(schema, "query($foo:[Int]!){x}", json!({}));
@@ -1449,6 +1888,171 @@ mod tests {
         );
     }
 
+    #[test]
+    fn variable_coercion() {
+        let schema: Schema = with_supergraph_boilerplate(
+            "enum Role { ADMIN GUEST }
+            input Filter { name: String! role: Role = GUEST tags: [String] }
+            type Query { x: String }",
+        )
+        .parse()
+        .expect("could not parse schema");
+        let coerce = |query: &str, variables: Value| {
+            let request = Request::builder()
+                .variables(variables.as_object().unwrap().clone())
+                .query(Some(query.to_string()))
+                .build();
+            Query::parse(query, &schema)
+                .expect("could not parse query")
+                .coerce_variables(&request, &schema)
+                .map(Value::Object)
+        };
+
+        assert_eq!(
+            coerce(
+                "query($ids:[ID], $ratio:Float, $limit:Int = 10, $name:String = \"me\"){x}",
+                json!({"ids": 1, "ratio": 2}),
+            )
+            .unwrap(),
+            json!({"ids": ["1"], "ratio": 2.0, "limit": 10, "name": "me"})
+        );
+        assert_eq!(
+            coerce(
+                "query($filter:Filter){x}",
+                json!({"filter": {"name": "a", "tags": "b"}}),
+            )
+            .unwrap(),
+            json!({"filter": {"name": "a", "tags": ["b"], "role": "GUEST"}})
+        );
+        // unknown input fields and enum values
+        assert!(coerce(
+            "query($filter:Filter){x}",
+            json!({"filter": {"name": "a", "unknown": 1}}),
+        )
+        .is_err());
+        assert!(coerce(
+            "query($filter:Filter){x}",
+            json!({"filter": {"name": "a", "role": "OWNER"}}),
+        )
+        .is_err());
+        // missing required input fields, and null for a non null variable
+        assert!(coerce("query($filter:Filter){x}", json!({"filter": {}})).is_err());
+        assert!(coerce("query($name:String!){x}", json!({ "name": null })).is_err());
+        assert!(coerce("query($name:String!){x}", json!({})).is_err());
+        // Int values must fit in 32 bits, strings are parsed
+        assert!(coerce("query($limit:Int){x}", json!({"limit": 2147483648_i64})).is_err());
+        assert_eq!(
+            coerce(
+                "query($limit:Int, $ok:Boolean){x}",
+                json!({"limit": "3", "ok": "true"})
+            )
+            .unwrap(),
+            json!({"limit": 3, "ok": true})
+        );
+        // every level of nested lists is coerced
+        assert_eq!(
+            coerce("query($ids:[[ID]]){x}", json!({"ids": [1, [2]]})).unwrap(),
+            json!({"ids": [["1"], ["2"]]})
+        );
+    }
+
+    #[test]
+    fn argument_literal_coercion() {
+        let schema: Schema = with_supergraph_boilerplate(
+            "enum Role { ADMIN GUEST }
+            input Filter { name: String! role: Role = GUEST tags: [String] }
+            type Query {
+                user(id: ID!): User
+                search(filter: Filter, limit: Int, ratio: Float, roles: [Role!]): [User]
+            }
+            type User { name(upper: Boolean): String }",
+        )
+        .parse()
+        .expect("could not parse schema");
+        let errors = |query: &str| {
+            let request = Request::builder().query(Some(query.to_string())).build();
+            match Query::parse(query, &schema)
+                .expect("could not parse query")
+                .coerce_variables(&request, &schema)
+            {
+                Ok(_) => Vec::new(),
+                Err(response) => response
+                    .errors
+                    .into_iter()
+                    .map(|error| error.message)
+                    .collect(),
+            }
+        };
+        let invalid = |field: &str, name: &str| {
+            vec![format!(
+                "invalid value for argument '{}' of field '{}'",
+                name, field
+            )]
+        };
+
+        // an Int literal is a valid ID
+        assert!(errors("{ user(id: 1) { name } }").is_empty());
+        assert!(errors(r#"{ user(id: "1") { name } }"#).is_empty());
+        assert_eq!(
+            errors("{ user(id: 1.5) { name } }"),
+            invalid("Query.user", "id")
+        );
+        assert_eq!(
+            errors("{ user(id: null) { name } }"),
+            invalid("Query.user", "id")
+        );
+        // Int literals must fit in 32 bits, and are not parsed from strings
+        assert_eq!(
+            errors("{ search(limit: 2147483648) { name } }"),
+            invalid("Query.search", "limit")
+        );
+        assert_eq!(
+            errors(r#"{ search(limit: "3") { name } }"#),
+            invalid("Query.search", "limit")
+        );
+        // an Int literal is a valid Float
+        assert!(errors("{ search(ratio: 1) { name } }").is_empty());
+        // enum values must be enum literals of the enum
+        assert!(errors("{ search(roles: [ADMIN, GUEST]) { name } }").is_empty());
+        assert_eq!(
+            errors(r#"{ search(roles: ["ADMIN"]) { name } }"#),
+            invalid("Query.search", "roles")
+        );
+        assert_eq!(
+            errors("{ search(roles: [OWNER]) { name } }"),
+            invalid("Query.search", "roles")
+        );
+        // a single value is coerced to a list
+        assert!(errors("{ search(roles: ADMIN) { name } }").is_empty());
+        assert_eq!(
+            errors("{ search(roles: [null]) { name } }"),
+            invalid("Query.search", "roles")
+        );
+        // input objects need their required fields, and only accept known fields
+        assert!(errors(r#"{ search(filter: { name: "a", tags: "b" }) { name } }"#).is_empty());
+        assert_eq!(
+            errors("{ search(filter: { role: ADMIN }) { name } }"),
+            invalid("Query.search", "filter")
+        );
+        assert_eq!(
+            errors(r#"{ search(filter: { name: "a", unknown: 1 }) { name } }"#),
+            invalid("Query.search", "filter")
+        );
+        // variables are coerced on their own, nested fields and fragments are checked
+        assert!(errors(
+            r#"query($name: String = "a") { search(filter: { name: $name }) { name } }"#
+        )
+        .is_empty());
+        assert_eq!(
+            errors(r#"{ user(id: 1) { name(upper: "yes") } }"#),
+            invalid("User.name", "upper")
+        );
+        assert_eq!(
+            errors(r#"{ ...F } fragment F on Query { search(ratio: "1") { name } }"#),
+            invalid("Query.search", "ratio")
+        );
+    }
+
     #[test]
     fn filter_root_errors() {
         let schema = "type Query {
@@ -4263,4 +4867,161 @@ mod tests {
             }},
         );
     }
+
+    #[test]
+    fn defer() {
+        let schema = with_supergraph_boilerplate(
+            "type Query {
+            get: Product
+        }
+
+        type Product {
+            id: String!
+            name: String
+            price: Int
+        }",
+        )
+        .parse::<Schema>()
+        .expect("could not parse schema");
+        let api_schema = schema.api_schema();
+        let query = Query::parse(
+            r#"query($shouldDefer: Boolean) {
+                get {
+                    id
+                    ... @defer(label: "name") {
+                        name
+                    }
+                    ... @defer(if: $shouldDefer) {
+                        price
+                    }
+                }
+            }"#,
+            &schema,
+        )
+        .expect("could not parse query");
+        let data = json! {{
+            "get": {
+                "id": "a",
+                "name": "Chair",
+                "price": 12,
+            },
+        }};
+
+        // the deferred fragments are removed from the primary response
+        let mut response = Response::builder()
+            .data(data.clone())
+            .has_next(true)
+            .build();
+        query.format_response(
+            &mut response,
+            None,
+            json! {{ "shouldDefer": false }}
+                .as_object()
+                .unwrap()
+                .clone(),
+            api_schema,
+        );
+        assert_eq_and_ordered!(
+            response.data.as_ref().unwrap(),
+            &json! {{
+                "get": {
+                    "id": "a",
+                    "price": 12,
+                },
+            }}
+        );
+
+        // but kept when the response is complete
+        let mut response = Response::builder().data(data.clone()).build();
+        query.format_response(
+            &mut response,
+            None,
+            json! {{ "shouldDefer": true }}.as_object().unwrap().clone(),
+            api_schema,
+        );
+        assert_eq_and_ordered!(response.data.as_ref().unwrap(), &data);
+
+        // the patches only contain the fields of their deferred fragment
+        let variables = json! {{ "shouldDefer": true }}.as_object().unwrap().clone();
+        let mut patch = Response::builder()
+            .label("name".to_string())
+            .path(Path::from("get"))
+            .data(json! {{ "id": "a", "name": "Chair", "price": 12 }})
+            .has_next(true)
+            .build();
+        query.format_response(&mut patch, None, variables.clone(), api_schema);
+        assert_eq_and_ordered!(patch.data.as_ref().unwrap(), &json! {{ "name": "Chair" }});
+
+        let mut patch = Response::builder()
+            .path(Path::from("get"))
+            .data(json! {{ "id": "a", "name": "Chair", "price": 12 }})
+            .has_next(false)
+            .build();
+        query.format_response(&mut patch, None, variables.clone(), api_schema);
+        assert_eq_and_ordered!(patch.data.as_ref().unwrap(), &json! {{ "price": 12 }});
+    }
+
+    #[test]
+    fn deferred_fragments() {
+        let schema = with_supergraph_boilerplate(
+            "type Query {
+            get: Product
+            list: [Product!]!
+        }
+
+        type Product {
+            id: String!
+            name: String
+            price: Int
+        }",
+        )
+        .parse::<Schema>()
+        .expect("could not parse schema");
+        let query = Query::parse(
+            r#"query($shouldDefer: Boolean) {
+                get {
+                    id
+                    ... @defer(label: "name") {
+                        name
+                    }
+                }
+                list {
+                    id
+                    ...Price @defer(if: $shouldDefer)
+                }
+            }
+
+            fragment Price on Product {
+                price
+            }"#,
+            &schema,
+        )
+        .expect("could not parse query");
+
+        assert_eq!(
+            query.deferred_fragments(None, json! {{ "shouldDefer": true }}.as_object().unwrap()),
+            vec![
+                (Some("name".to_string()), Path::from("get")),
+                (None, Path::from("list/@")),
+            ]
+        );
+        assert_eq!(
+            query.deferred_fragments(None, json! {{ "shouldDefer": false }}.as_object().unwrap()),
+            vec![(Some("name".to_string()), Path::from("get"))]
+        );
+
+        // the patch of a list element is formatted with the selections of the list
+        let mut patch = Response::builder()
+            .path(Path::from("list/1"))
+            .data(json! {{ "id": "b", "price": 5 }})
+            .has_next(false)
+            .build();
+        query.format_response(
+            &mut patch,
+            None,
+            json! {{ "shouldDefer": true }}.as_object().unwrap().clone(),
+            schema.api_schema(),
+        );
+        assert_eq_and_ordered!(patch.data.as_ref().unwrap(), &json! {{ "price": 5 }});
+    }
 }
@@ -1,5 +1,6 @@
 //! GraphQL schema.
 
+use super::parse_value;
 use crate::*;
 use apollo_parser::ast;
 use http::Uri;
@@ -236,6 +237,7 @@ impl std::str::FromStr for Schema {
                         .for_each(|extension| {
                             if let Some(instance) = map.get_mut(&extension.name) {
                                 instance.fields.extend(extension.fields);
+                                instance.default_values.extend(extension.default_values);
                                 instance.interfaces.extend(extension.interfaces);
                             } else {
                                 failfast_debug!(
@@ -291,6 +293,7 @@ impl std::str::FromStr for Schema {
                         .for_each(|extension| {
                             if let Some(instance) = map.get_mut(&extension.name) {
                                 instance.fields.extend(extension.fields);
+                                instance.default_values.extend(extension.default_values);
                             } else {
                                 failfast_debug!(
                                     concat!(
@@ -436,6 +439,8 @@ macro_rules! implement_object_type_or_interface {
         $visibility struct $name {
             pub(crate) name: String,
             fields: HashMap<String, FieldType>,
+            /// The types of the arguments of every field.
+            arguments: HashMap<String, HashMap<String, FieldType>>,
             interfaces: Vec<String>,
         }
 
@@ -443,6 +448,10 @@ macro_rules! implement_object_type_or_interface {
             pub(crate) fn field(&self, name: &str) -> Option<&FieldType> {
                 self.fields.get(name)
             }
+
+            pub(crate) fn arguments(&self, field: &str) -> Option<&HashMap<String, FieldType>> {
+                self.arguments.get(field)
+            }
         }
 
         $(
@@ -453,6 +462,7 @@ macro_rules! implement_object_type_or_interface {
                     .expect("the node Name is not optional in the spec; qed")
                     .text()
                     .to_string();
+                let mut arguments = HashMap::new();
                 let fields = definition
                     .fields_definition()
                     .iter()
@@ -467,6 +477,24 @@ macro_rules! implement_object_type_or_interface {
                             .ty()
                             .expect("the node Type is not optional in the spec; qed")
                             .into();
+                        let field_arguments = x
+                            .arguments_definition()
+                            .iter()
+                            .flat_map(|x| x.input_value_definitions())
+                            .map(|x| {
+                                let name = x
+                                    .name()
+                                    .expect("the node Name is not optional in the spec; qed")
+                                    .text()
+                                    .to_string();
+                                let ty = x
+                                    .ty()
+                                    .expect("the node Type is not optional in the spec; qed")
+                                    .into();
+                                (name, ty)
+                            })
+                            .collect();
+                        arguments.insert(name.clone(), field_arguments);
                         (name, ty)
                     })
                     .collect();
@@ -485,6 +513,7 @@ macro_rules! implement_object_type_or_interface {
                 $name {
                     name,
                     fields,
+                    arguments,
                     interfaces,
                 }
             }
@@ -514,22 +543,78 @@ macro_rules! implement_input_object_type_or_interface {
         $visibility struct $name {
             name: String,
             fields: HashMap<String, FieldType>,
+            default_values: HashMap<String, Value>,
         }
 
         impl $name {
-            pub(crate) fn validate_object(
+            /// Coerce an input object, rejecting unknown fields and applying the default values
+            /// of the missing fields.
+            ///
+            /// Spec: https://spec.graphql.org/draft/#sec-Input-Objects.Input-Coercion
+            pub(crate) fn coerce_object(
                 &self,
                 object: &Object,
                 schema: &Schema,
+            ) -> Result<Object, InvalidObject> {
+                let mut coerced = Object::with_capacity(self.fields.len());
+                for (name, value) in object {
+                    let ty = self.fields.get(name.as_str()).ok_or(InvalidObject)?;
+                    let value = ty.coerce_value(value, schema).map_err(|_| InvalidObject)?;
+                    coerced.insert(name.clone(), value);
+                }
+
+                for (name, ty) in &self.fields {
+                    if object.contains_key(name.as_str()) {
+                        continue;
+                    }
+                    match self.default_values.get(name) {
+                        Some(default_value) => {
+                            let value = ty
+                                .coerce_value(default_value, schema)
+                                .map_err(|_| InvalidObject)?;
+                            coerced.insert(name.as_str(), value);
+                        }
+                        None if ty.is_non_null() => return Err(InvalidObject),
+                        None => {}
+                    }
+                }
+
+                Ok(coerced)
+            }
+
+            /// Check that an input object literal of the query can be coerced to this type.
+            ///
+            /// Spec: https://spec.graphql.org/draft/#sec-Input-Objects.Input-Coercion
+            pub(crate) fn validate_literal(
+                &self,
+                object: &ast::ObjectValue,
+                schema: &Schema,
             ) -> Result<(), InvalidObject> {
-                self
-                    .fields
-                    .iter()
-                    .try_for_each(|(name, ty)| {
-                        let value = object.get(name.as_str()).unwrap_or(&Value::Null);
-                        ty.validate_value(value, schema)
-                    })
-                    .map_err(|_| InvalidObject)
+                let mut names = HashSet::new();
+                for field in object.object_fields() {
+                    let name = field
+                        .name()
+                        .expect("the node Name is not optional in the spec; qed")
+                        .text()
+                        .to_string();
+                    let ty = self.fields.get(&name).ok_or(InvalidObject)?;
+                    if let Some(value) = field.value() {
+                        ty.validate_literal(&value, schema)
+                            .map_err(|_| InvalidObject)?;
+                    }
+                    names.insert(name);
+                }
+
+                for (name, ty) in &self.fields {
+                    if ty.is_non_null()
+                        && !names.contains(name)
+                        && !self.default_values.contains_key(name)
+                    {
+                        return Err(InvalidObject);
+                    }
+                }
+
+                Ok(())
             }
         }
 
@@ -541,6 +626,7 @@ macro_rules! implement_input_object_type_or_interface {
                     .expect("the node Name is not optional in the spec; qed")
                     .text()
                     .to_string();
+                let mut default_values = HashMap::new();
                 let fields = definition
                     .input_fields_definition()
                     .iter()
@@ -555,6 +641,13 @@ macro_rules! implement_input_object_type_or_interface {
                             .ty()
                             .expect("the node Type is not optional in the spec; qed")
                             .into();
+                        if let Some(default_value) = x
+                            .default_value()
+                            .and_then(|default_value| default_value.value())
+                            .and_then(|value| parse_value(&value))
+                        {
+                            default_values.insert(name.clone(), default_value);
+                        }
                         (name, ty)
                     })
                     .collect();
@@ -562,6 +655,7 @@ macro_rules! implement_input_object_type_or_interface {
                 $name {
                     name,
                     fields,
+                    default_values,
                 }
             }
         }
@@ -1,3 +1,4 @@
+use crate::introspection::schema::string_value;
 use crate::{FieldType, Fragment, Object, Schema};
 use apollo_parser::ast::{self, Value};
 use serde_json_bytes::ByteString;
@@ -15,12 +16,16 @@ pub(crate) enum Selection {
     InlineFragment {
         fragment: Fragment,
         known_type: bool,
+        defer: Defer,
+        defer_label: Option<String>,
     },
     FragmentSpread {
         name: String,
         known_type: Option<String>,
         skip: Skip,
         include: Include,
+        defer: Defer,
+        defer_label: Option<String>,
     },
 }
 
@@ -170,6 +175,18 @@ impl Selection {
                     })
                     .unwrap_or(Include::Yes);
 
+                let (defer, defer_label) = inline_fragment
+                    .directives()
+                    .map(|directives| {
+                        for directive in directives.directives() {
+                            if let Some(defer) = parse_defer(&directive) {
+                                return (defer, parse_defer_label(&directive));
+                            }
+                        }
+                        (Defer::No, None)
+                    })
+                    .unwrap_or((Defer::No, None));
+
                 let known_type = current_type.inner_type_name() == Some(type_condition.as_str());
                 Some(Self::InlineFragment {
                     fragment: Fragment {
@@ -179,6 +196,8 @@ impl Selection {
                         include,
                     },
                     known_type,
+                    defer,
+                    defer_label,
                 })
             }
             // Spec: https://spec.graphql.org/draft/#FragmentSpread
@@ -214,11 +233,25 @@ impl Selection {
                     })
                     .unwrap_or(Include::Yes);
 
+                let (defer, defer_label) = fragment_spread
+                    .directives()
+                    .map(|directives| {
+                        for directive in directives.directives() {
+                            if let Some(defer) = parse_defer(&directive) {
+                                return (defer, parse_defer_label(&directive));
+                            }
+                        }
+                        (Defer::No, None)
+                    })
+                    .unwrap_or((Defer::No, None));
+
                 Some(Self::FragmentSpread {
                     name,
                     known_type: current_type.inner_type_name().map(|s| s.to_string()),
                     skip,
                     include,
+                    defer,
+                    defer_label,
                 })
             }
         }
@@ -336,3 +369,81 @@ impl Include {
         }
     }
 }
+
+pub(crate) fn parse_defer(directive: &ast::Directive) -> Option<Defer> {
+    if directive
+        .name()
+        .map(|name| &name.text().to_string() == "defer")
+        .unwrap_or(false)
+    {
+        for argument in directive
+            .arguments()
+            .into_iter()
+            .flat_map(|args| args.arguments())
+        {
+            if argument
+                .name()
+                .map(|name| &name.text().to_string() == "if")
+                .unwrap_or(false)
+            {
+                // invalid argument values should have been already validated
+                let res = match argument.value() {
+                    Some(Value::BooleanValue(b)) => {
+                        match (b.true_token().is_some(), b.false_token().is_some()) {
+                            (true, false) => Some(Defer::Yes),
+                            (false, true) => Some(Defer::No),
+                            _ => None,
+                        }
+                    }
+                    Some(Value::Variable(variable)) => variable
+                        .name()
+                        .map(|name| Defer::Variable(name.text().to_string())),
+                    _ => None,
+                };
+                return res;
+            }
+        }
+
+        // the `if` argument defaults to true
+        return Some(Defer::Yes);
+    }
+
+    None
+}
+
+/// The `label` argument of a `@defer` directive, identifying its incremental responses.
+pub(crate) fn parse_defer_label(directive: &ast::Directive) -> Option<String> {
+    directive
+        .arguments()
+        .into_iter()
+        .flat_map(|args| args.arguments())
+        .find(|argument| {
+            argument
+                .name()
+                .map(|name| &name.text().to_string() == "label")
+                .unwrap_or(false)
+        })
+        .and_then(|argument| match argument.value() {
+            Some(Value::StringValue(label)) => Some(string_value(&label.to_string())),
+            _ => None,
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Defer {
+    Yes,
+    No,
+    Variable(String),
+}
+
+impl Defer {
+    pub(crate) fn should_defer(&self, variables: &Object) -> Option<bool> {
+        match self {
+            Defer::Yes => Some(true),
+            Defer::No => Some(false),
+            Defer::Variable(variable_name) => variables
+                .get(variable_name.as_str())
+                .and_then(|v| v.as_bool()),
+        }
+    }
+}
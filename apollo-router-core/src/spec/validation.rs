@@ -0,0 +1,1161 @@
+//! Validation of the client operations against the API schema.
+//!
+//! Spec: https://spec.graphql.org/draft/#sec-Validation
+
+use crate::introspection::schema::{
+    DirectiveDefinition, FieldDefinition, InputValueDefinition, IntrospectionSchema,
+    TypeDefinition, TypeKind, TypeReference,
+};
+use crate::{Error, Location, Object, Value};
+use apollo_parser::ast::{self, AstNode};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Code of the errors of operations that are not valid GraphQL.
+pub(crate) const GRAPHQL_PARSE_FAILED: &str = "GRAPHQL_PARSE_FAILED";
+/// Code of the errors of operations that are not valid against the schema.
+pub(crate) const GRAPHQL_VALIDATION_FAILED: &str = "GRAPHQL_VALIDATION_FAILED";
+
+static SCHEMA_FIELD: Lazy<FieldDefinition> = Lazy::new(|| FieldDefinition {
+    name: "__schema".to_string(),
+    description: None,
+    args: Vec::new(),
+    ty: TypeReference::NonNull(Box::new(TypeReference::Named("__Schema".to_string()))),
+    deprecation_reason: None,
+});
+
+static TYPE_FIELD: Lazy<FieldDefinition> = Lazy::new(|| FieldDefinition {
+    name: "__type".to_string(),
+    description: None,
+    args: vec![InputValueDefinition {
+        name: "name".to_string(),
+        description: None,
+        ty: TypeReference::NonNull(Box::new(TypeReference::Named("String".to_string()))),
+        default_value: None,
+        deprecation_reason: None,
+    }],
+    ty: TypeReference::Named("__Type".to_string()),
+    deprecation_reason: None,
+});
+
+/// Validate an operation document, returning errors located in the document.
+pub(crate) fn validate(query: &str, schema: &IntrospectionSchema) -> Result<(), Vec<Error>> {
+    let tree = apollo_parser::Parser::new(query).parse();
+    let syntax_errors: Vec<Error> = tree
+        .errors()
+        .map(|error| {
+            graphql_error(
+                format!("Syntax Error: {}", error.message()),
+                vec![location(query, error.index())],
+                GRAPHQL_PARSE_FAILED,
+            )
+        })
+        .collect();
+    if !syntax_errors.is_empty() {
+        return Err(syntax_errors);
+    }
+
+    let mut validator = Validator {
+        query,
+        schema,
+        fragments: HashMap::new(),
+        errors: Vec::new(),
+        usages: Vec::new(),
+    };
+    validator.validate_document(&tree.document());
+
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+fn graphql_error(message: String, locations: Vec<Location>, code: &str) -> Error {
+    let mut extensions = Object::new();
+    extensions.insert("code", Value::String(code.into()));
+    Error::builder()
+        .message(message)
+        .locations(locations)
+        .extensions(extensions)
+        .build()
+}
+
+/// The line and column of an offset in the document, starting at 1.
+fn location(query: &str, offset: usize) -> Location {
+    let before = query.get(..offset).unwrap_or(query);
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|line| line.chars().count())
+        .unwrap_or(0)
+        + 1;
+    Location {
+        line: line as i32,
+        column: column as i32,
+    }
+}
+
+/// Variables used by a selection set, and the fragments it spreads.
+#[derive(Default)]
+struct Usages {
+    variables: Vec<VariableUsage>,
+    spreads: Vec<String>,
+}
+
+struct VariableUsage {
+    name: String,
+    /// The type of the argument or input field receiving the variable.
+    expected: TypeReference,
+    location: Location,
+}
+
+/// The operation or fragment whose selection set is validated.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Owner {
+    Operation(usize),
+    Fragment(String),
+}
+
+struct Validator<'a> {
+    query: &'a str,
+    schema: &'a IntrospectionSchema,
+    fragments: HashMap<String, ast::FragmentDefinition>,
+    errors: Vec<Error>,
+    usages: Vec<(Owner, Usages)>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, message: String, node: &impl AstNode) {
+        let location = self.location(node);
+        self.errors.push(graphql_error(
+            message,
+            vec![location],
+            GRAPHQL_VALIDATION_FAILED,
+        ));
+    }
+
+    fn location(&self, node: &impl AstNode) -> Location {
+        // nodes can start with the ignored tokens preceding them
+        let start = usize::from(node.syntax().text_range().start());
+        let rest = self.query.get(start..).unwrap_or_default();
+        let ignored = rest.len() - rest.trim_start_matches(is_ignored).len();
+        location(self.query, start + ignored)
+    }
+
+    fn validate_document(&mut self, document: &ast::Document) {
+        let mut operations = Vec::new();
+        for definition in document.definitions() {
+            match definition {
+                ast::Definition::OperationDefinition(operation) => operations.push(operation),
+                ast::Definition::FragmentDefinition(fragment) => {
+                    let name = fragment_name(&fragment);
+                    // Spec: https://spec.graphql.org/draft/#sec-Fragment-Name-Uniqueness
+                    if self.fragments.contains_key(&name) {
+                        self.error(
+                            format!("There can be only one fragment named \"{}\".", name),
+                            &fragment,
+                        );
+                    } else {
+                        self.fragments.insert(name, fragment);
+                    }
+                }
+                // Spec: https://spec.graphql.org/draft/#sec-Executable-Definitions
+                definition => self.error(
+                    "Type system definitions are not executable.".to_string(),
+                    &definition,
+                ),
+            }
+        }
+
+        // Spec: https://spec.graphql.org/draft/#sec-Operation-Name-Uniqueness
+        // Spec: https://spec.graphql.org/draft/#sec-Lone-Anonymous-Operation
+        let mut names = HashSet::new();
+        for operation in &operations {
+            match operation.name() {
+                Some(name) => {
+                    let name = name.text().to_string();
+                    if !names.insert(name.clone()) {
+                        self.error(
+                            format!("There can be only one operation named \"{}\".", name),
+                            operation,
+                        );
+                    }
+                }
+                None if operations.len() > 1 => self.error(
+                    "This anonymous operation must be the only defined operation.".to_string(),
+                    operation,
+                ),
+                None => {}
+            }
+        }
+
+        let mut fragments: Vec<_> = self.fragments.values().cloned().collect();
+        fragments.sort_by_key(|fragment| usize::from(fragment.syntax().text_range().start()));
+        for fragment in &fragments {
+            self.validate_fragment(fragment);
+        }
+
+        for (index, operation) in operations.iter().enumerate() {
+            self.validate_operation(index, operation);
+        }
+
+        self.validate_fragment_spreads(&fragments, operations.len());
+    }
+
+    fn validate_fragment(&mut self, fragment: &ast::FragmentDefinition) {
+        let name = fragment_name(fragment);
+        let mut usages = Usages::default();
+        self.validate_directives(fragment.directives(), "FRAGMENT_DEFINITION", &mut usages);
+
+        let type_condition = fragment.type_condition();
+        if let Some(ty) = self.type_condition(type_condition.as_ref(), Some(&name)) {
+            if let Some(selection_set) = fragment.selection_set() {
+                self.validate_selection_set(ty, &selection_set, &mut usages);
+            }
+        }
+        self.usages.push((Owner::Fragment(name), usages));
+    }
+
+    fn validate_operation(&mut self, index: usize, operation: &ast::OperationDefinition) {
+        let mut usages = Usages::default();
+        let (kind, location) = match operation.operation_type() {
+            Some(kind) if kind.mutation_token().is_some() => ("mutation", "MUTATION"),
+            Some(kind) if kind.subscription_token().is_some() => ("subscription", "SUBSCRIPTION"),
+            _ => ("query", "QUERY"),
+        };
+        self.validate_directives(operation.directives(), location, &mut usages);
+
+        // Spec: https://spec.graphql.org/draft/#sec-Validation.Variables
+        let mut variables: HashMap<String, (TypeReference, bool)> = HashMap::new();
+        for definition in operation
+            .variable_definitions()
+            .iter()
+            .flat_map(|definitions| definitions.variable_definitions())
+        {
+            let name = match definition.variable().and_then(|variable| variable.name()) {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+            let ty: TypeReference = match definition.ty() {
+                Some(ty) => ty.into(),
+                None => continue,
+            };
+            if variables.contains_key(&name) {
+                self.error(
+                    format!("There can be only one variable named \"${}\".", name),
+                    &definition,
+                );
+                continue;
+            }
+
+            match self.schema.types.get(named_type(&ty)) {
+                None => self.error(
+                    format!("Unknown type \"{}\".", named_type(&ty)),
+                    &definition,
+                ),
+                Some(definition_type)
+                    if !matches!(
+                        definition_type.kind,
+                        TypeKind::Scalar | TypeKind::Enum | TypeKind::InputObject
+                    ) =>
+                {
+                    self.error(
+                        format!(
+                            "Variable \"${}\" cannot be non-input type \"{}\".",
+                            name,
+                            DisplayType(&ty)
+                        ),
+                        &definition,
+                    )
+                }
+                Some(_) => {
+                    if let Some(value) = definition
+                        .default_value()
+                        .and_then(|default_value| default_value.value())
+                    {
+                        // default values cannot use variables, no usage is recorded
+                        let mut default_usages = Usages::default();
+                        self.validate_value(&value, &ty, &mut default_usages);
+                        for usage in default_usages.variables {
+                            self.errors.push(graphql_error(
+                                format!(
+                                    "Variable \"${}\" cannot be used in a default value.",
+                                    usage.name
+                                ),
+                                vec![usage.location],
+                                GRAPHQL_VALIDATION_FAILED,
+                            ));
+                        }
+                    }
+                }
+            }
+            self.validate_directives(definition.directives(), "VARIABLE_DEFINITION", &mut usages);
+            let has_default = definition.default_value().is_some();
+            variables.insert(name, (ty, has_default));
+        }
+
+        let root_type = match kind {
+            "mutation" => self.schema.mutation_type.as_ref(),
+            "subscription" => self.schema.subscription_type.as_ref(),
+            _ => Some(&self.schema.query_type),
+        }
+        .and_then(|name| self.schema.types.get(name));
+        match root_type {
+            Some(root_type) => {
+                if let Some(selection_set) = operation.selection_set() {
+                    self.validate_selection_set(root_type, &selection_set, &mut usages);
+                }
+            }
+            None => self.error(
+                format!("Schema is not configured for {}s.", kind),
+                operation,
+            ),
+        }
+
+        let operation_name = operation.name().map(|name| name.text().to_string());
+        self.usages.push((Owner::Operation(index), usages));
+        let usages = self.transitive_usages(&Owner::Operation(index));
+
+        // Spec: https://spec.graphql.org/draft/#sec-All-Variable-Uses-Defined
+        // Spec: https://spec.graphql.org/draft/#sec-All-Variable-Usages-Are-Allowed
+        let mut used = HashSet::new();
+        for usage in usages {
+            used.insert(usage.name.clone());
+            let message = match variables.get(&usage.name) {
+                None => Some(match &operation_name {
+                    Some(operation_name) => format!(
+                        "Variable \"${}\" is not defined by operation \"{}\".",
+                        usage.name, operation_name
+                    ),
+                    None => format!("Variable \"${}\" is not defined.", usage.name),
+                }),
+                Some((ty, has_default)) if !is_allowed(ty, *has_default, &usage.expected) => {
+                    Some(format!(
+                        "Variable \"${}\" of type \"{}\" used in position expecting type \"{}\".",
+                        usage.name,
+                        DisplayType(ty),
+                        DisplayType(&usage.expected)
+                    ))
+                }
+                Some(_) => None,
+            };
+            if let Some(message) = message {
+                self.errors.push(graphql_error(
+                    message,
+                    vec![usage.location],
+                    GRAPHQL_VALIDATION_FAILED,
+                ));
+            }
+        }
+
+        // Spec: https://spec.graphql.org/draft/#sec-All-Variables-Used
+        for definition in operation
+            .variable_definitions()
+            .iter()
+            .flat_map(|definitions| definitions.variable_definitions())
+        {
+            if let Some(name) = definition.variable().and_then(|variable| variable.name()) {
+                let name = name.text().to_string();
+                if !used.contains(&name) {
+                    let message = match &operation_name {
+                        Some(operation_name) => format!(
+                            "Variable \"${}\" is never used in operation \"{}\".",
+                            name, operation_name
+                        ),
+                        None => format!("Variable \"${}\" is never used.", name),
+                    };
+                    self.error(message, &definition);
+                }
+            }
+        }
+    }
+
+    /// The variables used by an operation or a fragment, and by the fragments they spread.
+    fn transitive_usages(&self, owner: &Owner) -> Vec<VariableUsage> {
+        let mut result = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![owner.clone()];
+        while let Some(owner) = stack.pop() {
+            if !visited.insert(owner.clone()) {
+                continue;
+            }
+            if let Some((_, usages)) = self.usages.iter().find(|(o, _)| *o == owner) {
+                result.extend(usages.variables.iter().map(|usage| VariableUsage {
+                    name: usage.name.clone(),
+                    expected: usage.expected.clone(),
+                    location: usage.location.clone(),
+                }));
+                stack.extend(usages.spreads.iter().cloned().map(Owner::Fragment));
+            }
+        }
+        result
+    }
+
+    fn validate_fragment_spreads(
+        &mut self,
+        fragments: &[ast::FragmentDefinition],
+        operations: usize,
+    ) {
+        let spreads = |owner: &Owner| -> Vec<String> {
+            self.usages
+                .iter()
+                .find(|(o, _)| o == owner)
+                .map(|(_, usages)| usages.spreads.clone())
+                .unwrap_or_default()
+        };
+
+        // Spec: https://spec.graphql.org/draft/#sec-Fragments-Must-Be-Used
+        let mut used = HashSet::new();
+        let mut stack: Vec<Owner> = (0..operations).map(Owner::Operation).collect();
+        while let Some(owner) = stack.pop() {
+            for spread in spreads(&owner) {
+                if used.insert(spread.clone()) {
+                    stack.push(Owner::Fragment(spread));
+                }
+            }
+        }
+
+        // Spec: https://spec.graphql.org/draft/#sec-Fragment-spreads-must-not-form-cycles
+        let mut errors = Vec::new();
+        for fragment in fragments {
+            let name = fragment_name(fragment);
+            if !used.contains(&name) {
+                errors.push((format!("Fragment \"{}\" is never used.", name), fragment));
+            }
+
+            let mut visited = HashSet::new();
+            let mut stack = spreads(&Owner::Fragment(name.clone()));
+            while let Some(spread) = stack.pop() {
+                if spread == name {
+                    errors.push((
+                        format!("Cannot spread fragment \"{}\" within itself.", name),
+                        fragment,
+                    ));
+                    break;
+                }
+                if visited.insert(spread.clone()) {
+                    stack.extend(spreads(&Owner::Fragment(spread)));
+                }
+            }
+        }
+        for (message, fragment) in errors {
+            self.error(message, fragment);
+        }
+    }
+
+    /// The type of a fragment, if it exists and is composite.
+    fn type_condition(
+        &mut self,
+        type_condition: Option<&ast::TypeCondition>,
+        fragment_name: Option<&str>,
+    ) -> Option<&'a TypeDefinition> {
+        let type_condition = type_condition?;
+        let name = type_condition
+            .named_type()
+            .and_then(|named| named.name())
+            .map(|name| name.text().to_string())?;
+
+        // Spec: https://spec.graphql.org/draft/#sec-Fragment-Spread-Type-Existence
+        // Spec: https://spec.graphql.org/draft/#sec-Fragments-On-Composite-Types
+        match self.schema.types.get(&name) {
+            None => {
+                self.error(format!("Unknown type \"{}\".", name), type_condition);
+                None
+            }
+            Some(ty) if !is_composite(ty) => {
+                let message = match fragment_name {
+                    Some(fragment_name) => format!(
+                        "Fragment \"{}\" cannot condition on non composite type \"{}\".",
+                        fragment_name, name
+                    ),
+                    None => format!(
+                        "Fragment cannot condition on non composite type \"{}\".",
+                        name
+                    ),
+                };
+                self.error(message, type_condition);
+                None
+            }
+            Some(ty) => Some(ty),
+        }
+    }
+
+    fn validate_selection_set(
+        &mut self,
+        parent: &'a TypeDefinition,
+        selection_set: &ast::SelectionSet,
+        usages: &mut Usages,
+    ) {
+        for selection in selection_set.selections() {
+            match selection {
+                ast::Selection::Field(field) => self.validate_field(parent, &field, usages),
+                ast::Selection::InlineFragment(fragment) => {
+                    self.validate_directives(fragment.directives(), "INLINE_FRAGMENT", usages);
+                    let ty = match fragment.type_condition() {
+                        Some(type_condition) => self.type_condition(Some(&type_condition), None),
+                        None => Some(parent),
+                    };
+                    if let (Some(ty), Some(selection_set)) = (ty, fragment.selection_set()) {
+                        self.validate_selection_set(ty, &selection_set, usages);
+                    }
+                }
+                ast::Selection::FragmentSpread(spread) => {
+                    self.validate_directives(spread.directives(), "FRAGMENT_SPREAD", usages);
+                    let name = spread
+                        .fragment_name()
+                        .and_then(|fragment_name| fragment_name.name())
+                        .map(|name| name.text().to_string())
+                        .unwrap_or_default();
+                    // Spec: https://spec.graphql.org/draft/#sec-Fragment-spread-target-defined
+                    if self.fragments.contains_key(&name) {
+                        usages.spreads.push(name);
+                    } else {
+                        self.error(format!("Unknown fragment \"{}\".", name), &spread);
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_field(
+        &mut self,
+        parent: &'a TypeDefinition,
+        field: &ast::Field,
+        usages: &mut Usages,
+    ) {
+        let name = match field.name() {
+            Some(name) => name.text().to_string(),
+            None => return,
+        };
+        self.validate_directives(field.directives(), "FIELD", usages);
+
+        // Spec: https://spec.graphql.org/draft/#sec-Field-Selections
+        let definition: &'a FieldDefinition = if name == "__typename" {
+            if let Some(selection_set) = field.selection_set() {
+                self.error(
+                    "Field \"__typename\" must not have a selection since type \"String!\" has no subfields.".to_string(),
+                    &selection_set,
+                );
+            }
+            self.validate_arguments(field.arguments(), &[], &name, usages);
+            return;
+        } else if name == "__schema" && parent.name == self.schema.query_type {
+            &SCHEMA_FIELD
+        } else if name == "__type" && parent.name == self.schema.query_type {
+            &TYPE_FIELD
+        } else {
+            match parent
+                .fields
+                .iter()
+                .find(|definition| definition.name == name)
+            {
+                Some(definition) => definition,
+                None => {
+                    self.error(
+                        format!(
+                            "Cannot query field \"{}\" on type \"{}\".",
+                            name, parent.name
+                        ),
+                        field,
+                    );
+                    return;
+                }
+            }
+        };
+
+        let coordinate = format!("{}.{}", parent.name, name);
+        self.validate_arguments(field.arguments(), &definition.args, &coordinate, usages);
+        // Spec: https://spec.graphql.org/draft/#sec-Required-Arguments
+        for argument in &definition.args {
+            let provided = field
+                .arguments()
+                .iter()
+                .flat_map(|arguments| arguments.arguments())
+                .any(|provided| argument_name(&provided) == argument.name);
+            if !provided && is_required(argument) {
+                self.error(
+                    format!(
+                        "Field \"{}\" argument \"{}\" of type \"{}\" is required, but it was not provided.",
+                        name,
+                        argument.name,
+                        DisplayType(&argument.ty)
+                    ),
+                    field,
+                );
+            }
+        }
+
+        // Spec: https://spec.graphql.org/draft/#sec-Leaf-Field-Selections
+        let ty = match self.schema.types.get(named_type(&definition.ty)) {
+            Some(ty) => ty,
+            None => return,
+        };
+        match (is_composite(ty), field.selection_set()) {
+            (true, Some(selection_set)) => self.validate_selection_set(ty, &selection_set, usages),
+            (true, None) => self.error(
+                format!(
+                    "Field \"{}\" of type \"{}\" must have a selection of subfields. Did you mean \"{} {{ ... }}\"?",
+                    name,
+                    DisplayType(&definition.ty),
+                    name
+                ),
+                field,
+            ),
+            (false, Some(selection_set)) => self.error(
+                format!(
+                    "Field \"{}\" must not have a selection since type \"{}\" has no subfields.",
+                    name,
+                    DisplayType(&definition.ty)
+                ),
+                &selection_set,
+            ),
+            (false, None) => {}
+        }
+    }
+
+    fn validate_arguments(
+        &mut self,
+        arguments: Option<ast::Arguments>,
+        definitions: &[InputValueDefinition],
+        coordinate: &str,
+        usages: &mut Usages,
+    ) {
+        let mut names = HashSet::new();
+        for argument in arguments.iter().flat_map(|arguments| arguments.arguments()) {
+            let name = argument_name(&argument);
+            // Spec: https://spec.graphql.org/draft/#sec-Argument-Uniqueness
+            if !names.insert(name.clone()) {
+                self.error(
+                    format!("There can be only one argument named \"{}\".", name),
+                    &argument,
+                );
+                continue;
+            }
+            // Spec: https://spec.graphql.org/draft/#sec-Argument-Names
+            match definitions
+                .iter()
+                .find(|definition| definition.name == name)
+            {
+                Some(definition) => {
+                    if let Some(value) = argument.value() {
+                        self.validate_value(&value, &definition.ty, usages);
+                    }
+                }
+                None => {
+                    let message = match coordinate.strip_prefix('@') {
+                        Some(directive) => {
+                            format!(
+                                "Unknown argument \"{}\" on directive \"@{}\".",
+                                name, directive
+                            )
+                        }
+                        None => {
+                            format!("Unknown argument \"{}\" on field \"{}\".", name, coordinate)
+                        }
+                    };
+                    self.error(message, &argument);
+                }
+            }
+        }
+    }
+
+    // Spec: https://spec.graphql.org/draft/#sec-Validation.Directives
+    fn validate_directives(
+        &mut self,
+        directives: Option<ast::Directives>,
+        location: &str,
+        usages: &mut Usages,
+    ) {
+        let mut names = HashSet::new();
+        for directive in directives
+            .iter()
+            .flat_map(|directives| directives.directives())
+        {
+            let name = match directive.name() {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+            let definition: &'a DirectiveDefinition = match self
+                .schema
+                .directives
+                .iter()
+                .find(|definition| definition.name == name)
+            {
+                Some(definition) => definition,
+                None => {
+                    self.error(format!("Unknown directive \"@{}\".", name), &directive);
+                    continue;
+                }
+            };
+            if !definition
+                .locations
+                .iter()
+                .any(|allowed| allowed == location)
+            {
+                self.error(
+                    format!("Directive \"@{}\" may not be used on {}.", name, location),
+                    &directive,
+                );
+            }
+            if !names.insert(name.clone()) && !definition.is_repeatable {
+                self.error(
+                    format!(
+                        "The directive \"@{}\" can only be used once at this location.",
+                        name
+                    ),
+                    &directive,
+                );
+            }
+
+            self.validate_arguments(
+                directive.arguments(),
+                &definition.args,
+                &format!("@{}", name),
+                usages,
+            );
+            for argument in &definition.args {
+                let provided = directive
+                    .arguments()
+                    .iter()
+                    .flat_map(|arguments| arguments.arguments())
+                    .any(|provided| argument_name(&provided) == argument.name);
+                if !provided && is_required(argument) {
+                    self.error(
+                        format!(
+                            "Directive \"@{}\" argument \"{}\" of type \"{}\" is required, but it was not provided.",
+                            name,
+                            argument.name,
+                            DisplayType(&argument.ty)
+                        ),
+                        &directive,
+                    );
+                }
+            }
+        }
+    }
+
+    // Spec: https://spec.graphql.org/draft/#sec-Values-of-Correct-Type
+    fn validate_value(&mut self, value: &ast::Value, ty: &TypeReference, usages: &mut Usages) {
+        if let ast::Value::Variable(variable) = value {
+            if let Some(name) = variable.name() {
+                let location = self.location(variable);
+                usages.variables.push(VariableUsage {
+                    name: name.text().to_string(),
+                    expected: ty.clone(),
+                    location,
+                });
+            }
+            return;
+        }
+
+        match ty {
+            TypeReference::NonNull(inner) => {
+                if matches!(value, ast::Value::NullValue(_)) {
+                    self.error(
+                        format!(
+                            "Expected value of type \"{}\", found null.",
+                            DisplayType(ty)
+                        ),
+                        value,
+                    );
+                } else {
+                    self.validate_value(value, inner, usages);
+                }
+            }
+            TypeReference::List(inner) => match value {
+                ast::Value::ListValue(list) => {
+                    for item in list.values() {
+                        self.validate_value(&item, inner, usages);
+                    }
+                }
+                // input coercion accepts a single item for a list
+                value => self.validate_value(value, inner, usages),
+            },
+            TypeReference::Named(name) => {
+                let definition = match self.schema.types.get(name) {
+                    Some(definition) => definition,
+                    None => return,
+                };
+                let is_valid = match (definition.kind, value) {
+                    (_, ast::Value::NullValue(_)) => true,
+                    (TypeKind::Scalar, value) => match (name.as_str(), value) {
+                        ("Int", ast::Value::IntValue(int)) => {
+                            int.to_string().trim().parse::<i32>().is_ok()
+                        }
+                        ("Float", ast::Value::IntValue(_) | ast::Value::FloatValue(_)) => true,
+                        ("String", ast::Value::StringValue(_)) => true,
+                        ("Boolean", ast::Value::BooleanValue(_)) => true,
+                        ("ID", ast::Value::StringValue(_) | ast::Value::IntValue(_)) => true,
+                        ("Int" | "Float" | "String" | "Boolean" | "ID", _) => false,
+                        // custom scalars accept any literal
+                        _ => true,
+                    },
+                    (TypeKind::Enum, ast::Value::EnumValue(enum_value)) => {
+                        let enum_value = enum_value
+                            .name()
+                            .map(|name| name.text().to_string())
+                            .unwrap_or_default();
+                        definition
+                            .enum_values
+                            .iter()
+                            .any(|value| value.name == enum_value)
+                    }
+                    (TypeKind::InputObject, ast::Value::ObjectValue(object)) => {
+                        self.validate_input_object(definition, object, usages);
+                        true
+                    }
+                    _ => false,
+                };
+                if !is_valid {
+                    self.error(
+                        format!(
+                            "Expected value of type \"{}\", found {}.",
+                            name,
+                            value.syntax().text().to_string().trim_matches(is_ignored)
+                        ),
+                        value,
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate_input_object(
+        &mut self,
+        definition: &'a TypeDefinition,
+        object: &ast::ObjectValue,
+        usages: &mut Usages,
+    ) {
+        let mut names = HashSet::new();
+        for field in object.object_fields() {
+            let name = match field.name() {
+                Some(name) => name.text().to_string(),
+                None => continue,
+            };
+            // Spec: https://spec.graphql.org/draft/#sec-Input-Object-Field-Uniqueness
+            if !names.insert(name.clone()) {
+                self.error(
+                    format!("There can be only one input field named \"{}\".", name),
+                    &field,
+                );
+                continue;
+            }
+            // Spec: https://spec.graphql.org/draft/#sec-Input-Object-Field-Names
+            match definition
+                .input_fields
+                .iter()
+                .find(|input_field| input_field.name == name)
+            {
+                Some(input_field) => {
+                    if let Some(value) = field.value() {
+                        self.validate_value(&value, &input_field.ty, usages);
+                    }
+                }
+                None => self.error(
+                    format!(
+                        "Field \"{}\" is not defined by type \"{}\".",
+                        name, definition.name
+                    ),
+                    &field,
+                ),
+            }
+        }
+        // Spec: https://spec.graphql.org/draft/#sec-Input-Object-Required-Fields
+        for input_field in &definition.input_fields {
+            if is_required(input_field) && !names.contains(&input_field.name) {
+                self.error(
+                    format!(
+                        "Field \"{}.{}\" of required type \"{}\" was not provided.",
+                        definition.name,
+                        input_field.name,
+                        DisplayType(&input_field.ty)
+                    ),
+                    object,
+                );
+            }
+        }
+    }
+}
+
+/// Whitespace and commas are ignored tokens.
+fn is_ignored(c: char) -> bool {
+    c.is_whitespace() || c == ','
+}
+
+fn fragment_name(fragment: &ast::FragmentDefinition) -> String {
+    fragment
+        .fragment_name()
+        .and_then(|fragment_name| fragment_name.name())
+        .map(|name| name.text().to_string())
+        .unwrap_or_default()
+}
+
+fn argument_name(argument: &ast::Argument) -> String {
+    argument
+        .name()
+        .map(|name| name.text().to_string())
+        .unwrap_or_default()
+}
+
+fn named_type(ty: &TypeReference) -> &str {
+    match ty {
+        TypeReference::Named(name) => name,
+        TypeReference::List(inner) | TypeReference::NonNull(inner) => named_type(inner),
+    }
+}
+
+fn is_composite(ty: &TypeDefinition) -> bool {
+    matches!(
+        ty.kind,
+        TypeKind::Object | TypeKind::Interface | TypeKind::Union
+    )
+}
+
+fn is_required(input_value: &InputValueDefinition) -> bool {
+    matches!(input_value.ty, TypeReference::NonNull(_)) && input_value.default_value.is_none()
+}
+
+/// Whether a variable can be used where a value of the `expected` type is expected.
+///
+/// Spec: https://spec.graphql.org/draft/#IsVariableUsageAllowed()
+fn is_allowed(variable: &TypeReference, has_default: bool, expected: &TypeReference) -> bool {
+    match (variable, expected) {
+        // a default value makes a nullable variable usable for a non null type
+        (TypeReference::Named(_) | TypeReference::List(_), TypeReference::NonNull(expected))
+            if has_default =>
+        {
+            is_subtype(variable, expected)
+        }
+        _ => is_subtype(variable, expected),
+    }
+}
+
+fn is_subtype(variable: &TypeReference, expected: &TypeReference) -> bool {
+    match (variable, expected) {
+        (TypeReference::NonNull(variable), TypeReference::NonNull(expected)) => {
+            is_subtype(variable, expected)
+        }
+        (TypeReference::NonNull(variable), expected) => is_subtype(variable, expected),
+        (_, TypeReference::NonNull(_)) => false,
+        (TypeReference::List(variable), TypeReference::List(expected)) => {
+            is_subtype(variable, expected)
+        }
+        (TypeReference::Named(variable), TypeReference::Named(expected)) => variable == expected,
+        _ => false,
+    }
+}
+
+/// A type reference in the GraphQL syntax.
+struct DisplayType<'a>(&'a TypeReference);
+
+impl fmt::Display for DisplayType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            TypeReference::Named(name) => write!(f, "{}", name),
+            TypeReference::List(inner) => write!(f, "[{}]", DisplayType(inner)),
+            TypeReference::NonNull(inner) => write!(f, "{}!", DisplayType(inner)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        type Query {
+          me: User
+          user(id: ID!): User
+          search(filter: Filter, limit: Int = 10): [User!]!
+        }
+
+        type Mutation {
+          rename(name: String!): User
+        }
+
+        type User {
+          id: ID!
+          name: String
+          role: Role
+          friends(first: Int): [User]
+        }
+
+        enum Role {
+          ADMIN
+          GUEST
+        }
+
+        input Filter {
+          name: String!
+          role: Role
+        }
+    "#;
+
+    fn errors(query: &str) -> Vec<(String, Vec<Location>)> {
+        let schema = IntrospectionSchema::parse(SCHEMA);
+        match validate(query, &schema) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .into_iter()
+                .map(|error| {
+                    assert_eq!(
+                        error.extensions.get("code"),
+                        Some(&Value::String(GRAPHQL_VALIDATION_FAILED.into()))
+                    );
+                    (error.message, error.locations)
+                })
+                .collect(),
+        }
+    }
+
+    fn messages(query: &str) -> Vec<String> {
+        errors(query)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_operations() {
+        assert_eq!(
+            messages(
+                r#"query Search($filter: Filter, $limit: Int!, $skip: Boolean = false) {
+                    me { ...UserInfo @skip(if: $skip) }
+                    search(filter: $filter, limit: $limit) { id ... on User { name } }
+                    user(id: 1) { friends(first: 2) { __typename } }
+                    __schema { queryType { name } }
+                }
+
+                fragment UserInfo on User {
+                    id
+                    role
+                }
+
+                mutation Rename {
+                    rename(name: "admin") { id }
+                }"#
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn it_locates_errors() {
+        assert_eq!(
+            errors("{\n  me {\n    id\n    email\n  }\n}"),
+            vec![(
+                "Cannot query field \"email\" on type \"User\".".to_string(),
+                vec![Location { line: 4, column: 5 }]
+            )]
+        );
+    }
+
+    #[test]
+    fn it_validates_fields_and_arguments() {
+        assert_eq!(
+            messages(
+                r#"{
+                    me
+                    user { name { length } }
+                    search(filter: { role: OWNER }, limit: "ten", first: 1) { id }
+                }"#
+            ),
+            vec![
+                "Field \"me\" of type \"User\" must have a selection of subfields. Did you mean \"me { ... }\"?",
+                "Field \"user\" argument \"id\" of type \"ID!\" is required, but it was not provided.",
+                "Field \"name\" must not have a selection since type \"String\" has no subfields.",
+                "Expected value of type \"Role\", found OWNER.",
+                "Field \"Filter.name\" of required type \"String!\" was not provided.",
+                "Expected value of type \"Int\", found \"ten\".",
+                "Unknown argument \"first\" on field \"Query.search\".",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_fragments() {
+        assert_eq!(
+            messages(
+                r#"{
+                    me { ...A ...Missing }
+                }
+
+                fragment A on User { ...B }
+                fragment B on User { ...A }
+                fragment Unused on Role { name }"#
+            ),
+            vec![
+                "Fragment \"Unused\" cannot condition on non composite type \"Role\".",
+                "Unknown fragment \"Missing\".",
+                "Cannot spread fragment \"A\" within itself.",
+                "Cannot spread fragment \"B\" within itself.",
+                "Fragment \"Unused\" is never used.",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_variables() {
+        assert_eq!(
+            messages(
+                r#"query Q($id: ID, $first: Int = 1, $unused: String, $user: User) {
+                    user(id: $id) { friends(first: $first) { id } }
+                    me { ...Friends }
+                }
+
+                fragment Friends on User { friends(first: $undefined) { id } }"#
+            ),
+            vec![
+                "Variable \"$user\" cannot be non-input type \"User\".",
+                "Variable \"$id\" of type \"ID\" used in position expecting type \"ID!\".",
+                "Variable \"$undefined\" is not defined by operation \"Q\".",
+                "Variable \"$unused\" is never used in operation \"Q\".",
+                "Variable \"$user\" is never used in operation \"Q\".",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_directives() {
+        assert_eq!(
+            messages(
+                r#"query @skip(if: true) {
+                    me @include(if: true) @include(if: false) @unknown { id @skip }
+                }"#
+            ),
+            vec![
+                "Directive \"@skip\" may not be used on QUERY.",
+                "The directive \"@include\" can only be used once at this location.",
+                "Unknown directive \"@unknown\".",
+                "Directive \"@skip\" argument \"if\" of type \"Boolean!\" is required, but it was not provided.",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_validates_operations() {
+        assert_eq!(
+            messages("{ me { id } } query Q { me { id } } query Q { me { id } } subscription { me { id } }"),
+            vec![
+                "This anonymous operation must be the only defined operation.",
+                "There can be only one operation named \"Q\".",
+                "This anonymous operation must be the only defined operation.",
+                "Schema is not configured for subscriptions.",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_syntax_errors() {
+        let schema = IntrospectionSchema::parse(SCHEMA);
+        let errors = validate("{ me { id }", &schema).unwrap_err();
+        assert_eq!(
+            errors[0].extensions.get("code"),
+            Some(&Value::String(GRAPHQL_PARSE_FAILED.into()))
+        );
+        assert_eq!(errors[0].locations.len(), 1);
+    }
+}
@@ -1,17 +1,20 @@
 //! Axum http server factory. Axum provides routing capability on top of Hyper HTTP.
-use crate::configuration::{Configuration, Cors, ListenAddr};
+use crate::configuration::{Batching, Configuration, Cors, ListenAddr};
 use crate::http_server_factory::{HttpServerFactory, HttpServerHandle, Listener, NetworkStream};
+use crate::tls;
+use crate::websocket;
 use crate::FederatedServerError;
-use apollo_router_core::ResponseBody;
+use apollo_router_core::graphql_ws::GRAPHQL_TRANSPORT_WS_PROTOCOL;
 use apollo_router_core::{http_compat, Handler};
 use apollo_router_core::{prelude::*, DEFAULT_BUFFER_SIZE};
-use axum::extract::{Extension, Host, OriginalUri};
+use apollo_router_core::{IncrementalResponses, ResponseBody};
+use axum::extract::{ws::WebSocketUpgrade, Extension, Host, OriginalUri};
 use axum::http::{header::HeaderMap, StatusCode};
 use axum::response::*;
 use axum::routing::get;
 use axum::Router;
 use bytes::Bytes;
-use futures::{channel::oneshot, prelude::*};
+use futures::{channel::oneshot, future::join_all, prelude::*};
 use http::{HeaderValue, Request, Uri};
 use hyper::server::conn::Http;
 use hyper::Body;
@@ -23,10 +26,12 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 #[cfg(unix)]
 use tokio::net::UnixListener;
 use tokio::sync::Notify;
+use tokio_rustls::TlsAcceptor;
 use tower::buffer::Buffer;
 use tower::util::BoxService;
 use tower::MakeService;
@@ -47,7 +52,7 @@ impl AxumHttpServerFactory {
     }
 }
 
-type BufferedService = Buffer<
+pub(crate) type BufferedService = Buffer<
     BoxService<
         http_compat::Request<graphql::Request>,
         http_compat::Response<ResponseBody>,
@@ -65,6 +70,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
         service: RS,
         configuration: Arc<Configuration>,
         listener: Option<Listener>,
+        tls_acceptor: Option<TlsAcceptor>,
         plugin_handlers: HashMap<String, Handler>,
     ) -> Self::Future
     where
@@ -103,12 +109,22 @@ impl HttpServerFactory for AxumHttpServerFactory {
                     get({
                         let display_landing_page = configuration.server.landing_page;
                         move |host: Host,
+                              upgrade: Option<WebSocketUpgrade>,
                               service: Extension<BufferedService>,
                               http_request: Request<Body>| {
-                            handle_get(host, service, http_request, display_landing_page)
+                            handle_get(host, upgrade, service, http_request, display_landing_page)
                         }
                     })
-                    .post(handle_post),
+                    .post({
+                        let batching = configuration.server.batching.clone();
+                        move |host: Host,
+                              uri: OriginalUri,
+                              payload: Json<serde_json::Value>,
+                              service: Extension<BufferedService>,
+                              header_map: HeaderMap| {
+                            handle_post(host, uri, payload, service, header_map, batching)
+                        }
+                    }),
                 )
                 .layer(
                     TraceLayer::new_for_http()
@@ -129,7 +145,8 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 )
                 .route("/.well-known/apollo/server-health", get(health_check))
                 .layer(Extension(boxed_service))
-                .layer(cors);
+                .layer(cors)
+                .layer(configuration.server.compression.clone().into_layer());
 
             for (plugin_name, handler) in plugin_handlers {
                 router = router.route(
@@ -173,9 +190,15 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 .local_addr()
                 .map_err(FederatedServerError::ServerCreationError)?;
 
+            let exposed_address = match &actual_listen_address {
+                ListenAddr::SocketAddr(addr) if tls_acceptor.is_some() => {
+                    format!("https://{}", addr)
+                }
+                address => address.to_string(),
+            };
             tracing::info!(
                 "GraphQL endpoint exposed at {}{} 🚀",
-                actual_listen_address,
+                exposed_address,
                 configuration.server.endpoint
             );
             // this server reproduces most of hyper::server::Server's behaviour
@@ -197,6 +220,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
                         res = listener.accept() => {
                             let mut svc = svc.clone();
                             let connection_shutdown = connection_shutdown.clone();
+                            let tls_acceptor = tls_acceptor.clone();
 
                             match res {
                                 Ok(res) => {
@@ -215,23 +239,23 @@ impl HttpServerFactory for AxumHttpServerFactory {
                                                     .expect(
                                                         "this should not fail unless the socket is invalid",
                                                     );
-                                                    let connection = Http::new()
-                                                    .http1_keep_alive(true)
-                                                    .serve_connection(stream, app);
-
-                                                tokio::pin!(connection);
-                                                tokio::select! {
-                                                    // the connection finished first
-                                                    _res = &mut connection => {
-                                                    }
-                                                    // the shutdown receiver was triggered first,
-                                                    // so we tell the connection to do a graceful shutdown
-                                                    // on the next request, then we wait for it to finish
-                                                    _ = connection_shutdown.notified() => {
-                                                        let c = connection.as_mut();
-                                                        c.graceful_shutdown();
-
-                                                        let _= connection.await;
+
+                                                match tls_acceptor {
+                                                    Some(acceptor) => match acceptor.accept(stream).await {
+                                                        Ok(stream) => {
+                                                            // HTTP/2 was negotiated with ALPN
+                                                            let http2_only = stream.get_ref().1
+                                                                .alpn_protocol() == Some(tls::ALPN_H2);
+                                                            serve_connection(stream, app, http2_only, connection_shutdown)
+                                                                .await;
+                                                        }
+                                                        Err(e) => {
+                                                            tracing::debug!("TLS handshake failed: {}", e);
+                                                        }
+                                                    },
+                                                    None => {
+                                                        serve_connection(stream, app, false, connection_shutdown)
+                                                            .await;
                                                     }
                                                 }
                                             }
@@ -239,25 +263,7 @@ impl HttpServerFactory for AxumHttpServerFactory {
                                             NetworkStream::Unix(stream) => {
                                                 // TODO: unwrap?
                                                 let app = svc.make_service(&stream).await.unwrap();
-                                                let connection = Http::new()
-                                                .http1_keep_alive(true)
-                                                .serve_connection(stream, app);
-
-                                                tokio::pin!(connection);
-                                                tokio::select! {
-                                                    // the connection finished first
-                                                    _res = &mut connection => {
-                                                    }
-                                                    // the shutdown receiver was triggered first,
-                                                    // so we tell the connection to do a graceful shutdown
-                                                    // on the next request, then we wait for it to finish
-                                                    _ = connection_shutdown.notified() => {
-                                                        let c = connection.as_mut();
-                                                        c.graceful_shutdown();
-
-                                                        let _= connection.await;
-                                                    }
-                                                }
+                                                serve_connection(stream, app, false, connection_shutdown).await;
                                             }
                                         }
                                     });
@@ -361,6 +367,38 @@ impl HttpServerFactory for AxumHttpServerFactory {
     }
 }
 
+/// Serve an HTTP connection until it is finished, or until the server shuts down.
+async fn serve_connection<S>(
+    stream: S,
+    app: Router,
+    http2_only: bool,
+    connection_shutdown: Arc<Notify>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = Http::new()
+        .http1_keep_alive(true)
+        .http2_only(http2_only)
+        .serve_connection(stream, app)
+        .with_upgrades();
+
+    tokio::pin!(connection);
+    tokio::select! {
+        // the connection finished first
+        _res = &mut connection => {
+        }
+        // the shutdown receiver was triggered first,
+        // so we tell the connection to do a graceful shutdown
+        // on the next request, then we wait for it to finish
+        _ = connection_shutdown.notified() => {
+            let c = connection.as_mut();
+            c.graceful_shutdown();
+
+            let _= connection.await;
+        }
+    }
+}
+
 #[derive(Debug)]
 struct CustomRejection {
     #[allow(dead_code)]
@@ -408,10 +446,21 @@ async fn custom_plugin_handler(
 
 async fn handle_get(
     Host(host): Host,
+    upgrade: Option<WebSocketUpgrade>,
     Extension(service): Extension<BufferedService>,
     http_request: Request<Body>,
     display_landing_page: bool,
 ) -> impl IntoResponse {
+    if let Some(upgrade) = upgrade {
+        let uri = Uri::from_str(&format!("http://{}{}", host, http_request.uri()))
+            .expect("the URL is already valid because it comes from axum; qed");
+        let headers = http_request.headers().clone();
+        return upgrade
+            .protocols([GRAPHQL_TRANSPORT_WS_PROTOCOL])
+            .on_upgrade(move |socket| websocket::serve(socket, service, uri, headers))
+            .into_response();
+    }
+
     if http_request
         .headers()
         .get(&http::header::ACCEPT)
@@ -438,24 +487,135 @@ async fn handle_get(
     (StatusCode::BAD_REQUEST, "Invalid Graphql request").into_response()
 }
 
+/// Answer a POST request, whose body is a single GraphQL request, or a batch of them.
+///
+/// The body is parsed as JSON first, so that a malformed request gets its own deserialization
+/// error, and a malformed batch entry only fails on its own.
 async fn handle_post(
     Host(host): Host,
     OriginalUri(uri): OriginalUri,
-    Json(request): Json<graphql::Request>,
+    Json(payload): Json<serde_json::Value>,
     Extension(service): Extension<BufferedService>,
     header_map: HeaderMap,
+    batching: Batching,
 ) -> impl IntoResponse {
-    let mut http_request = Request::post(
-        Uri::from_str(&format!("http://{}{}", host, uri))
-            .expect("the URL is already valid because it comes from axum; qed"),
-    )
-    .body(request)
-    .expect("body has already been parsed; qed");
-    *http_request.headers_mut() = header_map;
-
-    run_graphql_request(service, http_request)
-        .await
-        .into_response()
+    let uri = Uri::from_str(&format!("http://{}{}", host, uri))
+        .expect("the URL is already valid because it comes from axum; qed");
+
+    match payload {
+        serde_json::Value::Object(_) => {
+            let request = match serde_json::from_value::<graphql::Request>(payload) {
+                Ok(request) => request,
+                Err(e) => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("invalid GraphQL request: {}", e),
+                    )
+                }
+            };
+            let mut http_request = Request::post(uri)
+                .body(request)
+                .expect("body has already been parsed; qed");
+            *http_request.headers_mut() = header_map;
+
+            run_graphql_request(service, http_request)
+                .await
+                .into_response()
+        }
+        serde_json::Value::Array(requests) => {
+            run_graphql_batch(service, uri, header_map, requests, &batching).await
+        }
+        _ => error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid GraphQL request: expected an object, or an array of objects for a batch"
+                .to_string(),
+        ),
+    }
+}
+
+async fn run_graphql_batch(
+    service: BufferedService,
+    uri: Uri,
+    header_map: HeaderMap,
+    requests: Vec<serde_json::Value>,
+    batching: &Batching,
+) -> Response {
+    let batch_size = requests.len();
+    Span::current().record("graphql.batch_size", &(batch_size as u64));
+
+    if !batching.enabled {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "batched requests are not enabled".to_string(),
+        );
+    }
+    if batch_size == 0 {
+        return error_response(StatusCode::BAD_REQUEST, "empty batch".to_string());
+    }
+    if batch_size > batching.max_size {
+        return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "batch contains {} operations, the maximum allowed is {}",
+                batch_size, batching.max_size
+            ),
+        );
+    }
+
+    // every entry goes through the router service on its own, with its own context,
+    // and the responses are collected in the order of the requests
+    let responses = join_all(requests.into_iter().map(|request| {
+        let service = service.clone();
+        let uri = uri.clone();
+        let header_map = header_map.clone();
+        async move {
+            let request = match serde_json::from_value::<graphql::Request>(request) {
+                Ok(request) => request,
+                Err(e) => {
+                    return ResponseBody::GraphQL(request_error(format!(
+                        "invalid GraphQL request: {}",
+                        e
+                    )))
+                }
+            };
+            let mut http_request = Request::post(uri)
+                .body(request)
+                .expect("body has already been parsed; qed");
+            *http_request.headers_mut() = header_map;
+
+            // subscriptions and deferred responses are only known once the query is resolved
+            // (including persisted queries) and planned, and their following responses could
+            // not be sent in the array: they are dropped, which stops the operation
+            match call_router_service(service, http_request).await {
+                Ok(response)
+                    if response
+                        .extensions()
+                        .get::<IncrementalResponses>()
+                        .is_some() =>
+                {
+                    ResponseBody::GraphQL(request_error(INCREMENTAL_IN_BATCH.to_string()))
+                }
+                Ok(response) => response.into_body(),
+                Err((_, message)) => ResponseBody::GraphQL(request_error(message.to_string())),
+            }
+        }
+    }))
+    .await;
+
+    tracing::trace_span!("serialize_response").in_scope(|| Json(responses).into_response())
+}
+
+const INCREMENTAL_IN_BATCH: &str =
+    "batched requests do not support @defer and subscriptions, send them on their own";
+
+fn request_error(message: String) -> graphql::Response {
+    graphql::Response::builder()
+        .errors(vec![graphql::Error::builder().message(message).build()])
+        .build()
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(request_error(message))).into_response()
 }
 
 fn display_home_page() -> Html<Bytes> {
@@ -478,6 +638,18 @@ async fn run_graphql_request(
     >,
     http_request: Request<graphql::Request>,
 ) -> impl IntoResponse {
+    match call_router_service(service, http_request).await {
+        Ok(response) => {
+            tracing::trace_span!("serialize_response").in_scope(|| response.into_response())
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+pub(crate) async fn call_router_service(
+    service: BufferedService,
+    http_request: Request<graphql::Request>,
+) -> Result<http_compat::Response<ResponseBody>, (StatusCode, &'static str)> {
     match service.ready_oneshot().await {
         Ok(mut service) => {
             let (head, body) = http_request.into_parts();
@@ -485,25 +657,20 @@ async fn run_graphql_request(
             service
                 .call(http_compat::Request::from_parts(head, body))
                 .await
-                .map(|response| {
-                    tracing::trace_span!("serialize_response").in_scope(|| response.into_response())
-                })
-                .unwrap_or_else(|e| {
+                .map_err(|e| {
                     tracing::error!("router service call failed: {}", e);
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         "router service call failed",
                     )
-                        .into_response()
                 })
         }
         Err(e) => {
             tracing::error!("router service is not available to process request: {}", e);
-            (
+            Err((
                 StatusCode::SERVICE_UNAVAILABLE,
                 "router service is not available to process request",
-            )
-                .into_response()
+            ))
         }
     }
 }
@@ -548,7 +715,8 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
                 uri = %request.uri(),
                 version = ?request.version(),
                 "otel.kind" = %SpanKind::Server,
-                "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str()
+                "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str(),
+                "graphql.batch_size" = tracing::field::Empty
             )
         } else {
             // No remote span, we can go ahead and create the span without context.
@@ -559,7 +727,8 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
                 uri = %request.uri(),
                 version = ?request.version(),
                 "otel.kind" = %SpanKind::Server,
-                "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str()
+                "otel.status_code" = %opentelemetry::trace::StatusCode::Unset.as_str(),
+                "graphql.batch_size" = tracing::field::Empty
             )
         }
     }
@@ -573,9 +742,9 @@ mod tests {
     use http::header::{self, CONTENT_TYPE};
     use mockall::mock;
     use reqwest::header::{
-        ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCEPT, ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
         ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
-        ORIGIN,
+        CONTENT_ENCODING, ORIGIN,
     };
     use reqwest::redirect::Policy;
     use reqwest::{Client, Method, StatusCode};
@@ -663,6 +832,7 @@ mod tests {
                         .build(),
                 ),
                 None,
+                None,
                 HashMap::new(),
             )
             .await
@@ -685,6 +855,13 @@ mod tests {
     ) -> (HttpServerHandle, Client) {
         let server_factory = AxumHttpServerFactory::new();
         let (service, mut handle) = tower_test::mock::spawn();
+        let tls_acceptor = conf
+            .server
+            .tls
+            .as_ref()
+            .map(tls::acceptor)
+            .transpose()
+            .expect("the certificates are valid");
 
         tokio::spawn(async move {
             loop {
@@ -697,7 +874,13 @@ mod tests {
             }
         });
         let server = server_factory
-            .create(service.into_inner(), Arc::new(conf), None, plugin_handlers)
+            .create(
+                service.into_inner(),
+                Arc::new(conf),
+                None,
+                tls_acceptor,
+                plugin_handlers,
+            )
             .await
             .expect("Failed to create server factory");
         let mut default_headers = HeaderMap::new();
@@ -747,6 +930,7 @@ mod tests {
                         .build(),
                 ),
                 None,
+                None,
                 HashMap::new(),
             )
             .await
@@ -872,6 +1056,89 @@ mod tests {
         Ok(())
     }
 
+    #[test(tokio::test)]
+    async fn it_answers_over_tls_with_client_certificates() -> Result<(), FederatedServerError> {
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/tls");
+        let expected_response = graphql::Response::builder()
+            .data(json!({"response": "yay"}))
+            .build();
+        let example_response = expected_response.clone();
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .returning(move |_| {
+                let example_response = example_response.clone();
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(example_response))
+                    .unwrap()
+                    .into())
+            });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .tls(Some(
+                        crate::configuration::Tls::builder()
+                            .certificate_chain(testdata.join("server.pem"))
+                            .key(testdata.join("server.key"))
+                            .client_ca(testdata.join("ca.pem"))
+                            .build(),
+                    ))
+                    .build(),
+            )
+            .build();
+        let (server, _) = init_with_config(expectations, conf, HashMap::new()).await;
+        let url = match server.listen_address() {
+            ListenAddr::SocketAddr(addr) => format!("https://{}/", addr),
+            #[cfg(unix)]
+            _ => unreachable!("the server listens on a socket address"),
+        };
+        let ca = reqwest::Certificate::from_pem(&std::fs::read(testdata.join("ca.pem")).unwrap())
+            .unwrap();
+
+        // the client certificate is required
+        let client = Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca.clone())
+            .build()
+            .unwrap();
+        assert!(client
+            .post(url.as_str())
+            .json(&json!({ "query": "query" }))
+            .send()
+            .await
+            .is_err());
+
+        let mut identity = std::fs::read(testdata.join("client.key")).unwrap();
+        identity.extend(std::fs::read(testdata.join("client.pem")).unwrap());
+        let client = Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca)
+            .identity(reqwest::Identity::from_pem(&identity).unwrap())
+            .build()
+            .unwrap();
+        let response = client
+            .post(url.as_str())
+            .json(&json!({ "query": "query" }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        // HTTP/2 is negotiated with ALPN
+        assert_eq!(response.version(), http::Version::HTTP_2);
+        assert_eq!(
+            response.json::<graphql::Response>().await.unwrap(),
+            expected_response,
+        );
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn bad_response() -> Result<(), FederatedServerError> {
         let expectations = MockRouterService::new();
@@ -1639,4 +1906,511 @@ Content-Type: application/json\r
         );
         server.shutdown().await
     }
+
+    fn batching_conf(enabled: bool, max_size: usize) -> Configuration {
+        Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .batching(
+                        crate::configuration::Batching::builder()
+                            .enabled(enabled)
+                            .max_size(max_size)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test(tokio::test)]
+    async fn it_answers_batched_requests_in_order() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(2)
+            .returning(move |req| {
+                let operation_name = req.body().operation_name.clone().unwrap();
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(
+                        graphql::Response::builder()
+                            .data(json!({ "operation": operation_name }))
+                            .build(),
+                    ))
+                    .unwrap()
+                    .into())
+            });
+        let (server, client) =
+            init_with_config(expectations, batching_conf(true, 10), HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(
+                json!([
+                    { "query": "query", "operationName": "first" },
+                    { "query": "query", "operationName": "second" },
+                ])
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        assert_eq!(
+            response.json::<Vec<graphql::Response>>().await.unwrap(),
+            vec![
+                graphql::Response::builder()
+                    .data(json!({ "operation": "first" }))
+                    .build(),
+                graphql::Response::builder()
+                    .data(json!({ "operation": "second" }))
+                    .build(),
+            ]
+        );
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_compresses_responses() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations.expect_service_call().times(2).returning(|_| {
+            Ok(http::Response::builder()
+                .status(200)
+                .body(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"response": "yay"}))
+                        .build(),
+                ))
+                .unwrap()
+                .into())
+        });
+        let conf = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .compression(
+                        crate::configuration::ResponseCompression::builder()
+                            .enabled(true)
+                            .min_size(0)
+                            .algorithms(vec![apollo_router_core::Compression::Gzip])
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let (server, client) = init_with_config(expectations, conf, HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .header(ACCEPT_ENCODING, "br, gzip")
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+
+        // not compressed if the client does not accept it
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(json!({ "query": "query" }).to_string())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_isolates_invalid_batch_entries() -> Result<(), FederatedServerError> {
+        let expected_response = graphql::Response::builder()
+            .data(json!({"response": "yay"}))
+            .build();
+        let example_response = expected_response.clone();
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .returning(move |_| {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .body(ResponseBody::GraphQL(example_response.clone()))
+                    .unwrap()
+                    .into())
+            });
+        let (server, client) =
+            init_with_config(expectations, batching_conf(true, 10), HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(json!([{ "query": 42 }, { "query": "query" }]).to_string())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json::<Vec<graphql::Response>>()
+            .await
+            .unwrap();
+
+        assert_eq!(response.len(), 2);
+        assert!(response[0].data.is_none());
+        assert_eq!(response[0].errors.len(), 1);
+        assert_eq!(response[1], expected_response);
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_rejects_batches_when_disabled() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let (server, client) = init(expectations).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(json!([{ "query": "query" }]).to_string())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_rejects_batches_over_max_size() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let (server, client) =
+            init_with_config(expectations, batching_conf(true, 1), HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(json!([{ "query": "query" }, { "query": "query" }]).to_string())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = response.json::<graphql::Response>().await.unwrap();
+        assert_eq!(
+            response.errors[0].message,
+            "batch contains 2 operations, the maximum allowed is 1"
+        );
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_rejects_incremental_operations_in_batches() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(4)
+            .returning(|req| {
+                let mut response = http::Response::new(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"response": "yay"}))
+                        .build(),
+                ));
+                // the router service answers subscriptions and deferred operations with
+                // incremental responses, whether their query is sent or persisted
+                let incremental = match req.body().query.as_deref() {
+                    Some(query) => query.starts_with("subscription") || query.contains("@defer"),
+                    None => true,
+                };
+                if incremental {
+                    response
+                        .extensions_mut()
+                        .insert(IncrementalResponses::new(stream::empty().boxed()));
+                }
+                Ok(response.into())
+            });
+        let (server, client) =
+            init_with_config(expectations, batching_conf(true, 10), HashMap::new()).await;
+
+        let response = client
+            .post(format!("{}/", server.listen_address()))
+            .body(
+                json!([
+                    { "query": "subscription { reviewAdded { id } }" },
+                    { "query": "{ me { id ... @defer { name } } }" },
+                    { "query": "{ me { id } }" },
+                    {
+                        "extensions": {
+                            "persistedQuery": {
+                                "version": 1,
+                                "sha256Hash": "ecf4edb46db40b5132295c0291d62fb65d6759a9eedfa4d5d612dd5ec54a6b38"
+                            }
+                        }
+                    },
+                ])
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json::<Vec<graphql::Response>>()
+            .await
+            .unwrap();
+
+        assert_eq!(response.len(), 4);
+        for rejected in [0, 1, 3] {
+            assert!(response[rejected].data.is_none());
+            assert_eq!(response[rejected].errors[0].message, INCREMENTAL_IN_BATCH);
+        }
+        assert_eq!(
+            response[2],
+            graphql::Response::builder()
+                .data(json!({"response": "yay"}))
+                .build()
+        );
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_reports_invalid_requests() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let (server, client) = init(expectations).await;
+
+        for body in [json!({ "query": 42 }), json!("query")] {
+            let response = client
+                .post(format!("{}/", server.listen_address()))
+                .body(body.to_string())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let response = response.json::<graphql::Response>().await.unwrap();
+            assert!(
+                response.errors[0]
+                    .message
+                    .starts_with("invalid GraphQL request: "),
+                "{}",
+                response.errors[0].message
+            );
+        }
+
+        server.shutdown().await
+    }
+
+    async fn websocket_connect(
+        server: &HttpServerHandle,
+    ) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>
+    {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let url = match server.listen_address() {
+            ListenAddr::SocketAddr(addr) => format!("ws://{}/", addr),
+            #[cfg(unix)]
+            ListenAddr::UnixSocket(_) => unreachable!("the test server listens on TCP"),
+        };
+        let mut request = url.into_client_request().unwrap();
+        request.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(GRAPHQL_TRANSPORT_WS_PROTOCOL),
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        socket
+    }
+
+    async fn websocket_send(
+        socket: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        message: serde_json::Value,
+    ) {
+        socket
+            .send(tokio_tungstenite::tungstenite::Message::Text(
+                message.to_string(),
+            ))
+            .await
+            .unwrap();
+    }
+
+    async fn websocket_receive(
+        socket: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> serde_json::Value {
+        match socket.next().await.unwrap().unwrap() {
+            tokio_tungstenite::tungstenite::Message::Text(text) => {
+                serde_json::from_str(&text).unwrap()
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn it_answers_subscriptions_over_websocket() -> Result<(), FederatedServerError> {
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(1)
+            .withf(|req| {
+                req.method() == Method::POST
+                    && req.body().query.as_deref() == Some("subscription { reviewAdded { id } }")
+            })
+            .returning(|_| {
+                let events = stream::iter(vec![graphql::Response::builder()
+                    .data(json!({"reviewAdded": {"id": "2"}}))
+                    .build()])
+                .boxed();
+                let mut response = http::Response::new(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"reviewAdded": {"id": "1"}}))
+                        .build(),
+                ));
+                response
+                    .extensions_mut()
+                    .insert(apollo_router_core::IncrementalResponses::new(events));
+                Ok(response.into())
+            });
+        let (server, _) = init(expectations).await;
+
+        let mut socket = websocket_connect(&server).await;
+        websocket_send(&mut socket, json!({"type": "connection_init"})).await;
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "connection_ack"})
+        );
+
+        websocket_send(&mut socket, json!({"type": "ping"})).await;
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "pong"})
+        );
+
+        websocket_send(
+            &mut socket,
+            json!({
+                "type": "subscribe",
+                "id": "1",
+                "payload": { "query": "subscription { reviewAdded { id } }" }
+            }),
+        )
+        .await;
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "next", "id": "1", "payload": {"data": {"reviewAdded": {"id": "1"}}}})
+        );
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "next", "id": "1", "payload": {"data": {"reviewAdded": {"id": "2"}}}})
+        );
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "complete", "id": "1"})
+        );
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_stops_an_operation_resubscribed_with_the_same_id(
+    ) -> Result<(), FederatedServerError> {
+        let stopped = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let operations = stopped.clone();
+        let mut expectations = MockRouterService::new();
+        expectations
+            .expect_service_call()
+            .times(2)
+            .returning(move |_| {
+                // the receiver is notified when the operation drops its event stream
+                let (guard, stopped) = oneshot::channel::<()>();
+                operations.lock().unwrap().push(stopped);
+                let events = stream::poll_fn(move |_| {
+                    let _guard = &guard;
+                    std::task::Poll::<Option<graphql::Response>>::Pending
+                })
+                .boxed();
+                let mut response = http::Response::new(ResponseBody::GraphQL(
+                    graphql::Response::builder()
+                        .data(json!({"reviewAdded": {"id": "1"}}))
+                        .build(),
+                ));
+                response
+                    .extensions_mut()
+                    .insert(apollo_router_core::IncrementalResponses::new(events));
+                Ok(response.into())
+            });
+        let (server, _) = init(expectations).await;
+
+        let mut socket = websocket_connect(&server).await;
+        websocket_send(&mut socket, json!({"type": "connection_init"})).await;
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "connection_ack"})
+        );
+        let subscribe = json!({
+            "type": "subscribe",
+            "id": "1",
+            "payload": { "query": "subscription { reviewAdded { id } }" }
+        });
+        let next =
+            json!({"type": "next", "id": "1", "payload": {"data": {"reviewAdded": {"id": "1"}}}});
+
+        websocket_send(&mut socket, subscribe.clone()).await;
+        assert_eq!(websocket_receive(&mut socket).await, next);
+        websocket_send(&mut socket, json!({"type": "complete", "id": "1"})).await;
+        websocket_send(&mut socket, subscribe).await;
+        assert_eq!(websocket_receive(&mut socket).await, next);
+
+        let first = stopped.lock().unwrap().remove(0);
+        tokio::time::timeout(Duration::from_secs(5), first)
+            .await
+            .expect("the first operation is stopped")
+            .unwrap_err();
+        // the end of the first operation is handled before the next messages
+        websocket_send(&mut socket, json!({"type": "ping"})).await;
+        assert_eq!(
+            websocket_receive(&mut socket).await,
+            json!({"type": "pong"})
+        );
+
+        websocket_send(&mut socket, json!({"type": "complete", "id": "1"})).await;
+        let second = stopped.lock().unwrap().remove(0);
+        tokio::time::timeout(Duration::from_secs(5), second)
+            .await
+            .expect("the second operation is stopped")
+            .unwrap_err();
+
+        server.shutdown().await
+    }
+
+    #[test(tokio::test)]
+    async fn it_closes_websocket_on_subscribe_before_init() -> Result<(), FederatedServerError> {
+        let expectations = MockRouterService::new();
+        let (server, _) = init(expectations).await;
+
+        let mut socket = websocket_connect(&server).await;
+        websocket_send(
+            &mut socket,
+            json!({
+                "type": "subscribe",
+                "id": "1",
+                "payload": { "query": "subscription { reviewAdded { id } }" }
+            }),
+        )
+        .await;
+        match socket.next().await.unwrap().unwrap() {
+            tokio_tungstenite::tungstenite::Message::Close(Some(frame)) => {
+                assert_eq!(u16::from(frame.code), 4401);
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+
+        server.shutdown().await
+    }
 }
@@ -3,7 +3,7 @@
 mod yaml;
 
 use crate::subscriber::is_global_subscriber_set;
-use apollo_router_core::plugins;
+use apollo_router_core::{plugins, Compression, OperationLimits};
 use derivative::Derivative;
 use displaydoc::Display;
 use envmnt::{ExpandOptions, ExpansionType};
@@ -16,10 +16,15 @@ use serde::{Deserialize, Serialize};
 use serde_json::Map;
 use serde_json::Value;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use thiserror::Error;
+use tower_http::compression::predicate::Predicate;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{self, CorsLayer};
 use typed_builder::TypedBuilder;
 
@@ -71,6 +76,31 @@ pub struct Configuration {
     #[builder(default)]
     pub server: Server,
 
+    /// Connection settings of the clients sending requests to the subgraphs
+    #[serde(default)]
+    #[builder(default)]
+    pub transport: Transport,
+
+    /// Query planning options
+    #[serde(default)]
+    #[builder(default)]
+    pub query_planning: QueryPlanning,
+
+    /// Limits on the depth, the size and the estimated cost of the operations
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub limits: Option<OperationLimits>,
+
+    /// Safelisting of the operations with a persisted query manifest
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub persisted_queries: Option<PersistedQueries>,
+
+    /// Automatic persisted queries (APQ) options
+    #[serde(default)]
+    #[builder(default)]
+    pub apq: Apq,
+
     /// Plugin configuration
     #[serde(default)]
     #[builder(default)]
@@ -275,6 +305,452 @@ pub struct Server {
     #[serde(default = "default_endpoint")]
     #[builder(default_code = "default_endpoint()", setter(into))]
     pub endpoint: String,
+
+    /// Client side batching of GraphQL requests on the POST endpoint
+    #[serde(default)]
+    #[builder(default)]
+    pub batching: Batching,
+
+    /// TLS termination for the listener
+    #[serde(default)]
+    #[builder(default)]
+    pub tls: Option<Tls>,
+
+    /// Compression of the responses, negotiated with the clients
+    #[serde(default)]
+    #[builder(default)]
+    pub compression: ResponseCompression,
+}
+
+/// Client side batching configuration.
+///
+/// When enabled, the GraphQL endpoint accepts a JSON array of requests in a single POST
+/// and answers with an array of responses in the same order.
+#[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Batching {
+    /// Set to true to accept batched requests.
+    ///
+    /// Defaults to false
+    #[serde(default)]
+    #[builder(default)]
+    pub enabled: bool,
+
+    /// The maximum number of operations accepted in a single batch.
+    /// Defaults to 32
+    #[serde(default = "default_batching_max_size")]
+    #[builder(default_code = "default_batching_max_size()")]
+    pub max_size: usize,
+}
+
+fn default_batching_max_size() -> usize {
+    32
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching::builder().build()
+    }
+}
+
+/// TLS configuration of the listener.
+///
+/// The certificate files are read again every time the configuration is reloaded.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// Path to the PEM encoded certificate chain, starting with the server certificate
+    #[builder(setter(into))]
+    pub certificate_chain: PathBuf,
+
+    /// Path to the PEM encoded private key (PKCS#8, RSA or EC)
+    #[builder(setter(into))]
+    pub key: PathBuf,
+
+    /// Path to the PEM encoded CA certificates used to verify client certificates.
+    /// When set, clients must present a certificate signed by one of them.
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub client_ca: Option<PathBuf>,
+
+    /// Advertise HTTP/2 with ALPN
+    /// enabled by default
+    #[serde(default = "default_tls_http2")]
+    #[builder(default_code = "default_tls_http2()")]
+    pub http2: bool,
+}
+
+fn default_tls_http2() -> bool {
+    true
+}
+
+/// Query planning options.
+#[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QueryPlanning {
+    /// Number of the most used queries of the previous router planned again when the schema or
+    /// the configuration changes, before switching the traffic to the new router.
+    /// Defaults to 20, limited by the size of the query plan cache
+    #[serde(default = "default_warmed_up_queries")]
+    #[builder(default_code = "default_warmed_up_queries()")]
+    pub warmed_up_queries: usize,
+
+    /// Maximum time spent planning the warmed up queries (e.g. "10s").
+    /// Defaults to 5s
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default, setter(strip_option))]
+    pub warm_up_budget: Option<Duration>,
+}
+
+fn default_warmed_up_queries() -> usize {
+    20
+}
+
+impl QueryPlanning {
+    /// Maximum time spent planning the warmed up queries.
+    pub fn warm_up_budget(&self) -> Duration {
+        self.warm_up_budget.unwrap_or(Duration::from_secs(5))
+    }
+}
+
+impl Default for QueryPlanning {
+    fn default() -> Self {
+        QueryPlanning::builder().build()
+    }
+}
+
+/// Safelisting of the operations with a persisted query manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PersistedQueries {
+    /// Path of the manifest listing the approved operations, in the Apollo persisted query
+    /// manifest format. It is reloaded when it changes
+    #[builder(setter(into))]
+    pub manifest: PathBuf,
+
+    /// Only log the operations missing from the manifest, without rejecting them
+    /// disabled by default
+    #[serde(default)]
+    #[builder(default)]
+    pub log_only: bool,
+}
+
+/// Automatic persisted queries (APQ) options.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Apq {
+    /// Accept requests only sending the hash of a registered query
+    /// enabled by default
+    #[serde(default = "default_apq_enabled")]
+    #[builder(default_code = "default_apq_enabled()")]
+    pub enabled: bool,
+
+    /// Maximum number of queries kept in the memory of the router.
+    /// Defaults to 512, unless `max_bytes` is set
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub max_entries: Option<u64>,
+
+    /// Maximum total size in bytes of the queries kept in the memory of the router, instead of a
+    /// number of queries
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub max_bytes: Option<u64>,
+
+    /// Time after which a registered query expires (e.g. "24h").
+    /// Queries do not expire by default
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default, setter(strip_option))]
+    pub ttl: Option<Duration>,
+
+    /// Share the registered queries between routers through Redis
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub redis: Option<ApqRedis>,
+}
+
+fn default_apq_enabled() -> bool {
+    true
+}
+
+impl Default for Apq {
+    fn default() -> Self {
+        Apq::builder().build()
+    }
+}
+
+/// Redis storage of the registered queries.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ApqRedis {
+    /// Redis URL (e.g. "redis://127.0.0.1:6379")
+    #[builder(setter(into))]
+    pub url: String,
+}
+
+/// Connection settings of the subgraph clients.
+///
+/// The settings of a subgraph are merged with the `all` settings, each field of the subgraph
+/// settings replacing the one of `all`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Transport {
+    /// Settings applied to all the subgraphs
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub all: Option<SubgraphTransport>,
+
+    /// Settings of a single subgraph, overriding the `all` settings field by field
+    #[serde(default)]
+    #[builder(default)]
+    pub subgraphs: HashMap<String, SubgraphTransport>,
+}
+
+impl Transport {
+    /// The settings of this subgraph merged with the `all` settings, if any.
+    pub fn subgraph(&self, name: &str) -> Option<SubgraphTransport> {
+        match (self.subgraphs.get(name), &self.all) {
+            (Some(transport), all) => Some(transport.merge(all.as_ref())),
+            (None, all) => all.clone(),
+        }
+    }
+}
+
+/// Connection settings of a subgraph client.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphTransport {
+    /// TLS settings of the https connections
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub tls: Option<SubgraphTls>,
+
+    /// Only use HTTP/2, without upgrading the http connections first (h2c)
+    /// disabled by default
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub http2_prior_knowledge: Option<bool>,
+
+    /// Maximum size in bytes of a compressed response body once decompressed, larger responses
+    /// fail (default: 52428800, 50MB)
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub max_decompressed_size: Option<usize>,
+
+    /// Maximum number of idle connections kept per host
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// Duration after which an idle connection is closed (e.g. "90s")
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default, setter(strip_option))]
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Interval of the TCP keep-alive probes, disabled if not set (e.g. "60s")
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    #[builder(default, setter(strip_option))]
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Compress the request bodies sent to the subgraph
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub request_compression: Option<RequestCompression>,
+}
+
+impl SubgraphTransport {
+    fn merge(&self, fallback: Option<&SubgraphTransport>) -> SubgraphTransport {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => SubgraphTransport {
+                tls: match (&self.tls, &fallback.tls) {
+                    (Some(tls), fallback) => Some(tls.merge(fallback.as_ref())),
+                    (None, fallback) => fallback.clone(),
+                },
+                http2_prior_knowledge: self
+                    .http2_prior_knowledge
+                    .or(fallback.http2_prior_knowledge),
+                max_decompressed_size: self
+                    .max_decompressed_size
+                    .or(fallback.max_decompressed_size),
+                pool_max_idle_per_host: self
+                    .pool_max_idle_per_host
+                    .or(fallback.pool_max_idle_per_host),
+                pool_idle_timeout: self.pool_idle_timeout.or(fallback.pool_idle_timeout),
+                tcp_keepalive: self.tcp_keepalive.or(fallback.tcp_keepalive),
+                request_compression: self
+                    .request_compression
+                    .clone()
+                    .or_else(|| fallback.request_compression.clone()),
+            },
+        }
+    }
+}
+
+/// Compression of the requests sent to a subgraph.
+///
+/// The subgraph responses are decompressed whatever this setting.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RequestCompression {
+    /// Compression algorithm, which the subgraph must support
+    pub algorithm: Compression,
+
+    /// Minimum size in bytes of a request body to compress it.
+    /// Defaults to 1024
+    #[serde(default = "default_compression_min_size")]
+    #[builder(default_code = "default_compression_min_size()")]
+    pub min_size: usize,
+}
+
+/// TLS settings of a subgraph client.
+///
+/// The certificate files are read again every time the configuration is reloaded.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubgraphTls {
+    /// Path to the PEM encoded CA certificates used to verify the subgraph certificate.
+    /// When set, they replace the system roots
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub certificate_authorities: Option<PathBuf>,
+
+    /// Client certificate presented to the subgraph
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub client_authentication: Option<ClientAuthentication>,
+}
+
+impl SubgraphTls {
+    fn merge(&self, fallback: Option<&SubgraphTls>) -> SubgraphTls {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => SubgraphTls {
+                certificate_authorities: self
+                    .certificate_authorities
+                    .clone()
+                    .or_else(|| fallback.certificate_authorities.clone()),
+                client_authentication: self
+                    .client_authentication
+                    .clone()
+                    .or_else(|| fallback.client_authentication.clone()),
+            },
+        }
+    }
+}
+
+/// Client certificate of a subgraph client.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuthentication {
+    /// Path to the PEM encoded certificate chain, starting with the client certificate
+    #[builder(setter(into))]
+    pub certificate_chain: PathBuf,
+
+    /// Path to the PEM encoded private key (PKCS#8, RSA or EC)
+    #[builder(setter(into))]
+    pub key: PathBuf,
+}
+
+/// Response compression configuration.
+///
+/// The algorithm is picked according to the `Accept-Encoding` header of the request.
+/// Multipart responses are never compressed, so their parts are delivered as soon as possible.
+#[derive(Debug, Clone, Deserialize, Serialize, TypedBuilder, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseCompression {
+    /// Set to true to compress the responses.
+    ///
+    /// Defaults to false
+    #[serde(default)]
+    #[builder(default)]
+    pub enabled: bool,
+
+    /// Minimum size in bytes of a response to compress it.
+    /// Defaults to 1024
+    #[serde(default = "default_compression_min_size")]
+    #[builder(default_code = "default_compression_min_size()")]
+    pub min_size: usize,
+
+    /// Algorithms accepted from the clients.
+    /// Defaults to gzip, br and deflate
+    #[serde(default = "default_compression_algorithms")]
+    #[builder(default_code = "default_compression_algorithms()")]
+    pub algorithms: Vec<Compression>,
+}
+
+fn default_compression_min_size() -> usize {
+    1024
+}
+
+fn default_compression_algorithms() -> Vec<Compression> {
+    vec![Compression::Gzip, Compression::Br, Compression::Deflate]
+}
+
+impl Default for ResponseCompression {
+    fn default() -> Self {
+        ResponseCompression::builder().build()
+    }
+}
+
+impl ResponseCompression {
+    pub fn into_layer(self) -> CompressionLayer<MinSizePredicate> {
+        let algorithms = if self.enabled {
+            self.algorithms
+        } else {
+            Vec::new()
+        };
+
+        let mut layer = CompressionLayer::new();
+        if !algorithms.contains(&Compression::Gzip) {
+            layer = layer.no_gzip();
+        }
+        if !algorithms.contains(&Compression::Br) {
+            layer = layer.no_br();
+        }
+        if !algorithms.contains(&Compression::Deflate) {
+            layer = layer.no_deflate();
+        }
+        layer.compress_when(MinSizePredicate {
+            min_size: self.min_size as u64,
+        })
+    }
+}
+
+/// Compress the responses of at least `min_size` bytes, and the responses of unknown size,
+/// except the streamed ones.
+#[derive(Debug, Clone, Copy)]
+pub struct MinSizePredicate {
+    min_size: u64,
+}
+
+impl Predicate for MinSizePredicate {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: http_body::Body,
+    {
+        let content_type = response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if content_type.starts_with("multipart/") || content_type.starts_with("text/event-stream") {
+            return false;
+        }
+
+        let size = response.body().size_hint().exact().or_else(|| {
+            response
+                .headers()
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        });
+        size.map(|size| size >= self.min_size).unwrap_or(true)
+    }
 }
 
 /// Listening address.
@@ -655,6 +1131,29 @@ pub fn validate_configuration(raw_yaml: &str) -> Result<Configuration, Configura
             );
     }
 
+    #[cfg(unix)]
+    if config.server.tls.is_some() && matches!(config.server.listen, ListenAddr::UnixSocket(_)) {
+        return Err(ConfigurationError::InvalidConfiguration {
+            message: "invalid 'server.tls' configuration",
+            error: "TLS termination is only available when listening on a socket address"
+                .to_string(),
+        });
+    }
+
+    if config.server.batching.enabled && config.server.batching.max_size == 0 {
+        return Err(ConfigurationError::InvalidConfiguration {
+            message: "invalid 'server.batching' configuration",
+            error: "'max_size' must be greater than 0 when batching is enabled".to_string(),
+        });
+    }
+
+    if config.apq.max_entries.is_some() && config.apq.max_bytes.is_some() {
+        return Err(ConfigurationError::InvalidConfiguration {
+            message: "invalid 'apq' configuration",
+            error: "'max_entries' and 'max_bytes' cannot be both set".to_string(),
+        });
+    }
+
     Ok(config)
 }
 
@@ -831,6 +1330,77 @@ server:
         assert_eq!(error.to_string(), String::from("invalid 'server.endpoint' configuration: '/test*' is invalid, you can only set a wildcard after a '/'"));
     }
 
+    #[test]
+    fn bad_batching_configuration_with_zero_max_size() {
+        let error = validate_configuration(
+            r#"
+server:
+  batching:
+    enabled: true
+    max_size: 0
+  "#,
+        )
+        .expect_err("should have resulted in an error");
+        assert_eq!(error.to_string(), String::from("invalid 'server.batching' configuration: 'max_size' must be greater than 0 when batching is enabled"));
+    }
+
+    #[test]
+    fn bad_apq_configuration_with_both_limits() {
+        let error = validate_configuration(
+            r#"
+apq:
+  max_entries: 100
+  max_bytes: 1000000
+  "#,
+        )
+        .expect_err("should have resulted in an error");
+        assert_eq!(
+            error.to_string(),
+            String::from(
+                "invalid 'apq' configuration: 'max_entries' and 'max_bytes' cannot be both set"
+            )
+        );
+    }
+
+    #[test]
+    fn tls_configuration() {
+        let configuration = validate_configuration(
+            r#"
+server:
+  tls:
+    certificate_chain: /etc/router/chain.pem
+    key: /etc/router/key.pem
+  "#,
+        )
+        .expect("should be valid");
+        assert_eq!(
+            configuration.server.tls,
+            Some(
+                Tls::builder()
+                    .certificate_chain("/etc/router/chain.pem")
+                    .key("/etc/router/key.pem")
+                    .build()
+            )
+        );
+        assert!(configuration.server.tls.unwrap().http2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bad_tls_configuration_with_unix_socket() {
+        let error = validate_configuration(
+            r#"
+server:
+  listen: /tmp/router.sock
+  tls:
+    certificate_chain: /etc/router/chain.pem
+    key: /etc/router/key.pem
+  "#,
+        )
+        .expect_err("should have resulted in an error");
+        assert_eq!(error.to_string(), String::from("invalid 'server.tls' configuration: TLS termination is only available when listening on a socket address"));
+    }
+
     #[test]
     fn line_precise_config_errors() {
         let error = validate_configuration(
@@ -863,6 +1433,71 @@ server:
         insta::assert_snapshot!(error.to_string());
     }
 
+    #[test]
+    fn it_merges_the_subgraph_transport_with_all() {
+        let transport: Transport = serde_yaml::from_str(
+            r#"
+            all:
+              http2_prior_knowledge: true
+              pool_max_idle_per_host: 4
+              pool_idle_timeout: 30s
+              tls:
+                certificate_authorities: ca.pem
+            subgraphs:
+              accounts:
+                http2_prior_knowledge: false
+                tcp_keepalive: 60s
+                tls:
+                  client_authentication:
+                    certificate_chain: client.pem
+                    key: client.key
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transport.subgraph("accounts"),
+            Some(
+                SubgraphTransport::builder()
+                    .http2_prior_knowledge(false)
+                    .pool_max_idle_per_host(4)
+                    .pool_idle_timeout(Duration::from_secs(30))
+                    .tcp_keepalive(Duration::from_secs(60))
+                    .tls(
+                        SubgraphTls::builder()
+                            .certificate_authorities("ca.pem")
+                            .client_authentication(
+                                ClientAuthentication::builder()
+                                    .certificate_chain("client.pem")
+                                    .key("client.key")
+                                    .build()
+                            )
+                            .build()
+                    )
+                    .build()
+            )
+        );
+        assert_eq!(transport.subgraph("products"), transport.all);
+
+        let transport = Transport::builder()
+            .subgraphs(HashMap::from([(
+                "accounts".to_string(),
+                SubgraphTransport::builder()
+                    .pool_max_idle_per_host(1)
+                    .build(),
+            )]))
+            .build();
+        assert_eq!(
+            transport.subgraph("accounts"),
+            Some(
+                SubgraphTransport::builder()
+                    .pool_max_idle_per_host(1)
+                    .build()
+            )
+        );
+        assert_eq!(transport.subgraph("products"), None);
+    }
+
     #[test]
     fn line_precise_config_errors_bad_type() {
         let error = validate_configuration(
@@ -10,6 +10,7 @@ use futures::channel::oneshot;
 use futures::prelude::*;
 use std::sync::Arc;
 use std::{collections::HashMap, pin::Pin};
+use tokio_rustls::TlsAcceptor;
 use tower::BoxError;
 use tower::Service;
 
@@ -25,6 +26,7 @@ pub(crate) trait HttpServerFactory {
         service: RS,
         configuration: Arc<Configuration>,
         listener: Option<Listener>,
+        tls_acceptor: Option<TlsAcceptor>,
         plugin_handlers: HashMap<String, Handler>,
     ) -> Self::Future
     where
@@ -87,6 +89,7 @@ impl HttpServerHandle {
         factory: &SF,
         router: RS,
         configuration: Arc<Configuration>,
+        tls_acceptor: Option<TlsAcceptor>,
         plugin_handlers: HashMap<String, Handler>,
     ) -> Result<Self, FederatedServerError>
     where
@@ -128,6 +131,7 @@ impl HttpServerHandle {
                 router,
                 Arc::clone(&configuration),
                 listener,
+                tls_acceptor,
                 plugin_handlers,
             )
             .await?;
@@ -12,6 +12,8 @@ mod reload;
 mod router_factory;
 mod state_machine;
 pub mod subscriber;
+mod tls;
+mod websocket;
 
 use crate::configuration::validate_configuration;
 use crate::reload::Error as ReloadError;
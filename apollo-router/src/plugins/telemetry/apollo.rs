@@ -1,9 +1,12 @@
 //! Configuration for apollo telemetry.
 // This entire file is license key functionality
 use crate::graphql::plugin::utils::serde::deserialize_header_name;
+use apollo_spaceport::server::{ReportDestination, ReportFormat, SpoolConfig};
 use http::header::HeaderName;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
 use url::Url;
 
 #[derive(Debug, Clone, Deserialize, JsonSchema)]
@@ -34,12 +37,121 @@ pub struct Config {
     )]
     pub client_version_header: HeaderName,
 
+    /// The fraction of the requests sent with field level instrumentation, from 0 to 1.
+    /// The subgraphs are asked for the federated traces of the sampled requests.
+    #[serde(default = "field_level_instrumentation_sampler_default")]
+    #[schemars(default = "field_level_instrumentation_sampler_default")]
+    pub field_level_instrumentation_sampler: f64,
+
+    /// Where the in-process spaceport sends the reports, the Apollo ingress if not specified.
+    pub reports_destination: Option<ReportsDestination>,
+
+    /// Directory of the in-process spaceport holding the reports which could not be sent, to
+    /// retry them, even after a restart.
+    pub reports_spool: Option<ReportsSpool>,
+
     // This'll get overridden if a user tries to set it.
     // The purpose is to allow is to pass this in to the plugin.
     #[schemars(skip)]
     pub(crate) schema_id: String,
 }
 
+impl Config {
+    /// The Apollo key and graph reference of the reports, or `None` if they are not sent.
+    ///
+    /// They are required by the Apollo ingress, the other destinations get the reports even
+    /// without them.
+    pub(crate) fn reporting_graph(&self) -> Option<(String, String)> {
+        match (&self.apollo_key, &self.apollo_graph_ref) {
+            (Some(key), Some(reference)) => Some((key.clone(), reference.clone())),
+            _ if matches!(
+                self.reports_destination,
+                Some(ReportsDestination::Endpoint { .. } | ReportsDestination::File { .. })
+            ) =>
+            {
+                Some((
+                    self.apollo_key.clone().unwrap_or_default(),
+                    self.apollo_graph_ref.clone().unwrap_or_default(),
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum ReportsDestination {
+    Ingress,
+    Endpoint {
+        #[schemars(with = "String")]
+        url: Url,
+    },
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        format: ReportsFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum ReportsFormat {
+    Json,
+    Protobuf,
+}
+
+impl Default for ReportsFormat {
+    fn default() -> Self {
+        ReportsFormat::Json
+    }
+}
+
+impl From<ReportsDestination> for ReportDestination {
+    fn from(destination: ReportsDestination) -> Self {
+        match destination {
+            ReportsDestination::Ingress => ReportDestination::Ingress,
+            ReportsDestination::Endpoint { url } => ReportDestination::Endpoint(url.to_string()),
+            ReportsDestination::File { path, format } => ReportDestination::File {
+                path,
+                format: match format {
+                    ReportsFormat::Json => ReportFormat::Json,
+                    ReportsFormat::Protobuf => ReportFormat::Protobuf,
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReportsSpool {
+    /// The directory of the spool.
+    pub path: PathBuf,
+    /// The maximum size of the spool in bytes, 100MB by default.
+    #[serde(default = "reports_spool_max_size_default")]
+    #[schemars(default = "reports_spool_max_size_default")]
+    pub max_size: u64,
+    /// The maximum age of the spooled reports (e.g. "24h"), unlimited by default.
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    pub max_age: Option<Duration>,
+}
+
+fn reports_spool_max_size_default() -> u64 {
+    100 * 1024 * 1024
+}
+
+impl From<ReportsSpool> for SpoolConfig {
+    fn from(spool: ReportsSpool) -> Self {
+        SpoolConfig {
+            path: spool.path,
+            max_size: spool.max_size,
+            max_age: spool.max_age,
+        }
+    }
+}
+
 fn apollo_key() -> Option<String> {
     std::env::var("APOLLO_KEY").ok()
 }
@@ -64,6 +176,10 @@ fn client_version_header_default() -> HeaderName {
     HeaderName::from_static(client_version_header_default_str())
 }
 
+fn field_level_instrumentation_sampler_default() -> f64 {
+    0.01
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -72,6 +188,9 @@ impl Default for Config {
             apollo_graph_ref: None,
             client_name_header: client_name_header_default(),
             client_version_header: client_version_header_default(),
+            field_level_instrumentation_sampler: field_level_instrumentation_sampler_default(),
+            reports_destination: None,
+            reports_spool: None,
             schema_id: "<no_schema_id>".to_string(),
         }
     }
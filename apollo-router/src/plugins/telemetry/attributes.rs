@@ -0,0 +1,525 @@
+//! Custom attributes of the spans and metrics, taken from the requests and responses.
+use super::{CLIENT_NAME, CLIENT_VERSION};
+use crate::graphql::plugin::utils::serde::deserialize_header_name;
+use apollo_router_core::Context;
+use http::header::HeaderName;
+use http::{HeaderMap, StatusCode};
+use opentelemetry::KeyValue;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tracing::Span;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+/// The value replacing the values which are not allowed, or which are beyond the limit of
+/// distinct values.
+const OTHER: &str = "other";
+
+/// The default limit of distinct values of the operation name, client name and client version.
+const DEFAULT_MAX_DISTINCT_VALUES: usize = 100;
+
+#[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub struct Config {
+    /// Attributes of the router span and of the router metrics.
+    pub router: Option<Forward>,
+
+    /// Attributes of the subgraph spans and of the subgraph metrics.
+    pub subgraph: Option<Forward>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case", default)]
+pub struct Forward {
+    /// Attributes taken from the request headers.
+    pub request_headers: Vec<HeaderForward>,
+
+    /// Attributes taken from the context entries, once the response is received.
+    pub context: Vec<ContextForward>,
+
+    /// Add the name of the operation as `operation_name`.
+    pub operation_name: bool,
+
+    /// Add the kind of the operation as `operation_kind`.
+    pub operation_kind: bool,
+
+    /// Add the name of the client as `client_name`.
+    pub client_name: bool,
+
+    /// Add the version of the client as `client_version`.
+    pub client_version: bool,
+
+    /// The maximum number of distinct values of `operation_name`, `client_name` and
+    /// `client_version`, each. Once it is reached, new values are replaced by `other`
+    /// (default: 100)
+    pub max_distinct_values: Option<usize>,
+
+    /// Add the HTTP status of the response as `status`.
+    pub response_status: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub struct HeaderForward {
+    /// The name of the header.
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    pub named: HeaderName,
+
+    /// The name of the attribute, the name of the header by default.
+    pub rename: Option<String>,
+
+    /// The value of the attribute when the header is missing. The attribute is omitted if there
+    /// is no default.
+    pub default: Option<String>,
+
+    /// The values kept as they are. The other values are replaced by `other`.
+    pub allowed_values: Option<Vec<String>>,
+
+    /// The maximum number of distinct values of the attribute. Once it is reached, new values
+    /// are replaced by `other`.
+    pub max_distinct_values: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub struct ContextForward {
+    /// The key of the context entry.
+    pub named: String,
+
+    /// The name of the attribute, the key of the entry by default.
+    pub rename: Option<String>,
+
+    /// The value of the attribute when the entry is missing. The attribute is omitted if there
+    /// is no default.
+    pub default: Option<String>,
+
+    /// The values kept as they are. The other values are replaced by `other`.
+    pub allowed_values: Option<Vec<String>>,
+
+    /// The maximum number of distinct values of the attribute. Once it is reached, new values
+    /// are replaced by `other`.
+    pub max_distinct_values: Option<usize>,
+}
+
+/// The cardinality guards of an attribute, with the distinct values seen so far.
+#[derive(Debug, Default)]
+struct Guard {
+    allowed_values: Option<Vec<String>>,
+    max_distinct_values: Option<usize>,
+    seen_values: Mutex<HashSet<String>>,
+}
+
+impl Guard {
+    fn new(allowed_values: Option<Vec<String>>, max_distinct_values: Option<usize>) -> Self {
+        Self {
+            allowed_values,
+            max_distinct_values,
+            seen_values: Default::default(),
+        }
+    }
+
+    /// Apply the guards to a value of the attribute.
+    fn apply(&self, value: String) -> String {
+        if let Some(allowed_values) = &self.allowed_values {
+            if !allowed_values.contains(&value) {
+                return OTHER.to_string();
+            }
+        }
+        if let Some(max_distinct_values) = self.max_distinct_values {
+            let mut seen_values = self.seen_values.lock().expect("lock poisoned");
+            if !seen_values.contains(&value) {
+                if seen_values.len() >= max_distinct_values {
+                    return OTHER.to_string();
+                }
+                seen_values.insert(value.clone());
+            }
+        }
+        value
+    }
+}
+
+/// The guards of the attributes coming from the client or from the context.
+#[derive(Debug, Default)]
+struct Guards {
+    /// In the order of the configuration.
+    request_headers: Vec<Guard>,
+    /// In the order of the configuration.
+    context: Vec<Guard>,
+    operation_name: Guard,
+    client_name: Guard,
+    client_version: Guard,
+}
+
+/// Computes the custom attributes configured for a service.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AttributesForwarder {
+    config: Forward,
+    guards: Arc<Guards>,
+}
+
+impl AttributesForwarder {
+    pub(crate) fn new(config: Forward) -> Self {
+        let max_distinct_values = Some(
+            config
+                .max_distinct_values
+                .unwrap_or(DEFAULT_MAX_DISTINCT_VALUES),
+        );
+        let guards = Guards {
+            request_headers: config
+                .request_headers
+                .iter()
+                .map(|forward| {
+                    Guard::new(forward.allowed_values.clone(), forward.max_distinct_values)
+                })
+                .collect(),
+            context: config
+                .context
+                .iter()
+                .map(|forward| {
+                    Guard::new(forward.allowed_values.clone(), forward.max_distinct_values)
+                })
+                .collect(),
+            operation_name: Guard::new(None, max_distinct_values),
+            client_name: Guard::new(None, max_distinct_values),
+            client_version: Guard::new(None, max_distinct_values),
+        };
+        Self {
+            config,
+            guards: Arc::new(guards),
+        }
+    }
+
+    /// The attributes known when the request is received.
+    ///
+    /// The client name and version are read from the context.
+    pub(crate) fn request_attributes(
+        &self,
+        headers: &HeaderMap,
+        operation_name: Option<&str>,
+        context: &Context,
+    ) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        for (forward, guard) in self
+            .config
+            .request_headers
+            .iter()
+            .zip(&self.guards.request_headers)
+        {
+            let value = headers
+                .get(&forward.named)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+                .or_else(|| forward.default.clone());
+            if let Some(value) = value {
+                attributes.push(KeyValue::new(
+                    forward
+                        .rename
+                        .clone()
+                        .unwrap_or_else(|| forward.named.to_string()),
+                    guard.apply(value),
+                ));
+            }
+        }
+        if self.config.operation_name {
+            attributes.push(KeyValue::new(
+                "operation_name",
+                self.guards
+                    .operation_name
+                    .apply(operation_name.unwrap_or_default().to_string()),
+            ));
+        }
+        if self.config.client_name {
+            attributes.push(KeyValue::new(
+                "client_name",
+                self.guards.client_name.apply(
+                    context
+                        .get::<_, String>(CLIENT_NAME)
+                        .unwrap_or_default()
+                        .unwrap_or_default(),
+                ),
+            ));
+        }
+        if self.config.client_version {
+            attributes.push(KeyValue::new(
+                "client_version",
+                self.guards.client_version.apply(
+                    context
+                        .get::<_, String>(CLIENT_VERSION)
+                        .unwrap_or_default()
+                        .unwrap_or_default(),
+                ),
+            ));
+        }
+        attributes
+    }
+
+    /// The attributes known once the response is received.
+    pub(crate) fn response_attributes(
+        &self,
+        context: &Context,
+        operation_kind: Option<&str>,
+        status: Option<StatusCode>,
+    ) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        for (forward, guard) in self.config.context.iter().zip(&self.guards.context) {
+            let value = context
+                .get::<_, serde_json::Value>(&forward.named)
+                .unwrap_or_default()
+                .map(|value| match value {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                })
+                .or_else(|| forward.default.clone());
+            if let Some(value) = value {
+                attributes.push(KeyValue::new(
+                    forward
+                        .rename
+                        .clone()
+                        .unwrap_or_else(|| forward.named.clone()),
+                    guard.apply(value),
+                ));
+            }
+        }
+        if self.config.operation_kind {
+            if let Some(operation_kind) = operation_kind {
+                attributes.push(KeyValue::new("operation_kind", operation_kind.to_string()));
+            }
+        }
+        if self.config.response_status {
+            if let Some(status) = status {
+                attributes.push(KeyValue::new("status", status.as_u16().to_string()));
+            }
+        }
+        attributes
+    }
+}
+
+/// Add attributes to the OpenTelemetry span of a tracing span, while it is not closed.
+///
+/// Tracing only records the fields declared when a span is created, so attributes with
+/// configured names are added to the span data kept by `tracing-opentelemetry` instead.
+pub(crate) fn set_span_attributes(span: &Span, attributes: &[KeyValue]) {
+    if attributes.is_empty() {
+        return;
+    }
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(registry) = dispatch.downcast_ref::<Registry>() {
+            if let Some(span) = registry.span(id) {
+                if let Some(data) = span.extensions_mut().get_mut::<OtelData>() {
+                    data.builder
+                        .attributes
+                        .get_or_insert_with(Vec::new)
+                        .extend(attributes.iter().cloned());
+                }
+            }
+        }
+    });
+}
+
+/// Add the attributes to the labels of a metric, unless a label has the same name.
+pub(crate) fn merge_attributes(labels: &mut Vec<KeyValue>, attributes: &[KeyValue]) {
+    for attribute in attributes {
+        if !labels.iter().any(|label| label.key == attribute.key) {
+            labels.push(attribute.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn header_forward(max_distinct_values: Option<usize>) -> Forward {
+        serde_json::from_value(serde_json::json!({
+            "request_headers": [{
+                "named": "x-client",
+                "rename": "client",
+                "default": "unknown",
+                "max_distinct_values": max_distinct_values,
+            }],
+            "operation_name": true,
+            "client_name": true,
+        }))
+        .unwrap()
+    }
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client", HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn it_forwards_request_attributes() {
+        let forwarder = AttributesForwarder::new(header_forward(None));
+        let context = Context::new();
+        context.insert(CLIENT_NAME, "web".to_string()).unwrap();
+
+        assert_eq!(
+            forwarder.request_attributes(&headers("ios"), Some("MyQuery"), &context),
+            vec![
+                KeyValue::new("client", "ios"),
+                KeyValue::new("operation_name", "MyQuery"),
+                KeyValue::new("client_name", "web"),
+            ]
+        );
+        assert_eq!(
+            forwarder.request_attributes(&HeaderMap::new(), None, &Context::new()),
+            vec![
+                KeyValue::new("client", "unknown"),
+                KeyValue::new("operation_name", ""),
+                KeyValue::new("client_name", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_limits_distinct_header_values() {
+        let forwarder = AttributesForwarder::new(header_forward(Some(2)));
+        let context = Context::new();
+        let client = |value| {
+            forwarder.request_attributes(&headers(value), None, &context)[0]
+                .value
+                .to_string()
+        };
+
+        assert_eq!(client("a"), "a");
+        assert_eq!(client("b"), "b");
+        assert_eq!(client("c"), OTHER);
+        assert_eq!(client("a"), "a");
+    }
+
+    #[test]
+    fn it_replaces_values_not_allowed() {
+        let forwarder = AttributesForwarder::new(
+            serde_json::from_value(serde_json::json!({
+                "request_headers": [{
+                    "named": "x-client",
+                    "allowed_values": ["ios", "android"],
+                }],
+            }))
+            .unwrap(),
+        );
+
+        assert_eq!(
+            forwarder.request_attributes(&headers("android"), None, &Context::new()),
+            vec![KeyValue::new("x-client", "android")]
+        );
+        assert_eq!(
+            forwarder.request_attributes(&headers("desktop"), None, &Context::new()),
+            vec![KeyValue::new("x-client", OTHER)]
+        );
+    }
+
+    #[test]
+    fn it_limits_distinct_client_values() {
+        let forwarder = AttributesForwarder::new(
+            serde_json::from_value(serde_json::json!({
+                "operation_name": true,
+                "client_name": true,
+                "client_version": true,
+                "max_distinct_values": 1,
+            }))
+            .unwrap(),
+        );
+        let attributes = |operation_name, client_name: &str, client_version: &str| {
+            let context = Context::new();
+            context
+                .insert(CLIENT_NAME, client_name.to_string())
+                .unwrap();
+            context
+                .insert(CLIENT_VERSION, client_version.to_string())
+                .unwrap();
+            forwarder.request_attributes(&HeaderMap::new(), Some(operation_name), &context)
+        };
+
+        assert_eq!(
+            attributes("MyQuery", "web", "1.0"),
+            vec![
+                KeyValue::new("operation_name", "MyQuery"),
+                KeyValue::new("client_name", "web"),
+                KeyValue::new("client_version", "1.0"),
+            ]
+        );
+        assert_eq!(
+            attributes("Other", "ios", "2.0"),
+            vec![
+                KeyValue::new("operation_name", OTHER),
+                KeyValue::new("client_name", OTHER),
+                KeyValue::new("client_version", OTHER),
+            ]
+        );
+        assert_eq!(
+            attributes("MyQuery", "web", "2.0"),
+            vec![
+                KeyValue::new("operation_name", "MyQuery"),
+                KeyValue::new("client_name", "web"),
+                KeyValue::new("client_version", OTHER),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_guards_context_values() {
+        let forwarder = AttributesForwarder::new(
+            serde_json::from_value(serde_json::json!({
+                "context": [
+                    {"named": "tenant", "max_distinct_values": 1},
+                    {"named": "plan", "allowed_values": ["free", "pro"]},
+                ],
+            }))
+            .unwrap(),
+        );
+        let attributes = |tenant: &str, plan: &str| {
+            let context = Context::new();
+            context.insert("tenant", tenant.to_string()).unwrap();
+            context.insert("plan", plan.to_string()).unwrap();
+            forwarder.response_attributes(&context, None, None)
+        };
+
+        assert_eq!(
+            attributes("acme", "pro"),
+            vec![
+                KeyValue::new("tenant", "acme"),
+                KeyValue::new("plan", "pro")
+            ]
+        );
+        assert_eq!(
+            attributes("globex", "enterprise"),
+            vec![KeyValue::new("tenant", OTHER), KeyValue::new("plan", OTHER)]
+        );
+    }
+
+    #[test]
+    fn it_forwards_response_attributes() {
+        let forwarder = AttributesForwarder::new(
+            serde_json::from_value(serde_json::json!({
+                "context": [
+                    {"named": "tenant"},
+                    {"named": "plan", "rename": "billing_plan", "default": "free"},
+                    {"named": "missing"},
+                ],
+                "operation_kind": true,
+                "response_status": true,
+            }))
+            .unwrap(),
+        );
+        let context = Context::new();
+        context.insert("tenant", "acme".to_string()).unwrap();
+
+        assert_eq!(
+            forwarder.response_attributes(&context, Some("query"), Some(StatusCode::OK)),
+            vec![
+                KeyValue::new("tenant", "acme"),
+                KeyValue::new("billing_plan", "free"),
+                KeyValue::new("operation_kind", "query"),
+                KeyValue::new("status", "200"),
+            ]
+        );
+    }
+}
@@ -40,6 +40,7 @@ pub struct Conf {
     pub metrics: Option<Metrics>,
     pub tracing: Option<Tracing>,
     pub apollo: Option<apollo::Config>,
+    pub attributes: Option<attributes::Config>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
@@ -54,7 +55,46 @@ pub struct Metrics {
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
 pub struct MetricsCommon {
+    #[serde(default)]
     pub delay_interval: Duration,
+
+    /// The prefix added to the names of the metrics.
+    #[serde(default)]
+    pub prefix: String,
+
+    /// The bucket boundaries of the histograms, by name of the metric without the prefix. The
+    /// other histograms keep the default boundaries of their exporter, except
+    /// `subgraph_response_size_bytes` which has boundaries from 100B to 10MB.
+    #[serde(default)]
+    pub buckets: BTreeMap<String, Vec<f64>>,
+
+    /// The labels added to all the metrics.
+    #[serde(default)]
+    pub resource: BTreeMap<String, String>,
+}
+
+impl MetricsCommon {
+    pub(crate) fn validate(&self) -> Result<(), BoxError> {
+        for (name, boundaries) in &self.buckets {
+            if boundaries.iter().any(|boundary| !boundary.is_finite())
+                || boundaries.windows(2).any(|pair| pair[0] >= pair[1])
+            {
+                return Err(format!(
+                    "the buckets of {} must be finite and in increasing order, got {:?}",
+                    name, boundaries
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn resource_labels(&self) -> Vec<KeyValue> {
+        self.resource
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect()
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize, JsonSchema)]
@@ -18,6 +18,7 @@ use tower::BoxError;
 use url::Url;
 
 mod duration_histogram;
+pub(crate) mod ftv1;
 pub(crate) mod studio;
 
 const DEFAULT_QUEUE_SIZE: usize = 65_536;
@@ -58,18 +59,13 @@ impl MetricsConfigurator for Config {
     ) -> Result<MetricsBuilder, BoxError> {
         tracing::debug!("configuring Apollo metrics");
         static ENABLED: AtomicBool = AtomicBool::new(false);
-        Ok(match self {
-            Config {
-                endpoint: Some(endpoint),
-                apollo_key: Some(key),
-                apollo_graph_ref: Some(reference),
-                schema_id,
-                ..
-            } => {
+        Ok(match (&self.endpoint, self.reporting_graph()) {
+            (Some(endpoint), Some((key, reference))) => {
                 if !ENABLED.swap(true, Ordering::Relaxed) {
                     tracing::info!("Apollo Studio usage reporting is enabled. See https://go.apollo.dev/o/data for details");
                 }
-                let exporter = ApolloMetricsExporter::new(endpoint, key, reference, schema_id)?;
+                let exporter =
+                    ApolloMetricsExporter::new(endpoint, &key, &reference, &self.schema_id)?;
 
                 builder
                     .with_apollo_metrics_collector(exporter.provider())
@@ -257,6 +253,9 @@ mod test {
             apollo_graph_ref: None,
             client_name_header: HeaderName::from_static("name_header"),
             client_version_header: HeaderName::from_static("version_header"),
+            field_level_instrumentation_sampler: 0.0,
+            reports_destination: None,
+            reports_spool: None,
             schema_id: "schema_sha".to_string(),
         })
         .await?;
@@ -335,13 +334,63 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn apollo_metrics_field_level_instrumentation() -> Result<(), BoxError> {
+        let query = "query {topProducts{name}}";
+        let plugin = create_plugin_with_apollo_config(apollo::Config {
+            endpoint: None,
+            apollo_key: Some("key".to_string()),
+            apollo_graph_ref: Some("ref".to_string()),
+            client_name_header: HeaderName::from_static("name_header"),
+            client_version_header: HeaderName::from_static("version_header"),
+            field_level_instrumentation_sampler: 1.0,
+            reports_destination: None,
+            reports_spool: None,
+            schema_id: "schema_sha".to_string(),
+        })
+        .await?;
+        let results = get_metrics_for_request_with_plugin(plugin, query, None, None).await?;
+        let stats = &results[0].traces_and_stats["# -\n{topProducts{name}}"];
+        assert!(
+            !stats
+                .stats_with_context
+                .query_latency_stats
+                .without_field_instrumentation
+        );
+        assert_eq!(stats.traces.len(), 1);
+        assert_eq!(stats.traces[0].client_name, "test_client");
+        assert_eq!(stats.traces[0].field_execution_weight, 1.0);
+        assert!(stats.traces[0].query_plan.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn apollo_metrics_invalid_sampler() {
+        let result = create_plugin_with_apollo_config(apollo::Config {
+            field_level_instrumentation_sampler: 2.0,
+            ..Default::default()
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
     async fn get_metrics_for_request(
         query: &str,
         operation_name: Option<&str>,
         context: Option<Context>,
+    ) -> Result<Vec<SingleReport>, BoxError> {
+        get_metrics_for_request_with_plugin(create_plugin().await?, query, operation_name, context)
+            .await
+    }
+
+    async fn get_metrics_for_request_with_plugin(
+        mut plugin: Telemetry,
+        query: &str,
+        operation_name: Option<&str>,
+        context: Option<Context>,
     ) -> Result<Vec<SingleReport>, BoxError> {
         let _ = tracing_subscriber::fmt::try_init();
-        let mut plugin = create_plugin().await?;
         // Replace the apollo metrics sender so we can test metrics collection.
         let (tx, rx) = futures::channel::mpsc::channel(100);
         plugin.apollo_metrics_sender = Sender::Spaceport(tx);
@@ -384,6 +433,9 @@ mod test {
             apollo_graph_ref: Some("ref".to_string()),
             client_name_header: HeaderName::from_static("name_header"),
             client_version_header: HeaderName::from_static("version_header"),
+            field_level_instrumentation_sampler: 0.0,
+            reports_destination: None,
+            reports_spool: None,
             schema_id: "schema_sha".to_string(),
         })
     }
@@ -395,6 +447,7 @@ mod test {
             metrics: None,
             tracing: None,
             apollo: Some(apollo_config),
+            attributes: None,
         })
         .await
     }
@@ -0,0 +1,537 @@
+// This entire file is license key functionality
+//! Federated tracing (ftv1) of the subgraph requests.
+//!
+//! Subgraphs asked for it with the `apollo-federation-include-trace: ftv1` header return a
+//! base64 encoded protobuf trace in the `ftv1` extension of their response. Those traces are
+//! assembled along the query plan into the trace of the client request, and provide the field
+//! level statistics of the report.
+use super::studio::{SingleFieldStat, SinglePathErrorStats, SingleTypeStat};
+use apollo_router_core::{Object, Value};
+use apollo_spaceport::trace::query_plan_node::{
+    FetchNode, FlattenNode, Node, ParallelNode, ResponsePathElement, SequenceNode,
+};
+use apollo_spaceport::trace::{self, QueryPlanNode};
+use apollo_spaceport::Trace;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tower::BoxError;
+
+/// The header requesting an inline trace from a subgraph.
+pub(crate) const INCLUDE_TRACE_HEADER: &str = "apollo-federation-include-trace";
+/// The trace format, also the name of the response extension carrying the trace.
+pub(crate) const FTV1: &str = "ftv1";
+
+/// A request to a subgraph, with the trace it returned.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SubgraphTrace {
+    pub(crate) service_name: String,
+    pub(crate) operation: String,
+    /// The base64 encoded trace of the `ftv1` response extension, if any.
+    pub(crate) ftv1: Option<String>,
+    pub(crate) sent_time: SystemTime,
+    pub(crate) received_time: SystemTime,
+}
+
+impl SubgraphTrace {
+    fn decode(&self) -> Option<Result<Trace, BoxError>> {
+        self.ftv1.as_ref().map(|ftv1| {
+            let bytes = base64::decode(ftv1)?;
+            Ok(prost::Message::decode(bytes.as_slice())?)
+        })
+    }
+}
+
+/// Assemble the trace of a request from its query plan, in the JSON format of the query planner,
+/// and the traces of its subgraph requests.
+pub(crate) fn federated_trace(
+    query_plan: &Value,
+    mut subgraph_traces: Vec<SubgraphTrace>,
+    start_time: SystemTime,
+    duration: Duration,
+) -> Trace {
+    Trace {
+        start_time: Some(start_time.into()),
+        end_time: Some((start_time + duration).into()),
+        duration_ns: duration.as_nanos() as u64,
+        root: Some(trace::Node::default()),
+        query_plan: query_plan_node(query_plan, &mut subgraph_traces, start_time).map(Into::into),
+        ..Default::default()
+    }
+}
+
+#[allow(clippy::useless_conversion)]
+fn query_plan_node(
+    node: &Value,
+    subgraph_traces: &mut Vec<SubgraphTrace>,
+    start_time: SystemTime,
+) -> Option<QueryPlanNode> {
+    let object = node.as_object()?;
+
+    let node = match object.get("kind")?.as_str()? {
+        "Sequence" => Node::Sequence(
+            SequenceNode {
+                nodes: query_plan_nodes(object.get("nodes"), subgraph_traces, start_time),
+            }
+            .into(),
+        ),
+        "Parallel" => Node::Parallel(
+            ParallelNode {
+                nodes: query_plan_nodes(object.get("nodes"), subgraph_traces, start_time),
+            }
+            .into(),
+        ),
+        "Fetch" => Node::Fetch(fetch_node(object, subgraph_traces, start_time).into()),
+        "Flatten" => Node::Flatten(
+            FlattenNode {
+                response_path: object
+                    .get("path")
+                    .and_then(Value::as_array)
+                    .map(|path| path.iter().filter_map(response_path_element).collect())
+                    .unwrap_or_default(),
+                node: object
+                    .get("node")
+                    .and_then(|node| query_plan_node(node, subgraph_traces, start_time))
+                    .map(Into::into),
+            }
+            .into(),
+        ),
+        // The deferred fragments are executed after the primary response.
+        "Defer" => {
+            let primary = object
+                .get("primary")
+                .and_then(Value::as_object)
+                .and_then(|primary| primary.get("node"))
+                .and_then(|node| query_plan_node(node, subgraph_traces, start_time));
+            let deferred = object
+                .get("deferred")
+                .and_then(Value::as_array)
+                .map(|deferred| {
+                    deferred
+                        .iter()
+                        .filter_map(|deferred| deferred.as_object()?.get("node"))
+                        .filter_map(|node| query_plan_node(node, subgraph_traces, start_time))
+                        .collect()
+                })
+                .unwrap_or_default();
+            Node::Sequence(
+                SequenceNode {
+                    nodes: primary
+                        .into_iter()
+                        .chain(std::iter::once(QueryPlanNode {
+                            node: Some(Node::Parallel(ParallelNode { nodes: deferred }.into())),
+                        }))
+                        .collect(),
+                }
+                .into(),
+            )
+        }
+        "Subscription" => {
+            let primary = object
+                .get("primary")
+                .and_then(Value::as_object)
+                .map(|primary| QueryPlanNode {
+                    node: Some(Node::Fetch(
+                        fetch_node(primary, subgraph_traces, start_time).into(),
+                    )),
+                });
+            let rest = object
+                .get("rest")
+                .and_then(|node| query_plan_node(node, subgraph_traces, start_time));
+            Node::Sequence(
+                SequenceNode {
+                    nodes: primary.into_iter().chain(rest).collect(),
+                }
+                .into(),
+            )
+        }
+        _ => return None,
+    };
+
+    Some(QueryPlanNode { node: Some(node) })
+}
+
+fn query_plan_nodes(
+    nodes: Option<&Value>,
+    subgraph_traces: &mut Vec<SubgraphTrace>,
+    start_time: SystemTime,
+) -> Vec<QueryPlanNode> {
+    nodes
+        .and_then(Value::as_array)
+        .map(|nodes| {
+            nodes
+                .iter()
+                .filter_map(|node| query_plan_node(node, subgraph_traces, start_time))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn fetch_node(
+    node: &Object,
+    subgraph_traces: &mut Vec<SubgraphTrace>,
+    start_time: SystemTime,
+) -> FetchNode {
+    let service_name = node
+        .get("serviceName")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let operation = node
+        .get("operation")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    // The same fetch can be made several times, in which case the traces are matched in order.
+    let subgraph_trace = match subgraph_traces
+        .iter()
+        .position(|trace| trace.service_name == service_name && trace.operation == operation)
+    {
+        Some(index) => subgraph_traces.remove(index),
+        None => {
+            return FetchNode {
+                service_name: service_name.to_string(),
+                ..Default::default()
+            }
+        }
+    };
+
+    let (trace, trace_parsing_failed) = match subgraph_trace.decode() {
+        Some(Ok(trace)) => (Some(trace), false),
+        Some(Err(err)) => {
+            tracing::debug!(
+                "cannot decode the trace of subgraph {}: {}",
+                service_name,
+                err
+            );
+            (None, true)
+        }
+        None => (None, false),
+    };
+
+    FetchNode {
+        service_name: service_name.to_string(),
+        trace_parsing_failed,
+        trace: trace.map(Into::into),
+        sent_time_offset: subgraph_trace
+            .sent_time
+            .duration_since(start_time)
+            .unwrap_or_default()
+            .as_nanos() as u64,
+        sent_time: Some(subgraph_trace.sent_time.into()),
+        received_time: Some(subgraph_trace.received_time.into()),
+    }
+}
+
+fn response_path_element(element: &Value) -> Option<ResponsePathElement> {
+    let id = match element {
+        Value::String(name) => {
+            trace::query_plan_node::response_path_element::Id::FieldName(name.as_str().to_string())
+        }
+        Value::Number(index) => {
+            trace::query_plan_node::response_path_element::Id::Index(index.as_u64()? as u32)
+        }
+        _ => return None,
+    };
+    Some(ResponsePathElement { id: Some(id) })
+}
+
+/// The field level statistics of a federated trace.
+#[derive(Default, Debug)]
+pub(crate) struct FieldStats {
+    pub(crate) per_type_stat: HashMap<String, SingleTypeStat>,
+    pub(crate) root_error_stats: SinglePathErrorStats,
+}
+
+impl FieldStats {
+    /// Collect the statistics of the subgraph traces of a federated trace. Every execution of a
+    /// field stands for `weight` executions, the inverse of the sampling rate.
+    pub(crate) fn new(trace: &Trace, weight: f64) -> Self {
+        let mut stats = Self::default();
+        if let Some(query_plan) = &trace.query_plan {
+            stats.add_query_plan_node(query_plan, &mut Vec::new(), weight);
+        }
+        stats
+    }
+
+    fn add_query_plan_node(&mut self, node: &QueryPlanNode, path: &mut Vec<String>, weight: f64) {
+        match &node.node {
+            Some(Node::Sequence(sequence)) => {
+                for node in sequence.nodes.iter() {
+                    self.add_query_plan_node(node, path, weight);
+                }
+            }
+            Some(Node::Parallel(parallel)) => {
+                for node in parallel.nodes.iter() {
+                    self.add_query_plan_node(node, path, weight);
+                }
+            }
+            Some(Node::Fetch(fetch)) => {
+                if let Some(root) = fetch.trace.as_ref().and_then(|trace| trace.root.as_ref()) {
+                    self.add_errors(path, root.error.len() as u64);
+                    for child in root.child.iter() {
+                        if response_name(child) == Some("_entities") {
+                            // The entities are merged at the path of the enclosing flatten node.
+                            for entity in child.child.iter() {
+                                for field in entity.child.iter() {
+                                    self.add_node(field, path, weight);
+                                }
+                            }
+                        } else {
+                            self.add_node(child, path, weight);
+                        }
+                    }
+                }
+            }
+            Some(Node::Flatten(flatten)) => {
+                let mut path = path.clone();
+                path.extend(
+                    flatten
+                        .response_path
+                        .iter()
+                        .filter_map(|element| match &element.id {
+                            Some(trace::query_plan_node::response_path_element::Id::FieldName(
+                                name,
+                            )) if name != "@" => Some(name.clone()),
+                            _ => None,
+                        }),
+                );
+                for node in flatten.node.iter() {
+                    self.add_query_plan_node(node, &mut path, weight);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_node(&mut self, node: &trace::Node, path: &mut Vec<String>, weight: f64) {
+        let errors_count = node.error.len() as u64;
+        let response_name = response_name(node);
+        if let Some(response_name) = response_name {
+            path.push(response_name.to_string());
+            let field_name = if node.original_field_name.is_empty() {
+                response_name.to_string()
+            } else {
+                node.original_field_name.clone()
+            };
+            let stat = self
+                .per_type_stat
+                .entry(node.parent_type.clone())
+                .or_default()
+                .per_field_stat
+                .entry(field_name)
+                .or_insert_with(SingleFieldStat::default);
+            stat.return_type = node.r#type.clone();
+            stat.estimated_execution_count += weight;
+            stat.errors_count += errors_count;
+            stat.requests_with_errors_count = (stat.errors_count > 0) as u64;
+            stat.latencies.push(Duration::from_nanos(
+                node.end_time.saturating_sub(node.start_time),
+            ));
+        }
+
+        self.add_errors(path, errors_count);
+        for child in node.child.iter() {
+            self.add_node(child, path, weight);
+        }
+
+        if response_name.is_some() {
+            path.pop();
+        }
+    }
+
+    fn add_errors(&mut self, path: &[String], errors_count: u64) {
+        if errors_count == 0 {
+            return;
+        }
+        let mut stats = &mut self.root_error_stats;
+        for name in path {
+            stats = stats.children.entry(name.clone()).or_default();
+        }
+        stats.errors_count += errors_count;
+        stats.requests_with_errors_count = 1;
+    }
+}
+
+fn response_name(node: &trace::Node) -> Option<&str> {
+    match &node.id {
+        Some(trace::node::Id::ResponseName(name)) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json_bytes::json;
+
+    fn field(name: &str, parent_type: &str, ty: &str, child: Vec<trace::Node>) -> trace::Node {
+        trace::Node {
+            id: Some(trace::node::Id::ResponseName(name.to_string())),
+            parent_type: parent_type.to_string(),
+            r#type: ty.to_string(),
+            start_time: 1_000,
+            end_time: 3_000,
+            child,
+            ..Default::default()
+        }
+    }
+
+    fn index(index: u32, child: Vec<trace::Node>) -> trace::Node {
+        trace::Node {
+            id: Some(trace::node::Id::Index(index)),
+            child,
+            ..Default::default()
+        }
+    }
+
+    fn subgraph_trace(service_name: &str, operation: &str, root: trace::Node) -> SubgraphTrace {
+        let trace = Trace {
+            root: Some(root),
+            ..Default::default()
+        };
+        SubgraphTrace {
+            service_name: service_name.to_string(),
+            operation: operation.to_string(),
+            ftv1: Some(base64::encode(prost::Message::encode_to_vec(&trace))),
+            sent_time: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            received_time: SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn it_assembles_the_subgraph_traces() {
+        let query_plan = json!({
+            "kind": "Sequence",
+            "nodes": [
+                {
+                    "kind": "Fetch",
+                    "serviceName": "products",
+                    "variableUsages": [],
+                    "operation": "{topProducts{__typename upc}}",
+                    "operationKind": "query"
+                },
+                {
+                    "kind": "Flatten",
+                    "path": ["topProducts", "@"],
+                    "node": {
+                        "kind": "Fetch",
+                        "serviceName": "reviews",
+                        "variableUsages": [],
+                        "operation": "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{reviews{body}}}}",
+                        "operationKind": "query"
+                    }
+                }
+            ]
+        });
+
+        let products = field(
+            "topProducts",
+            "Query",
+            "[Product]",
+            vec![index(0, vec![field("upc", "Product", "String!", vec![])])],
+        );
+        let mut reviews = field("reviews", "Product", "[Review]", vec![]);
+        reviews.error.push(trace::Error {
+            message: "review service unavailable".to_string(),
+            ..Default::default()
+        });
+        let entities = field(
+            "_entities",
+            "Query",
+            "[_Entity]!",
+            vec![index(0, vec![reviews])],
+        );
+        let subgraph_traces = vec![
+            subgraph_trace(
+                "products",
+                "{topProducts{__typename upc}}",
+                trace::Node {
+                    child: vec![products],
+                    ..Default::default()
+                },
+            ),
+            subgraph_trace(
+                "reviews",
+                "query($representations:[_Any!]!){_entities(representations:$representations){...on Product{reviews{body}}}}",
+                trace::Node {
+                    child: vec![entities],
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let trace = federated_trace(
+            &query_plan,
+            subgraph_traces,
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(3),
+        );
+        assert_eq!(trace.duration_ns, 3_000_000_000);
+
+        let nodes = match trace
+            .query_plan
+            .as_ref()
+            .and_then(|node| node.node.as_ref())
+        {
+            Some(Node::Sequence(sequence)) => &sequence.nodes,
+            node => panic!("expected a sequence, got {:?}", node),
+        };
+        match &nodes[0].node {
+            Some(Node::Fetch(fetch)) => {
+                assert_eq!(fetch.service_name, "products");
+                assert!(fetch.trace.is_some());
+                assert_eq!(fetch.sent_time_offset, 1_000_000_000);
+            }
+            node => panic!("expected a fetch, got {:?}", node),
+        }
+        match &nodes[1].node {
+            Some(Node::Flatten(flatten)) => assert_eq!(flatten.response_path.len(), 2),
+            node => panic!("expected a flatten, got {:?}", node),
+        }
+
+        let stats = FieldStats::new(&trace, 2.0);
+        let top_products = &stats.per_type_stat["Query"].per_field_stat["topProducts"];
+        assert_eq!(top_products.return_type, "[Product]");
+        assert_eq!(top_products.estimated_execution_count, 2.0);
+        assert_eq!(top_products.latencies, vec![Duration::from_nanos(2_000)]);
+        assert!(!stats.per_type_stat["Query"]
+            .per_field_stat
+            .contains_key("_entities"));
+        let product = &stats.per_type_stat["Product"].per_field_stat;
+        assert_eq!(product["upc"].errors_count, 0);
+        assert_eq!(product["reviews"].errors_count, 1);
+        assert_eq!(product["reviews"].requests_with_errors_count, 1);
+        assert_eq!(
+            stats.root_error_stats.children["topProducts"].children["reviews"].errors_count,
+            1
+        );
+    }
+
+    #[test]
+    fn it_flags_unparsable_traces() {
+        let query_plan = json!({
+            "kind": "Fetch",
+            "serviceName": "products",
+            "variableUsages": [],
+            "operation": "{topProducts{upc}}",
+            "operationKind": "query"
+        });
+        let mut subgraph_trace =
+            subgraph_trace("products", "{topProducts{upc}}", trace::Node::default());
+        subgraph_trace.ftv1 = Some("not a trace".to_string());
+
+        let trace = federated_trace(
+            &query_plan,
+            vec![subgraph_trace],
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(1),
+        );
+        match trace.query_plan.and_then(|node| node.node) {
+            Some(Node::Fetch(fetch)) => {
+                assert!(fetch.trace_parsing_failed);
+                assert!(fetch.trace.is_none());
+            }
+            node => panic!("expected a fetch, got {:?}", node),
+        }
+    }
+}
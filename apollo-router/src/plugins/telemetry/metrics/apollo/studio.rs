@@ -1,5 +1,5 @@
 use super::duration_histogram::DurationHistogram;
-use apollo_spaceport::{ReferencedFieldsForType, ReportHeader, StatsContext};
+use apollo_spaceport::{ReferencedFieldsForType, ReportHeader, StatsContext, Trace};
 use itertools::Itertools;
 use serde::Serialize;
 use std::collections::HashMap;
@@ -41,6 +41,8 @@ pub(crate) struct SingleReport {
 pub(crate) struct SingleTracesAndStats {
     pub(crate) stats_with_context: SingleContextualizedStats,
     pub(crate) referenced_fields_by_type: HashMap<String, ReferencedFieldsForType>,
+    #[serde(skip)]
+    pub(crate) traces: Vec<Trace>,
 }
 
 #[derive(Default, Debug, Serialize)]
@@ -84,7 +86,8 @@ pub(crate) struct SingleFieldStat {
     pub(crate) errors_count: u64,
     pub(crate) estimated_execution_count: f64,
     pub(crate) requests_with_errors_count: u64,
-    pub(crate) latency: Duration,
+    /// The latency of every execution of the field in the request.
+    pub(crate) latencies: Vec<Duration>,
 }
 
 #[derive(Default, Serialize)]
@@ -108,6 +111,8 @@ pub(crate) struct TracesAndStats {
     #[serde(with = "vectorize")]
     pub(crate) stats_with_context: HashMap<StatsContext, ContextualizedStats>,
     pub(crate) referenced_fields_by_type: HashMap<String, ReferencedFieldsForType>,
+    #[serde(skip)]
+    pub(crate) traces: Vec<Trace>,
 }
 
 impl AddAssign<SingleTracesAndStats> for TracesAndStats {
@@ -119,6 +124,7 @@ impl AddAssign<SingleTracesAndStats> for TracesAndStats {
 
         // No merging required here because references fields by type will always be the same for each stats report key.
         self.referenced_fields_by_type = stats.referenced_fields_by_type;
+        self.traces.extend(stats.traces);
     }
 }
 
@@ -217,7 +223,9 @@ pub(crate) struct FieldStat {
 
 impl AddAssign<SingleFieldStat> for FieldStat {
     fn add_assign(&mut self, stat: SingleFieldStat) {
-        self.latency.increment_duration(Some(stat.latency), 1);
+        for latency in stat.latencies {
+            self.latency.increment_duration(Some(latency), 1);
+        }
         self.requests_with_errors_count += stat.requests_with_errors_count;
         self.estimated_execution_count += stat.estimated_execution_count;
         self.errors_count += stat.errors_count;
@@ -244,6 +252,7 @@ impl From<TracesAndStats> for apollo_spaceport::TracesAndStats {
         Self {
             stats_with_context: stats.stats_with_context.into_values().map_into().collect(),
             referenced_fields_by_type: stats.referenced_fields_by_type,
+            trace: stats.traces,
             ..Default::default()
         }
     }
@@ -455,7 +464,7 @@ mod test {
             errors_count: count.inc_u64(),
             estimated_execution_count: count.inc_f64(),
             requests_with_errors_count: count.inc_u64(),
-            latency: Duration::from_secs(1),
+            latencies: vec![Duration::from_secs(1)],
         }
     }
 
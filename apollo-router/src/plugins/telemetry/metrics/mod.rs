@@ -1,12 +1,19 @@
+use crate::plugins::telemetry::attributes::merge_attributes;
 use crate::plugins::telemetry::config::MetricsCommon;
 use crate::plugins::telemetry::metrics::apollo::Sender;
-use apollo_router_core::{http_compat, Handler, ResponseBody};
+use apollo_router_core::{http_compat, Handler, ResponseBody, ResponseBodySize, SubgraphResponse};
+use apollo_spaceport::server::ReportCounters;
 use bytes::Bytes;
-use opentelemetry::metrics::{Counter, Meter, MeterProvider, Number, ValueRecorder};
+use opentelemetry::metrics::{
+    Counter, Descriptor, InstrumentKind, Meter, MeterProvider, Number, SumObserver, ValueRecorder,
+};
+use opentelemetry::sdk::export::metrics::{Aggregator, AggregatorSelector};
+use opentelemetry::sdk::metrics::aggregators;
 use opentelemetry::KeyValue;
 use std::any::Any;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tower::util::BoxService;
 use tower::BoxError;
 
@@ -24,14 +31,25 @@ pub(crate) struct MetricsBuilder {
     meter_providers: Vec<Arc<dyn MeterProvider + Send + Sync + 'static>>,
     custom_endpoints: HashMap<String, Handler>,
     apollo_metrics: Sender,
+    name_prefix: String,
 }
 
 impl MetricsBuilder {
+    pub(crate) fn new(metrics_config: &MetricsCommon) -> Self {
+        Self {
+            name_prefix: metrics_config.prefix.clone(),
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn exporters(&mut self) -> Vec<MetricsExporterHandle> {
         std::mem::take(&mut self.exporters)
     }
     pub(crate) fn meter_provider(&mut self) -> AggregateMeterProvider {
-        AggregateMeterProvider::new(std::mem::take(&mut self.meter_providers))
+        AggregateMeterProvider::new(
+            std::mem::take(&mut self.meter_providers),
+            self.name_prefix.clone(),
+        )
     }
     pub(crate) fn custom_endpoints(&mut self) -> HashMap<String, Handler> {
         std::mem::take(&mut self.custom_endpoints)
@@ -87,32 +105,170 @@ impl BasicMetrics {
     pub fn new(meter_provider: &AggregateMeterProvider) -> BasicMetrics {
         let meter = meter_provider.meter("apollo/router", None);
         BasicMetrics {
-            http_requests_total: meter.build_counter(|m| {
-                m.u64_counter("http_requests_total")
+            http_requests_total: meter.build_counter("http_requests_total", |m, name| {
+                m.u64_counter(name)
                     .with_description("Total number of HTTP requests made.")
                     .init()
             }),
-            http_requests_error_total: meter.build_counter(|m| {
-                m.u64_counter("http_requests_error_total")
-                    .with_description("Total number of HTTP requests in error made.")
+            http_requests_error_total: meter.build_counter(
+                "http_requests_error_total",
+                |m, name| {
+                    m.u64_counter(name)
+                        .with_description("Total number of HTTP requests in error made.")
+                        .init()
+                },
+            ),
+            http_requests_duration: meter.build_value_recorder(
+                "http_request_duration_seconds",
+                |m, name| {
+                    m.f64_value_recorder(name)
+                        .with_description("Total number of HTTP requests made.")
+                        .init()
+                },
+            ),
+        }
+    }
+}
+
+/// Metrics of the requests made to a subgraph, labelled by subgraph name and operation kind.
+#[derive(Clone)]
+pub(crate) struct SubgraphMetrics {
+    pub requests_total: AggregateCounter<u64>,
+    pub requests_error_total: AggregateCounter<u64>,
+    pub request_duration: AggregateValueRecorder<f64>,
+    pub graphql_errors_total: AggregateCounter<u64>,
+    pub response_size: AggregateValueRecorder<u64>,
+}
+
+impl SubgraphMetrics {
+    pub fn new(meter_provider: &AggregateMeterProvider) -> SubgraphMetrics {
+        let meter = meter_provider.meter("apollo/router", None);
+        SubgraphMetrics {
+            requests_total: meter.build_counter("subgraph_requests_total", |m, name| {
+                m.u64_counter(name)
+                    .with_description("Total number of requests made to subgraphs.")
                     .init()
             }),
-            http_requests_duration: meter.build_value_recorder(|m| {
-                m.f64_value_recorder("http_request_duration_seconds")
-                    .with_description("Total number of HTTP requests made.")
+            requests_error_total: meter.build_counter(
+                "subgraph_requests_error_total",
+                |m, name| {
+                    m.u64_counter(name)
+                        .with_description(
+                            "Total number of requests made to subgraphs which failed.",
+                        )
+                        .init()
+                },
+            ),
+            request_duration: meter.build_value_recorder(
+                "subgraph_request_duration_seconds",
+                |m, name| {
+                    m.f64_value_recorder(name)
+                        .with_description("Duration of the requests made to subgraphs.")
+                        .init()
+                },
+            ),
+            graphql_errors_total: meter.build_counter(
+                "subgraph_graphql_errors_total",
+                |m, name| {
+                    m.u64_counter(name)
+                        .with_description("Total number of GraphQL errors returned by subgraphs.")
+                        .init()
+                },
+            ),
+            response_size: meter.build_value_recorder("subgraph_response_size_bytes", |m, name| {
+                m.u64_value_recorder(name)
+                    .with_description(
+                        "Size of the response bodies returned by subgraphs, once decompressed.",
+                    )
                     .init()
             }),
         }
     }
+
+    /// Record the outcome of a subgraph request.
+    pub fn record(
+        &self,
+        attributes: &[KeyValue],
+        result: &Result<SubgraphResponse, BoxError>,
+        duration: Duration,
+    ) {
+        match result {
+            Ok(response) => {
+                let mut status_attributes = vec![KeyValue::new(
+                    "status",
+                    response.response.status().as_u16().to_string(),
+                )];
+                merge_attributes(&mut status_attributes, attributes);
+                self.requests_total.add(1, &status_attributes);
+
+                let errors = response.response.body().errors.len() as u64;
+                if errors > 0 {
+                    self.graphql_errors_total.add(errors, attributes);
+                }
+                if let Some(ResponseBodySize(size)) = response.response.extensions().get() {
+                    self.response_size.record(*size as u64, attributes);
+                }
+            }
+            Err(_) => {
+                self.requests_error_total.add(1, attributes);
+            }
+        }
+        self.request_duration
+            .record(duration.as_secs_f64(), attributes);
+    }
+}
+
+/// Metrics of the reports handled by the embedded spaceport, observed from its counters.
+pub(crate) struct SpaceportMetrics {
+    _observers: Vec<SumObserver<u64>>,
+}
+
+impl SpaceportMetrics {
+    pub fn new(meter_provider: &AggregateMeterProvider, counters: Arc<ReportCounters>) -> Self {
+        let meter = meter_provider.meter("apollo/router", None);
+        let counted: [(&str, &str, fn(&ReportCounters) -> u64); 3] = [
+            (
+                "spaceport_reports_spooled_total",
+                "Total number of reports written to the spool after a failed transfer.",
+                ReportCounters::spooled,
+            ),
+            (
+                "spaceport_reports_sent_total",
+                "Total number of reports transferred by spaceport.",
+                ReportCounters::sent,
+            ),
+            (
+                "spaceport_reports_dropped_total",
+                "Total number of reports discarded by spaceport.",
+                ReportCounters::dropped,
+            ),
+        ];
+        SpaceportMetrics {
+            _observers: counted
+                .into_iter()
+                .flat_map(|(name, description, count)| {
+                    let counters = counters.clone();
+                    meter.build_sum_observer(name, description, move || count(&counters))
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(Clone, Default)]
-pub(crate) struct AggregateMeterProvider(Vec<Arc<dyn MeterProvider + Send + Sync + 'static>>);
+pub(crate) struct AggregateMeterProvider {
+    providers: Vec<Arc<dyn MeterProvider + Send + Sync + 'static>>,
+    name_prefix: String,
+}
 impl AggregateMeterProvider {
     pub fn new(
         meters: Vec<Arc<dyn MeterProvider + Send + Sync + 'static>>,
+        name_prefix: String,
     ) -> AggregateMeterProvider {
-        AggregateMeterProvider(meters)
+        AggregateMeterProvider {
+            providers: meters,
+            name_prefix,
+        }
     }
 
     pub fn meter(
@@ -120,30 +276,128 @@ impl AggregateMeterProvider {
         instrumentation_name: &'static str,
         instrumentation_version: Option<&'static str>,
     ) -> AggregateMeter {
-        AggregateMeter(
-            self.0
+        AggregateMeter {
+            meters: self
+                .providers
                 .iter()
                 .map(|p| Arc::new(p.meter(instrumentation_name, instrumentation_version)))
                 .collect(),
-        )
+            name_prefix: self.name_prefix.clone(),
+        }
     }
 }
 
+/// The meters of all the providers. The instruments they build are named with the configured
+/// prefix.
 #[derive(Clone)]
-pub struct AggregateMeter(Vec<Arc<Meter>>);
+pub struct AggregateMeter {
+    meters: Vec<Arc<Meter>>,
+    name_prefix: String,
+}
 impl AggregateMeter {
     pub fn build_counter<T: Into<Number> + Copy>(
         &self,
-        build: fn(&Meter) -> Counter<T>,
+        name: &str,
+        build: fn(&Meter, String) -> Counter<T>,
     ) -> AggregateCounter<T> {
-        AggregateCounter(self.0.iter().map(|m| build(m)).collect())
+        AggregateCounter(
+            self.meters
+                .iter()
+                .map(|m| build(m, self.name(name)))
+                .collect(),
+        )
     }
 
     pub fn build_value_recorder<T: Into<Number> + Copy>(
         &self,
-        build: fn(&Meter) -> ValueRecorder<T>,
+        name: &str,
+        build: fn(&Meter, String) -> ValueRecorder<T>,
     ) -> AggregateValueRecorder<T> {
-        AggregateValueRecorder(self.0.iter().map(|m| build(m)).collect())
+        AggregateValueRecorder(
+            self.meters
+                .iter()
+                .map(|m| build(m, self.name(name)))
+                .collect(),
+        )
+    }
+
+    pub fn build_sum_observer<F>(
+        &self,
+        name: &str,
+        description: &str,
+        observe: F,
+    ) -> Vec<SumObserver<u64>>
+    where
+        F: Fn() -> u64 + Clone + Send + Sync + 'static,
+    {
+        self.meters
+            .iter()
+            .map(|m| {
+                let observe = observe.clone();
+                m.u64_sum_observer(self.name(name), move |result| {
+                    result.observe(observe(), &[])
+                })
+                .with_description(description)
+                .init()
+            })
+            .collect()
+    }
+
+    fn name(&self, name: &str) -> String {
+        format!("{}{}", self.name_prefix, name)
+    }
+}
+
+/// The bucket boundaries of the histograms which do not measure durations, unless configured:
+/// the default boundaries of the exporters are meant for seconds.
+const DEFAULT_BUCKETS: [(&str, &[f64]); 1] = [(
+    "subgraph_response_size_bytes",
+    &[
+        100.0,
+        1_000.0,
+        10_000.0,
+        100_000.0,
+        1_000_000.0,
+        10_000_000.0,
+    ],
+)];
+
+/// Aggregates the histograms having configured buckets, and delegates the other instruments to
+/// the selector of the exporter.
+#[derive(Debug)]
+pub(crate) struct CustomAggregatorSelector<S> {
+    /// The bucket boundaries, by full name of the metric.
+    buckets: HashMap<String, Vec<f64>>,
+    fallback: S,
+}
+
+impl<S> CustomAggregatorSelector<S> {
+    pub(crate) fn new(metrics_config: &MetricsCommon, fallback: S) -> Self {
+        Self {
+            buckets: DEFAULT_BUCKETS
+                .iter()
+                .map(|(name, boundaries)| (name.to_string(), boundaries.to_vec()))
+                .chain(metrics_config.buckets.clone())
+                .map(|(name, boundaries)| {
+                    (format!("{}{}", metrics_config.prefix, name), boundaries)
+                })
+                .collect(),
+            fallback,
+        }
+    }
+}
+
+impl<S: AggregatorSelector> AggregatorSelector for CustomAggregatorSelector<S> {
+    fn aggregator_for(&self, descriptor: &Descriptor) -> Option<Arc<dyn Aggregator + Send + Sync>> {
+        match (
+            descriptor.instrument_kind(),
+            self.buckets.get(descriptor.name()),
+        ) {
+            (InstrumentKind::ValueRecorder, Some(boundaries)) => {
+                Some(Arc::new(aggregators::histogram(descriptor, boundaries)))
+            }
+            _ => self.fallback.aggregator_for(descriptor),
+        }
     }
 }
 
@@ -1,5 +1,7 @@
 use crate::plugins::telemetry::config::MetricsCommon;
-use crate::plugins::telemetry::metrics::{MetricsBuilder, MetricsConfigurator};
+use crate::plugins::telemetry::metrics::{
+    CustomAggregatorSelector, MetricsBuilder, MetricsConfigurator,
+};
 use futures::{Stream, StreamExt};
 use opentelemetry::sdk::metrics::selectors;
 use opentelemetry::util::tokio_interval_stream;
@@ -32,7 +34,7 @@ impl MetricsConfigurator for super::super::otlp::Config {
     fn apply(
         &self,
         mut builder: MetricsBuilder,
-        _metrics_config: &MetricsCommon,
+        metrics_config: &MetricsCommon,
     ) -> Result<MetricsBuilder, BoxError> {
         let exporter: MetricExporterBuilder = self.exporter()?;
         match exporter.exporter {
@@ -40,7 +42,11 @@ impl MetricsConfigurator for super::super::otlp::Config {
                 let exporter = opentelemetry_otlp::new_pipeline()
                     .metrics(tokio::spawn, delayed_interval)
                     .with_exporter(exporter)
-                    .with_aggregator_selector(selectors::simple::Selector::Exact)
+                    .with_aggregator_selector(CustomAggregatorSelector::new(
+                        metrics_config,
+                        selectors::simple::Selector::Exact,
+                    ))
+                    .with_resource(metrics_config.resource_labels())
                     .build()?;
                 builder = builder.with_meter_provider(exporter.provider());
                 builder = builder.with_exporter(exporter);
@@ -1,9 +1,13 @@
 use crate::future::BoxFuture;
 use crate::plugins::telemetry::config::MetricsCommon;
-use crate::plugins::telemetry::metrics::{MetricsBuilder, MetricsConfigurator};
+use crate::plugins::telemetry::metrics::{
+    CustomAggregatorSelector, MetricsBuilder, MetricsConfigurator,
+};
 use apollo_router_core::{http_compat, ResponseBody};
 use bytes::Bytes;
 use http::StatusCode;
+use opentelemetry::sdk::metrics::selectors;
+use opentelemetry::sdk::Resource;
 use prometheus::{Encoder, Registry, TextEncoder};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -11,6 +15,9 @@ use std::task::{Context, Poll};
 use tower::{BoxError, ServiceExt};
 use tower_service::Service;
 
+/// The boundaries of the histograms without configured buckets, the default ones of the exporter.
+const DEFAULT_HISTOGRAM_BOUNDARIES: [f64; 3] = [0.5, 0.9, 0.99];
+
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -21,10 +28,16 @@ impl MetricsConfigurator for Config {
     fn apply(
         &self,
         mut builder: MetricsBuilder,
-        _metrics_config: &MetricsCommon,
+        metrics_config: &MetricsCommon,
     ) -> Result<MetricsBuilder, BoxError> {
         if self.enabled {
-            let exporter = opentelemetry_prometheus::exporter().try_init()?;
+            let exporter = opentelemetry_prometheus::exporter()
+                .with_resource(Resource::new(metrics_config.resource_labels()))
+                .with_aggregator_selector(CustomAggregatorSelector::new(
+                    metrics_config,
+                    selectors::simple::Selector::Histogram(DEFAULT_HISTOGRAM_BOUNDARIES.to_vec()),
+                ))
+                .try_init()?;
             builder = builder.with_custom_endpoint(
                 "/prometheus",
                 PrometheusService {
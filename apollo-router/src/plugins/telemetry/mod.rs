@@ -1,13 +1,19 @@
 //! Telemetry plugin.
 // This entire file is license key functionality
 use crate::plugins::telemetry::apollo::Config;
+use crate::plugins::telemetry::attributes::{
+    merge_attributes, set_span_attributes, AttributesForwarder,
+};
 use crate::plugins::telemetry::config::{MetricsCommon, Trace};
+use crate::plugins::telemetry::metrics::apollo::ftv1::{
+    self, FieldStats, SubgraphTrace, FTV1, INCLUDE_TRACE_HEADER,
+};
 use crate::plugins::telemetry::metrics::apollo::studio::{
     SingleContextualizedStats, SingleQueryLatencyStats, SingleReport, SingleTracesAndStats,
 };
 use crate::plugins::telemetry::metrics::{
     AggregateMeterProvider, BasicMetrics, MetricsBuilder, MetricsConfigurator,
-    MetricsExporterHandle,
+    MetricsExporterHandle, SpaceportMetrics, SubgraphMetrics,
 };
 use crate::plugins::telemetry::tracing::TracingConfigurator;
 use crate::subscriber::replace_layer;
@@ -16,7 +22,7 @@ use apollo_router_core::reexports::router_bridge::planner::UsageReporting;
 use apollo_router_core::{
     http_compat, register_plugin, Context, ExecutionRequest, ExecutionResponse, Handler, Plugin,
     QueryPlannerRequest, QueryPlannerResponse, ResponseBody, RouterRequest, RouterResponse,
-    ServiceBuilderExt, SubgraphRequest, SubgraphResponse, USAGE_REPORTING,
+    ServiceBuilderExt, SubgraphRequest, SubgraphResponse, Value, USAGE_REPORTING,
 };
 use apollo_spaceport::server::ReportSpaceport;
 use apollo_spaceport::StatsContext;
@@ -34,13 +40,14 @@ use opentelemetry::{global, KeyValue};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use tower::steer::Steer;
 use tower::util::BoxService;
 use tower::{service_fn, BoxError, ServiceBuilder, ServiceExt};
 use url::Url;
 
 pub mod apollo;
+pub mod attributes;
 pub mod config;
 mod metrics;
 mod otlp;
@@ -50,6 +57,10 @@ pub static ROUTER_SPAN_NAME: &str = "router";
 static CLIENT_NAME: &str = "apollo_telemetry::client_name";
 static CLIENT_VERSION: &str = "apollo_telemetry::client_version";
 pub(crate) static STUDIO_EXCLUDE: &str = "apollo_telemetry::studio::exclude";
+static OPERATION_KIND: &str = "apollo_telemetry::operation_kind";
+static FTV1_SAMPLED: &str = "apollo_telemetry::ftv1::sampled";
+static FTV1_QUERY_PLAN: &str = "apollo_telemetry::ftv1::query_plan";
+static FTV1_SUBGRAPH_TRACES: &str = "apollo_telemetry::ftv1::subgraph_traces";
 
 pub struct Telemetry {
     config: config::Conf,
@@ -61,7 +72,11 @@ pub struct Telemetry {
     meter_provider: AggregateMeterProvider,
     custom_endpoints: HashMap<String, Handler>,
     spaceport_shutdown: Option<futures::channel::oneshot::Sender<()>>,
+    // The observers of the counters of the embedded spaceport, if any.
+    _spaceport_metrics: Option<SpaceportMetrics>,
     apollo_metrics_sender: metrics::apollo::Sender,
+    router_attributes: AttributesForwarder,
+    subgraph_attributes: AttributesForwarder,
 }
 
 #[derive(Debug)]
@@ -153,20 +168,28 @@ impl Plugin for Telemetry {
             .apollo
             .as_mut()
             .expect("telemetry apollo config must be present");
+        if !(0.0..=1.0).contains(&apollo.field_level_instrumentation_sampler) {
+            return Err(format!(
+                "field_level_instrumentation_sampler must be between 0 and 1, got {}",
+                apollo.field_level_instrumentation_sampler
+            )
+            .into());
+        }
 
-        // If we have key and graph ref but no endpoint we start embedded spaceport
-        let (spaceport, shutdown_tx) = match apollo {
-            apollo::Config {
-                apollo_key: Some(_),
-                apollo_graph_ref: Some(_),
-                endpoint: None,
-                ..
-            } => {
+        // If we send reports but have no endpoint we start embedded spaceport
+        let (spaceport, shutdown_tx) =
+            if apollo.endpoint.is_none() && apollo.reporting_graph().is_some() {
                 ::tracing::debug!("starting Spaceport");
                 let (shutdown_tx, shutdown_rx) = futures::channel::oneshot::channel();
                 let report_spaceport = ReportSpaceport::new(
                     "127.0.0.1:0".parse()?,
                     Some(Box::pin(shutdown_rx.map(|_| ()))),
+                    apollo
+                        .reports_destination
+                        .clone()
+                        .map(Into::into)
+                        .unwrap_or_default(),
+                    apollo.reports_spool.clone().map(Into::into),
                 )
                 .await?;
                 // Now that the port is known update the config
@@ -175,9 +198,9 @@ impl Plugin for Telemetry {
                     report_spaceport.address()
                 ))?);
                 (Some(report_spaceport), Some(shutdown_tx))
-            }
-            _ => (None, None),
-        };
+            } else {
+                (None, None)
+            };
 
         // Setup metrics
         // The act of setting up metrics will overwrite a global meter. However it is essential that
@@ -186,19 +209,30 @@ impl Plugin for Telemetry {
         // eventually be one.
         let mut builder = Self::create_metrics_exporters(&config)?;
 
+        let attributes = config.attributes.clone().unwrap_or_default();
+        let router_attributes = AttributesForwarder::new(attributes.router.unwrap_or_default());
+        let subgraph_attributes = AttributesForwarder::new(attributes.subgraph.unwrap_or_default());
+
         //// THIS IS IMPORTANT
         // Once the trace provider has been created this method MUST NOT FAIL
         // The trace provider will not be shut down if drop is not called and it will result in a hang.
         // Don't add anything fallible after the tracer provider has been created.
         let tracer_provider = Self::create_tracer_provider(&config)?;
 
+        let meter_provider = builder.meter_provider();
+        let spaceport_metrics = spaceport
+            .as_ref()
+            .map(|spaceport| SpaceportMetrics::new(&meter_provider, spaceport.counters()));
         let plugin = Ok(Telemetry {
             spaceport_shutdown: shutdown_tx,
+            _spaceport_metrics: spaceport_metrics,
             tracer_provider: Some(tracer_provider),
             custom_endpoints: builder.custom_endpoints(),
             _metrics_exporters: builder.exporters(),
-            meter_provider: builder.meter_provider(),
+            meter_provider,
             apollo_metrics_sender: builder.apollo_metrics_provider(),
+            router_attributes,
+            subgraph_attributes,
             config,
         });
 
@@ -229,23 +263,63 @@ impl Plugin for Telemetry {
         let metrics_sender = self.apollo_metrics_sender.clone();
         let metrics = BasicMetrics::new(&self.meter_provider);
         let config = self.config.apollo.clone().unwrap_or_default();
+        let router_attributes = self.router_attributes.clone();
+        let request_attributes = self.router_attributes.clone();
+        let field_level_instrumentation_sampler = if matches!(metrics_sender, Sender::Noop) {
+            0.0
+        } else {
+            config.field_level_instrumentation_sampler
+        };
         ServiceBuilder::new()
             .instrument(Self::router_service_span(config.clone()))
             .map_future_with_context(
                 move |req: &RouterRequest| {
                     Self::populate_context(&config, req);
-                    req.context.clone()
+                    if field_level_instrumentation_sampler > 0.0
+                        && rand::random::<f64>() < field_level_instrumentation_sampler
+                    {
+                        let _ = req.context.insert(FTV1_SAMPLED, true);
+                    }
+                    let http_request = &req.originating_request;
+                    let attributes = request_attributes.request_attributes(
+                        http_request.headers(),
+                        http_request.body().operation_name.as_deref(),
+                        &req.context,
+                    );
+                    (req.context.clone(), attributes)
                 },
-                move |ctx, fut| {
+                move |(ctx, mut attributes): (Context, Vec<KeyValue>), fut| {
                     let metrics = metrics.clone();
+                    let router_attributes = router_attributes.clone();
                     let sender = metrics_sender.clone();
                     let start = Instant::now();
+                    let start_time = SystemTime::now();
                     async move {
                         let result: Result<RouterResponse, BoxError> = fut.await;
                         if !matches!(sender, Sender::Noop) {
-                            Self::update_apollo_metrics(ctx, sender, &result, start.elapsed());
+                            Self::update_apollo_metrics(
+                                ctx,
+                                sender,
+                                &result,
+                                start_time,
+                                start.elapsed(),
+                                field_level_instrumentation_sampler,
+                            );
                         }
-                        Self::update_metrics(metrics, &result);
+                        let operation_kind =
+                            ctx.get::<_, String>(OPERATION_KIND).unwrap_or_default();
+                        attributes.extend(
+                            router_attributes.response_attributes(
+                                &ctx,
+                                operation_kind.as_deref(),
+                                result
+                                    .as_ref()
+                                    .ok()
+                                    .map(|response| response.response.status()),
+                            ),
+                        );
+                        set_span_attributes(&Span::current(), &attributes);
+                        Self::update_metrics(metrics, &result, &attributes);
                         result
                     }
                 },
@@ -270,6 +344,25 @@ impl Plugin for Telemetry {
     ) -> BoxService<ExecutionRequest, ExecutionResponse, BoxError> {
         ServiceBuilder::new()
             .instrument(move |_| info_span!("execution", "otel.kind" = %SpanKind::Internal))
+            .map_request(|req: ExecutionRequest| {
+                let operation_kind = if req.query_plan.is_subscription() {
+                    "subscription"
+                } else if req.query_plan.contains_mutations() {
+                    "mutation"
+                } else {
+                    "query"
+                };
+                let _ = req
+                    .context
+                    .insert(OPERATION_KIND, operation_kind.to_string());
+                // The query plan is the skeleton of the federated trace.
+                if Self::ftv1_sampled(&req.context) {
+                    let _ = req
+                        .context
+                        .insert(FTV1_QUERY_PLAN, req.query_plan.to_json());
+                }
+                req
+            })
             .service(service)
             .boxed()
     }
@@ -281,7 +374,12 @@ impl Plugin for Telemetry {
     ) -> BoxService<SubgraphRequest, SubgraphResponse, BoxError> {
         let metrics = BasicMetrics::new(&self.meter_provider);
         let subgraph_attribute = KeyValue::new("subgraph", name.to_string());
+        let subgraph_metrics = SubgraphMetrics::new(&self.meter_provider);
+        let subgraph_name = name.to_string();
+        let subgraph_attributes = self.subgraph_attributes.clone();
+        let request_attributes = self.subgraph_attributes.clone();
         let name = name.to_owned();
+        let service_name = name.clone();
         ServiceBuilder::new()
             .instrument(move |_| {
                 info_span!("subgraph",
@@ -289,6 +387,100 @@ impl Plugin for Telemetry {
                     "otel.kind" = %SpanKind::Internal,
                 )
             })
+            .map_request(|mut req: SubgraphRequest| {
+                if Self::ftv1_sampled(&req.context) {
+                    req.subgraph_request
+                        .headers_mut()
+                        .insert(INCLUDE_TRACE_HEADER, HeaderValue::from_static(FTV1));
+                }
+                req
+            })
+            .map_future_with_context(
+                move |req: &SubgraphRequest| {
+                    let attributes = request_attributes.request_attributes(
+                        req.subgraph_request.headers(),
+                        req.originating_request.body().operation_name.as_deref(),
+                        &req.context,
+                    );
+                    (
+                        req.operation_kind.to_string().to_lowercase(),
+                        req.context.clone(),
+                        attributes,
+                    )
+                },
+                move |ctx: (String, Context, Vec<KeyValue>), f| {
+                    let (operation_kind, context, request_attributes) = ctx;
+                    let subgraph_metrics = subgraph_metrics.clone();
+                    let subgraph_attributes = subgraph_attributes.clone();
+                    let mut attributes = vec![
+                        KeyValue::new("subgraph", subgraph_name.clone()),
+                        KeyValue::new("operation_kind", operation_kind.clone()),
+                    ];
+                    let now = Instant::now();
+                    async move {
+                        let result: Result<SubgraphResponse, BoxError> = f.await;
+                        let mut custom_attributes = request_attributes;
+                        custom_attributes.extend(
+                            subgraph_attributes.response_attributes(
+                                &context,
+                                Some(&operation_kind),
+                                result
+                                    .as_ref()
+                                    .ok()
+                                    .map(|response| response.response.status()),
+                            ),
+                        );
+                        set_span_attributes(&Span::current(), &custom_attributes);
+                        merge_attributes(&mut attributes, &custom_attributes);
+                        subgraph_metrics.record(&attributes, &result, now.elapsed());
+                        result
+                    }
+                },
+            )
+            .map_future_with_context(
+                |req: &SubgraphRequest| {
+                    Self::ftv1_sampled(&req.context).then(|| {
+                        (
+                            req.context.clone(),
+                            req.subgraph_request
+                                .body()
+                                .query
+                                .clone()
+                                .unwrap_or_default(),
+                        )
+                    })
+                },
+                move |sampled: Option<(Context, String)>, f| {
+                    let service_name = service_name.clone();
+                    let sent_time = SystemTime::now();
+                    async move {
+                        let result: Result<SubgraphResponse, BoxError> = f.await;
+                        if let (Some((context, operation)), Ok(response)) = (sampled, &result) {
+                            let subgraph_trace = SubgraphTrace {
+                                service_name,
+                                operation,
+                                ftv1: response
+                                    .response
+                                    .body()
+                                    .extensions
+                                    .get(FTV1)
+                                    .and_then(Value::as_str)
+                                    .map(str::to_string),
+                                sent_time,
+                                received_time: SystemTime::now(),
+                            };
+                            let _ = context.upsert(
+                                FTV1_SUBGRAPH_TRACES,
+                                move |mut traces: Vec<SubgraphTrace>| {
+                                    traces.push(subgraph_trace.clone());
+                                    traces
+                                },
+                            );
+                        }
+                        result
+                    }
+                },
+            )
             .service(service)
             .map_future(move |f| {
                 let metrics = metrics.clone();
@@ -404,7 +596,8 @@ impl Telemetry {
     fn create_metrics_exporters(config: &config::Conf) -> Result<MetricsBuilder, BoxError> {
         let metrics_config = config.metrics.clone().unwrap_or_default();
         let metrics_common_config = &metrics_config.common.unwrap_or_default();
-        let mut builder = MetricsBuilder::default();
+        metrics_common_config.validate()?;
+        let mut builder = MetricsBuilder::new(metrics_common_config);
         builder = setup_metrics_exporter(builder, &config.apollo, metrics_common_config)?;
         builder =
             setup_metrics_exporter(builder, &metrics_config.prometheus, metrics_common_config)?;
@@ -473,11 +666,20 @@ impl Telemetry {
         }
     }
 
+    fn ftv1_sampled(context: &Context) -> bool {
+        context
+            .get::<_, bool>(FTV1_SAMPLED)
+            .unwrap_or_default()
+            .unwrap_or_default()
+    }
+
     fn update_apollo_metrics(
         context: Context,
         sender: Sender,
         result: &Result<RouterResponse, BoxError>,
+        start_time: SystemTime,
         duration: Duration,
+        field_level_instrumentation_sampler: f64,
     ) {
         let metrics = if let Some(usage_reporting) = context
             .get::<_, UsageReporting>(USAGE_REPORTING)
@@ -498,6 +700,40 @@ impl Telemetry {
                     ..Default::default()
                 }
             } else {
+                let client_name: String = context
+                    .get(CLIENT_NAME)
+                    .unwrap_or_default()
+                    .unwrap_or_default();
+                let client_version: String = context
+                    .get(CLIENT_VERSION)
+                    .unwrap_or_default()
+                    .unwrap_or_default();
+                // Field level statistics are only known for the requests with a federated trace.
+                let trace = context
+                    .get::<_, Value>(FTV1_QUERY_PLAN)
+                    .unwrap_or_default()
+                    .filter(|_| Self::ftv1_sampled(&context))
+                    .map(|query_plan| {
+                        let subgraph_traces = context
+                            .get(FTV1_SUBGRAPH_TRACES)
+                            .unwrap_or_default()
+                            .unwrap_or_default();
+                        apollo_spaceport::Trace {
+                            client_name: client_name.clone(),
+                            client_version: client_version.clone(),
+                            field_execution_weight: 1.0 / field_level_instrumentation_sampler,
+                            ..ftv1::federated_trace(
+                                &query_plan,
+                                subgraph_traces,
+                                start_time,
+                                duration,
+                            )
+                        }
+                    });
+                let field_stats = trace
+                    .as_ref()
+                    .map(|trace| FieldStats::new(trace, trace.field_execution_weight))
+                    .unwrap_or_default();
                 metrics::apollo::studio::SingleReport {
                     operation_count,
                     traces_and_stats: HashMap::from([(
@@ -505,14 +741,8 @@ impl Telemetry {
                         SingleTracesAndStats {
                             stats_with_context: SingleContextualizedStats {
                                 context: StatsContext {
-                                    client_name: context
-                                        .get(CLIENT_NAME)
-                                        .unwrap_or_default()
-                                        .unwrap_or_default(),
-                                    client_version: context
-                                        .get(CLIENT_VERSION)
-                                        .unwrap_or_default()
-                                        .unwrap_or_default(),
+                                    client_name,
+                                    client_version,
                                 },
                                 query_latency_stats: SingleQueryLatencyStats {
                                     latency: duration,
@@ -529,15 +759,18 @@ impl Telemetry {
                                         Err(_) => true,
                                     },
                                     persisted_query_hit,
+                                    root_error_stats: field_stats.root_error_stats,
+                                    without_field_instrumentation: trace.is_none(),
                                     ..Default::default()
                                 },
-                                ..Default::default()
+                                per_type_stat: field_stats.per_type_stat,
                             },
                             referenced_fields_by_type: usage_reporting
                                 .referenced_fields_by_type
                                 .into_iter()
                                 .map(|(k, v)| (k, convert(v)))
                                 .collect(),
+                            traces: trace.into_iter().collect(),
                         },
                     )]),
                 }
@@ -552,26 +785,29 @@ impl Telemetry {
         sender.send(metrics);
     }
 
-    fn update_metrics(metrics: BasicMetrics, result: &Result<RouterResponse, BoxError>) {
+    fn update_metrics(
+        metrics: BasicMetrics,
+        result: &Result<RouterResponse, BoxError>,
+        attributes: &[KeyValue],
+    ) {
         // Using Instant because it is guaranteed to be monotonically increasing.
         let now = Instant::now();
         match &result {
             Ok(response) => {
-                metrics.http_requests_total.add(
-                    1,
-                    &[KeyValue::new(
-                        "status",
-                        response.response.status().as_u16().to_string(),
-                    )],
-                );
+                let mut labels = vec![KeyValue::new(
+                    "status",
+                    response.response.status().as_u16().to_string(),
+                )];
+                merge_attributes(&mut labels, attributes);
+                metrics.http_requests_total.add(1, &labels);
             }
             Err(_) => {
-                metrics.http_requests_error_total.add(1, &[]);
+                metrics.http_requests_error_total.add(1, attributes);
             }
         }
         metrics
             .http_requests_duration
-            .record(now.elapsed().as_secs_f64(), &[]);
+            .record(now.elapsed().as_secs_f64(), attributes);
     }
 
     fn populate_context(config: &Config, req: &RouterRequest) {
@@ -643,6 +879,11 @@ register_plugin!("apollo", "telemetry", Telemetry);
 //
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use apollo_router_core::plugin::utils::test::MockSubgraphService;
+    use apollo_router_core::{Error, ResponseBodySize};
+    use http::Uri;
+    use tower::Service;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn plugin_registered() {
@@ -680,4 +921,213 @@ mod tests {
             .await
             .unwrap();
     }
+
+    /// Send a request to a subgraph through the plugin, and return the metrics exported to
+    /// Prometheus.
+    async fn subgraph_metrics(config: serde_json::Value, request: SubgraphRequest) -> String {
+        let mut mock_service = MockSubgraphService::new();
+        mock_service
+            .expect_call()
+            .times(1)
+            .returning(|req: SubgraphRequest| {
+                let mut response = SubgraphResponse::fake_builder()
+                    .errors(vec![Error::default()])
+                    .context(req.context)
+                    .build();
+                response
+                    .response
+                    .extensions_mut()
+                    .insert(ResponseBodySize(42));
+                Ok(response)
+            });
+
+        let mut dyn_plugin = apollo_router_core::plugins()
+            .get("apollo.telemetry")
+            .expect("Plugin not found")
+            .create_instance(&config)
+            .await
+            .unwrap();
+        let mut subgraph_service =
+            dyn_plugin.subgraph_service("products", BoxService::new(mock_service.build()));
+        subgraph_service
+            .ready()
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let mut request = http_compat::Request::<Bytes>::mock();
+        *request.uri_mut() = Uri::from_static("/prometheus");
+        let response = dyn_plugin
+            .custom_endpoint()
+            .expect("custom endpoint not found")
+            .oneshot(request)
+            .await
+            .unwrap();
+        match response.body() {
+            ResponseBody::Text(metrics) => metrics.clone(),
+            _ => panic!("metrics should be text"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_records_subgraph_metrics() {
+        let metrics = subgraph_metrics(
+            serde_json::json!({
+                "apollo": {"schema_id":"abc"},
+                "metrics": {"prometheus": {"enabled": true}}
+            }),
+            SubgraphRequest::fake_builder().build(),
+        )
+        .await;
+        for name in [
+            "subgraph_requests_total{",
+            "subgraph_request_duration_seconds_count{",
+            "subgraph_graphql_errors_total{",
+            "subgraph_response_size_bytes_sum{",
+        ] {
+            let line = metrics
+                .lines()
+                .find(|line| line.starts_with(name))
+                .unwrap_or_else(|| panic!("{} not found in {}", name, metrics));
+            assert!(line.contains(r#"subgraph="products""#));
+            assert!(line.contains(r#"operation_kind="query""#));
+        }
+        assert!(metrics.contains(r#"status="200""#));
+
+        // the response sizes have byte-scale buckets by default
+        let size_buckets: Vec<&str> = metrics
+            .lines()
+            .filter(|line| line.starts_with("subgraph_response_size_bytes_bucket{"))
+            .collect();
+        assert_eq!(size_buckets.len(), 7, "{}", metrics);
+        assert!(size_buckets[0].contains(r#"le="100""#));
+        assert!(size_buckets[0].ends_with(" 1"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_applies_common_metrics_config() {
+        let metrics = subgraph_metrics(
+            serde_json::json!({
+                "apollo": {"schema_id":"abc"},
+                "metrics": {
+                    "common": {
+                        "prefix": "gateway_",
+                        "buckets": {"subgraph_response_size_bytes": [10.0, 100.0, 1000.0]},
+                        "resource": {"env": "test"}
+                    },
+                    "prometheus": {"enabled": true}
+                }
+            }),
+            SubgraphRequest::fake_builder().build(),
+        )
+        .await;
+        let buckets: Vec<&str> = metrics
+            .lines()
+            .filter(|line| line.starts_with("gateway_subgraph_response_size_bytes_bucket{"))
+            .collect();
+        assert_eq!(buckets.len(), 4, "{}", metrics);
+        assert!(buckets[0].contains(r#"le="10""#));
+        assert!(buckets[0].ends_with(" 0"));
+        assert!(buckets[1].contains(r#"le="100""#));
+        assert!(buckets[1].ends_with(" 1"));
+        assert!(buckets.iter().all(|line| line.contains(r#"env="test""#)));
+        assert!(!metrics
+            .lines()
+            .any(|line| line.starts_with("subgraph_requests_total")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_labels_subgraph_metrics_with_custom_attributes() {
+        let mut subgraph_request = http_compat::Request::<apollo_router_core::Request>::mock();
+        subgraph_request
+            .headers_mut()
+            .insert("x-platform", HeaderValue::from_static("ios"));
+        let context = Context::new();
+        context.insert("tenant", "acme".to_string()).unwrap();
+        let metrics = subgraph_metrics(
+            serde_json::json!({
+                "apollo": {"schema_id":"abc"},
+                "metrics": {"prometheus": {"enabled": true}},
+                "attributes": {
+                    "subgraph": {
+                        "request_headers": [{"named": "x-platform", "rename": "platform"}],
+                        "context": [{"named": "tenant"}]
+                    }
+                }
+            }),
+            SubgraphRequest::fake_builder()
+                .subgraph_request(subgraph_request)
+                .context(context)
+                .build(),
+        )
+        .await;
+        let line = metrics
+            .lines()
+            .find(|line| line.starts_with("subgraph_requests_total{"))
+            .unwrap_or_else(|| panic!("subgraph_requests_total not found in {}", metrics));
+        assert!(line.contains(r#"platform="ios""#), "{}", line);
+        assert!(line.contains(r#"tenant="acme""#), "{}", line);
+        assert!(line.contains(r#"subgraph="products""#), "{}", line);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_exposes_the_spaceport_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dyn_plugin = apollo_router_core::plugins()
+            .get("apollo.telemetry")
+            .expect("Plugin not found")
+            .create_instance(&serde_json::json!({
+                "apollo": {
+                    "schema_id": "abc",
+                    "reports_destination": {"file": {"path": dir.path()}}
+                },
+                "metrics": {"prometheus": {"enabled": true}}
+            }))
+            .await
+            .unwrap();
+
+        let mut request = http_compat::Request::<Bytes>::mock();
+        *request.uri_mut() = Uri::from_static("/prometheus");
+        let response = dyn_plugin
+            .custom_endpoint()
+            .expect("custom endpoint not found")
+            .oneshot(request)
+            .await
+            .unwrap();
+        let metrics = match response.body() {
+            ResponseBody::Text(metrics) => metrics.clone(),
+            _ => panic!("metrics should be text"),
+        };
+        for name in [
+            "spaceport_reports_spooled_total",
+            "spaceport_reports_sent_total",
+            "spaceport_reports_dropped_total",
+        ] {
+            assert!(
+                metrics.lines().any(|line| line.starts_with(name)),
+                "{} not found in {}",
+                name,
+                metrics
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_rejects_unordered_buckets() {
+        let result = apollo_router_core::plugins()
+            .get("apollo.telemetry")
+            .expect("Plugin not found")
+            .create_instance(&serde_json::json!({
+                "apollo": {"schema_id":"abc"},
+                "metrics": {
+                    "common": {
+                        "buckets": {"http_request_duration_seconds": [1.0, 0.5]}
+                    }
+                }
+            }))
+            .await;
+        assert!(result.is_err());
+    }
 }
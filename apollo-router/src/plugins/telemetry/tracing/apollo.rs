@@ -10,20 +10,12 @@ use tower::BoxError;
 impl TracingConfigurator for Config {
     fn apply(&self, builder: Builder, trace_config: &Trace) -> Result<Builder, BoxError> {
         tracing::debug!("configuring Apollo tracing");
-        Ok(match self {
-            Config {
-                endpoint: Some(endpoint),
-                apollo_key: Some(key),
-                apollo_graph_ref: Some(reference),
-                ..
-            } => {
+        Ok(match (&self.endpoint, self.reporting_graph()) {
+            (Some(endpoint), Some((key, reference))) => {
                 tracing::debug!("configuring exporter to Spaceport");
                 let exporter = apollo_telemetry::new_pipeline()
                     .with_trace_config(trace_config.into())
-                    .with_graph_config(&Some(StudioGraph {
-                        reference: reference.clone(),
-                        key: key.clone(),
-                    }))
+                    .with_graph_config(&Some(StudioGraph { reference, key }))
                     .with_spaceport_config(&Some(SpaceportConfig {
                         collector: endpoint.to_string(),
                     }))
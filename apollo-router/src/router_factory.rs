@@ -1,16 +1,29 @@
 // This entire file is license key functionality
-use crate::configuration::{Configuration, ConfigurationError};
+use crate::configuration::{
+    Apq, Configuration, ConfigurationError, SubgraphTls, SubgraphTransport,
+};
+use crate::files;
+use crate::tls;
 use apollo_router_core::prelude::*;
 use apollo_router_core::{
     http_compat::{Request, Response},
     PluggableRouterServiceBuilder, Plugins, ResponseBody, Schema, ServiceBuilderExt,
 };
-use apollo_router_core::{DynPlugin, TowerSubgraphService};
+use apollo_router_core::{
+    ApqStorage, BridgeQueryPlanner, CachingQueryPlanner, DynPlugin, InMemoryApqStorage,
+    PersistedQueryManifest, RedisApqStorage, Safelist, TowerSubgraphService,
+    DEFAULT_APQ_MAX_ENTRIES,
+};
 use envmnt::types::ExpandOptions;
 use envmnt::ExpansionType;
+use futures::StreamExt;
+use hyper::client::HttpConnector;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use tower::buffer::Buffer;
 use tower::util::{BoxCloneService, BoxService};
 use tower::{BoxError, ServiceBuilder, ServiceExt};
@@ -43,29 +56,188 @@ pub trait RouterServiceFactory: Send + Sync + 'static {
 
 /// Main implementation of the RouterService factory, supporting the extensions system
 #[derive(Default)]
-pub struct YamlRouterServiceFactory;
+pub struct YamlRouterServiceFactory {
+    manifest_watcher: Option<ManifestWatcher>,
+    /// Kept between the routers with the same APQ configuration, so that the registered queries
+    /// survive reloads
+    apq_storage: Option<(Apq, Arc<dyn ApqStorage>)>,
+}
+
+/// Watches the persisted query manifest, updating the safelist shared by the routers.
+///
+/// The watch stops when it is dropped.
+struct ManifestWatcher {
+    path: PathBuf,
+    safelist: Safelist,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ManifestWatcher {
+    fn new(path: &Path) -> Result<Self, BoxError> {
+        let safelist = Safelist::new(read_manifest(path)?);
+        let task = tokio::spawn({
+            let path = path.to_path_buf();
+            let safelist = safelist.clone();
+            async move {
+                // the first event only asks to read the manifest, which is already done
+                let mut changes = files::watch(path.clone(), None).skip(1).boxed();
+                while changes.next().await.is_some() {
+                    match read_manifest(&path) {
+                        Ok(manifest) => {
+                            tracing::info!(
+                                "reloaded the persisted query manifest with {} operations",
+                                manifest.len()
+                            );
+                            safelist.update(manifest);
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "could not reload the persisted query manifest: {}",
+                                err
+                            )
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            safelist,
+            task,
+        })
+    }
+}
+
+impl Drop for ManifestWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<PersistedQueryManifest, BoxError> {
+    let manifest = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "could not read the persisted query manifest {}: {}",
+            path.display(),
+            err
+        )
+    })?;
+    PersistedQueryManifest::from_json(&manifest).map_err(|err| {
+        format!(
+            "could not parse the persisted query manifest {}: {}",
+            path.display(),
+            err
+        )
+        .into()
+    })
+}
+
+type BufferedRouterService = Buffer<
+    BoxCloneService<Request<graphql::Request>, Response<ResponseBody>, BoxError>,
+    Request<graphql::Request>,
+>;
+
+/// RouterService created by the [`YamlRouterServiceFactory`].
+///
+/// It keeps a handle on its query planner, so that the next router can plan the hottest queries
+/// of this one before taking over the traffic.
+#[derive(Clone)]
+pub struct YamlRouterService {
+    service: BufferedRouterService,
+    query_planner: CachingQueryPlanner<BridgeQueryPlanner>,
+}
+
+impl Service<Request<graphql::Request>> for YamlRouterService {
+    type Response = Response<ResponseBody>;
+    type Error = BoxError;
+    type Future = <BufferedRouterService as Service<Request<graphql::Request>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<graphql::Request>) -> Self::Future {
+        self.service.call(request)
+    }
+}
 
 #[async_trait::async_trait]
 impl RouterServiceFactory for YamlRouterServiceFactory {
-    type RouterService = Buffer<
-        BoxCloneService<Request<graphql::Request>, Response<ResponseBody>, BoxError>,
-        Request<graphql::Request>,
-    >;
+    type RouterService = YamlRouterService;
     type Future = <Self::RouterService as Service<Request<graphql::Request>>>::Future;
 
     async fn create<'a>(
         &'a mut self,
         configuration: Arc<Configuration>,
         schema: Arc<Schema>,
-        _previous_router: Option<&'a Self::RouterService>,
+        previous_router: Option<&'a Self::RouterService>,
     ) -> Result<(Self::RouterService, Plugins), BoxError> {
         let mut builder = PluggableRouterServiceBuilder::new(schema.clone());
         if configuration.server.introspection {
             builder = builder.with_naive_introspection();
         }
+        if let Some(limits) = &configuration.limits {
+            builder = builder.with_operation_limits(limits.clone());
+        }
+
+        // the manifest is only loaded again if its path changes, the watcher keeps it up to date
+        let manifest_watcher = match (&configuration.persisted_queries, &self.manifest_watcher) {
+            (Some(persisted_queries), Some(watcher))
+                if watcher.path == persisted_queries.manifest =>
+            {
+                None
+            }
+            (Some(persisted_queries), _) => {
+                Some(ManifestWatcher::new(&persisted_queries.manifest)?)
+            }
+            (None, _) => None,
+        };
+        if let Some(persisted_queries) = &configuration.persisted_queries {
+            let watcher = manifest_watcher
+                .as_ref()
+                .or(self.manifest_watcher.as_ref())
+                .expect("the manifest watcher was created if missing; qed");
+            builder =
+                builder.with_safelist(watcher.safelist.with_log_only(persisted_queries.log_only));
+        }
+
+        let apq_storage = if configuration.apq.enabled {
+            let storage = match &self.apq_storage {
+                Some((apq, storage)) if *apq == configuration.apq => storage.clone(),
+                _ => create_apq_storage(&configuration.apq)?,
+            };
+            builder = builder.with_apq_storage(storage.clone());
+            Some((configuration.apq.clone(), storage))
+        } else {
+            builder = builder.without_apq();
+            None
+        };
 
         for (name, _) in schema.subgraphs() {
-            let subgraph_service = BoxService::new(TowerSubgraphService::new(name.to_string()));
+            let subgraph_service = match configuration.transport.subgraph(name) {
+                Some(transport) => {
+                    let tls_config = tls::client_config(
+                        transport.tls.as_ref().unwrap_or(&SubgraphTls::default()),
+                    )?;
+                    // the http client negotiates its protocol with ALPN, the WebSocket
+                    // connections keep the configuration without it
+                    let mut service = TowerSubgraphService::with_client(
+                        name.to_string(),
+                        subgraph_client(&transport, tls_config.clone()),
+                    )
+                    .with_tls_config(Arc::new(tls_config));
+                    if let Some(compression) = &transport.request_compression {
+                        service = service
+                            .with_request_compression(compression.algorithm, compression.min_size);
+                    }
+                    if let Some(max_size) = transport.max_decompressed_size {
+                        service = service.with_max_decompressed_size(max_size);
+                    }
+                    BoxService::new(service)
+                }
+                None => BoxService::new(TowerSubgraphService::new(name.to_string())),
+            };
 
             builder = builder.with_subgraph_service(name, subgraph_service);
         }
@@ -76,7 +248,21 @@ impl RouterServiceFactory for YamlRouterServiceFactory {
             builder = builder.with_dyn_plugin(plugin_name, plugin);
         }
 
-        let (pluggable_router_service, mut plugins) = builder.build().await?;
+        let (pluggable_router_service, mut plugins, query_planner) =
+            builder.build_with_query_planner().await?;
+
+        if let Some(previous_router) = previous_router {
+            let mut keys = previous_router.query_planner.get_hot_keys().await;
+            keys.truncate(configuration.query_planning.warmed_up_queries);
+            if !keys.is_empty() {
+                let count = keys.len();
+                let planned = query_planner
+                    .warm_up(keys, configuration.query_planning.warm_up_budget())
+                    .await;
+                tracing::info!("warmed up the query planner with {planned}/{count} queries");
+            }
+        }
+
         let service = ServiceBuilder::new().buffered().service(
             pluggable_router_service
                 .map_request(|http_request: Request<apollo_router_core::Request>| {
@@ -96,8 +282,66 @@ impl RouterServiceFactory for YamlRouterServiceFactory {
             tracing::debug!("activated plugin {}", plugin.name());
         }
 
-        Ok((service, plugins))
+        // Only replace the watcher once the router is created, the previous router still uses it
+        // otherwise
+        if manifest_watcher.is_some() || configuration.persisted_queries.is_none() {
+            self.manifest_watcher = manifest_watcher;
+        }
+        self.apq_storage = apq_storage;
+
+        Ok((
+            YamlRouterService {
+                service,
+                query_planner,
+            },
+            plugins,
+        ))
+    }
+}
+
+/// Create the storage of the queries registered with APQ.
+fn create_apq_storage(apq: &Apq) -> Result<Arc<dyn ApqStorage>, BoxError> {
+    let local = match apq.max_bytes {
+        Some(max_bytes) => InMemoryApqStorage::with_max_bytes(max_bytes, apq.ttl),
+        None => {
+            InMemoryApqStorage::new(apq.max_entries.unwrap_or(DEFAULT_APQ_MAX_ENTRIES), apq.ttl)
+        }
+    };
+    Ok(match &apq.redis {
+        Some(redis) => Arc::new(RedisApqStorage::new(&redis.url, apq.ttl, local)?),
+        None => Arc::new(local),
+    })
+}
+
+/// Create the http client of a subgraph from its transport settings.
+fn subgraph_client(
+    transport: &SubgraphTransport,
+    tls_config: rustls::ClientConfig,
+) -> hyper::Client<HttpsConnector<HttpConnector>> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_keepalive(transport.tcp_keepalive);
+    let http2_prior_knowledge = transport.http2_prior_knowledge.unwrap_or_default();
+
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http();
+    let connector = if http2_prior_knowledge {
+        builder.enable_http2().wrap_connector(http)
+    } else {
+        builder.enable_http1().enable_http2().wrap_connector(http)
+    };
+
+    let mut builder = hyper::Client::builder();
+    builder.http2_only(http2_prior_knowledge);
+    if let Some(max_idle) = transport.pool_max_idle_per_host {
+        builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(idle_timeout) = transport.pool_idle_timeout {
+        builder.pool_idle_timeout(idle_timeout);
     }
+
+    builder.build(connector)
 }
 
 async fn create_plugins(
@@ -202,14 +446,15 @@ fn visit(value: &mut serde_json::Value) {
 mod test {
     use crate::router_factory::{inject_schema_id, RouterServiceFactory};
     use crate::{Configuration, YamlRouterServiceFactory};
-    use apollo_router_core::Schema;
     use apollo_router_core::{register_plugin, Plugin};
+    use apollo_router_core::{QueryPlanOptions, Schema};
     use schemars::JsonSchema;
     use serde::Deserialize;
     use serde_json::json;
     use std::error::Error;
     use std::fmt;
     use std::sync::Arc;
+    use std::time::Duration;
     use tower_http::BoxError;
 
     #[derive(Debug)]
@@ -321,6 +566,120 @@ mod test {
         assert!(service.is_err())
     }
 
+    #[tokio::test]
+    async fn test_yaml_subgraph_transport() {
+        let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/testdata/tls");
+        let config: Configuration = serde_yaml::from_str(&format!(
+            r#"
+            transport:
+              all:
+                pool_max_idle_per_host: 4
+                pool_idle_timeout: 30s
+                request_compression:
+                  algorithm: gzip
+              subgraphs:
+                accounts:
+                  http2_prior_knowledge: true
+                  tls:
+                    certificate_authorities: {testdata}/ca.pem
+                    client_authentication:
+                      certificate_chain: {testdata}/client.pem
+                      key: {testdata}/client.key
+        "#
+        ))
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service.is_ok())
+    }
+
+    #[tokio::test]
+    async fn test_yaml_subgraph_transport_with_missing_certificate() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            transport:
+              subgraphs:
+                accounts:
+                  tls:
+                    certificate_authorities: missing.pem
+        "#,
+        )
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service.is_err())
+    }
+
+    #[tokio::test]
+    async fn test_yaml_persisted_queries() {
+        let manifest = std::env::temp_dir().join("router_factory_test_manifest.json");
+        std::fs::write(
+            &manifest,
+            r#"{
+                "format": "apollo-persisted-query-manifest",
+                "version": 1,
+                "operations": [{ "id": "me", "body": "{ me { id } }" }]
+            }"#,
+        )
+        .unwrap();
+        let config: Configuration = serde_yaml::from_str(&format!(
+            r#"
+            persisted_queries:
+              manifest: {}
+              log_only: true
+        "#,
+            manifest.display()
+        ))
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service.is_ok())
+    }
+
+    #[tokio::test]
+    async fn test_apq_storage_is_kept_between_routers() {
+        let schema: Arc<Schema> =
+            Arc::new(include_str!("testdata/supergraph.graphql").parse().unwrap());
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            apq:
+              max_bytes: 1000000
+              ttl: 24h
+        "#,
+        )
+        .unwrap();
+        let config = Arc::new(config);
+        let mut factory = YamlRouterServiceFactory::default();
+
+        factory
+            .create(config.clone(), schema.clone(), None)
+            .await
+            .unwrap();
+        let storage = factory.apq_storage.as_ref().unwrap().1.clone();
+        factory.create(config, schema.clone(), None).await.unwrap();
+        assert!(Arc::ptr_eq(
+            &storage,
+            &factory.apq_storage.as_ref().unwrap().1
+        ));
+
+        let disabled: Configuration = serde_yaml::from_str("apq:\n  enabled: false").unwrap();
+        factory
+            .create(Arc::new(disabled), schema, None)
+            .await
+            .unwrap();
+        assert!(factory.apq_storage.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_yaml_persisted_queries_with_missing_manifest() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+            persisted_queries:
+              manifest: missing.json
+        "#,
+        )
+        .unwrap();
+        let service = create_service(config).await;
+        assert!(service.is_err())
+    }
+
     // This test must use the multi_thread tokio executor or the opentelemetry hang bug will
     // be encountered. (See https://github.com/open-telemetry/opentelemetry-rust/issues/536)
     #[tokio::test(flavor = "multi_thread")]
@@ -358,6 +717,34 @@ mod test {
         service.map(|_| ()).unwrap_err();
     }
 
+    #[tokio::test]
+    async fn test_query_planner_warm_up() {
+        let schema: Arc<Schema> =
+            Arc::new(include_str!("testdata/supergraph.graphql").parse().unwrap());
+        let config = Arc::new(Configuration::builder().build());
+        let mut factory = YamlRouterServiceFactory::default();
+
+        let (previous_router, _) = factory
+            .create(config.clone(), schema.clone(), None)
+            .await
+            .unwrap();
+        let key = (
+            "{ me { id } }".to_string(),
+            None,
+            QueryPlanOptions::default(),
+        );
+        previous_router
+            .query_planner
+            .warm_up(vec![key.clone()], Duration::from_secs(5))
+            .await;
+
+        let (router, _) = factory
+            .create(config, schema, Some(&previous_router))
+            .await
+            .unwrap();
+        assert_eq!(router.query_planner.get_hot_keys().await, vec![key]);
+    }
+
     async fn create_service(config: Configuration) -> Result<(), BoxError> {
         let schema: Schema = include_str!("testdata/supergraph.graphql").parse().unwrap();
 
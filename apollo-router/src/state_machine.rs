@@ -5,6 +5,7 @@ use super::Event::{UpdateConfiguration, UpdateSchema};
 use super::FederatedServerError::{NoConfiguration, NoSchema};
 use super::{Event, FederatedServerError, State};
 use crate::configuration::Configuration;
+use crate::tls;
 use apollo_router_core::Schema;
 use apollo_router_core::{prelude::*, Handler, Plugins};
 use futures::channel::mpsc;
@@ -12,6 +13,7 @@ use futures::prelude::*;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
 use Event::{NoMoreConfiguration, NoMoreSchema, Shutdown};
 
 /// This state maintains private information that is not exposed to the user via state listener.
@@ -265,6 +267,10 @@ where
             let configuration = Arc::new(configuration);
             let schema = Arc::new(schema);
 
+            let tls_acceptor = tls_acceptor(&configuration).map_err(|err| {
+                tracing::error!("cannot load the TLS certificates: {}", err);
+                Errored(err)
+            })?;
             let (router, plugins) = self
                 .router_factory
                 .create(configuration.clone(), schema.clone(), None)
@@ -285,7 +291,13 @@ where
 
             let server_handle = self
                 .http_server_factory
-                .create(router.clone(), configuration.clone(), None, plugin_handlers)
+                .create(
+                    router.clone(),
+                    configuration.clone(),
+                    None,
+                    tls_acceptor,
+                    plugin_handlers,
+                )
                 .await
                 .map_err(|err| {
                     tracing::error!("cannot start the router: {}", err);
@@ -321,6 +333,25 @@ where
         let new_schema = new_schema.unwrap_or_else(|| schema.clone());
         let new_configuration = new_configuration.unwrap_or_else(|| configuration.clone());
 
+        // the certificates are loaded before the current server is stopped, so that it keeps
+        // serving with the previous configuration if they are missing or invalid
+        let tls_acceptor = match tls_acceptor(&new_configuration) {
+            Ok(tls_acceptor) => tls_acceptor,
+            Err(err) => {
+                tracing::error!(
+                    "cannot load the TLS certificates, keeping previous configuration: {}",
+                    err
+                );
+                return Err(Running {
+                    configuration,
+                    schema,
+                    router_service,
+                    server_handle,
+                    plugins,
+                });
+            }
+        };
+
         match self
             .router_factory
             .create(
@@ -347,6 +378,7 @@ where
                         &self.http_server_factory,
                         new_router_service.clone(),
                         new_configuration.clone(),
+                        tls_acceptor,
                         plugin_handlers,
                     )
                     .await
@@ -379,6 +411,20 @@ where
     }
 }
 
+/// Load the certificates of the listener, every time the server is created so that they are
+/// updated when the configuration is reloaded.
+fn tls_acceptor(
+    configuration: &Configuration,
+) -> Result<Option<TlsAcceptor>, FederatedServerError> {
+    configuration
+        .server
+        .tls
+        .as_ref()
+        .map(tls::acceptor)
+        .transpose()
+        .map_err(FederatedServerError::ServerCreationError)
+}
+
 trait ResultExt<T> {
     // Unstable method can be deleted in future
     fn into_ok_or_err2(self) -> T;
@@ -396,6 +442,8 @@ impl<T> ResultExt<T> for Result<T, T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::axum_http_server_factory::AxumHttpServerFactory;
+    use crate::configuration::ListenAddr;
     use crate::http_server_factory::Listener;
     use crate::router_factory::RouterServiceFactory;
     use apollo_router_core::http_compat::{Request, Response};
@@ -670,6 +718,70 @@ mod tests {
         assert_eq!(shutdown_receivers.lock().unwrap().len(), 1);
     }
 
+    #[test(tokio::test)]
+    async fn invalid_certificates_keep_the_previous_server() {
+        let mut router_factory = MockMyRouterFactory::new();
+        router_factory
+            .expect_create()
+            .times(1)
+            .returning(|_, _, _| Ok((cloneable_router(), Default::default())));
+        let configuration = Configuration::builder()
+            .server(
+                crate::configuration::Server::builder()
+                    .listen(SocketAddr::from_str("127.0.0.1:0").unwrap())
+                    .build(),
+            )
+            .build();
+        let mut invalid_configuration = configuration.clone();
+        invalid_configuration.server.tls = Some(
+            crate::configuration::Tls::builder()
+                .certificate_chain("does-not-exist.pem")
+                .key("does-not-exist.key")
+                .build(),
+        );
+
+        let (state_listener, mut state_receiver) = mpsc::channel(100);
+        let state_machine = StateMachine::new(
+            AxumHttpServerFactory::new(),
+            Some(state_listener),
+            router_factory,
+        );
+        let events = stream::iter(vec![
+            UpdateConfiguration(configuration.boxed()),
+            UpdateSchema(Box::new(example_schema())),
+            UpdateConfiguration(invalid_configuration.boxed()),
+        ])
+        // the state machine asks for this event once the invalid configuration was handled
+        .chain(stream::once(async {
+            assert_eq!(state_receiver.next().await, Some(State::Startup));
+            let address = match state_receiver.next().await {
+                Some(State::Running {
+                    address: ListenAddr::SocketAddr(address),
+                    ..
+                }) => address,
+                state => panic!("unexpected state {:?}", state),
+            };
+            let response = reqwest::get(format!(
+                "http://{}/.well-known/apollo/server-health",
+                address
+            ))
+            .await
+            .expect("the previous server is still running");
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            Shutdown
+        }))
+        .boxed();
+
+        assert!(matches!(state_machine.process_events(events).await, Ok(())));
+    }
+
+    /// A router which can be cloned any number of times, for the real http server.
+    fn cloneable_router() -> MockMyRouter {
+        let mut router = MockMyRouter::new();
+        router.expect_clone().returning(cloneable_router);
+        router
+    }
+
     mock! {
         #[derive(Debug)]
         MyRouterFactory {}
@@ -732,6 +844,7 @@ mod tests {
             _service: RS,
             configuration: Arc<Configuration>,
             listener: Option<Listener>,
+            _tls_acceptor: Option<TlsAcceptor>,
             _plugin_handlers: HashMap<String, Handler>,
         ) -> Pin<Box<dyn Future<Output = Result<HttpServerHandle, FederatedServerError>> + Send>>
         where
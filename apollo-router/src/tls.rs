@@ -0,0 +1,186 @@
+//! TLS termination for the listener, and TLS settings of the subgraph clients.
+//!
+//! The certificates are read when the http server and the subgraph services are created, so a
+//! configuration reload picks up the new files while the listener is kept.
+
+use crate::configuration::{SubgraphTls, Tls};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+
+/// ALPN identifier of HTTP/2.
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+/// ALPN identifier of HTTP/1.1.
+const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// Create the acceptor wrapping the connections of the listener.
+pub(crate) fn acceptor(tls: &Tls) -> io::Result<TlsAcceptor> {
+    let certificate_chain = load_certificates(&tls.certificate_chain)?;
+    let key = load_key(&tls.key)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(path)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|err| invalid_data(path, err))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certificate_chain, key)
+        .map_err(|err| invalid_data(&tls.certificate_chain, err))?;
+
+    config.alpn_protocols = if tls.http2 {
+        vec![ALPN_H2.to_vec(), ALPN_HTTP1.to_vec()]
+    } else {
+        vec![ALPN_HTTP1.to_vec()]
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Create the TLS configuration of a subgraph client.
+pub(crate) fn client_config(tls: &SubgraphTls) -> io::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match &tls.certificate_authorities {
+        Some(path) => {
+            for certificate in load_certificates(path)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|err| invalid_data(path, err))?;
+            }
+        }
+        None => {
+            let certificates = rustls_native_certs::load_native_certs()?
+                .into_iter()
+                .map(|certificate| certificate.0)
+                .collect::<Vec<_>>();
+            roots.add_parsable_certificates(&certificates);
+        }
+    }
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    match &tls.client_authentication {
+        Some(authentication) => builder
+            .with_single_cert(
+                load_certificates(&authentication.certificate_chain)?,
+                load_key(&authentication.key)?,
+            )
+            .map_err(|err| invalid_data(&authentication.certificate_chain, err)),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+fn load_certificates(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path).map_err(|err| with_path(path, err))?);
+    let certificates = rustls_pemfile::certs(&mut reader).map_err(|err| with_path(path, err))?;
+    if certificates.is_empty() {
+        return Err(invalid_data(path, "no certificate found"));
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path).map_err(|err| with_path(path, err))?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|err| with_path(path, err))? {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => return Err(invalid_data(path, "no private key found")),
+        }
+    }
+}
+
+fn with_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+fn invalid_data(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::ClientAuthentication;
+
+    fn testdata(file: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/testdata/tls")
+            .join(file)
+    }
+
+    #[test]
+    fn it_loads_certificates_and_key() {
+        acceptor(
+            &Tls::builder()
+                .certificate_chain(testdata("server.pem"))
+                .key(testdata("server.key"))
+                .client_ca(testdata("ca.pem"))
+                .build(),
+        )
+        .expect("the test certificates are valid");
+    }
+
+    #[test]
+    fn it_fails_without_private_key() {
+        let err = acceptor(
+            &Tls::builder()
+                .certificate_chain(testdata("server.pem"))
+                .key(testdata("server.pem"))
+                .build(),
+        )
+        .err()
+        .expect("the certificate chain does not contain a key");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .ends_with("server.pem: no private key found"));
+    }
+
+    #[test]
+    fn it_fails_with_missing_files() {
+        let err = acceptor(
+            &Tls::builder()
+                .certificate_chain(testdata("missing.pem"))
+                .key(testdata("server.key"))
+                .build(),
+        )
+        .err()
+        .expect("the file does not exist");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn it_loads_client_certificates() {
+        client_config(
+            &SubgraphTls::builder()
+                .certificate_authorities(testdata("ca.pem"))
+                .client_authentication(
+                    ClientAuthentication::builder()
+                        .certificate_chain(testdata("client.pem"))
+                        .key(testdata("client.key"))
+                        .build(),
+                )
+                .build(),
+        )
+        .expect("the test certificates are valid");
+    }
+}
@@ -0,0 +1,289 @@
+//! GraphQL operations over WebSocket, with the `graphql-transport-ws` protocol.
+//!
+//! Every operation received on the connection goes through the router service, and all the
+//! responses it produces (like subscription events) are sent back as `next` messages.
+
+use crate::axum_http_server_factory::{call_router_service, BufferedService};
+use apollo_router_core::graphql_ws::{self, Message, GRAPHQL_TRANSPORT_WS_PROTOCOL};
+use apollo_router_core::prelude::*;
+use apollo_router_core::{IncrementalResponses, ResponseBody};
+use axum::extract::ws::{CloseFrame, Message as WsMessage, WebSocket};
+use futures::channel::mpsc;
+use futures::future::{self, AbortHandle};
+use futures::prelude::*;
+use http::{HeaderMap, Request, Uri};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::Instrument;
+
+/// The connection is closed if the client does not send `connection_init` in time.
+const CONNECTION_INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handle the `graphql-transport-ws` protocol on an upgraded connection.
+///
+/// `uri` and `headers` come from the upgrade request, and are used for every operation.
+pub(crate) async fn serve(
+    mut socket: WebSocket,
+    service: BufferedService,
+    uri: Uri,
+    headers: HeaderMap,
+) {
+    if socket
+        .protocol()
+        .map(|protocol| protocol != GRAPHQL_TRANSPORT_WS_PROTOCOL)
+        .unwrap_or(true)
+    {
+        let _ = socket
+            .send(WsMessage::Close(Some(CloseFrame {
+                code: graphql_ws::CLOSE_SUBPROTOCOL_NOT_ACCEPTABLE,
+                reason: "Subprotocol not acceptable".into(),
+            })))
+            .await;
+        return;
+    }
+
+    let (mut sink, mut stream) = socket.split();
+
+    // the operations send their messages concurrently, so they go through a channel
+    let (mut outgoing, mut outgoing_receiver) = mpsc::channel::<WsMessage>(16);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing_receiver.next().await {
+            let is_close = matches!(message, WsMessage::Close(_));
+            if sink.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
+
+    // an id can be reused once its operation is complete, so the operations are also told
+    // apart by a sequence number, and a finished operation only removes its own entry
+    let (finished_sender, mut finished) = mpsc::unbounded::<(String, u64)>();
+    let mut operations: HashMap<String, (u64, AbortHandle)> = HashMap::new();
+    let mut next_sequence: u64 = 0;
+    let mut acknowledged = false;
+
+    let init_timeout = tokio::time::sleep(CONNECTION_INIT_TIMEOUT);
+    tokio::pin!(init_timeout);
+
+    loop {
+        let message = tokio::select! {
+            _ = &mut init_timeout, if !acknowledged => {
+                close(
+                    &mut outgoing,
+                    graphql_ws::CLOSE_CONNECTION_INIT_TIMEOUT,
+                    "Connection initialisation timeout".to_string(),
+                )
+                .await;
+                break;
+            }
+            Some((id, sequence)) = finished.next() => {
+                if matches!(operations.get(&id), Some((current, _)) if *current == sequence) {
+                    operations.remove(&id);
+                }
+                continue;
+            }
+            message = stream.next() => message,
+        };
+
+        let text = match message {
+            Some(Ok(WsMessage::Text(text))) => text,
+            Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+            // ping frames are answered by the WebSocket implementation
+            Some(Ok(_)) => continue,
+        };
+
+        let message = match serde_json::from_str::<Message>(&text) {
+            Ok(message) => message,
+            Err(err) => {
+                close(
+                    &mut outgoing,
+                    graphql_ws::CLOSE_INVALID_MESSAGE,
+                    format!("Invalid message received: {}", err),
+                )
+                .await;
+                break;
+            }
+        };
+
+        match message {
+            Message::ConnectionInit { .. } => {
+                if acknowledged {
+                    close(
+                        &mut outgoing,
+                        graphql_ws::CLOSE_TOO_MANY_INITIALISATION_REQUESTS,
+                        "Too many initialisation requests".to_string(),
+                    )
+                    .await;
+                    break;
+                }
+                acknowledged = true;
+                send(&mut outgoing, Message::ConnectionAck { payload: None }).await;
+            }
+            Message::Ping { .. } => send(&mut outgoing, Message::Pong { payload: None }).await,
+            Message::Pong { .. } => {}
+            Message::Subscribe { id, payload } => {
+                if !acknowledged {
+                    close(
+                        &mut outgoing,
+                        graphql_ws::CLOSE_UNAUTHORIZED,
+                        "Unauthorized".to_string(),
+                    )
+                    .await;
+                    break;
+                }
+                if operations.contains_key(&id) {
+                    close(
+                        &mut outgoing,
+                        graphql_ws::CLOSE_SUBSCRIBER_ALREADY_EXISTS,
+                        format!("Subscriber for {} already exists", id),
+                    )
+                    .await;
+                    break;
+                }
+
+                let (operation, abort_handle) = future::abortable(execute(
+                    service.clone(),
+                    uri.clone(),
+                    headers.clone(),
+                    id.clone(),
+                    payload,
+                    outgoing.clone(),
+                ));
+                let finished_sender = finished_sender.clone();
+                let operation_id = id.clone();
+                let sequence = next_sequence;
+                next_sequence += 1;
+                tokio::spawn(
+                    async move {
+                        let _ = operation.await;
+                        let _ = finished_sender.unbounded_send((operation_id, sequence));
+                    }
+                    .instrument(
+                        tracing::info_span!("websocket_operation", "graphql.operation.id" = %id),
+                    ),
+                );
+                operations.insert(id, (sequence, abort_handle));
+            }
+            Message::Complete { id } => {
+                if let Some((_, abort_handle)) = operations.remove(&id) {
+                    abort_handle.abort();
+                }
+            }
+            Message::ConnectionAck { .. } | Message::Next { .. } | Message::Error { .. } => {
+                close(
+                    &mut outgoing,
+                    graphql_ws::CLOSE_INVALID_MESSAGE,
+                    "Unexpected message received".to_string(),
+                )
+                .await;
+                break;
+            }
+        }
+    }
+
+    // stopping the operations closes their subgraph subscriptions
+    for (_, (_, abort_handle)) in operations {
+        abort_handle.abort();
+    }
+    drop(outgoing);
+    let _ = writer.await;
+}
+
+/// Execute one operation and send its responses.
+async fn execute(
+    service: BufferedService,
+    uri: Uri,
+    headers: HeaderMap,
+    id: String,
+    request: graphql::Request,
+    mut outgoing: mpsc::Sender<WsMessage>,
+) {
+    // the operation is not sent by a browser form, so it is handled like a POST request
+    let mut http_request = Request::post(uri)
+        .body(request)
+        .expect("the URI comes from a valid request; qed");
+    *http_request.headers_mut() = headers;
+
+    let (parts, body) = match call_router_service(service, http_request).await {
+        Ok(response) => response.into_parts(),
+        Err((_, reason)) => {
+            send(
+                &mut outgoing,
+                Message::Error {
+                    id,
+                    payload: vec![graphql::Error::builder()
+                        .message(reason.to_string())
+                        .build()],
+                },
+            )
+            .await;
+            return;
+        }
+    };
+
+    let response = match body {
+        ResponseBody::GraphQL(response) => response,
+        _ => {
+            send(
+                &mut outgoing,
+                Message::Error {
+                    id,
+                    payload: vec![graphql::Error::builder()
+                        .message("the router did not answer with a GraphQL response".to_string())
+                        .build()],
+                },
+            )
+            .await;
+            return;
+        }
+    };
+
+    // the request was rejected before execution
+    if (parts.status.is_client_error() || parts.status.is_server_error()) && response.data.is_none()
+    {
+        send(
+            &mut outgoing,
+            Message::Error {
+                id,
+                payload: response.errors,
+            },
+        )
+        .await;
+        return;
+    }
+
+    let following = parts
+        .extensions
+        .get::<IncrementalResponses>()
+        .and_then(|responses| responses.take())
+        .unwrap_or_else(|| stream::empty().boxed());
+    let mut responses = stream::once(future::ready(response)).chain(following);
+    while let Some(payload) = responses.next().await {
+        let message = Message::Next {
+            id: id.clone(),
+            payload,
+        };
+        if outgoing
+            .send(WsMessage::Text(message.to_text()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    send(&mut outgoing, Message::Complete { id }).await;
+}
+
+async fn send(outgoing: &mut mpsc::Sender<WsMessage>, message: Message) {
+    let _ = outgoing.send(WsMessage::Text(message.to_text())).await;
+}
+
+async fn close(outgoing: &mut mpsc::Sender<WsMessage>, code: u16, reason: String) {
+    let _ = outgoing
+        .send(WsMessage::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await;
+}
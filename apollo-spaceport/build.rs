@@ -42,17 +42,23 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Process the proto files
     let proto_files = vec!["proto/agents.proto", "proto/reports.proto"];
 
-    tonic_build::configure()
-        .type_attribute("ContextualizedStats", "#[derive(serde::Serialize)]")
-        .type_attribute("StatsContext", "#[derive(serde::Serialize)]")
-        .type_attribute("QueryLatencyStats", "#[derive(serde::Serialize)]")
-        .type_attribute("TypeStat", "#[derive(serde::Serialize)]")
-        .type_attribute("PathErrorStats", "#[derive(serde::Serialize)]")
-        .type_attribute("FieldStat", "#[derive(serde::Serialize)]")
-        .type_attribute("ReferencedFieldsForType", "#[derive(serde::Serialize)]")
-        .type_attribute("StatsContext", "#[derive(Eq, Hash)]")
-        .build_server(true)
-        .compile(&proto_files, &["."])?;
+    // All the messages are serializable, so that reports can be written as JSON
+    let mut builder = tonic_build::configure()
+        .type_attribute(".", "#[derive(serde::Serialize)]")
+        .type_attribute("StatsContext", "#[derive(Eq, Hash)]");
+    for timestamp in [
+        "Report.end_time",
+        "Trace.start_time",
+        "Trace.end_time",
+        "FetchNode.sent_time",
+        "FetchNode.received_time",
+    ] {
+        builder = builder.field_attribute(
+            timestamp,
+            "#[serde(serialize_with = \"crate::serialize_timestamp\")]",
+        );
+    }
+    builder.build_server(true).compile(&proto_files, &["."])?;
 
     for file in proto_files {
         println!("cargo:rerun-if-changed={}", file);
@@ -9,11 +9,13 @@ mod agent {
 
 /// The server module contains the server components
 pub mod server;
+mod spool;
 
 use agent::reporter_client::ReporterClient;
 pub use agent::*;
 pub use prost_types::Timestamp;
 pub use report::*;
+use serde::Serialize;
 use std::error::Error;
 use sys_info::hostname;
 use tokio::task::JoinError;
@@ -85,6 +87,29 @@ impl std::fmt::Display for ReporterError {
     }
 }
 
+/// Serialize the protobuf timestamps of the reports, which are not serializable themselves.
+pub(crate) fn serialize_timestamp<S>(
+    timestamp: &Option<Timestamp>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    #[derive(serde::Serialize)]
+    struct SerializableTimestamp {
+        seconds: i64,
+        nanos: i32,
+    }
+
+    timestamp
+        .as_ref()
+        .map(|timestamp| SerializableTimestamp {
+            seconds: timestamp.seconds,
+            nanos: timestamp.nanos,
+        })
+        .serialize(serializer)
+}
+
 impl Report {
     /// Try to create a new Report.
     ///
@@ -1,74 +1,191 @@
 // This entire file is license key functionality
+pub use crate::spool::SpoolConfig;
 use crate::{
     agent::{
         reporter_server::{Reporter, ReporterServer},
         ReporterRequest, ReporterResponse,
     },
     report::Report,
+    spool::Spool,
 };
 use bytes::BytesMut;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use prost::Message;
 use reqwest::Client;
+use std::fs::OpenOptions;
 use std::future::Future;
 use std::io::Write;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 use tokio::time::Duration;
 use tokio::{net::TcpListener, sync::mpsc::error::TrySendError};
 use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::{Error, Server};
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status};
 
 static DEFAULT_APOLLO_USAGE_REPORTING_INGRESS_URL: &str =
     "https://usage-reporting.api.apollographql.com/api/ingress/traces";
+const MIN_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(300);
 
-/// Accept Traces and Stats from clients and transfer to an Apollo Ingress
+/// Where the spaceport transfers the reports.
+#[derive(Clone, Debug)]
+pub enum ReportDestination {
+    /// The Apollo ingress, or the URL of the `APOLLO_USAGE_REPORTING_INGRESS_URL` environment
+    /// variable if it is set.
+    Ingress,
+
+    /// An HTTP endpoint accepting reports like the Apollo ingress.
+    Endpoint(String),
+
+    /// A local file or directory.
+    ///
+    /// A file receives all the reports, separated by newlines in JSON or length delimited in
+    /// protobuf. A directory receives a file per report.
+    File { path: PathBuf, format: ReportFormat },
+}
+
+impl Default for ReportDestination {
+    fn default() -> Self {
+        ReportDestination::Ingress
+    }
+}
+
+/// The format of the reports written to a file.
+#[derive(Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Json,
+    Protobuf,
+}
+
+/// Accept Traces and Stats from clients and transfer to an Apollo Ingress, or another
+/// [`ReportDestination`]
 pub struct ReportSpaceport {
     shutdown_signal: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
     listener: Option<TcpListener>,
     addr: SocketAddr,
     tx: Sender<ReporterRequest>,
+    counters: Arc<ReportCounters>,
+}
+
+/// Counters of the reports handled by a spaceport.
+#[derive(Debug, Default)]
+pub struct ReportCounters {
+    spooled: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl ReportCounters {
+    /// The reports written to the spool after a failed transfer.
+    pub fn spooled(&self) -> u64 {
+        self.spooled.load(Ordering::Relaxed)
+    }
+
+    /// The reports transferred to the destination, directly or from the spool.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// The reports discarded: rejected by the destination, failing without a spool, dropped
+    /// from a full spool or expired, or refused because the queue of the spaceport was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 }
 
 impl ReportSpaceport {
     /// Create a new ReportSpaceport which is configured to serve requests at the
     /// supplied address
     ///
-    /// The spaceport will transfer reports to the Apollo Ingress.
+    /// The spaceport will transfer reports to the supplied destination.
     ///
-    /// The spaceport will attempt to make the transfer 5 times before failing. If
-    /// the spaceport fails, the data is discarded.
+    /// The spaceport will attempt to make an HTTP transfer 5 times before failing. If
+    /// the spaceport fails, the report is written to the spool if there is one, and
+    /// retried with a backoff, or discarded.
     pub async fn new(
         addr: SocketAddr,
         shutdown_signal: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+        destination: ReportDestination,
+        spool: Option<SpoolConfig>,
     ) -> Result<Self, std::io::Error> {
         let listener = TcpListener::bind(addr).await?;
         let addr = listener.local_addr()?;
 
+        let counters = Arc::new(ReportCounters::default());
+        let spool = match spool {
+            Some(config) => Some(Arc::new(Spool::new(config).await?)),
+            None => None,
+        };
+        let client = Client::new();
+
         // Spawn a task which will transmit reports
         let (tx, mut rx) = tokio::sync::mpsc::channel::<ReporterRequest>(1024);
+        let (closed_tx, closed_rx) = oneshot::channel::<()>();
 
+        let task_destination = destination.clone();
+        let task_client = client.clone();
+        let task_spool = spool.clone();
+        let task_counters = counters.clone();
         tokio::task::spawn(async move {
-            let client = Client::new();
-            while let Some(report) = rx.recv().await {
-                if let Some(report_to_send) = report.report {
-                    match ReportSpaceport::submit_report(&client, report.apollo_key, report_to_send)
-                        .await
-                    {
-                        Ok(v) => tracing::debug!("report submission succeeded: {:?}", v),
-                        Err(e) => tracing::error!("report submission failed: {}", e),
+            while let Some(request) = rx.recv().await {
+                match ReportSpaceport::transfer(&task_client, &task_destination, &request).await {
+                    Ok(v) => {
+                        tracing::debug!("report submission succeeded: {:?}", v);
+                        task_counters.sent.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        tracing::error!("report submission failed: {}", e);
+                        match &task_spool {
+                            // Transient failures are retried from the spool
+                            Some(spool) if e.code() == Code::Unavailable => {
+                                ReportSpaceport::spool_report(spool, &request, &task_counters).await
+                            }
+                            _ => {
+                                task_counters.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
                     }
                 }
             }
+            let _ = closed_tx.send(());
         });
+
+        // Spawn a task which will retry the spooled reports, including the ones left by a
+        // previous run
+        if let Some(spool) = spool {
+            let counters = counters.clone();
+            tokio::task::spawn(async move {
+                tokio::pin!(closed_rx);
+                let mut backoff = MIN_RETRY_BACKOFF;
+                loop {
+                    tokio::select! {
+                        _ = &mut closed_rx => break,
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                    match ReportSpaceport::replay(&client, &destination, &spool, &counters).await {
+                        Ok(()) => backoff = MIN_RETRY_BACKOFF,
+                        Err(e) => {
+                            tracing::warn!("could not transfer the spooled reports: {}", e);
+                            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                        }
+                    }
+                }
+            });
+        }
         Ok(Self {
             shutdown_signal,
             listener: Some(listener),
             addr,
             tx,
+            counters,
         })
     }
 
@@ -76,6 +193,11 @@ impl ReportSpaceport {
         &self.addr
     }
 
+    /// The counters of the reports handled by this spaceport.
+    pub fn counters(&self) -> Arc<ReportCounters> {
+        self.counters.clone()
+    }
+
     /// Start serving requests.
     pub async fn serve(mut self) -> Result<(), Error> {
         let shutdown_signal = self
@@ -92,10 +214,154 @@ impl ReportSpaceport {
             .await
     }
 
+    async fn transfer(
+        client: &Client,
+        destination: &ReportDestination,
+        request: &ReporterRequest,
+    ) -> Result<Response<ReporterResponse>, Status> {
+        let report = request
+            .report
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("missing report"))?;
+        match destination {
+            ReportDestination::Ingress => {
+                let ingress = match std::env::var("APOLLO_USAGE_REPORTING_INGRESS_URL") {
+                    Ok(v) => v,
+                    Err(_e) => DEFAULT_APOLLO_USAGE_REPORTING_INGRESS_URL.to_string(),
+                };
+                ReportSpaceport::submit_report(client, &ingress, &request.apollo_key, report).await
+            }
+            ReportDestination::Endpoint(endpoint) => {
+                ReportSpaceport::submit_report(client, endpoint, &request.apollo_key, report).await
+            }
+            ReportDestination::File { path, format } => {
+                let path = path.clone();
+                let format = *format;
+                let report = report.clone();
+                match tokio::task::spawn_blocking(move || {
+                    ReportSpaceport::write_report(&path, format, &report)
+                })
+                .await
+                {
+                    Ok(result) => result,
+                    Err(e) => Err(Status::internal(e.to_string())),
+                }
+            }
+        }
+    }
+
+    async fn spool_report(spool: &Spool, request: &ReporterRequest, counters: &ReportCounters) {
+        match spool.push(request).await {
+            Ok(evicted) => {
+                counters.spooled.fetch_add(1, Ordering::Relaxed);
+                counters.dropped.fetch_add(evicted, Ordering::Relaxed);
+            }
+            Err(e) => {
+                tracing::error!("could not spool the report: {}", e);
+                counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Transfer the spooled reports, oldest first, until the destination is unavailable.
+    async fn replay(
+        client: &Client,
+        destination: &ReportDestination,
+        spool: &Spool,
+        counters: &ReportCounters,
+    ) -> Result<(), Status> {
+        let expired = spool
+            .prune(0)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        counters.dropped.fetch_add(expired, Ordering::Relaxed);
+        let entries = spool
+            .entries()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        for entry in entries {
+            let request = match Spool::read(&entry).await {
+                Ok(request) => request,
+                // Dropped to make room for a newer report
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    tracing::error!("dropping unreadable spooled report {:?}: {}", entry, e);
+                    let _ = Spool::remove(&entry).await;
+                    counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            match ReportSpaceport::transfer(client, destination, &request).await {
+                Ok(_) => counters.sent.fetch_add(1, Ordering::Relaxed),
+                Err(e) if e.code() == Code::Unavailable => return Err(e),
+                Err(e) => {
+                    tracing::error!("dropping spooled report rejected by the destination: {}", e);
+                    counters.dropped.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+            let _ = Spool::remove(&entry).await;
+        }
+        tracing::debug!("spooled reports transferred, {:?}", counters);
+        Ok(())
+    }
+
+    fn write_report(
+        path: &Path,
+        format: ReportFormat,
+        report: &Report,
+    ) -> Result<Response<ReporterResponse>, Status> {
+        let is_dir = path.is_dir();
+        let content = match format {
+            ReportFormat::Json => {
+                let mut content = serde_json::to_vec(report)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+                if !is_dir {
+                    content.push(b'\n');
+                }
+                content
+            }
+            ReportFormat::Protobuf if is_dir => report.encode_to_vec(),
+            ReportFormat::Protobuf => report.encode_length_delimited_to_vec(),
+        };
+        tracing::debug!(
+            "writing report of {} bytes to {}",
+            content.len(),
+            path.display()
+        );
+
+        let result = if is_dir {
+            let extension = match format {
+                ReportFormat::Json => "json",
+                ReportFormat::Protobuf => "pb",
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            std::fs::write(
+                path.join(format!("report-{}.{}", timestamp, extension)),
+                content,
+            )
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(&content))
+        };
+        result.map_err(|e| Status::internal(e.to_string()))?;
+
+        let response = ReporterResponse {
+            message: "Report written".to_string(),
+        };
+        Ok(Response::new(response))
+    }
+
     async fn submit_report(
         client: &Client,
-        key: String,
-        report: Report,
+        endpoint: &str,
+        key: &str,
+        report: &Report,
     ) -> Result<Response<ReporterResponse>, Status> {
         tracing::debug!("submitting report: {:?}", report);
         // Protobuf encode message
@@ -114,12 +380,8 @@ impl ReportSpaceport {
             .finish()
             .map_err(|e| Status::internal(e.to_string()))?;
         let mut backoff = Duration::from_millis(0);
-        let ingress = match std::env::var("APOLLO_USAGE_REPORTING_INGRESS_URL") {
-            Ok(v) => v,
-            Err(_e) => DEFAULT_APOLLO_USAGE_REPORTING_INGRESS_URL.to_string(),
-        };
         let req = client
-            .post(ingress)
+            .post(endpoint)
             .body(compressed_content)
             .header("X-Api-Key", key)
             .header("Content-Encoding", "gzip")
@@ -205,7 +467,242 @@ impl ReportSpaceport {
                 Ok(Response::new(response))
             }
             Err(TrySendError::Closed(_)) => Err(Status::internal("channel closed")),
-            Err(TrySendError::Full(_)) => Err(Status::resource_exhausted("channel full")),
+            Err(TrySendError::Full(_)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(Status::resource_exhausted("channel full"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReportHeader;
+
+    fn report(graph_ref: &str) -> Report {
+        Report {
+            header: Some(ReportHeader {
+                graph_ref: graph_ref.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn graph_ref(report: &Report) -> &str {
+        &report.header.as_ref().unwrap().graph_ref
+    }
+
+    fn request(graph_ref: &str) -> ReporterRequest {
+        ReporterRequest {
+            apollo_key: "key".to_string(),
+            report: Some(report(graph_ref)),
+        }
+    }
+
+    fn spool_config(path: &Path, max_size: u64) -> SpoolConfig {
+        SpoolConfig {
+            path: path.to_path_buf(),
+            max_size,
+            max_age: None,
+        }
+    }
+
+    /// An endpoint refusing the connections.
+    async fn unavailable_endpoint() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn it_spools_the_reports_which_could_not_be_transferred() {
+        let dir = tempfile::tempdir().unwrap();
+        let spaceport = ReportSpaceport::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            ReportDestination::Endpoint(unavailable_endpoint().await),
+            Some(spool_config(dir.path(), 1024)),
+        )
+        .await
+        .unwrap();
+        spaceport
+            .add_report(request("graph@current"))
+            .await
+            .unwrap();
+
+        let counters = spaceport.counters();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while counters.spooled() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the report should be spooled");
+        assert_eq!(counters.sent(), 0);
+        assert_eq!(counters.dropped(), 0);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_retries_the_spooled_reports_until_they_are_transferred() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::new(spool_config(&dir.path().join("spool"), 1024))
+            .await
+            .unwrap();
+        spool.push(&request("first@current")).await.unwrap();
+        spool.push(&request("second@current")).await.unwrap();
+        let counters = ReportCounters::default();
+        let client = Client::new();
+
+        // The reports are kept while the destination is unavailable
+        let unavailable = ReportDestination::Endpoint(unavailable_endpoint().await);
+        let status = ReportSpaceport::replay(&client, &unavailable, &spool, &counters)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(spool.entries().await.unwrap().len(), 2);
+        assert_eq!(counters.sent(), 0);
+
+        let path = dir.path().join("reports.json");
+        let file = ReportDestination::File {
+            path: path.clone(),
+            format: ReportFormat::Json,
+        };
+        ReportSpaceport::replay(&client, &file, &spool, &counters)
+            .await
+            .unwrap();
+        assert!(spool.entries().await.unwrap().is_empty());
+        assert_eq!(counters.sent(), 2);
+        assert_eq!(counters.dropped(), 0);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let graph_refs = content
+            .lines()
+            .map(|line| {
+                let report: serde_json::Value = serde_json::from_str(line).unwrap();
+                report["header"]["graph_ref"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(graph_refs, ["first@current", "second@current"]);
+    }
+
+    #[tokio::test]
+    async fn it_drops_the_spooled_reports_rejected_or_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool_path = dir.path().join("spool");
+        std::fs::create_dir(&spool_path).unwrap();
+        std::fs::write(
+            spool_path.join("00000000000000000000-0000000000.report"),
+            request("expired@current").encode_to_vec(),
+        )
+        .unwrap();
+        let spool = Spool::new(SpoolConfig {
+            max_age: Some(Duration::from_secs(3600)),
+            ..spool_config(&spool_path, 1024)
+        })
+        .await
+        .unwrap();
+        // Rejected because it has no report
+        spool.push(&ReporterRequest::default()).await.unwrap();
+
+        let counters = ReportCounters::default();
+        let file = ReportDestination::File {
+            path: dir.path().join("reports.json"),
+            format: ReportFormat::Json,
+        };
+        ReportSpaceport::replay(&Client::new(), &file, &spool, &counters)
+            .await
+            .unwrap();
+        assert!(spool.entries().await.unwrap().is_empty());
+        assert_eq!(counters.sent(), 0);
+        assert_eq!(counters.dropped(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_counts_the_reports_dropped_from_a_full_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = request("graph@current").encode_to_vec().len() as u64;
+        let spool = Spool::new(spool_config(dir.path(), size)).await.unwrap();
+        let counters = ReportCounters::default();
+        ReportSpaceport::spool_report(&spool, &request("graph@current"), &counters).await;
+        ReportSpaceport::spool_report(&spool, &request("graph@current"), &counters).await;
+        assert_eq!(counters.spooled(), 2);
+        assert_eq!(counters.dropped(), 1);
+        assert_eq!(spool.entries().await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_appends_json_reports_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reports.json");
+        for graph_ref in ["first@current", "second@current"] {
+            ReportSpaceport::write_report(&path, ReportFormat::Json, &report(graph_ref)).unwrap();
         }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let graph_refs = content
+            .lines()
+            .map(|line| {
+                let report: serde_json::Value = serde_json::from_str(line).unwrap();
+                report["header"]["graph_ref"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(graph_refs, ["first@current", "second@current"]);
+    }
+
+    #[test]
+    fn it_appends_length_delimited_protobuf_reports_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reports.pb");
+        for graph_ref in ["first@current", "second@current"] {
+            ReportSpaceport::write_report(&path, ReportFormat::Protobuf, &report(graph_ref))
+                .unwrap();
+        }
+
+        let content = std::fs::read(&path).unwrap();
+        let mut buffer = content.as_slice();
+        let first = Report::decode_length_delimited(&mut buffer).unwrap();
+        let second = Report::decode_length_delimited(&mut buffer).unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(graph_ref(&first), "first@current");
+        assert_eq!(graph_ref(&second), "second@current");
+    }
+
+    #[test]
+    fn it_writes_a_file_per_report_in_a_directory() {
+        for (format, extension) in [(ReportFormat::Json, "json"), (ReportFormat::Protobuf, "pb")] {
+            let dir = tempfile::tempdir().unwrap();
+            ReportSpaceport::write_report(dir.path(), format, &report("graph@current")).unwrap();
+            ReportSpaceport::write_report(dir.path(), format, &report("graph@current")).unwrap();
+
+            let files = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect::<Vec<_>>();
+            assert_eq!(files.len(), 2);
+            for file in files {
+                assert_eq!(file.extension().unwrap(), extension);
+                let content = std::fs::read(&file).unwrap();
+                match format {
+                    ReportFormat::Json => {
+                        let report: serde_json::Value = serde_json::from_slice(&content).unwrap();
+                        assert_eq!(report["header"]["graph_ref"], "graph@current");
+                    }
+                    ReportFormat::Protobuf => {
+                        let report = Report::decode(content.as_slice()).unwrap();
+                        assert_eq!(graph_ref(&report), "graph@current");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_fails_when_the_file_cannot_be_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("reports.json");
+        let status =
+            ReportSpaceport::write_report(&path, ReportFormat::Json, &report("graph")).unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
     }
 }
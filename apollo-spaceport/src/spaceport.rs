@@ -1,12 +1,15 @@
 //! Main entry point for CLI command to start spaceport.
 // This entire file is license key functionality
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use apollo_spaceport::server::ReportSpaceport;
-use clap::Parser;
+use apollo_spaceport::server::{ReportDestination, ReportFormat, ReportSpaceport, SpoolConfig};
+use clap::{ArgEnum, Parser};
 use tracing_subscriber::filter::EnvFilter;
 
 const DEFAULT_LISTEN: &str = "127.0.0.1:50051";
+const DEFAULT_SPOOL_MAX_SIZE: &str = "104857600";
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -14,6 +17,45 @@ struct Args {
     /// Address to serve
     #[clap(short, long, default_value = DEFAULT_LISTEN)]
     address: SocketAddr,
+
+    /// HTTP endpoint receiving the reports instead of the Apollo ingress
+    #[clap(long, conflicts_with = "file")]
+    endpoint: Option<String>,
+
+    /// File or directory receiving the reports instead of the Apollo ingress
+    #[clap(long)]
+    file: Option<PathBuf>,
+
+    /// Format of the reports written to a file or directory
+    #[clap(long, arg_enum, default_value = "json")]
+    format: Format,
+
+    /// Directory holding the reports which could not be transferred, to retry them
+    #[clap(long)]
+    spool: Option<PathBuf>,
+
+    /// Maximum size of the spool in bytes
+    #[clap(long, default_value = DEFAULT_SPOOL_MAX_SIZE)]
+    spool_max_size: u64,
+
+    /// Maximum age of the spooled reports (e.g. "24h"), unlimited by default
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    spool_max_age: Option<Duration>,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Format {
+    Json,
+    Protobuf,
+}
+
+impl From<Format> for ReportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => ReportFormat::Json,
+            Format::Protobuf => ReportFormat::Protobuf,
+        }
+    }
 }
 
 #[tokio::main]
@@ -33,7 +75,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .json()
         .init();
     tracing::info!("spaceport starting");
-    let spaceport = ReportSpaceport::new(args.address, None).await?;
+    let destination = match (args.endpoint, args.file) {
+        (Some(endpoint), _) => ReportDestination::Endpoint(endpoint),
+        (None, Some(path)) => ReportDestination::File {
+            path,
+            format: args.format.into(),
+        },
+        (None, None) => ReportDestination::Ingress,
+    };
+    let spool = args.spool.map(|path| SpoolConfig {
+        path,
+        max_size: args.spool_max_size,
+        max_age: args.spool_max_age,
+    });
+    let spaceport = ReportSpaceport::new(args.address, None, destination, spool).await?;
+
+    let counters = spaceport.counters();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            tracing::info!(
+                spooled = counters.spooled(),
+                sent = counters.sent(),
+                dropped = counters.dropped(),
+                "report counters"
+            );
+        }
+    });
     spaceport.serve().await?;
 
     Ok(())
@@ -0,0 +1,293 @@
+// This entire file is license key functionality
+//! On-disk spool of the reports which could not be delivered.
+use crate::agent::ReporterRequest;
+use prost::Message;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+
+const EXTENSION: &str = "report";
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// Configuration of the spool holding the reports which could not be delivered.
+#[derive(Clone, Debug)]
+pub struct SpoolConfig {
+    /// The directory of the spool, created if needed.
+    pub path: PathBuf,
+
+    /// The maximum size of the spool in bytes. The oldest reports are dropped to make room for
+    /// new ones.
+    pub max_size: u64,
+
+    /// The maximum age of the spooled reports, older reports are dropped. Unlimited if `None`.
+    pub max_age: Option<Duration>,
+}
+
+/// The reports waiting for delivery, a file per report, in the order of their names.
+///
+/// The reports are stored with their API key, so that they can be delivered after a restart. On
+/// Unix, the spool directory and the reports are only accessible to the owner of the process.
+#[derive(Debug)]
+pub(crate) struct Spool {
+    path: PathBuf,
+    max_size: u64,
+    max_age: Option<Duration>,
+    sequence: AtomicU64,
+}
+
+impl Spool {
+    pub(crate) async fn new(config: SpoolConfig) -> io::Result<Self> {
+        let mut builder = tokio::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&config.path).await?;
+
+        // Reports left incomplete by a crash are never renamed, and would not be counted in
+        // the size of the spool.
+        let mut directory = tokio::fs::read_dir(&config.path).await?;
+        while let Some(entry) = directory.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str())
+                == Some(TEMPORARY_EXTENSION)
+            {
+                remove_entry(&path).await?;
+            }
+        }
+
+        Ok(Self {
+            path: config.path,
+            max_size: config.max_size,
+            max_age: config.max_age,
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Add a report to the spool, and return the number of older reports dropped to make room
+    /// for it or because they expired.
+    pub(crate) async fn push(&self, request: &ReporterRequest) -> io::Result<u64> {
+        let content = request.encode_to_vec();
+        let size = content.len() as u64;
+        if size > self.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the report is larger than the spool",
+            ));
+        }
+        let dropped = self.prune(size).await?;
+
+        // Names sort in the order of the reports, and the rename makes the report visible once
+        // it is complete.
+        let name = format!(
+            "{:020}-{:010}",
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        let temporary = self.path.join(&name).with_extension(TEMPORARY_EXTENSION);
+        write_private(&temporary, &content).await?;
+        tokio::fs::rename(&temporary, self.path.join(name).with_extension(EXTENSION)).await?;
+        Ok(dropped)
+    }
+
+    /// Remove the expired reports, then the oldest ones until `incoming` bytes fit in the
+    /// spool, and return the number of reports removed.
+    pub(crate) async fn prune(&self, incoming: u64) -> io::Result<u64> {
+        let mut dropped = 0;
+        let mut entries = self.entries_with_size().await?;
+        let mut total: u64 = entries.iter().map(|(_, size)| size).sum();
+        entries.reverse();
+        while let Some((path, entry_size)) = entries.pop() {
+            if total + incoming <= self.max_size && !self.is_expired(&path) {
+                break;
+            }
+            remove_entry(&path).await?;
+            total -= entry_size;
+            dropped += 1;
+        }
+        Ok(dropped)
+    }
+
+    /// The reports of the spool, oldest first.
+    pub(crate) async fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .entries_with_size()
+            .await?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    async fn entries_with_size(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = Vec::new();
+        let mut directory = tokio::fs::read_dir(&self.path).await?;
+        while let Some(entry) = directory.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some(EXTENSION) {
+                entries.push((path, entry.metadata().await?.len()));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Whether the report is older than the maximum age, from the time in its name.
+    fn is_expired(&self, path: &Path) -> bool {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return false,
+        };
+        let created = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.split('-').next())
+            .and_then(|nanos| nanos.parse::<u64>().ok())
+            .map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos));
+        match created {
+            Some(created) => created.elapsed().unwrap_or_default() > max_age,
+            None => false,
+        }
+    }
+
+    pub(crate) async fn read(path: &Path) -> io::Result<ReporterRequest> {
+        let content = tokio::fs::read(path).await?;
+        ReporterRequest::decode(content.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub(crate) async fn remove(path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await
+    }
+}
+
+/// Write a new file which only the owner of the process can read, as it holds an API key.
+async fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await
+}
+
+/// Remove a report, which may have been delivered in the meantime.
+async fn remove_entry(path: &Path) -> io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(apollo_key: &str) -> ReporterRequest {
+        ReporterRequest {
+            apollo_key: apollo_key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn spool(path: &Path, max_size: u64, max_age: Option<Duration>) -> Spool {
+        Spool::new(SpoolConfig {
+            path: path.to_path_buf(),
+            max_size,
+            max_age,
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn keys(spool: &Spool) -> Vec<String> {
+        let mut keys = Vec::new();
+        for entry in spool.entries().await.unwrap() {
+            keys.push(Spool::read(&entry).await.unwrap().apollo_key);
+        }
+        keys
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_reports_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 1024, None).await;
+        for key in ["first", "second", "third"] {
+            assert_eq!(spool.push(&request(key)).await.unwrap(), 0);
+        }
+        assert_eq!(keys(&spool).await, ["first", "second", "third"]);
+
+        let entries = spool.entries().await.unwrap();
+        Spool::remove(&entries[0]).await.unwrap();
+        assert_eq!(keys(&spool).await, ["second", "third"]);
+
+        // The reports are found again after a restart
+        let spool = self::spool(dir.path(), 1024, None).await;
+        assert_eq!(keys(&spool).await, ["second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn it_drops_the_oldest_reports_to_respect_the_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = request("first").encode_to_vec().len() as u64;
+        let spool = spool(dir.path(), size * 2, None).await;
+        assert_eq!(spool.push(&request("first")).await.unwrap(), 0);
+        assert_eq!(spool.push(&request("secon")).await.unwrap(), 0);
+        assert_eq!(spool.push(&request("third")).await.unwrap(), 1);
+        assert_eq!(keys(&spool).await, ["secon", "third"]);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_a_report_larger_than_the_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 4, None).await;
+        assert!(spool.push(&request("too large")).await.is_err());
+        assert!(spool.entries().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_drops_the_expired_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        // Spooled at the epoch, by a previous run
+        std::fs::write(
+            dir.path().join("00000000000000000000-0000000000.report"),
+            request("expired").encode_to_vec(),
+        )
+        .unwrap();
+        let spool = spool(dir.path(), 1024, Some(Duration::from_secs(3600))).await;
+        assert_eq!(keys(&spool).await, ["expired"]);
+
+        assert_eq!(spool.push(&request("recent")).await.unwrap(), 1);
+        assert_eq!(keys(&spool).await, ["recent"]);
+        assert_eq!(spool.prune(0).await.unwrap(), 0);
+        assert_eq!(keys(&spool).await, ["recent"]);
+    }
+
+    #[tokio::test]
+    async fn it_removes_incomplete_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("incomplete.tmp"), b"").unwrap();
+        let spool = spool(dir.path(), 1024, None).await;
+        assert!(spool.entries().await.unwrap().is_empty());
+        assert!(!dir.path().join("incomplete.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn it_keeps_the_reports_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spool");
+        let spool = spool(&path, 1024, None).await;
+        spool.push(&request("secret")).await.unwrap();
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o700);
+        assert_eq!(mode(&spool.entries().await.unwrap()[0]), 0o600);
+    }
+}